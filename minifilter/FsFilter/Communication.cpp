@@ -2,6 +2,141 @@
 
 #define POOL_FLAG_NON_PAGED 0x0000000000000040UI64  // Non paged pool NX
 
+// exported by ntoskrnl, not declared by the WDK headers
+extern "C" NTKERNELAPI NTSTATUS PsSuspendProcess(PEPROCESS Process);
+extern "C" NTKERNELAPI NTSTATUS PsResumeProcess(PEPROCESS Process);
+extern "C" NTKERNELAPI PUCHAR PsGetProcessImageFileName(PEPROCESS Process);
+
+enum GID_COMMAND { GID_KILL, GID_SUSPEND, GID_RESUME };
+
+// GetGidSize and GetGidPids are retried this many times while pids join the gid
+#define GID_PIDS_ATTEMPTS 4
+
+// apply command to one process, the caller holds a reference on it
+static NTSTATUS RWFApplyPid(PEPROCESS process, GID_COMMAND command) {
+    if (command == GID_SUSPEND) {
+        return PsSuspendProcess(process);
+    }
+    if (command == GID_RESUME) {
+        return PsResumeProcess(process);
+    }
+    HANDLE processHandle;
+    NTSTATUS status = ObOpenObjectByPointer(
+        process,
+        OBJ_KERNEL_HANDLE,
+        NULL,
+        PROCESS_ALL_ACCESS,
+        *PsProcessType,
+        KernelMode,
+        &processHandle);
+    if (!NT_SUCCESS(status)) {
+        return status;
+    }
+    status = ZwTerminateProcess(processHandle, STATUS_FAIL_CHECK);
+    ZwClose(processHandle);
+    return status;
+}
+
+// apply command to every pid of gid, the outcome of the first MAX_GID_REPORT_PIDS ones is
+// written in report
+static VOID RWFApplyGid(ULONGLONG gid, GID_COMMAND command, PGID_REPORT report) {
+    report->status = STATUS_SUCCESS;
+    report->numPids = 0;
+    PULONG Buffer = nullptr;
+    ULONGLONG pidsReturned = 0;
+    // pids may join the gid between GetGidSize and GetGidPids, retry with the new size
+    for (ULONG attempt = 0; attempt < GID_PIDS_ATTEMPTS; attempt++) {
+        BOOLEAN isGidExist = FALSE;
+        ULONGLONG gidSize = driverData->GetGidSize(gid, &isGidExist);
+        if (gidSize == 0 || isGidExist == FALSE) {
+            pidsReturned = 0;  // the gid ended meanwhile
+            break;
+        }
+        if (Buffer != nullptr) {
+            ExFreePoolWithTag(Buffer, 'RW');
+        }
+        Buffer = (PULONG)ExAllocatePool2(POOL_FLAG_NON_PAGED, sizeof(ULONG) * gidSize, 'RW');
+        if (Buffer == nullptr) {
+            DbgPrint("!!! FS : memory allocation error on non paged pool\n");
+            report->status = STATUS_MEMORY_NOT_ALLOCATED;
+            return;
+        }
+        if (driverData->GetGidPids(gid, Buffer, gidSize, &pidsReturned)) {
+            break;
+        }
+    }
+    if (pidsReturned == 0) {
+        DbgPrint("!!! FS : Gid already ended or no such gid %d\n", gid);
+        report->status = STATUS_NO_SUCH_GROUP;
+        if (Buffer != nullptr) {
+            ExFreePoolWithTag(Buffer, 'RW');
+        }
+        return;
+    }
+    report->numPids = (ULONG)pidsReturned;
+    for (ULONGLONG i = 0; i < pidsReturned; i++) {
+        // only the report is capped, the command goes to every pid
+        PPID_OUTCOME outcome = i < MAX_GID_REPORT_PIDS ? &report->pids[i] : nullptr;
+        if (outcome != nullptr) {
+            RtlZeroMemory(outcome, sizeof(PID_OUTCOME));
+            outcome->pid = Buffer[i];
+        }
+        PEPROCESS process;
+        NTSTATUS status = PsLookupProcessByProcessId((HANDLE)Buffer[i], &process);
+        if (NT_SUCCESS(status)) {
+            PUCHAR imageName = PsGetProcessImageFileName(process);
+            if (outcome != nullptr && imageName != NULL) {
+                RtlCopyMemory(
+                    outcome->imageName,
+                    imageName,
+                    strnlen((const char*)imageName, MAX_IMAGE_NAME_LENGTH - 1));
+            }
+            status = RWFApplyPid(process, command);
+            ObDereferenceObject(process);
+        }
+        DbgPrint(
+            "!!! FS : Command %d on pid: %d from gid: %d, status: %x\n",
+            command,
+            Buffer[i],
+            gid,
+            status);
+        if (outcome != nullptr) {
+            outcome->status = status;
+        }
+    }
+    ExFreePoolWithTag(Buffer, 'RW');
+}
+
+// whether the current thread runs as an administrator or SYSTEM, impersonation included
+static BOOLEAN RWFIsCallerPrivileged() {
+    SECURITY_SUBJECT_CONTEXT subjectContext;
+    SeCaptureSubjectContext(&subjectContext);
+    SeLockSubjectContext(&subjectContext);
+    BOOLEAN isAdmin = SeTokenIsAdmin(SeQuerySubjectContextToken(&subjectContext));
+    SeUnlockSubjectContext(&subjectContext);
+    SeReleaseSubjectContext(&subjectContext);
+    return isAdmin;
+}
+
+// messages left to a privileged client: ServerPort has a NULL DACL, so any process may connect
+// while the application is away
+static BOOLEAN RWFIsPrivilegedMessage(ULONG type) {
+    switch (type) {
+        case MESSAGE_SUSPEND_GID:
+        case MESSAGE_RESUME_GID:
+        case MESSAGE_PAUSE_COLLECTION:
+        case MESSAGE_EXCLUDE_PID:
+        case MESSAGE_EXCLUDE_GID:
+        case MESSAGE_EXCLUDE_IMAGE:
+        case MESSAGE_REM_VERDICT_DIRECTORY:
+        case MESSAGE_SET_VERDICT_TIMEOUT:
+        case MESSAGE_SET_VERDICT_FAIL_CLOSED:
+            return TRUE;
+        default:
+            return FALSE;
+    }
+}
+
 NTSTATUS InitCommData() {
     HRESULT status;
     OBJECT_ATTRIBUTES oa;
@@ -45,6 +180,39 @@ NTSTATUS InitCommData() {
 
         FltFreeSecurityDescriptor(sd);
     }
+    if (!NT_SUCCESS(status)) {
+        return status;
+    }
+
+    //
+    //  Create the verdict port, left to ADMIN(s) & SYSTEM: its client decides which irp ops fail.
+    //
+    RtlInitUnicodeString(&uniString, VerdictPortName);
+
+    status = FltBuildDefaultSecurityDescriptor(&sd, FLT_PORT_ALL_ACCESS);
+    if (NT_SUCCESS(status)) {
+        InitializeObjectAttributes(
+            &oa,
+            &uniString,
+            OBJ_CASE_INSENSITIVE | OBJ_KERNEL_HANDLE,
+            NULL,
+            sd);
+
+        status = FltCreateCommunicationPort(
+            commHandle->Filter,
+            &commHandle->VerdictServerPort,
+            &oa,
+            NULL,
+            RWFVerdictConnect,
+            RWFVerdictDisconnect,
+            NULL,
+            1);
+        FltFreeSecurityDescriptor(sd);
+    }
+    if (!NT_SUCCESS(status)) {
+        FltCloseCommunicationPort(commHandle->ServerPort);
+        commHandle->ServerPort = NULL;
+    }
 
     return status;
 }
@@ -65,6 +233,16 @@ void CommClose() {
         FltCloseCommunicationPort(commHandle->ServerPort);
         commHandle->ServerPort = NULL;
     }
+
+    if (commHandle->VerdictClientPort) {
+        FltCloseClientPort(commHandle->Filter, &commHandle->VerdictClientPort);
+        commHandle->VerdictClientPort = NULL;
+    }
+
+    if (commHandle->VerdictServerPort) {
+        FltCloseCommunicationPort(commHandle->VerdictServerPort);
+        commHandle->VerdictServerPort = NULL;
+    }
     commHandle->UserProcess = NULL;
     commHandle->CommClosed = TRUE;
 }
@@ -95,7 +273,11 @@ RWFConnect(
     //
 
     commHandle->ClientPort = ClientPort;
-    DbgPrint("!!! user connected, port=0x%p\n", ClientPort);
+    commHandle->ClientPrivileged = RWFIsCallerPrivileged();
+    DbgPrint(
+        "!!! user connected, port=0x%p, privileged=%d\n",
+        ClientPort,
+        commHandle->ClientPrivileged);
 
     return STATUS_SUCCESS;
 }
@@ -111,6 +293,7 @@ VOID RWFDissconnect(_In_opt_ PVOID ConnectionCookie) {
     //
 
     FltCloseClientPort(commHandle->Filter, &commHandle->ClientPort);
+    commHandle->ClientPrivileged = FALSE;
 
     //
     //  Reset the user-process field.
@@ -119,6 +302,78 @@ VOID RWFDissconnect(_In_opt_ PVOID ConnectionCookie) {
     commHandle->CommClosed = TRUE;
 }
 
+NTSTATUS
+RWFVerdictConnect(
+    _In_ PFLT_PORT ClientPort,
+    _In_opt_ PVOID ServerPortCookie,
+    _In_reads_bytes_opt_(SizeOfContext) PVOID ConnectionContext,
+    _In_ ULONG SizeOfContext,
+    _Outptr_result_maybenull_ PVOID* ConnectionCookie) {
+    UNREFERENCED_PARAMETER(ServerPortCookie);
+    UNREFERENCED_PARAMETER(ConnectionContext);
+    UNREFERENCED_PARAMETER(SizeOfContext);
+    UNREFERENCED_PARAMETER(ConnectionCookie = NULL);
+
+    FLT_ASSERT(commHandle->VerdictClientPort == NULL);
+
+    commHandle->VerdictClientPort = ClientPort;
+    DbgPrint("!!! verdict handler connected, port=0x%p\n", ClientPort);
+
+    return STATUS_SUCCESS;
+}
+
+VOID RWFVerdictDisconnect(_In_opt_ PVOID ConnectionCookie) {
+    UNREFERENCED_PARAMETER(ConnectionCookie);
+
+    DbgPrint(
+        "!!! verdict handler disconnected, port=0x%p\n",
+        commHandle->VerdictClientPort);
+
+    //
+    //  FltSendMessage is synchronized with FltCloseClientPort, pending requests fail and
+    //  the fail policy applies to them.
+    //
+
+    FltCloseClientPort(commHandle->Filter, &commHandle->VerdictClientPort);
+}
+
+BOOLEAN RWFAskVerdict(PVERDICT_REQUEST request) {
+    BOOLEAN allowedByDefault = !driverData->isVerdictFailClosed();
+    if (request == NULL) {
+        return allowedByDefault;
+    }
+    if (commHandle->VerdictClientPort == NULL) {
+        ExFreePoolWithTag(request, 'RW');
+        return allowedByDefault;
+    }
+    VERDICT_REPLY reply;
+    reply.verdict = VERDICT_ALLOW;
+    ULONG replyLength = sizeof(VERDICT_REPLY);
+    LARGE_INTEGER timeout;
+    timeout.QuadPart =
+        -10000LL * driverData->getVerdictTimeout();  // relative, in 100ns
+    NTSTATUS status = FltSendMessage(
+        commHandle->Filter,
+        &commHandle->VerdictClientPort,
+        request,
+        sizeof(VERDICT_REQUEST),
+        &reply,
+        &replyLength,
+        &timeout);
+    DbgPrint(
+        "!!! FS : Verdict on pid %d, gid %llu: status %x, verdict %d\n",
+        request->pid,
+        request->gid,
+        status,
+        reply.verdict);
+    ExFreePoolWithTag(request, 'RW');
+    // STATUS_TIMEOUT is a success code
+    if (status != STATUS_SUCCESS || replyLength < sizeof(VERDICT_REPLY)) {
+        return allowedByDefault;
+    }
+    return reply.verdict == VERDICT_ALLOW;
+}
+
 NTSTATUS
 RWFNewMessage(
     IN PVOID PortCookie,
@@ -136,6 +391,11 @@ RWFNewMessage(
     if (message == NULL)
         return STATUS_INTERNAL_ERROR;  //failed message type
 
+    if (RWFIsPrivilegedMessage(message->type) && !commHandle->ClientPrivileged) {
+        DbgPrint("!!! FS : message %u denied to an unprivileged client\n", message->type);
+        return STATUS_ACCESS_DENIED;
+    }
+
     if (message->type == MESSAGE_ADD_SCAN_DIRECTORY) {
         DbgPrint("Received add directory message\n");
         PDIRECTORY_ENTRY newEntry = new DIRECTORY_ENTRY();
@@ -175,7 +435,7 @@ RWFNewMessage(
         return STATUS_SUCCESS;
     } else if (message->type == MESSAGE_GET_OPS) {
         if (OutputBuffer == NULL
-            || OutputBufferLength != MAX_COMM_BUFFER_SIZE) {
+            || OutputBufferLength < MIN_COMM_BUFFER_SIZE) {
             return STATUS_INVALID_PARAMETER;
         }
         driverData->DriverGetIrps(
@@ -194,91 +454,214 @@ RWFNewMessage(
         }
         return STATUS_INVALID_PARAMETER;
 
-    }
-    // TODO: the kill code to gid
-    else if (message->type == MESSAGE_KILL_GID) {
-        if (OutputBuffer == NULL || OutputBufferLength != sizeof(LONG)) {
+    } else if (message->type == MESSAGE_KILL_GID) {
+        if (OutputBuffer == NULL || OutputBufferLength < sizeof(GID_REPORT)) {
             return STATUS_INVALID_PARAMETER;
         }
-        *ReturnOutputBufferLength = sizeof(LONG);
-        NTSTATUS status = STATUS_SUCCESS;
-        HANDLE processHandle;
-        ULONGLONG GID = message->gid;
-        BOOLEAN isGidExist = FALSE;
-        ULONGLONG gidSize = driverData->GetGidSize(GID, &isGidExist);
-        if (gidSize == 0 || isGidExist == FALSE) {
-            DbgPrint("!!! FS : Gid already ended or no such gid %d\n", GID);
-            *((PLONG)OutputBuffer) =
-                STATUS_NO_SUCH_GROUP;  // fail to kill process
-            return STATUS_SUCCESS;
+        RWFApplyGid(message->gid, GID_KILL, (PGID_REPORT)OutputBuffer);
+        *ReturnOutputBufferLength = sizeof(GID_REPORT);
+        return STATUS_SUCCESS;
+    } else if (
+        message->type == MESSAGE_SUSPEND_GID
+        || message->type == MESSAGE_RESUME_GID) {
+        if (OutputBuffer == NULL || OutputBufferLength < sizeof(GID_REPORT)) {
+            return STATUS_INVALID_PARAMETER;
         }
-        // there is gid with processes
-        PULONG
-        Buffer = (PULONG)
-            ExAllocatePool2(POOL_FLAG_NON_PAGED, sizeof(ULONG) * gidSize, 'RW');
-        if (Buffer == nullptr) {
-            DbgPrint("!!! FS : memory allocation error on non paged pool\n");
-            *((PLONG)OutputBuffer) =
-                STATUS_MEMORY_NOT_ALLOCATED;  // fail to kill process
-            return STATUS_SUCCESS;
+        RWFApplyGid(
+            message->gid,
+            message->type == MESSAGE_SUSPEND_GID ? GID_SUSPEND : GID_RESUME,
+            (PGID_REPORT)OutputBuffer);
+        *ReturnOutputBufferLength = sizeof(GID_REPORT);
+        return STATUS_SUCCESS;
+    } else if (message->type == MESSAGE_GET_VERSION) {
+        if (OutputBuffer == NULL
+            || OutputBufferLength < sizeof(DRIVER_VERSION)) {
+            return STATUS_INVALID_PARAMETER;
+        }
+        PDRIVER_VERSION version = (PDRIVER_VERSION)OutputBuffer;
+        version->protocolVersion = PROTOCOL_VERSION;
+        version->comMessageSize = sizeof(COM_MESSAGE);
+        version->driverMessageSize = sizeof(DRIVER_MESSAGE);
+        version->replyIrpsSize = sizeof(RWD_REPLY_IRPS);
+        *ReturnOutputBufferLength = sizeof(DRIVER_VERSION);
+        return STATUS_SUCCESS;
+    } else if (
+        (message->type >= MESSAGE_SET_ENTROPY
+         && message->type <= MESSAGE_GET_CONFIG)
+        || message->type == MESSAGE_SET_VERDICT_TIMEOUT
+        || message->type == MESSAGE_SET_VERDICT_FAIL_CLOSED) {
+        if (OutputBuffer == NULL
+            || OutputBufferLength < sizeof(FILTER_CONFIG)) {
+            return STATUS_INVALID_PARAMETER;
         }
-        ULONGLONG pidsReturned = 0;
-        isGidExist =
-            driverData->GetGidPids(GID, Buffer, gidSize, &pidsReturned);
-        if (isGidExist) {  // got all irps and correct size
-            for (int i = 0; i < gidSize; i++) {  // kill each process
-                CLIENT_ID clientId;
-                clientId.UniqueProcess = (HANDLE)Buffer[i];
-                clientId.UniqueThread = 0;
-
-                OBJECT_ATTRIBUTES objAttribs;
-                NTSTATUS exitStatus = STATUS_FAIL_CHECK;
-
-                DbgPrint(
-                    "!!! FS : Attempt to terminate pid: %d from gid: %d\n",
-                    Buffer[i],
-                    GID);
-
-                InitializeObjectAttributes(
-                    &objAttribs,
-                    NULL,
-                    OBJ_KERNEL_HANDLE,
-                    NULL,
-                    NULL);
-
-                status = ZwOpenProcess(
-                    &processHandle,
-                    PROCESS_ALL_ACCESS,
-                    &objAttribs,
-                    &clientId);
-
-                if (!NT_SUCCESS(status)) {
-                    *((PLONG)OutputBuffer) = STATUS_FAIL_CHECK;  // fail
-                    DbgPrint(
-                        "!!! FS : Failed to open process %d, reason: %d\n",
-                        Buffer[i],
-                        status);
-                    continue;  // try to kill others
+        switch (message->type) {
+            case MESSAGE_SET_ENTROPY:
+                driverData->setEntropyEnabled(message->value != 0);
+                break;
+            case MESSAGE_SET_ENTROPY_MIN_SIZE:
+                if (message->value > MAXULONG) {
+                    return STATUS_INVALID_PARAMETER;
                 }
-                status = ZwTerminateProcess(processHandle, exitStatus);
-                if (!NT_SUCCESS(status)) {
-                    *((PLONG)OutputBuffer) = STATUS_FAIL_CHECK;  // fail
-                    DbgPrint(
-                        "!!! FS : Failed to kill process %d, reason: %d\n",
-                        Buffer[i],
-                        status);
-                    status = NtClose(processHandle);
-                    continue;  // try to kill others
+                driverData->setEntropyMinSize((ULONG)message->value);
+                break;
+            case MESSAGE_SET_MAX_OPS:
+                if (message->value > MAXULONG
+                    || !driverData->setMaxOps((ULONG)message->value)) {
+                    return STATUS_INVALID_PARAMETER;
                 }
-                NtClose(processHandle);
-
-                DbgPrint(
-                    "!!! FS : Termination of pid: %d from gid: %d succeeded\n",
-                    Buffer[i],
-                    GID);
-            }
+                break;
+            case MESSAGE_PAUSE_COLLECTION:
+                driverData->setCollectionPaused(TRUE);
+                break;
+            case MESSAGE_RESUME_COLLECTION:
+                driverData->setCollectionPaused(FALSE);
+                break;
+            case MESSAGE_SET_VERDICT_TIMEOUT:
+                if (message->value > MAXULONG
+                    || !driverData->setVerdictTimeout((ULONG)message->value)) {
+                    return STATUS_INVALID_PARAMETER;
+                }
+                break;
+            case MESSAGE_SET_VERDICT_FAIL_CLOSED:
+                driverData->setVerdictFailClosed(message->value != 0);
+                break;
+            default:  // MESSAGE_GET_CONFIG
+                break;
         }
-        ExFreePoolWithTag(Buffer, 'RW');
+        DbgPrint(
+            "!!! FS : Tuning message %d, value %llu\n",
+            message->type,
+            message->value);
+        driverData->GetConfig((PFILTER_CONFIG)OutputBuffer);
+        *ReturnOutputBufferLength = sizeof(FILTER_CONFIG);
+        return STATUS_SUCCESS;
+    } else if (
+        message->type >= MESSAGE_EXCLUDE_PID
+        && message->type <= MESSAGE_INCLUDE_GID) {
+        if (OutputBuffer == NULL || OutputBufferLength < sizeof(BOOLEAN)) {
+            return STATUS_INVALID_PARAMETER;
+        }
+        BOOLEAN changed;
+        switch (message->type) {
+            case MESSAGE_EXCLUDE_PID:
+                changed = driverData->ExcludePid(message->pid);
+                break;
+            case MESSAGE_INCLUDE_PID:
+                changed = driverData->IncludePid(message->pid);
+                break;
+            case MESSAGE_EXCLUDE_GID:
+                changed = driverData->ExcludeGid(message->gid);
+                break;
+            default:  // MESSAGE_INCLUDE_GID
+                changed = driverData->IncludeGid(message->gid);
+                break;
+        }
+        DbgPrint(
+            "!!! FS : Exclusion message %d, pid %d, gid %llu, changed %d\n",
+            message->type,
+            message->pid,
+            message->gid,
+            changed);
+        *((PBOOLEAN)OutputBuffer) = changed;
+        *ReturnOutputBufferLength = 1;
+        return STATUS_SUCCESS;
+    } else if (message->type == MESSAGE_GET_STATS) {
+        if (OutputBuffer == NULL || OutputBufferLength < sizeof(DRIVER_STATS)) {
+            return STATUS_INVALID_PARAMETER;
+        }
+        driverData->GetStats((PDRIVER_STATS)OutputBuffer);
+        *ReturnOutputBufferLength = sizeof(DRIVER_STATS);
+        return STATUS_SUCCESS;
+    } else if (message->type == MESSAGE_GET_GID_TREE) {
+        if (OutputBuffer == NULL || OutputBufferLength < sizeof(GID_TREE)) {
+            return STATUS_INVALID_PARAMETER;
+        }
+        // filled under the gid lock, the user buffer may be paged out
+        PGID_TREE tree = (PGID_TREE)
+            ExAllocatePool2(POOL_FLAG_NON_PAGED, sizeof(GID_TREE), 'RW');
+        if (tree == nullptr) {
+            return STATUS_INSUFFICIENT_RESOURCES;
+        }
+        tree->status = driverData->GetGidTree(message->gid, tree)
+            ? STATUS_SUCCESS
+            : STATUS_NO_SUCH_GROUP;
+        RtlCopyMemory(OutputBuffer, tree, sizeof(GID_TREE));
+        ExFreePoolWithTag(tree, 'RW');
+        *ReturnOutputBufferLength = sizeof(GID_TREE);
+        return STATUS_SUCCESS;
+    } else if (message->type == MESSAGE_EXCLUDE_IMAGE) {
+        if (OutputBuffer == NULL || OutputBufferLength < sizeof(BOOLEAN)) {
+            return STATUS_INVALID_PARAMETER;
+        }
+        PIMAGE_ENTRY newEntry = new IMAGE_ENTRY();
+        if (newEntry == NULL) {
+            return STATUS_INSUFFICIENT_RESOURCES;
+        }
+        NTSTATUS hr =
+            CopyWString(newEntry->path, message->path, MAX_FILE_NAME_LENGTH);
+        if (!NT_SUCCESS(hr)) {
+            delete newEntry;
+            return STATUS_INTERNAL_ERROR;
+        }
+        *ReturnOutputBufferLength = 1;
+        if (driverData->ExcludeImage(newEntry)) {
+            *((PBOOLEAN)OutputBuffer) = TRUE;
+            DbgPrint("Excluded image %ls\n", newEntry->path);
+        } else {
+            delete newEntry;
+            *((PBOOLEAN)OutputBuffer) = FALSE;
+        }
+        return STATUS_SUCCESS;
+    } else if (message->type == MESSAGE_INCLUDE_IMAGE) {
+        if (OutputBuffer == NULL || OutputBufferLength < sizeof(BOOLEAN)) {
+            return STATUS_INVALID_PARAMETER;
+        }
+        PIMAGE_ENTRY ptr = driverData->IncludeImage(message->path);
+        *ReturnOutputBufferLength = 1;
+        if (ptr == NULL) {
+            *((PBOOLEAN)OutputBuffer) = FALSE;
+            return STATUS_SUCCESS;
+        }
+        delete ptr;
+        *((PBOOLEAN)OutputBuffer) = TRUE;
+        DbgPrint("Included image %ls\n", message->path);
+        return STATUS_SUCCESS;
+    } else if (message->type == MESSAGE_ADD_VERDICT_DIRECTORY) {
+        if (OutputBuffer == NULL || OutputBufferLength < sizeof(BOOLEAN)) {
+            return STATUS_INVALID_PARAMETER;
+        }
+        PDIRECTORY_ENTRY newEntry = new DIRECTORY_ENTRY();
+        if (newEntry == NULL) {
+            return STATUS_INSUFFICIENT_RESOURCES;
+        }
+        NTSTATUS hr =
+            CopyWString(newEntry->path, message->path, MAX_FILE_NAME_LENGTH);
+        if (!NT_SUCCESS(hr)) {
+            delete newEntry;
+            return STATUS_INTERNAL_ERROR;
+        }
+        *ReturnOutputBufferLength = 1;
+        if (driverData->AddVerdictDirectory(newEntry)) {
+            *((PBOOLEAN)OutputBuffer) = TRUE;
+            DbgPrint("Added verdict directory %ls\n", newEntry->path);
+        } else {
+            delete newEntry;
+            *((PBOOLEAN)OutputBuffer) = FALSE;
+        }
+        return STATUS_SUCCESS;
+    } else if (message->type == MESSAGE_REM_VERDICT_DIRECTORY) {
+        if (OutputBuffer == NULL || OutputBufferLength < sizeof(BOOLEAN)) {
+            return STATUS_INVALID_PARAMETER;
+        }
+        PDIRECTORY_ENTRY ptr = driverData->RemVerdictDirectory(message->path);
+        *ReturnOutputBufferLength = 1;
+        if (ptr == NULL) {
+            *((PBOOLEAN)OutputBuffer) = FALSE;
+            return STATUS_SUCCESS;
+        }
+        delete ptr;
+        *((PBOOLEAN)OutputBuffer) = TRUE;
+        DbgPrint("Removed verdict directory %ls\n", message->path);
         return STATUS_SUCCESS;
     }
 
@@ -13,6 +13,12 @@ struct CommHandler {
     //  port for a connection to user-mode
     PFLT_PORT ClientPort;
 
+    //  Server-side port on which verdicts are asked
+    PFLT_PORT VerdictServerPort;
+
+    //  port for the connection of the user-mode verdict handler, NULL if none
+    PFLT_PORT VerdictClientPort;
+
     //  The filter handle that results from a call to
     PFLT_FILTER Filter;
 
@@ -23,12 +29,18 @@ struct CommHandler {
 
     ULONG UserProcess;
 
+    //  The client of ServerPort runs as an administrator or SYSTEM
+    BOOLEAN ClientPrivileged;
+
     CommHandler(PFLT_FILTER Filter) :
         ServerPort(NULL),
         ClientPort(NULL),
+        VerdictServerPort(NULL),
+        VerdictClientPort(NULL),
         Filter(Filter),
         CommClosed(TRUE),
-        UserProcess(0) {}
+        UserProcess(0),
+        ClientPrivileged(FALSE) {}
 };
 
 extern CommHandler* commHandle;
@@ -62,4 +74,21 @@ NTSTATUS RWFNewMessage(
 
 // AMFDisconnect: Handles user mode application which disconnects from the driver
 
-VOID RWFDissconnect(_In_opt_ PVOID ConnectionCookie);
\ No newline at end of file
+VOID RWFDissconnect(_In_opt_ PVOID ConnectionCookie);
+
+// RWFVerdictConnect: Handles the user mode verdict handler which connects to the verdict port
+
+NTSTATUS
+RWFVerdictConnect(
+    _In_ PFLT_PORT ClientPort,
+    _In_opt_ PVOID ServerPortCookie,
+    _In_reads_bytes_opt_(SizeOfContext) PVOID ConnectionContext,
+    _In_ ULONG SizeOfContext,
+    _Outptr_result_maybenull_ PVOID* ConnectionCookie);
+
+VOID RWFVerdictDisconnect(_In_opt_ PVOID ConnectionCookie);
+
+// Sends the request to the verdict handler and frees it, request may be NULL on allocation failure.
+// Returns whether the irp op is allowed: the fail policy applies without handler, reply or request.
+// Must be called at IRQL <= APC_LEVEL.
+BOOLEAN RWFAskVerdict(PVERDICT_REQUEST request);
\ No newline at end of file
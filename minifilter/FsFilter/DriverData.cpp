@@ -5,13 +5,31 @@ DriverData::DriverData(PDRIVER_OBJECT DriverObject) :
     Filter(nullptr),
     DriverObject(DriverObject),
     pid(0),
+    entropyEnabled(TRUE),
+    collectionPaused(FALSE),
+    entropyMinSize(0),
     irpOpsSize(0),
+    maxOpsSave(MAX_OPS_SAVE),
+    opsQueued(0),
+    opsDropped(0),
+    nextSequence(0),
+    entropyCalcs(0),
+    attachedInstances(0),
     directoryRootsSize(0),
+    verdictFailClosed(FALSE),
+    verdictTimeoutMs(DEFAULT_VERDICT_TIMEOUT_MS),
+    verdictDirectoriesSize(0),
     GidToPids(),
-    PidToGids() {
+    PidToGids(),
+    ExcludedPids(),
+    ExcludedGids(),
+    ImageExcludedPids(),
+    excludedImagesSize(0) {
     systemRootPath[0] = L'\0';
+    RtlZeroMemory(opsByIrp, sizeof(opsByIrp));
     InitializeListHead(&irpOps);
     InitializeListHead(&rootDirectories);
+    InitializeListHead(&verdictDirectories);
     KeInitializeSpinLock(&irpOpsLock);  //init spin lock
     KeInitializeSpinLock(&directoriesSpinLock);  //init spin lock
 
@@ -19,6 +37,7 @@ DriverData::DriverData(PDRIVER_OBJECT DriverObject) :
     KeInitializeSpinLock(&GIDSystemLock);  //init spin lock
     gidsSize = 0;
     InitializeListHead(&GidsList);
+    InitializeListHead(&excludedImages);
 }
 
 DriverData::~DriverData() {
@@ -45,7 +64,7 @@ BOOLEAN DriverData::RemoveProcessRecordAux(ULONG ProcessId, ULONGLONG gid) {
     while (iterator != header) {
         PPID_ENTRY pStrct =
             (PPID_ENTRY)CONTAINING_RECORD(iterator, PID_ENTRY, entry);
-        if (pStrct->Pid == ProcessId) {
+        if (pStrct->Alive && pStrct->Pid == ProcessId) {
             RemoveEntryList(iterator);
             delete pStrct->Path;
             delete pStrct;
@@ -56,7 +75,8 @@ BOOLEAN DriverData::RemoveProcessRecordAux(ULONG ProcessId, ULONGLONG gid) {
         iterator = iterator->Flink;
     }
     if (ret) {
-        if (IsListEmpty(header)) {
+        if (gidRecord->pidsSize == 0) {
+            RemoveGidRecordAux(gidRecord);  // release the exited pids
             GidToPids.deleteNode(gid);  // remove the gidRecord from GidToPids
             RemoveEntryList(
                 &(gidRecord->GidListEntry));  // unlink from list of gids
@@ -64,10 +84,51 @@ BOOLEAN DriverData::RemoveProcessRecordAux(ULONG ProcessId, ULONGLONG gid) {
             delete gidRecord;
         }
         PidToGids.deleteNode(ProcessId);
+        ImageExcludedPids.deleteNode(ProcessId);
     }
     return ret;
 }
 
+// call assumes protected code high irql
+BOOLEAN DriverData::ExitProcessRecordAux(ULONG ProcessId, ULONGLONG gid) {
+    PGID_ENTRY gidRecord = (PGID_ENTRY)GidToPids.get(gid);
+    if (gidRecord == nullptr) {  // shouldn't happen
+        return FALSE;
+    }
+    if (gidRecord->pidsSize == 1) {  // last one, the gid ends
+        return RemoveProcessRecordAux(ProcessId, gid);
+    }
+    PLIST_ENTRY header = &(gidRecord->HeadListPids);
+    PPID_ENTRY exited = nullptr;
+    PPID_ENTRY oldestExited = nullptr;
+    for (PLIST_ENTRY iterator = header->Flink; iterator != header;
+         iterator = iterator->Flink) {
+        PPID_ENTRY pStrct =
+            (PPID_ENTRY)CONTAINING_RECORD(iterator, PID_ENTRY, entry);
+        if (pStrct->Alive && pStrct->Pid == ProcessId) {
+            exited = pStrct;
+        } else if (!pStrct->Alive) {
+            oldestExited = pStrct;  // list is newest first
+        }
+    }
+    if (exited == nullptr) {
+        return FALSE;
+    }
+    exited->Alive = FALSE;
+    KeQuerySystemTimePrecise(&exited->ExitTime);
+    gidRecord->pidsSize--;
+    gidRecord->exitedPidsSize++;
+    PidToGids.deleteNode(ProcessId);  // the pid can be reused from now on
+    ImageExcludedPids.deleteNode(ProcessId);
+    if (gidRecord->exitedPidsSize > MAX_GID_TREE_PIDS && oldestExited != nullptr) {
+        RemoveEntryList(&oldestExited->entry);
+        delete oldestExited->Path;
+        delete oldestExited;
+        gidRecord->exitedPidsSize--;
+    }
+    return TRUE;
+}
+
 // call assumes protected code high irql
 BOOLEAN DriverData::RemoveGidRecordAux(PGID_ENTRY gidRecord) {
     BOOLEAN ret = FALSE;
@@ -80,7 +141,10 @@ BOOLEAN DriverData::RemoveGidRecordAux(PGID_ENTRY gidRecord) {
             (PPID_ENTRY)CONTAINING_RECORD(iterator, PID_ENTRY, entry);
         PLIST_ENTRY next = iterator->Flink;
         RemoveEntryList(iterator);
-        PidToGids.deleteNode(pStrct->Pid);
+        if (pStrct->Alive) {  // the pid of an exited entry may have been reused
+            PidToGids.deleteNode(pStrct->Pid);
+            ImageExcludedPids.deleteNode(pStrct->Pid);
+        }
         pidsSize--;
         delete pStrct->Path;  // release PUNICODE_STRING
         delete pStrct;  // release PID_ENTRY
@@ -99,7 +163,7 @@ BOOLEAN DriverData::RemoveProcess(ULONG ProcessId) {
     KeAcquireSpinLock(&GIDSystemLock, &irql);
     ULONGLONG gid = (ULONGLONG)PidToGids.get(ProcessId);
     if (gid) {  // there is Gid
-        ret = RemoveProcessRecordAux(ProcessId, gid);
+        ret = ExitProcessRecordAux(ProcessId, gid);
     }
 
     KeReleaseSpinLock(&GIDSystemLock, irql);
@@ -116,7 +180,12 @@ BOOLEAN DriverData::RecordNewProcess(
     ULONGLONG gid = (ULONGLONG)PidToGids.get(ParentPid);
     PPID_ENTRY pStrct = new PID_ENTRY;
     pStrct->Pid = ProcessId;
+    pStrct->ParentPid = ParentPid;
+    KeQuerySystemTimePrecise(&pStrct->CreateTime);
     pStrct->Path = ProcessName;
+    if (IsImageExcludedAux(ProcessName)) {
+        ImageExcludedPids.insertNode(ProcessId, (HANDLE)TRUE);
+    }
     if (gid) {  // there is Gid
         ULONGLONG retInsert;
         if ((retInsert =
@@ -198,7 +267,7 @@ BOOLEAN DriverData::GetGidPids(
             PPID_ENTRY pStrct =
                 (PPID_ENTRY)CONTAINING_RECORD(iterator, PID_ENTRY, entry);
             ASSERT(pStrct != nullptr);
-            if (pStrct != nullptr) {
+            if (pStrct != nullptr && pStrct->Alive) {
                 buffer[pidsIter++] = pStrct->Pid;
                 *returnedLength += 1;
             }
@@ -215,6 +284,42 @@ BOOLEAN DriverData::GetGidPids(
     return FALSE;
 }
 
+BOOLEAN DriverData::GetGidTree(ULONGLONG gid, PGID_TREE tree) {
+    ASSERT(tree != nullptr);
+    tree->numPids = 0;
+    KIRQL irql = KeGetCurrentIrql();
+    KeAcquireSpinLock(&GIDSystemLock, &irql);
+    PGID_ENTRY GidRecord = (PGID_ENTRY)GidToPids.get(gid);
+    if (GidRecord != nullptr) {  // there is such Gid
+        tree->numPids = (ULONG)(GidRecord->pidsSize + GidRecord->exitedPidsSize);
+        PLIST_ENTRY PidsListHeader = &(GidRecord->HeadListPids);
+        ULONG pidsIter = 0;
+        // oldest first, so that parents come before their children
+        for (PLIST_ENTRY iterator = PidsListHeader->Blink;
+             iterator != PidsListHeader && pidsIter < MAX_GID_TREE_PIDS;
+             iterator = iterator->Blink) {
+            PPID_ENTRY pStrct =
+                (PPID_ENTRY)CONTAINING_RECORD(iterator, PID_ENTRY, entry);
+            PPROCESS_NODE node = &tree->pids[pidsIter++];
+            RtlZeroMemory(node, sizeof(PROCESS_NODE));
+            node->pid = pStrct->Pid;
+            node->parentPid = pStrct->ParentPid;
+            node->createTime = pStrct->CreateTime.QuadPart;
+            node->exitTime = pStrct->ExitTime.QuadPart;
+            node->alive = pStrct->Alive;
+            if (pStrct->Path != nullptr && pStrct->Path->Buffer != nullptr) {
+                RtlCopyMemory(
+                    node->imagePath,
+                    pStrct->Path->Buffer,
+                    min(pStrct->Path->Length,
+                        (MAX_FILE_NAME_LENGTH - 1) * sizeof(WCHAR)));
+            }
+        }
+    }
+    KeReleaseSpinLock(&GIDSystemLock, irql);
+    return GidRecord != nullptr;
+}
+
 // if found return true on found else return false
 ULONGLONG DriverData::GetProcessGid(ULONG ProcessId, PBOOLEAN found) {
     ASSERT(found != nullptr);
@@ -230,6 +335,50 @@ ULONGLONG DriverData::GetProcessGid(ULONG ProcessId, PBOOLEAN found) {
     return ret;
 }
 
+BOOLEAN DriverData::GetProcessMessage(
+    ULONG ProcessId,
+    UCHAR IrpOp,
+    PIRP_ENTRY entry) {
+    ASSERT(entry != nullptr);
+    BOOLEAN ret = FALSE;
+    KIRQL irql = KeGetCurrentIrql();
+    KeAcquireSpinLock(&GIDSystemLock, &irql);
+    ULONGLONG gid = (ULONGLONG)PidToGids.get(ProcessId);
+    PGID_ENTRY gidRecord = gid ? (PGID_ENTRY)GidToPids.get(gid) : nullptr;
+    if (gidRecord != nullptr) {
+        PLIST_ENTRY header = &(gidRecord->HeadListPids);
+        for (PLIST_ENTRY iterator = header->Flink; iterator != header;
+             iterator = iterator->Flink) {
+            PPID_ENTRY pStrct =
+                (PPID_ENTRY)CONTAINING_RECORD(iterator, PID_ENTRY, entry);
+            if (pStrct->Alive && pStrct->Pid == ProcessId) {
+                PROCESS_MESSAGE_INFO info = {};
+                info.parentPid = pStrct->ParentPid;
+                if (IrpOp == IRP_PROCESS_EXIT) {  // called before RemoveProcess
+                    LARGE_INTEGER exitTime;
+                    KeQuerySystemTimePrecise(&exitTime);
+                    info.time = exitTime.QuadPart;
+                    info.alivePids = (ULONG)gidRecord->pidsSize - 1;
+                } else {
+                    info.time = pStrct->CreateTime.QuadPart;
+                    info.alivePids = (ULONG)gidRecord->pidsSize;
+                }
+                RtlCopyMemory(&entry->data.FileID, &info, sizeof(info));
+                if (pStrct->Path != nullptr) {
+                    RtlCopyUnicodeString(&entry->filePath, pStrct->Path);  // truncated to MAX_FILE_NAME_SIZE
+                }
+                entry->data.PID = ProcessId;
+                entry->data.IRP_OP = IrpOp;
+                entry->data.Gid = gid;
+                ret = TRUE;
+                break;
+            }
+        }
+    }
+    KeReleaseSpinLock(&GIDSystemLock, irql);
+    return ret;
+}
+
 //clear all data related to Gid system
 VOID DriverData::ClearGidsPids() {
     KIRQL irql = KeGetCurrentIrql();
@@ -261,6 +410,166 @@ ULONGLONG DriverData::GidsSize() {
     return ret;
 }
 
+//#######################################################################################
+//# Exclusions handling
+//#######################################################################################
+
+/****************** Private ******************/
+
+// call assumes protected code high irql
+BOOLEAN DriverData::IsImageExcludedAux(PUNICODE_STRING imagePath) {
+    PLIST_ENTRY pEntry = excludedImages.Flink;
+    while (pEntry != &excludedImages) {
+        PIMAGE_ENTRY pStrct =
+            (PIMAGE_ENTRY)CONTAINING_RECORD(pEntry, IMAGE_ENTRY, entry);
+        if (equalsIgnoreCase(imagePath, pStrct->path)) {
+            return TRUE;
+        }
+        pEntry = pEntry->Flink;
+    }
+    return FALSE;
+}
+
+// call assumes protected code high irql
+VOID DriverData::RefreshImageExclusionsAux() {
+    PLIST_ENTRY headGids = &GidsList;
+    PLIST_ENTRY iteratorGids = headGids->Flink;
+    while (iteratorGids != headGids) {
+        PGID_ENTRY gidRecord = (PGID_ENTRY)
+            CONTAINING_RECORD(iteratorGids, GID_ENTRY, GidListEntry);
+        PLIST_ENTRY headPids = &(gidRecord->HeadListPids);
+        PLIST_ENTRY iteratorPids = headPids->Flink;
+        while (iteratorPids != headPids) {
+            PPID_ENTRY pStrct =
+                (PPID_ENTRY)CONTAINING_RECORD(iteratorPids, PID_ENTRY, entry);
+            if (pStrct->Alive) {  // the pid of an exited entry may have been reused
+                if (IsImageExcludedAux(pStrct->Path)) {
+                    ImageExcludedPids.insertNode(pStrct->Pid, (HANDLE)TRUE);
+                } else {
+                    ImageExcludedPids.deleteNode(pStrct->Pid);
+                }
+            }
+            iteratorPids = iteratorPids->Flink;
+        }
+        iteratorGids = iteratorGids->Flink;
+    }
+}
+
+/****************** Public ******************/
+
+BOOLEAN DriverData::ExcludePid(ULONG ProcessId) {
+    BOOLEAN ret = FALSE;
+    KIRQL irql = KeGetCurrentIrql();
+    KeAcquireSpinLock(&GIDSystemLock, &irql);
+    if (ExcludedPids.get(ProcessId) == NULL) {
+        ExcludedPids.insertNode(ProcessId, (HANDLE)TRUE);
+        ret = TRUE;
+    }
+    KeReleaseSpinLock(&GIDSystemLock, irql);
+    return ret;
+}
+
+BOOLEAN DriverData::IncludePid(ULONG ProcessId) {
+    KIRQL irql = KeGetCurrentIrql();
+    KeAcquireSpinLock(&GIDSystemLock, &irql);
+    BOOLEAN ret = ExcludedPids.deleteNode(ProcessId) != NULL;
+    KeReleaseSpinLock(&GIDSystemLock, irql);
+    return ret;
+}
+
+BOOLEAN DriverData::ExcludeGid(ULONGLONG gid) {
+    BOOLEAN ret = FALSE;
+    KIRQL irql = KeGetCurrentIrql();
+    KeAcquireSpinLock(&GIDSystemLock, &irql);
+    if (ExcludedGids.get(gid) == NULL) {
+        ExcludedGids.insertNode(gid, (HANDLE)TRUE);
+        ret = TRUE;
+    }
+    KeReleaseSpinLock(&GIDSystemLock, irql);
+    return ret;
+}
+
+BOOLEAN DriverData::IncludeGid(ULONGLONG gid) {
+    KIRQL irql = KeGetCurrentIrql();
+    KeAcquireSpinLock(&GIDSystemLock, &irql);
+    BOOLEAN ret = ExcludedGids.deleteNode(gid) != NULL;
+    KeReleaseSpinLock(&GIDSystemLock, irql);
+    return ret;
+}
+
+BOOLEAN DriverData::ExcludeImage(PIMAGE_ENTRY newEntry) {
+    BOOLEAN ret = FALSE;
+    UNICODE_STRING newPath;
+    RtlInitUnicodeString(&newPath, newEntry->path);
+    KIRQL irql = KeGetCurrentIrql();
+    KeAcquireSpinLock(&GIDSystemLock, &irql);
+    if (!IsImageExcludedAux(&newPath)) {
+        InsertHeadList(&excludedImages, &newEntry->entry);
+        excludedImagesSize++;
+        RefreshImageExclusionsAux();  // running processes of the image
+        ret = TRUE;
+    }
+    KeReleaseSpinLock(&GIDSystemLock, irql);
+    return ret;
+}
+
+PIMAGE_ENTRY DriverData::IncludeImage(LPCWSTR imagePath) {
+    PIMAGE_ENTRY ret = NULL;
+    UNICODE_STRING path;
+    RtlInitUnicodeString(&path, imagePath);
+    KIRQL irql = KeGetCurrentIrql();
+    KeAcquireSpinLock(&GIDSystemLock, &irql);
+    PLIST_ENTRY pEntry = excludedImages.Flink;
+    while (pEntry != &excludedImages) {
+        PIMAGE_ENTRY pStrct =
+            (PIMAGE_ENTRY)CONTAINING_RECORD(pEntry, IMAGE_ENTRY, entry);
+        if (equalsIgnoreCase(&path, pStrct->path)) {
+            RemoveEntryList(pEntry);
+            excludedImagesSize--;
+            ret = pStrct;
+            break;
+        }
+        pEntry = pEntry->Flink;
+    }
+    if (ret != NULL) {
+        RefreshImageExclusionsAux();
+    }
+    KeReleaseSpinLock(&GIDSystemLock, irql);
+    return ret;
+}
+
+BOOLEAN DriverData::IsExcluded(ULONG ProcessId) {
+    BOOLEAN ret = FALSE;
+    KIRQL irql = KeGetCurrentIrql();
+    KeAcquireSpinLock(&GIDSystemLock, &irql);
+    if (ExcludedPids.get(ProcessId) != NULL
+        || ImageExcludedPids.get(ProcessId) != NULL) {
+        ret = TRUE;
+    } else {
+        ULONGLONG gid = (ULONGLONG)PidToGids.get(ProcessId);
+        ret = gid != 0 && ExcludedGids.get(gid) != NULL;
+    }
+    KeReleaseSpinLock(&GIDSystemLock, irql);
+    return ret;
+}
+
+VOID DriverData::ClearExclusions() {
+    KIRQL irql = KeGetCurrentIrql();
+    KeAcquireSpinLock(&GIDSystemLock, &irql);
+    ExcludedPids.clear();
+    ExcludedGids.clear();
+    ImageExcludedPids.clear();
+    PLIST_ENTRY pEntry = excludedImages.Flink;
+    while (pEntry != &excludedImages) {
+        PLIST_ENTRY next = pEntry->Flink;
+        delete (PIMAGE_ENTRY)CONTAINING_RECORD(pEntry, IMAGE_ENTRY, entry);
+        pEntry = next;
+    }
+    excludedImagesSize = 0;
+    InitializeListHead(&excludedImages);
+    KeReleaseSpinLock(&GIDSystemLock, irql);
+}
+
 //#######################################################################################
 //# Irp handling
 //#######################################################################################
@@ -294,10 +603,20 @@ ULONG DriverData::IrpSize() {
 BOOLEAN DriverData::AddIrpMessage(PIRP_ENTRY newEntry) {
     KIRQL irql = KeGetCurrentIrql();
     KeAcquireSpinLock(&irpOpsLock, &irql);
-    if (irpOpsSize < MAX_OPS_SAVE) {
+    if (newEntry->data.isEntropyCalc) {
+        entropyCalcs++;
+    }
+    // dropped ops take a sequence number too, user mode sees the gap
+    newEntry->data.Sequence = nextSequence++;
+    if (irpOpsSize < maxOpsSave) {
         irpOpsSize++;
         InsertTailList(&irpOps, &newEntry->entry);
+        opsQueued++;
+        if (newEntry->data.IRP_OP < IRP_MAJOR_OP_COUNT) {
+            opsByIrp[newEntry->data.IRP_OP]++;
+        }
     } else {
+        opsDropped++;
         KeReleaseSpinLock(&irpOpsLock, irql);
         return FALSE;
     }
@@ -305,6 +624,44 @@ BOOLEAN DriverData::AddIrpMessage(PIRP_ENTRY newEntry) {
     return TRUE;
 }
 
+BOOLEAN DriverData::setMaxOps(ULONG maxOps) {
+    if (maxOps == 0 || maxOps > MAX_OPS_SAVE_LIMIT) {
+        return FALSE;
+    }
+    KIRQL irql = KeGetCurrentIrql();
+    KeAcquireSpinLock(&irpOpsLock, &irql);
+    maxOpsSave = maxOps;
+    KeReleaseSpinLock(&irpOpsLock, irql);
+    return TRUE;
+}
+
+VOID DriverData::GetConfig(PFILTER_CONFIG config) {
+    RtlZeroMemory(config, sizeof(FILTER_CONFIG));
+    config->entropyEnabled = entropyEnabled;
+    config->collectionPaused = collectionPaused;
+    config->entropyMinSize = entropyMinSize;
+    config->verdictFailClosed = verdictFailClosed;
+    config->verdictTimeoutMs = verdictTimeoutMs;
+    KIRQL irql = KeGetCurrentIrql();
+    KeAcquireSpinLock(&irpOpsLock, &irql);
+    config->maxOps = maxOpsSave;
+    KeReleaseSpinLock(&irpOpsLock, irql);
+}
+
+VOID DriverData::GetStats(PDRIVER_STATS stats) {
+    RtlZeroMemory(stats, sizeof(DRIVER_STATS));
+    KIRQL irql = KeGetCurrentIrql();
+    KeAcquireSpinLock(&irpOpsLock, &irql);
+    stats->opsQueued = opsQueued;
+    stats->opsDropped = opsDropped;
+    RtlCopyMemory(stats->opsByIrp, opsByIrp, sizeof(opsByIrp));
+    stats->entropyCalcs = entropyCalcs;
+    stats->queueDepth = irpOpsSize;
+    KeReleaseSpinLock(&irpOpsLock, irql);
+    stats->attachedInstances = (ULONG)attachedInstances;
+    stats->gids = GidsSize();
+}
+
 BOOLEAN DriverData::RemIrpMessage(PIRP_ENTRY newEntry) {
     KIRQL irql = KeGetCurrentIrql();
     KeAcquireSpinLock(&irpOpsLock, &irql);
@@ -465,14 +822,12 @@ LIST_ENTRY DriverData::GetAllEntries() {
 //# Directory handling
 //#######################################################################################
 
-BOOLEAN DriverData::AddDirectoryEntry(PDIRECTORY_ENTRY newEntry) {
-    BOOLEAN ret = FALSE;
-    BOOLEAN foundMatch = FALSE;
-    KIRQL irql = KeGetCurrentIrql();
-    KeAcquireSpinLock(&directoriesSpinLock, &irql);
-
-    PLIST_ENTRY pEntry = rootDirectories.Flink;
-    while (pEntry != &rootDirectories) {
+BOOLEAN DriverData::AddDirectoryEntryAux(
+    PLIST_ENTRY list,
+    PULONG listSize,
+    PDIRECTORY_ENTRY newEntry) {
+    PLIST_ENTRY pEntry = list->Flink;
+    while (pEntry != list) {
         PDIRECTORY_ENTRY pStrct;
         //
         // Do some processing.
@@ -480,35 +835,26 @@ BOOLEAN DriverData::AddDirectoryEntry(PDIRECTORY_ENTRY newEntry) {
         pStrct =
             (PDIRECTORY_ENTRY)CONTAINING_RECORD(pEntry, DIRECTORY_ENTRY, entry);
 
-        if (!wcsncmp(
-                newEntry->path,
-                pStrct->path,
-                wcsnlen_s(newEntry->path, MAX_FILE_NAME_LENGTH))) {
-            foundMatch = TRUE;
-            break;
+        if (!wcsncmp(newEntry->path, pStrct->path, MAX_FILE_NAME_LENGTH)) {
+            return FALSE;
         }
         //
         //Move to next Entry in list.
         //
         pEntry = pEntry->Flink;
     }
-    if (foundMatch == FALSE) {
-        InsertHeadList(&rootDirectories, &newEntry->entry);
-        directoryRootsSize++;
-        ret = TRUE;
-    }
-    KeReleaseSpinLock(&directoriesSpinLock, irql);
-    return ret;
+    InsertHeadList(list, &newEntry->entry);
+    (*listSize)++;
+    return TRUE;
 }
 
-PDIRECTORY_ENTRY DriverData::RemDirectoryEntry(LPCWSTR directory) {
-    PDIRECTORY_ENTRY ret = NULL;
-    KIRQL irql = KeGetCurrentIrql();
-    KeAcquireSpinLock(&directoriesSpinLock, &irql);
-
-    PLIST_ENTRY pEntry = rootDirectories.Flink;
+PDIRECTORY_ENTRY DriverData::RemDirectoryEntryAux(
+    PLIST_ENTRY list,
+    PULONG listSize,
+    LPCWSTR directory) {
+    PLIST_ENTRY pEntry = list->Flink;
 
-    while (pEntry != &rootDirectories) {
+    while (pEntry != list) {
         PDIRECTORY_ENTRY pStrct;
         //
         // Do some processing.
@@ -516,14 +862,10 @@ PDIRECTORY_ENTRY DriverData::RemDirectoryEntry(LPCWSTR directory) {
         pStrct =
             (PDIRECTORY_ENTRY)CONTAINING_RECORD(pEntry, DIRECTORY_ENTRY, entry);
 
-        if (!wcsncmp(
-                directory,
-                pStrct->path,
-                wcsnlen_s(directory, MAX_FILE_NAME_LENGTH))) {
+        if (!wcsncmp(directory, pStrct->path, MAX_FILE_NAME_LENGTH)) {
             if (RemoveEntryList(pEntry)) {
-                ret = pStrct;
-                directoryRootsSize--;
-                break;
+                (*listSize)--;
+                return pStrct;
             }
         }
         //
@@ -531,6 +873,62 @@ PDIRECTORY_ENTRY DriverData::RemDirectoryEntry(LPCWSTR directory) {
         //
         pEntry = pEntry->Flink;
     }
+    return NULL;
+}
+
+BOOLEAN DriverData::IsContainingDirectoryAux(
+    PLIST_ENTRY list,
+    CONST PUNICODE_STRING path) {
+    PLIST_ENTRY pEntry = list->Flink;
+    while (pEntry != list) {
+        PDIRECTORY_ENTRY pStrct =
+            (PDIRECTORY_ENTRY)CONTAINING_RECORD(pEntry, DIRECTORY_ENTRY, entry);
+        for (ULONG i = 0; i <= path->Length / sizeof(WCHAR); i++) {
+            if (pStrct->path[i] == L'\0') {
+                return TRUE;
+            } else if (
+                i < path->Length / sizeof(WCHAR)
+                && pStrct->path[i] == path->Buffer[i]) {
+                continue;
+            } else {
+                break;  // for loop
+            }
+        }
+
+        // Move to next Entry in list.
+        pEntry = pEntry->Flink;
+    }
+    return FALSE;
+}
+
+VOID DriverData::ClearDirectoriesAux(PLIST_ENTRY list, PULONG listSize) {
+    PLIST_ENTRY pEntryDirs = list->Flink;
+    while (pEntryDirs != list) {
+        LIST_ENTRY temp = *pEntryDirs;
+        PDIRECTORY_ENTRY pStrct = (PDIRECTORY_ENTRY)
+            CONTAINING_RECORD(pEntryDirs, DIRECTORY_ENTRY, entry);
+        delete pStrct;
+        //next
+        pEntryDirs = temp.Flink;
+    }
+    *listSize = 0;
+    InitializeListHead(list);
+}
+
+BOOLEAN DriverData::AddDirectoryEntry(PDIRECTORY_ENTRY newEntry) {
+    KIRQL irql = KeGetCurrentIrql();
+    KeAcquireSpinLock(&directoriesSpinLock, &irql);
+    BOOLEAN ret =
+        AddDirectoryEntryAux(&rootDirectories, &directoryRootsSize, newEntry);
+    KeReleaseSpinLock(&directoriesSpinLock, irql);
+    return ret;
+}
+
+PDIRECTORY_ENTRY DriverData::RemDirectoryEntry(LPCWSTR directory) {
+    KIRQL irql = KeGetCurrentIrql();
+    KeAcquireSpinLock(&directoriesSpinLock, &irql);
+    PDIRECTORY_ENTRY ret =
+        RemDirectoryEntryAux(&rootDirectories, &directoryRootsSize, directory);
     KeReleaseSpinLock(&directoriesSpinLock, irql);
     return ret;
 }
@@ -547,27 +945,42 @@ BOOLEAN DriverData::IsContainingDirectory(CONST PUNICODE_STRING path) {
     //DbgPrint("Looking for path: %ls in lookup dirs", path);
     KeAcquireSpinLock(&directoriesSpinLock, &irql);
     if (directoryRootsSize != 0) {
-        PLIST_ENTRY pEntry = rootDirectories.Flink;
-        while (pEntry != &rootDirectories) {
-            PDIRECTORY_ENTRY pStrct = (PDIRECTORY_ENTRY)
-                CONTAINING_RECORD(pEntry, DIRECTORY_ENTRY, entry);
-            for (ULONG i = 0; i < path->Length; i++) {
-                if (pStrct->path[i] == L'\0') {
-                    ret = TRUE;
-                    break;
-                } else if (pStrct->path[i] == path->Buffer[i]) {
-                    continue;
-                } else {
-                    break;  // for loop
-                }
-            }
+        ret = IsContainingDirectoryAux(&rootDirectories, path);
+    }
+    KeReleaseSpinLock(&directoriesSpinLock, irql);
+    return ret;
+}
 
-            // ret = (wcsstr(path, pStrct->path) != NULL);
-            if (ret)
-                break;
-            // Move to next Entry in list.
-            pEntry = pEntry->Flink;
-        }
+BOOLEAN DriverData::AddVerdictDirectory(PDIRECTORY_ENTRY newEntry) {
+    KIRQL irql = KeGetCurrentIrql();
+    KeAcquireSpinLock(&directoriesSpinLock, &irql);
+    BOOLEAN ret = AddDirectoryEntryAux(
+        &verdictDirectories,
+        &verdictDirectoriesSize,
+        newEntry);
+    KeReleaseSpinLock(&directoriesSpinLock, irql);
+    return ret;
+}
+
+PDIRECTORY_ENTRY DriverData::RemVerdictDirectory(LPCWSTR directory) {
+    KIRQL irql = KeGetCurrentIrql();
+    KeAcquireSpinLock(&directoriesSpinLock, &irql);
+    PDIRECTORY_ENTRY ret = RemDirectoryEntryAux(
+        &verdictDirectories,
+        &verdictDirectoriesSize,
+        directory);
+    KeReleaseSpinLock(&directoriesSpinLock, irql);
+    return ret;
+}
+
+BOOLEAN DriverData::IsInVerdictDirectory(CONST PUNICODE_STRING path) {
+    if (path == NULL || path->Buffer == NULL)
+        return FALSE;
+    BOOLEAN ret = FALSE;
+    KIRQL irql = KeGetCurrentIrql();
+    KeAcquireSpinLock(&directoriesSpinLock, &irql);
+    if (verdictDirectoriesSize != 0) {
+        ret = IsContainingDirectoryAux(&verdictDirectories, path);
     }
     KeReleaseSpinLock(&directoriesSpinLock, irql);
     return ret;
@@ -576,16 +989,7 @@ BOOLEAN DriverData::IsContainingDirectory(CONST PUNICODE_STRING path) {
 VOID DriverData::ClearDirectories() {
     KIRQL irql = KeGetCurrentIrql();
     KeAcquireSpinLock(&directoriesSpinLock, &irql);
-    PLIST_ENTRY pEntryDirs = rootDirectories.Flink;
-    while (pEntryDirs != &rootDirectories) {
-        LIST_ENTRY temp = *pEntryDirs;
-        PDIRECTORY_ENTRY pStrct = (PDIRECTORY_ENTRY)
-            CONTAINING_RECORD(pEntryDirs, DIRECTORY_ENTRY, entry);
-        delete pStrct;
-        //next
-        pEntryDirs = temp.Flink;
-    }
-    directoryRootsSize = 0;
-    InitializeListHead(&rootDirectories);
+    ClearDirectoriesAux(&rootDirectories, &directoryRootsSize);
+    ClearDirectoriesAux(&verdictDirectories, &verdictDirectoriesSize);
     KeReleaseSpinLock(&directoriesSpinLock, irql);
 }
\ No newline at end of file
@@ -16,14 +16,34 @@ class DriverData {
     ULONG
     pid;  // pid of the current connected user mode application, set by communication
 
+    /* runtime settings, tuned by the user mode application */
+    BOOLEAN entropyEnabled;  // calculate the entropy of reads and writes
+    BOOLEAN collectionPaused;  // irp ops are not recorded while paused
+    ULONG entropyMinSize;  // smaller reads and writes are not sampled
+
     ULONG irpOpsSize;  // number of irp ops waiting in entry_list
+    ULONG maxOpsSave;  // irp ops dropped above, MAX_OPS_SAVE unless tuned
     LIST_ENTRY irpOps;  // list entry bidirectional list of irp ops
     KSPIN_LOCK irpOpsLock;  // lock for irp list ops
 
+    /* statistics, reported by MESSAGE_GET_STATS, protected by irpOpsLock */
+    ULONGLONG opsQueued;  // irp ops added to irpOps
+    ULONGLONG opsDropped;  // irp ops discarded, irpOps being full
+    ULONGLONG nextSequence;  // DRIVER_MESSAGE Sequence of the next irp op added or dropped
+    ULONGLONG opsByIrp[IRP_MAJOR_OP_COUNT];  // irp ops added to irpOps, per IRP_MAJOR_OP
+    ULONGLONG entropyCalcs;  // irp ops with entropy calculated
+    volatile LONG attachedInstances;  // volume instances attached, interlocked
+
     ULONG directoryRootsSize;  // number of protected dirs in list
     LIST_ENTRY rootDirectories;  // list entry bdirectional of protected dirs
     KSPIN_LOCK directoriesSpinLock;  // lock for directory list
 
+    /* verdicts, writes, renames and deletes in the verdict dirs wait for user mode */
+    BOOLEAN verdictFailClosed;  // irp ops without verdict are denied, allowed otherwise
+    ULONG verdictTimeoutMs;  // time given to user mode to answer
+    ULONG verdictDirectoriesSize;  // number of verdict dirs in list, protected by directoriesSpinLock
+    LIST_ENTRY verdictDirectories;  // list entry of verdict dirs
+
     /* GID system data members */
     ULONGLONG
     GidCounter;  // internal counter for gid, every new application receives a new gid
@@ -33,13 +53,43 @@ class DriverData {
     LIST_ENTRY GidsList;  // list entry of gids, used to clear memory
     KSPIN_LOCK GIDSystemLock;
 
+    /* exclusions, their irp ops are not recorded, protected by GIDSystemLock as they follow the processes */
+    HashMap ExcludedPids;  // pids excluded by the user mode application
+    HashMap ExcludedGids;  // gids excluded by the user mode application
+    HashMap ImageExcludedPids;  // pids of processes started from an excluded image
+    ULONG excludedImagesSize;  // number of excluded images in list
+    LIST_ENTRY excludedImages;  // list entry of excluded image paths
+
   private:
     // call assumes protected code - high IRQL
     BOOLEAN RemoveProcessRecordAux(ULONG ProcessId, ULONGLONG gid);
 
+    // call assumes protected code - high IRQL
+    BOOLEAN ExitProcessRecordAux(ULONG ProcessId, ULONGLONG gid);
+
     // call assumes protected code - high IRQL
     BOOLEAN RemoveGidRecordAux(PGID_ENTRY gidRecord);
 
+    // call assumes protected code - high IRQL
+    BOOLEAN IsImageExcludedAux(PUNICODE_STRING imagePath);
+
+    // matches the recorded processes with the excluded images again, call assumes protected code - high IRQL
+    VOID RefreshImageExclusionsAux();
+
+    // directory lists helpers, call assumes protected code - high IRQL
+    static BOOLEAN AddDirectoryEntryAux(
+        PLIST_ENTRY list,
+        PULONG listSize,
+        PDIRECTORY_ENTRY newEntry);
+
+    static PDIRECTORY_ENTRY
+    RemDirectoryEntryAux(PLIST_ENTRY list, PULONG listSize, LPCWSTR directory);
+
+    static BOOLEAN
+    IsContainingDirectoryAux(PLIST_ENTRY list, CONST PUNICODE_STRING path);
+
+    static VOID ClearDirectoriesAux(PLIST_ENTRY list, PULONG listSize);
+
   public:
     // c'tor init D.S.
     explicit DriverData(PDRIVER_OBJECT DriverObject);
@@ -61,7 +111,7 @@ class DriverData {
         DbgPrint("Set system root path %ls\n", systemRootPath);
     }
 
-    // remove a process which ended from the GID system, function raise IRQL
+    // mark a process which ended in the GID system, the gid is removed with its last process, function raise IRQL
     BOOLEAN RemoveProcess(ULONG ProcessId);
 
     // record a process which was created to the GID system, function raise IRQL
@@ -83,14 +133,44 @@ class DriverData {
         ULONGLONG bufferSize,
         PULONGLONG returnedLength);
 
+    // fills tree with the processes of gid, exited ones included, returns false if there is no such gid
+    BOOLEAN GetGidTree(ULONGLONG gid, PGID_TREE tree);
+
     // if found return true on found else return false
     ULONGLONG GetProcessGid(ULONG ProcessId, PBOOLEAN found);
 
+    // fills entry with the IRP_PROCESS_CREATE or IRP_PROCESS_EXIT message of a recorded process, returns false if the pid is not recorded, function raise IRQL
+    BOOLEAN GetProcessMessage(ULONG ProcessId, UCHAR IrpOp, PIRP_ENTRY entry);
+
     //clear all data related to Gid system
     VOID ClearGidsPids();
 
     ULONGLONG GidsSize();
 
+    // returns false if the pid was already excluded, function raise IRQL
+    BOOLEAN ExcludePid(ULONG ProcessId);
+
+    // returns false if the pid was not excluded, function raise IRQL
+    BOOLEAN IncludePid(ULONG ProcessId);
+
+    // returns false if the gid was already excluded, function raise IRQL
+    BOOLEAN ExcludeGid(ULONGLONG gid);
+
+    // returns false if the gid was not excluded, function raise IRQL
+    BOOLEAN IncludeGid(ULONGLONG gid);
+
+    // excludes the processes of an image, running or to come, returns false if already excluded, function raise IRQL
+    BOOLEAN ExcludeImage(PIMAGE_ENTRY newEntry);
+
+    // returns the removed entry, null if the image was not excluded, function raise IRQL
+    PIMAGE_ENTRY IncludeImage(LPCWSTR imagePath);
+
+    // whether the irp ops of a process are not to be recorded, function raise IRQL
+    BOOLEAN IsExcluded(ULONG ProcessId);
+
+    // clears all exclusions, function raise IRQL
+    VOID ClearExclusions();
+
     BOOLEAN setFilterStart() {
         return (FilterRun = TRUE);
     }
@@ -120,6 +200,65 @@ class DriverData {
         return Pid;
     }
 
+    VOID setEntropyEnabled(BOOLEAN enabled) {
+        entropyEnabled = enabled;
+    }
+
+    VOID setEntropyMinSize(ULONG size) {
+        entropyMinSize = size;
+    }
+
+    // whether the entropy of a read or a write of size bytes has to be calculated
+    BOOLEAN isEntropyToCalc(ULONGLONG size) {
+        return entropyEnabled && size >= entropyMinSize;
+    }
+
+    VOID setCollectionPaused(BOOLEAN paused) {
+        collectionPaused = paused;
+    }
+
+    BOOLEAN isCollectionPaused() {
+        return collectionPaused;
+    }
+
+    // returns false if maxOps is out of 1 to MAX_OPS_SAVE_LIMIT, ops already saved are kept
+    BOOLEAN setMaxOps(ULONG maxOps);
+
+    VOID setVerdictFailClosed(BOOLEAN failClosed) {
+        verdictFailClosed = failClosed;
+    }
+
+    BOOLEAN isVerdictFailClosed() {
+        return verdictFailClosed;
+    }
+
+    // returns false if timeoutMs is out of 1 to MAX_VERDICT_TIMEOUT_MS
+    BOOLEAN setVerdictTimeout(ULONG timeoutMs) {
+        if (timeoutMs == 0 || timeoutMs > MAX_VERDICT_TIMEOUT_MS) {
+            return FALSE;
+        }
+        verdictTimeoutMs = timeoutMs;
+        return TRUE;
+    }
+
+    ULONG getVerdictTimeout() {
+        return verdictTimeoutMs;
+    }
+
+    // copies the current settings, function raise IRQL
+    VOID GetConfig(PFILTER_CONFIG config);
+
+    VOID InstanceAttached() {
+        InterlockedIncrement(&attachedInstances);
+    }
+
+    VOID InstanceDetached() {
+        InterlockedDecrement(&attachedInstances);
+    }
+
+    // copies the statistics, function raise IRQL
+    VOID GetStats(PDRIVER_STATS stats);
+
     // clears all irps waiting to report, function raise IRQL
     VOID ClearIrps();
 
@@ -148,6 +287,14 @@ class DriverData {
     */
     BOOLEAN IsContainingDirectory(CONST PUNICODE_STRING path);
 
+    BOOLEAN AddVerdictDirectory(PDIRECTORY_ENTRY newEntry);
+
+    PDIRECTORY_ENTRY RemVerdictDirectory(LPCWSTR directory);
+
+    // whether the irp ops on path wait for a verdict, function raise IRQL
+    BOOLEAN IsInVerdictDirectory(CONST PUNICODE_STRING path);
+
+    // clears the scan and verdict directories, function raise IRQL
     VOID ClearDirectories();
 
     VOID Clear() {
@@ -159,6 +306,9 @@ class DriverData {
 
         // clear gid system
         ClearGidsPids();
+
+        // clear exclusions
+        ClearExclusions();
     }
 };
 
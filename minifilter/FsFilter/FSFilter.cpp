@@ -245,6 +245,7 @@ STATUS_FLT_DO_NOT_ATTACH - do not attach
     PDEVICE_OBJECT devObject;
     hr = FltGetDiskDeviceObject(FltObjects->Volume, &devObject);
     if (!NT_SUCCESS(hr)) {
+        driverData->InstanceAttached();
         return STATUS_SUCCESS;
         //return hr;
     }
@@ -254,6 +255,7 @@ STATUS_FLT_DO_NOT_ATTACH - do not attach
 
         return hr;
     }
+    driverData->InstanceAttached();
     return STATUS_SUCCESS;
 }
 
@@ -347,6 +349,7 @@ None.
     UNREFERENCED_PARAMETER(FltObjects);
     UNREFERENCED_PARAMETER(Flags);
     DbgPrint("FSFIlter: Entered FSInstanceTeardownComplete\n");
+    driverData->InstanceDetached();
 }
 
 FLT_PREOP_CALLBACK_STATUS
@@ -388,6 +391,10 @@ Return Value:
 
         return FLT_PREOP_SUCCESS_NO_CALLBACK;
     }
+    // excluded by the user mode application, not even the create post op
+    if (driverData->IsExcluded(FltGetRequestorProcessId(Data))) {
+        return FLT_PREOP_SUCCESS_NO_CALLBACK;
+    }
     if (FltObjects->FileObject == NULL) {  //no file object
         return FLT_PREOP_SUCCESS_NO_CALLBACK;
     }
@@ -398,6 +405,8 @@ Return Value:
     hr = FSProcessPreOperartion(Data, FltObjects, CompletionContext);
     if (hr == FLT_PREOP_SUCCESS_WITH_CALLBACK)
         return FLT_PREOP_SUCCESS_WITH_CALLBACK;
+    if (hr == FLT_PREOP_COMPLETE)  // failed here, e.g. denied by the verdict handler
+        return FLT_PREOP_COMPLETE;
 
     return FLT_PREOP_SUCCESS_NO_CALLBACK;
 }
@@ -408,7 +417,8 @@ FSProcessPreOperartion(
     _In_ PCFLT_RELATED_OBJECTS FltObjects,
     _Flt_CompletionContext_Outptr_ PVOID* CompletionContext) {
     // no communication
-    if (driverData->isFilterClosed() || IsCommClosed()) {
+    if (driverData->isFilterClosed() || IsCommClosed()
+        || driverData->isCollectionPaused()) {
         //DbgPrint("!!! FSFilter: Filter is closed or Port is closed, skipping data\n");
         return FLT_PREOP_SUCCESS_NO_CALLBACK;
     }
@@ -475,12 +485,17 @@ FSProcessPreOperartion(
         return hr;
     }
 
-    if (FSIsFileNameInScanDirs(FilePath)) {
+    if (FSIsFileNameInScanDirs(&nameInfo->Name)) {  // scan dirs are device paths
         if (IS_DEBUG_IRP)
             DbgPrint("!!! FSFilter: File in scan area \n");
         newItem->FileLocationInfo = FILE_PROTECTED;
     }
 
+    // paging writes are not asked for, the cached write they flush already was
+    BOOLEAN askVerdict = !FlagOn(Data->Iopb->IrpFlags, IRP_PAGING_IO)
+        && driverData->IsInVerdictDirectory(&nameInfo->Name);
+    PVERDICT_REQUEST verdictRequest = NULL;
+
     if (Data->Iopb->MajorFunction == IRP_MJ_READ
         || Data->Iopb->MajorFunction == IRP_MJ_WRITE) {
         CopyExtension(newItem->Extension, nameInfo);
@@ -527,6 +542,11 @@ FSProcessPreOperartion(
             {
                 break;
             }
+            if (!driverData->isEntropyToCalc(
+                    Data->Iopb->Parameters.Write.Length)) {  // not sampled
+                newItem->MemSizeUsed = Data->Iopb->Parameters.Write.Length;
+                break;
+            }
 
             // prepare buffer for entropy calc
             if (Data->Iopb->Parameters.Write.MdlAddress
@@ -628,31 +648,31 @@ FSProcessPreOperartion(
                     return FLT_PREOP_SUCCESS_NO_CALLBACK;
                 }
 
+                // renames out of and into verdict dirs, the request keeps both names
+                if (askVerdict
+                    || driverData->IsInVerdictDirectory(&newNameInfo->Name)) {
+                    askVerdict = TRUE;
+                    verdictRequest = FSNewVerdictRequest(FilePath);
+                    if (verdictRequest != NULL) {
+                        RtlCopyBytes(
+                            verdictRequest->newPath,
+                            NewFilePath.Buffer,
+                            min(NewFilePath.Length,
+                                MAX_FILE_NAME_SIZE - sizeof(WCHAR)));
+                    }
+                }
+
                 RtlCopyBytes(
                     newEntry->Buffer,
                     Buffer,
                     MAX_FILE_NAME_SIZE);  // replace buffer data with new file
-                newItem->FileLocationInfo = FILE_MOVED_OUT;
-                /*
-if (FSIsFileNameInScanDirs(&NewFilePath)) {
-    if (newItem->FileLocationInfo == FILE_NOT_PROTECTED) { // moved in - report new file name
-        newItem->FileLocationInfo = FILE_MOVED_IN;
-        //newEntry->filePath = NewFilePath; // remember file moved in
-        RtlCopyBytes(newEntry->Buffer, Buffer, MAX_FILE_NAME_SIZE); // replace buffer data with new file
-    } // else we still report old file name so we know it was changed
-}
-else { // new file name not protected
-    if (newItem->FileLocationInfo == FILE_PROTECTED) { // moved out - report old file name
-        newItem->FileLocationInfo = FILE_MOVED_OUT;
-    }
-    /*else { // we dont care - rename of file in unprotected area to unprotected area
-        delete newEntry;
-        FltReleaseFileNameInformation(nameInfo);
-        FltReleaseFileNameInformation(newNameInfo);
-        return FLT_PREOP_SUCCESS_NO_CALLBACK;
-    }
-}
-*/
+                if (FSIsFileNameInScanDirs(&newNameInfo->Name)) {
+                    if (newItem->FileLocationInfo == FILE_NOT_PROTECTED) {
+                        newItem->FileLocationInfo = FILE_MOVED_IN;
+                    }
+                } else if (newItem->FileLocationInfo == FILE_PROTECTED) {
+                    newItem->FileLocationInfo = FILE_MOVED_OUT;
+                }
 
                 CopyExtension(newItem->Extension, newNameInfo);
                 FltReleaseFileNameInformation(newNameInfo);
@@ -679,6 +699,28 @@ else { // new file name not protected
             delete newEntry;
             return FLT_PREOP_SUCCESS_NO_CALLBACK;
     }
+    if (askVerdict
+        && (newItem->IRP_OP == IRP_WRITE || newItem->IRP_OP == IRP_SETINFO)) {
+        if (verdictRequest == NULL) {
+            verdictRequest = FSNewVerdictRequest(FilePath);
+        }
+        if (verdictRequest != NULL) {
+            verdictRequest->pid = newItem->PID;
+            verdictRequest->gid = newItem->Gid;
+            verdictRequest->irpOp = newItem->IRP_OP;
+            verdictRequest->fileChange = newItem->FileChange;
+            verdictRequest->size = newItem->MemSizeUsed;
+            verdictRequest->entropy = newItem->Entropy;
+            verdictRequest->isEntropyCalc = newItem->isEntropyCalc;
+        }
+        // denied irp ops did not happen, they are not recorded
+        if (!RWFAskVerdict(verdictRequest)) {
+            delete newEntry;
+            Data->IoStatus.Status = STATUS_ACCESS_DENIED;
+            Data->IoStatus.Information = 0;
+            return FLT_PREOP_COMPLETE;
+        }
+    }
     if (IS_DEBUG_IRP)
         DbgPrint(
             "!!! FSFilter: Adding entry to irps %s\n",
@@ -750,7 +792,8 @@ FSProcessCreateIrp(
         return FLT_POSTOP_FINISHED_PROCESSING;
     }
 
-    if (driverData->isFilterClosed() || IsCommClosed()) {
+    if (driverData->isFilterClosed() || IsCommClosed()
+        || driverData->isCollectionPaused()) {
         //DbgPrint("!!! FSFilter: filter closed or comm closed, skip irp\n");
         return FLT_POSTOP_FINISHED_PROCESSING;
     }
@@ -782,7 +825,6 @@ FSProcessCreateIrp(
 
     newItem->PID = FltGetRequestorProcessId(Data);
     newItem->IRP_OP = IRP_CREATE;
-    newItem->FileLocationInfo = FILE_PROTECTED;
     PUNICODE_STRING FilePath = &(newEntry->filePath);
 
     BOOLEAN isGidFound;
@@ -815,6 +857,10 @@ FSProcessCreateIrp(
 
     CopyExtension(newItem->Extension, nameInfo);
 
+    if (FSIsFileNameInScanDirs(&nameInfo->Name)) {
+        newItem->FileLocationInfo = FILE_PROTECTED;
+    }
+
     FltReleaseFileNameInformation(nameInfo);
 
     /*
@@ -869,7 +915,8 @@ FSProcessPostReadIrp(
 
     PIRP_ENTRY entry = (PIRP_ENTRY)CompletionContext;
 
-    if (driverData->isFilterClosed() || IsCommClosed()) {
+    if (driverData->isFilterClosed() || IsCommClosed()
+        || driverData->isCollectionPaused()) {
         if (IS_DEBUG_IRP)
             DbgPrint("!!! FSFilter: Post op read, comm or filter closed\n");
         delete entry;
@@ -878,6 +925,15 @@ FSProcessPostReadIrp(
 
     FLT_POSTOP_CALLBACK_STATUS status = FLT_POSTOP_FINISHED_PROCESSING;
 
+    if (!driverData->isEntropyToCalc(
+            Data->IoStatus.Information)) {  // not sampled, no buffer needed
+        entry->data.MemSizeUsed = (ULONG)Data->IoStatus.Information;
+        if (!driverData->AddIrpMessage(entry)) {
+            delete entry;
+        }
+        return FLT_POSTOP_FINISHED_PROCESSING;
+    }
+
     PVOID ReadBuffer = NULL;
 
     // prepare buffer for entropy calc
@@ -981,6 +1037,21 @@ FSIsFileNameInScanDirs(CONST PUNICODE_STRING path) {
     return driverData->IsContainingDirectory(path);
 }
 
+PVERDICT_REQUEST
+FSNewVerdictRequest(CONST PUNICODE_STRING path) {
+    PVERDICT_REQUEST request = (PVERDICT_REQUEST)
+        ExAllocatePool2(POOL_FLAG_NON_PAGED, sizeof(VERDICT_REQUEST), 'RW');
+    if (request == NULL) {
+        DbgPrint("!!! FSFilter: memory allocation error on non paged pool\n");
+        return NULL;
+    }
+    RtlCopyBytes(
+        request->path,
+        path->Buffer,
+        min(path->Length, MAX_FILE_NAME_SIZE - sizeof(WCHAR)));
+    return request;
+}
+
 NTSTATUS
 FSEntrySetFileName(
     CONST PFLT_VOLUME Volume,
@@ -1129,6 +1200,23 @@ GetProcessNameByHandle(_In_ HANDLE ProcessHandle, _Out_ PUNICODE_STRING* Name) {
     return status;
 }
 
+VOID QueueProcessMessage(ULONG ProcessId, UCHAR IrpOp) {
+    if (driverData->isCollectionPaused() || driverData->IsExcluded(ProcessId)) {
+        return;
+    }
+    PIRP_ENTRY entry = new IRP_ENTRY();
+    if (entry == NULL) {
+        return;
+    }
+    if (!driverData->GetProcessMessage(ProcessId, IrpOp, entry)) {  // process not recorded
+        delete entry;
+        return;
+    }
+    if (!driverData->AddIrpMessage(entry)) {
+        delete entry;
+    }
+}
+
 // new code process recording
 VOID AddRemProcessRoutine(HANDLE ParentId, HANDLE ProcessId, BOOLEAN Create) {
     if (commHandle->CommClosed)
@@ -1251,10 +1339,12 @@ VOID AddRemProcessRoutine(HANDLE ParentId, HANDLE ProcessId, BOOLEAN Create) {
             (ULONG)(ULONG_PTR)ProcessId,
             (ULONG)(ULONG_PTR)ParentId);
         delete parentName;
+        QueueProcessMessage((ULONG)(ULONG_PTR)ProcessId, IRP_PROCESS_CREATE);
     } else {
         DbgPrint(
             "!!! FSFilter: Terminate Process, Process: %d pid\n",
             (ULONG)(ULONG_PTR)ProcessId);
+        QueueProcessMessage((ULONG)(ULONG_PTR)ProcessId, IRP_PROCESS_EXIT);  // before the pid is released
         driverData->RemoveProcess((ULONG)(ULONG_PTR)ProcessId);
     }
 }
\ No newline at end of file
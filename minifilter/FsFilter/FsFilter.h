@@ -103,6 +103,10 @@ FSProcessCreateIrp(
 BOOLEAN
 FSIsFileNameInScanDirs(CONST PUNICODE_STRING path);
 
+// allocates a zeroed VERDICT_REQUEST on the file path, freed by RWFAskVerdict, NULL on failure
+PVERDICT_REQUEST
+FSNewVerdictRequest(CONST PUNICODE_STRING path);
+
 // ZwQueryInformationProcess - dynamic loaded function which query info data about already opened processes
 typedef NTSTATUS (*QUERY_INFO_PROCESS)(
     __in HANDLE ProcessHandle,
@@ -131,7 +135,11 @@ VOID CopyExtension(PWCHAR dest, PFLT_FILE_NAME_INFORMATION nameInfo);
 // When a new process enter we add it to parent gid if there is any.
 // if parent doesn't have a gid and both are system process, new process isn't recorded
 // else we create a new gid for process
+// Recorded processes are also reported to the application as IRP_PROCESS_CREATE and IRP_PROCESS_EXIT messages.
 
 VOID AddRemProcessRoutine(HANDLE ParentId, HANDLE ProcessId, BOOLEAN Create);
 
+// queues an IRP_PROCESS_CREATE or IRP_PROCESS_EXIT message for a recorded process, unless it is excluded or the collection is paused
+VOID QueueProcessMessage(ULONG ProcessId, UCHAR IrpOp);
+
 UNICODE_STRING GvolumeData;
\ No newline at end of file
@@ -133,6 +133,18 @@ class HashMap {
         return NULL;
     }
 
+    //Function to delete all key value pairs
+    void clear() {
+        for (ULONGLONG i = 0; i < capacity; i++) {
+            PLIST_ENTRY head = arr[i];
+            while (!IsListEmpty(head)) {
+                PLIST_ENTRY entry = RemoveHeadList(head);
+                delete (HashNode*)CONTAINING_RECORD(entry, HashNode, entry);
+            }
+        }
+        size = 0;
+    }
+
     //Return current size
     ULONGLONG sizeofMap() {
         return size;
@@ -74,4 +74,12 @@ BOOLEAN startsWith(PUNICODE_STRING String, PWCHAR Pattern) {
         //DbgPrint("Chars are eq: %d, %d\n", RtlDowncaseUnicodeChar(Pattern[i]), RtlDowncaseUnicodeChar(buffer[i]));
     }
     return TRUE;
+}
+
+BOOLEAN equalsIgnoreCase(PUNICODE_STRING String, PWCHAR Pattern) {
+    if (String == NULL || Pattern == NULL)
+        return FALSE;
+    if (String->Length != 2 * wcslen(Pattern))
+        return FALSE;
+    return startsWith(String, Pattern);
 }
\ No newline at end of file
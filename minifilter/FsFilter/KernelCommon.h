@@ -19,9 +19,17 @@ typedef struct _PID_ENTRY {
     LIST_ENTRY entry;
     PUNICODE_STRING Path;
     ULONG Pid;
+    ULONG ParentPid;
+    LARGE_INTEGER CreateTime;
+    LARGE_INTEGER ExitTime;  // 0 while alive
+    BOOLEAN Alive;  // exited entries are kept in their gid for MESSAGE_GET_GID_TREE
 
     _PID_ENTRY() {
         Pid = 0;
+        ParentPid = 0;
+        CreateTime.QuadPart = 0;
+        ExitTime.QuadPart = 0;
+        Alive = TRUE;
         Path = nullptr;
         entry.Flink = nullptr;
         entry.Blink = nullptr;
@@ -52,6 +60,9 @@ typedef struct _DIRECTORY_ENTRY {
 
 } DIRECTORY_ENTRY, *PDIRECTORY_ENTRY;
 
+// IMAGE_ENTRY - image file of processes excluded from recording, same layout as DIRECTORY_ENTRY
+typedef DIRECTORY_ENTRY IMAGE_ENTRY, *PIMAGE_ENTRY;
+
 typedef struct _IRP_ENTRY {
     LIST_ENTRY entry;
     DRIVER_MESSAGE data;
@@ -70,6 +81,10 @@ typedef struct _IRP_ENTRY {
         data.isEntropyCalc = FALSE;
         data.FileChange = FILE_CHANGE_NOT_SET;
         data.FileLocationInfo = FILE_NOT_PROTECTED;
+        LARGE_INTEGER time;
+        KeQuerySystemTimePrecise(&time);
+        data.Time = time.QuadPart;
+        data.Sequence = 0;  // set by AddIrpMessage
     }
 
     void* _IRP_ENTRY::operator new(size_t size) {
@@ -97,13 +112,16 @@ WCHAR* stristr(const WCHAR* String, const WCHAR* Pattern);
 
 BOOLEAN startsWith(PUNICODE_STRING String, PWCHAR Pattern);
 
+BOOLEAN equalsIgnoreCase(PUNICODE_STRING String, PWCHAR Pattern);
+
 // GID_ENTRY - for each gid in the system we record, holds pids entries (PID_ENTRY)
 // the struct is meant to be used in blist (LIST_ENTRY)
 struct GID_ENTRY {
     LIST_ENTRY GidListEntry;
     ULONGLONG gid;
-    ULONGLONG pidsSize;
-    LIST_ENTRY HeadListPids;
+    ULONGLONG pidsSize;  // alive pids, the gid ends with the last one
+    ULONGLONG exitedPidsSize;  // exited pids still in HeadListPids
+    LIST_ENTRY HeadListPids;  // newest first
 
     // gid as input
     GID_ENTRY(ULONGLONG Gid) {
@@ -111,6 +129,7 @@ struct GID_ENTRY {
         InitializeListHead(&HeadListPids);
         InitializeListHead(&GidListEntry);
         pidsSize = 0;
+        exitedPidsSize = 0;
     }
 
     //copy
@@ -121,6 +140,7 @@ struct GID_ENTRY {
         GidListEntry.Blink = a.GidListEntry.Blink;
         gid = a.gid;
         pidsSize = a.pidsSize;
+        exitedPidsSize = a.exitedPidsSize;
     }
 
     const GID_ENTRY& operator=(const GID_ENTRY& a) {
@@ -130,6 +150,7 @@ struct GID_ENTRY {
         GidListEntry.Blink = a.GidListEntry.Blink;
         gid = a.gid;
         pidsSize = a.pidsSize;
+        exitedPidsSize = a.exitedPidsSize;
         this;
     }
 };
@@ -20,6 +20,18 @@ Environment :
 
 const PWSTR ComPortName = L"\\RWFilter";
 
+//
+//  Name of port on which the driver asks user mode for verdicts, only ADMIN(s) & SYSTEM can connect
+//
+
+const PWSTR VerdictPortName = L"\\RWFVerdict";
+
+//
+//  Version of the protocol below, bumped on every change of the messages or of their layout
+//
+
+#define PROTOCOL_VERSION 11
+
 #define MAX_FILE_NAME_LENGTH 520
 #define MAX_FILE_NAME_SIZE \
     (MAX_FILE_NAME_LENGTH \
@@ -30,9 +42,24 @@ const PWSTR ComPortName = L"\\RWFilter";
 //#define MAX_OPS_SAVE 0x10000 // max ops to save, we limit this to prevent driver from filling the non paged memory and crashing the os
 
 #define MAX_COMM_BUFFER_SIZE \
-    0x10000  // size of the buffer we allocate to recieve irp ops from the driver
+    0x10000  // default size of the buffer we allocate to recieve irp ops from the driver
+#define MIN_COMM_BUFFER_SIZE \
+    (sizeof(RWD_REPLY_IRPS) + sizeof(DRIVER_MESSAGE) + MAX_FILE_NAME_SIZE \
+     + sizeof(WCHAR))  // smallest buffer holding a message with the longest path
 #define MAX_OPS_SAVE \
     0x1000  // max ops to save, we limit this to prevent driver from filling the non paged memory and crashing the os
+#define MAX_OPS_SAVE_LIMIT \
+    0x10000  // highest max ops to save accepted by MESSAGE_SET_MAX_OPS
+#define MAX_GID_REPORT_PIDS \
+    1024  // max pids reported in a GID_REPORT, the others are counted in numPids only
+#define MAX_IMAGE_NAME_LENGTH \
+    16  // image file names are truncated by the kernel to 15 chars
+#define DEFAULT_VERDICT_TIMEOUT_MS \
+    1000  // time given to user mode to answer a VERDICT_REQUEST, the fail policy applies after
+#define MAX_VERDICT_TIMEOUT_MS \
+    60000  // highest timeout accepted by MESSAGE_SET_VERDICT_TIMEOUT
+#define MAX_GID_TREE_PIDS \
+    128  // max processes detailed in a GID_TREE, the others are counted in numPids only
 
 // msgs types that the application may send to the driver
 enum COM_MESSAGE_TYPE {
@@ -40,14 +67,38 @@ enum COM_MESSAGE_TYPE {
     MESSAGE_REM_SCAN_DIRECTORY,
     MESSAGE_GET_OPS,
     MESSAGE_SET_PID,
-    MESSAGE_KILL_GID
+    MESSAGE_KILL_GID,
+    MESSAGE_GET_VERSION,
+    MESSAGE_SUSPEND_GID,
+    MESSAGE_RESUME_GID,
+    MESSAGE_SET_ENTROPY,  // value: 0 to stop calculating the entropy of reads and writes
+    MESSAGE_SET_ENTROPY_MIN_SIZE,  // value: smaller reads and writes are not sampled
+    MESSAGE_SET_MAX_OPS,  // value: max ops to save, 1 to MAX_OPS_SAVE_LIMIT
+    MESSAGE_PAUSE_COLLECTION,
+    MESSAGE_RESUME_COLLECTION,
+    MESSAGE_GET_CONFIG,
+    MESSAGE_EXCLUDE_PID,  // pid: its irp ops are no longer recorded
+    MESSAGE_INCLUDE_PID,
+    MESSAGE_EXCLUDE_GID,  // gid: the irp ops of its processes are no longer recorded
+    MESSAGE_INCLUDE_GID,
+    MESSAGE_EXCLUDE_IMAGE,  // path: the processes of this image file are no longer recorded
+    MESSAGE_INCLUDE_IMAGE,
+    MESSAGE_GET_STATS,
+    MESSAGE_ADD_VERDICT_DIRECTORY,  // path: writes, renames and deletes in it wait for a verdict
+    MESSAGE_REM_VERDICT_DIRECTORY,
+    MESSAGE_SET_VERDICT_TIMEOUT,  // value: milliseconds, 1 to MAX_VERDICT_TIMEOUT_MS
+    MESSAGE_SET_VERDICT_FAIL_CLOSED,  // value: 1 to deny the irp ops without verdict, 0 to allow them
+    MESSAGE_GET_GID_TREE  // gid: the processes of the family, alive or exited, as a GID_TREE
 };
 
 // msgs struct that the application send when sending msg to the driver, type member should be one of the COM_MESSAGE_TYPE
 typedef struct _COM_MESSAGE {
     ULONG type;
     ULONG pid;
-    ULONGLONG gid;
+    union {
+        ULONGLONG gid;
+        ULONGLONG value;  // argument of the tuning messages
+    };
     WCHAR path[MAX_FILE_NAME_LENGTH];
 
 } COM_MESSAGE, *PCOM_MESSAGE;
@@ -78,9 +129,22 @@ enum IRP_MAJOR_OP {
     IRP_SETINFO,
     IRP_CREATE,
     IRP_CLEANUP,
+    IRP_PROCESS_CREATE,  // not a file operation, see PROCESS_MESSAGE_INFO
+    IRP_PROCESS_EXIT,  // not a file operation, see PROCESS_MESSAGE_INFO
 };
 
-// -64- bytes structure, fixed to -96- bytes, fixed to 104 bytes
+#define IRP_MAJOR_OP_COUNT (IRP_CLEANUP + 1)
+
+// process create and exit are queued as DRIVER_MESSAGEs with IRP_OP IRP_PROCESS_CREATE or
+// IRP_PROCESS_EXIT: PID, Gid and filePath (the image path) describe the process and FileID holds this struct
+typedef struct _PROCESS_MESSAGE_INFO {
+    LONGLONG time;  // 8 bytes, system time of the create or exit
+    ULONG parentPid;  // 4 bytes
+    ULONG alivePids;  // 4 bytes, alive processes left in the gid after the event, 0 when the gid ends
+    ULONGLONG reserved2;  // 8 bytes, up to the size of FileID
+} PROCESS_MESSAGE_INFO, *PPROCESS_MESSAGE_INFO;
+
+// -64- bytes structure, fixed to -96- bytes, fixed to 104 bytes, fixed to 120 bytes
 typedef struct _DRIVER_MESSAGE {
     WCHAR Extension
         [FILE_OBJEC_MAX_EXTENSION_SIZE + 1];  // null terminated 24 bytes
@@ -104,6 +168,9 @@ typedef struct _DRIVER_MESSAGE {
     UNICODE_STRING
         filePath;  // 16 bytes unicode string - filename, also contains size and max size, buffer is outside the struct
     ULONGLONG Gid;  // 8 bytes process ransomwatch gid
+    LONGLONG Time;  // 8 bytes, KeQuerySystemTimePrecise when the irp op was caught
+    ULONGLONG
+        Sequence;  // 8 bytes, numbers the irp ops queued or dropped since the driver started, a gap is a drop
     PVOID
         next;  // 8 bytes - next PDRIVER_MESSAGE, we use it to allow adding the fileName to the same buffer, this pointer should point to the next PDRIVER_MESSAGE in buffer (kernel handled)
 
@@ -138,4 +205,102 @@ typedef struct _RWD_REPLY_IRPS {
         dataSize(sizeof(_RWD_REPLY_IRPS)),
         data(nullptr),
         num_ops(0) {}
-} RWD_REPLY_IRPS, *PRWD_REPLY_IRPS;
\ No newline at end of file
+} RWD_REPLY_IRPS, *PRWD_REPLY_IRPS;
+
+// reply to MESSAGE_GET_VERSION, lets the application check that both sides share this header
+typedef struct _DRIVER_VERSION {
+    ULONG protocolVersion;  // PROTOCOL_VERSION
+    ULONG comMessageSize;  // sizeof(COM_MESSAGE)
+    ULONG driverMessageSize;  // sizeof(DRIVER_MESSAGE)
+    ULONG replyIrpsSize;  // sizeof(RWD_REPLY_IRPS)
+} DRIVER_VERSION, *PDRIVER_VERSION;
+
+// outcome of a command on one pid of a gid
+typedef struct _PID_OUTCOME {
+    ULONG pid;
+    LONG status;  // NTSTATUS
+    CHAR imageName[MAX_IMAGE_NAME_LENGTH];  // null terminated, empty if the pid was not found
+} PID_OUTCOME, *PPID_OUTCOME;
+
+// reply to MESSAGE_KILL_GID, MESSAGE_SUSPEND_GID and MESSAGE_RESUME_GID
+typedef struct _GID_REPORT {
+    LONG status;  // STATUS_NO_SUCH_GROUP if the gid is unknown, STATUS_SUCCESS otherwise
+    ULONG numPids;  // pids in the gid, only the first MAX_GID_REPORT_PIDS are in pids
+    PID_OUTCOME pids[MAX_GID_REPORT_PIDS];
+} GID_REPORT, *PGID_REPORT;
+
+// reply to the tuning messages and to MESSAGE_GET_CONFIG, the settings after the change
+typedef struct _FILTER_CONFIG {
+    BOOLEAN entropyEnabled;  // 1 byte
+    BOOLEAN collectionPaused;  // 1 byte
+    BOOLEAN verdictFailClosed;  // 1 byte
+    UCHAR reserved;  // 1 byte
+    ULONG entropyMinSize;  // 4 bytes
+    ULONG maxOps;  // 4 bytes
+    ULONG verdictTimeoutMs;  // 4 bytes
+} FILTER_CONFIG, *PFILTER_CONFIG;
+
+// a process of a gid, exited processes are kept while the gid lives
+typedef struct _PROCESS_NODE {
+    ULONG pid;  // 4 bytes
+    ULONG parentPid;  // 4 bytes, outside of the gid for its root
+    LONGLONG createTime;  // 8 bytes, system time (100ns since 1601)
+    LONGLONG exitTime;  // 8 bytes, system time, 0 while alive
+    BOOLEAN alive;  // 1 byte
+    UCHAR reserved[7];  // 7 bytes
+    WCHAR imagePath[MAX_FILE_NAME_LENGTH];  // null terminated, device form
+} PROCESS_NODE, *PPROCESS_NODE;
+
+// reply to MESSAGE_GET_GID_TREE, the processes in their order of creation
+typedef struct _GID_TREE {
+    LONG status;  // STATUS_NO_SUCH_GROUP if the gid is unknown, STATUS_SUCCESS otherwise
+    ULONG numPids;  // processes in the gid, only the first MAX_GID_TREE_PIDS are in pids
+    PROCESS_NODE pids[MAX_GID_TREE_PIDS];
+} GID_TREE, *PGID_TREE;
+
+// reply to MESSAGE_GET_STATS, counters since the driver started
+typedef struct _DRIVER_STATS {
+    ULONGLONG opsQueued;  // irp ops added to the queue
+    ULONGLONG opsDropped;  // irp ops discarded, the queue being full
+    ULONGLONG opsByIrp[IRP_MAJOR_OP_COUNT];  // irp ops queued, per IRP_MAJOR_OP
+    ULONGLONG entropyCalcs;  // entropies calculated for reads and writes
+    ULONG queueDepth;  // irp ops waiting to be fetched
+    ULONG attachedInstances;  // volume instances the filter is attached to
+    ULONGLONG gids;  // gids currently tracked
+} DRIVER_STATS, *PDRIVER_STATS;
+
+enum VERDICT { VERDICT_ALLOW, VERDICT_DENY };
+
+// sent by the driver on the verdict port before a write, rename or delete in a verdict directory
+typedef struct _VERDICT_REQUEST {
+    ULONG pid;  // 4 bytes
+    UCHAR irpOp;  // 1 byte, IRP_WRITE or IRP_SETINFO
+    UCHAR fileChange;  // 1 byte, FILE_CHANGE_INFO
+    BOOLEAN isEntropyCalc;  // 1 byte
+    UCHAR reserved;  // 1 byte
+    ULONGLONG gid;  // 8 bytes
+    ULONGLONG size;  // 8 bytes to write
+    DOUBLE entropy;  // 8 bytes of the data to write
+    WCHAR path[MAX_FILE_NAME_LENGTH];  // null terminated
+    WCHAR newPath[MAX_FILE_NAME_LENGTH];  // null terminated destination of a rename, empty otherwise
+} VERDICT_REQUEST, *PVERDICT_REQUEST;
+
+// reply of user mode to a VERDICT_REQUEST
+typedef struct _VERDICT_REPLY {
+    ULONG verdict;  // VERDICT
+} VERDICT_REPLY, *PVERDICT_REPLY;
+
+#ifdef _WIN64
+static_assert(sizeof(COM_MESSAGE) == 1056, "COM_MESSAGE layout changed");
+static_assert(sizeof(DRIVER_MESSAGE) == 120, "DRIVER_MESSAGE layout changed");
+static_assert(sizeof(PROCESS_MESSAGE_INFO) == 24, "PROCESS_MESSAGE_INFO layout changed");
+static_assert(sizeof(RWD_REPLY_IRPS) == 24, "RWD_REPLY_IRPS layout changed");
+static_assert(sizeof(DRIVER_VERSION) == 16, "DRIVER_VERSION layout changed");
+static_assert(sizeof(GID_REPORT) == 24584, "GID_REPORT layout changed");
+static_assert(sizeof(FILTER_CONFIG) == 16, "FILTER_CONFIG layout changed");
+static_assert(sizeof(DRIVER_STATS) == 88, "DRIVER_STATS layout changed");
+static_assert(sizeof(VERDICT_REQUEST) == 2112, "VERDICT_REQUEST layout changed");
+static_assert(sizeof(VERDICT_REPLY) == 4, "VERDICT_REPLY layout changed");
+static_assert(sizeof(PROCESS_NODE) == 1072, "PROCESS_NODE layout changed");
+static_assert(sizeof(GID_TREE) == 137224, "GID_TREE layout changed");
+#endif
@@ -1,45 +1,77 @@
+#[cfg(windows)]
 use minifilter_rs::driver_comm;
-use minifilter_rs::shared_def::{CDriverMsgs, IOMessage};
+#[cfg(windows)]
+use minifilter_rs::driver_comm::session::{DriverSession, SessionEvent};
+#[cfg(windows)]
+use minifilter_rs::output::{JsonLinesWriter, RotatingFile};
+#[cfg(windows)]
 use minifilter_rs::worker::Worker;
-use std::sync::mpsc::channel;
-use std::thread;
-use std::time::Duration;
+#[cfg(windows)]
+use std::io::Write;
 
+#[cfg(windows)]
 fn main() {
-    let driver = driver_comm::Driver::open_kernel_driver_com()
-        .expect("Cannot open driver communication (is the mini-filter started?)");
-    driver
-        .driver_set_app_pid()
-        .expect("Cannot set driver app pid");
-    let mut vecnew: Vec<u8> = Vec::with_capacity(65536);
-
-    let (tx_iomsgs, rx_iomsgs) = channel::<IOMessage>();
+    let driver = match driver_comm::Driver::open_kernel_driver_com() {
+        Ok(driver) => driver,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
 
-    thread::spawn(move || loop {
-        if let Some(reply_irp) = driver.get_irp(&mut vecnew) {
-            if reply_irp.num_ops > 0 {
-                let drivermsgs = CDriverMsgs::new(&reply_irp);
-                for drivermsg in drivermsgs {
-                    let iomsg = IOMessage::from(&drivermsg);
-                    if tx_iomsgs.send(iomsg).is_ok() {
-                    } else {
-                        panic!("Cannot send iomsg");
-                    }
-                }
-            } else {
-                thread::sleep(Duration::from_millis(10));
+    // The events go to the file given as argument, rotated by size, or to stdout
+    let out: Box<dyn Write> = match std::env::args_os().nth(1) {
+        Some(path) => match RotatingFile::create(path.as_ref()) {
+            Ok(file) => Box::new(file),
+            Err(e) => {
+                eprintln!("Can't open {}: {e}", path.to_string_lossy());
+                std::process::exit(1);
             }
-        } else {
-            panic!("Can't receive Driver Message?");
-        }
-    });
+        },
+        None => Box::new(std::io::stdout().lock()),
+    };
+    let mut out = JsonLinesWriter::new(out);
+
+    let (events, _session) = DriverSession::new(driver).spawn();
 
     let mut worker = Worker::new();
 
-    loop {
-        if let Ok(mut io_message) = rx_iomsgs.recv() {
-            worker.process_io(&mut io_message);
-            println!("{:#?}\n", io_message);
+    for event in events {
+        match event {
+            SessionEvent::IoMessage(mut io_message) => {
+                worker.process_io(&mut io_message);
+                if let Err(e) = out.write_iomsg(&io_message).and_then(|()| out.flush()) {
+                    eprintln!("Can't export the event: {e}");
+                }
+            }
+            SessionEvent::Process(process_event) => {
+                eprintln!("{process_event:?}");
+                if let Some(precord) = worker.process_event(&process_event) {
+                    eprintln!(
+                        "{} (gid {}) {}",
+                        precord.appname, precord.gid, precord.process_state
+                    );
+                }
+            }
+            SessionEvent::EventsLost { count } => {
+                eprintln!("{count} operations dropped by the driver")
+            }
+            SessionEvent::Connected => eprintln!("Connected to the driver"),
+            SessionEvent::Disconnected(e) => eprintln!("{e}, reconnecting"),
+            SessionEvent::ReconnectFailed {
+                attempt,
+                error,
+                retry_in,
+            } => eprintln!(
+                "Reconnection attempt {attempt} failed: {error}, retrying in {retry_in:?}"
+            ),
+            SessionEvent::Error(e) => eprintln!("Can't receive Driver Message: {e}"),
         }
     }
 }
+
+#[cfg(not(windows))]
+fn main() {
+    eprintln!("The FSFilter minifilter can only be reached on Windows");
+    std::process::exit(1);
+}
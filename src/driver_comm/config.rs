@@ -0,0 +1,97 @@
+//! How to reach the minifilter and fetch its events, see [`DriverConfig`].
+
+use crate::driver_comm::{COM_PORT_NAME, MAX_COMM_BUFFER_SIZE, MIN_COMM_BUFFER_SIZE};
+
+/// How many [`GetOps`](super::DriverComMessageType::GetOps) are sent by
+/// [`poll_iomsgs`](super::Driver::poll_iomsgs).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DrainPolicy {
+    /// One, the remaining events are fetched on the next poll.
+    Once,
+    /// Until the queue of the minifilter is empty, or `max_replies` have been received. Keeps up
+    /// with bursts of activity that a single reply buffer cannot hold.
+    UntilEmpty { max_replies: usize },
+}
+
+/// Settings of a [`Driver`](super::Driver), given to [`Driver::open`](super::Driver::open) or
+/// [`Driver::with_config`](super::Driver::with_config).
+///
+/// ```
+/// use minifilter_rs::driver_comm::config::{DrainPolicy, DriverConfig};
+///
+/// let config = DriverConfig::builder()
+///     .buffer_size(1 << 20)
+///     .drain(DrainPolicy::UntilEmpty { max_replies: 16 })
+///     .build();
+/// assert_eq!(config.port_name(), r"\RWFilter");
+/// assert_eq!(config.buffer_size(), 1 << 20);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DriverConfig {
+    port_name: String,
+    buffer_size: usize,
+    drain: DrainPolicy,
+}
+
+impl DriverConfig {
+    pub fn builder() -> DriverConfigBuilder {
+        DriverConfigBuilder {
+            config: DriverConfig::default(),
+        }
+    }
+
+    /// Name of the communication port opened by the minifilter.
+    pub fn port_name(&self) -> &str {
+        &self.port_name
+    }
+
+    /// Size of the reply buffer allocated by [`get_irp`](super::Driver::get_irp) when the one
+    /// given is too small.
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    pub fn drain(&self) -> DrainPolicy {
+        self.drain
+    }
+}
+
+/// The FSFilter port ([`COM_PORT_NAME`]), a reply buffer of [`MAX_COMM_BUFFER_SIZE`] and
+/// [`DrainPolicy::Once`]: what the minifilter accepted before [`DriverConfig`].
+impl Default for DriverConfig {
+    fn default() -> Self {
+        DriverConfig {
+            port_name: COM_PORT_NAME.to_string(),
+            buffer_size: MAX_COMM_BUFFER_SIZE,
+            drain: DrainPolicy::Once,
+        }
+    }
+}
+
+/// Builds a [`DriverConfig`], starting from the default one.
+#[derive(Debug)]
+pub struct DriverConfigBuilder {
+    config: DriverConfig,
+}
+
+impl DriverConfigBuilder {
+    pub fn port_name(mut self, port_name: &str) -> Self {
+        self.config.port_name = port_name.to_string();
+        self
+    }
+
+    /// Raised to [`MIN_COMM_BUFFER_SIZE`], the size needed by the longest message.
+    pub fn buffer_size(mut self, buffer_size: usize) -> Self {
+        self.config.buffer_size = buffer_size.max(MIN_COMM_BUFFER_SIZE);
+        self
+    }
+
+    pub fn drain(mut self, drain: DrainPolicy) -> Self {
+        self.config.drain = drain;
+        self
+    }
+
+    pub fn build(self) -> DriverConfig {
+        self.config
+    }
+}
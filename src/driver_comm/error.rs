@@ -0,0 +1,131 @@
+//! Everything that can go wrong while talking to the minifilter.
+
+use std::error::Error;
+use std::fmt;
+
+use windows::core::HRESULT;
+
+use crate::driver_comm::version::DriverVersion;
+use crate::shared_def::decoder::DecodeError;
+
+/// Max number of UTF-16 code units in a [`BufPath`](super::BufPath), the terminating NUL excluded.
+pub const MAX_PATH_LEN: usize = 519;
+
+/// `HRESULT_FROM_WIN32(ERROR_INVALID_HANDLE)`: the port handle is closed.
+const E_HANDLE: HRESULT = HRESULT(0x8007_0006_u32 as i32);
+/// `HRESULT_FROM_WIN32(ERROR_BROKEN_PIPE)`
+const E_BROKEN_PIPE: HRESULT = HRESULT(0x8007_006D_u32 as i32);
+/// `HRESULT_FROM_WIN32(ERROR_PIPE_NOT_CONNECTED)`
+const E_PIPE_NOT_CONNECTED: HRESULT = HRESULT(0x8007_00E9_u32 as i32);
+/// `HRESULT_FROM_NT(STATUS_PORT_DISCONNECTED)`: the minifilter closed the port (e.g. unloaded).
+const E_PORT_DISCONNECTED: HRESULT = HRESULT(0xD000_0037_u32 as i32);
+
+/// Error returned by [`Driver`](super::Driver) and the [`DriverTransport`](super::transport::DriverTransport)s.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DriverError {
+    /// The communication port could not be opened: the minifilter is not started, or another
+    /// app is already connected (it accepts only one connection at a time).
+    Connect(HRESULT),
+    /// A message could not be delivered to the minifilter.
+    Send(HRESULT),
+    /// The path has more than [`MAX_PATH_LEN`] UTF-16 code units.
+    PathTooLong { len: usize },
+    /// The path contains a NUL at `position`, the minifilter would cut it there.
+    PathWithNul { position: usize },
+    /// The path cannot be converted to a device path, see
+    /// [`ScanScope::normalize`](super::scan_scope::ScanScope::normalize).
+    InvalidScanDirectory { path: String },
+    /// The path cannot be converted to the device path of a file, see
+    /// [`ScanScope::normalize_file`](super::scan_scope::ScanScope::normalize_file).
+    InvalidFilePath { path: String },
+    /// The port has been closed, by this app or by the minifilter.
+    Disconnected,
+    /// The minifilter replied with a buffer that cannot be decoded.
+    MalformedReply(DecodeError),
+    /// The minifilter sent a [`VerdictRequest`](super::verdict::VerdictRequest) that cannot be
+    /// decoded. It has been answered with the fail policy.
+    MalformedRequest(DecodeError),
+    /// The minifilter does not speak the protocol of this crate. `found` is `None` if it does not
+    /// even know [`GetVersion`](super::DriverComMessageType::GetVersion) (built before it).
+    ProtocolMismatch {
+        expected: DriverVersion,
+        found: Option<DriverVersion>,
+    },
+}
+
+impl DriverError {
+    /// Classifies the failure code of a message sent to the minifilter.
+    pub fn from_send(code: HRESULT) -> DriverError {
+        match code {
+            E_HANDLE | E_BROKEN_PIPE | E_PIPE_NOT_CONNECTED | E_PORT_DISCONNECTED => {
+                DriverError::Disconnected
+            }
+            _ => DriverError::Send(code),
+        }
+    }
+
+    /// Whether the connection has to be opened again before any other call can succeed.
+    pub fn is_disconnected(&self) -> bool {
+        matches!(self, DriverError::Disconnected)
+    }
+}
+
+impl fmt::Display for DriverError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DriverError::Connect(code) => write!(
+                f,
+                "cannot open driver communication (is the mini-filter started?): {:#010X}",
+                code.0
+            ),
+            DriverError::Send(code) => {
+                write!(f, "cannot send message to the driver: {:#010X}", code.0)
+            }
+            DriverError::PathTooLong { len } => write!(
+                f,
+                "path of {len} UTF-16 code units is longer than {MAX_PATH_LEN}"
+            ),
+            DriverError::PathWithNul { position } => {
+                write!(f, "path contains a NUL at position {position}")
+            }
+            DriverError::InvalidScanDirectory { path } => {
+                write!(f, "{path} is not an absolute path on a local volume")
+            }
+            DriverError::InvalidFilePath { path } => {
+                write!(
+                    f,
+                    "{path} is not the absolute path of a file on a local volume"
+                )
+            }
+            DriverError::Disconnected => write!(f, "driver communication port is disconnected"),
+            DriverError::MalformedReply(e) => write!(f, "malformed driver reply: {e}"),
+            DriverError::MalformedRequest(e) => write!(f, "malformed verdict request: {e}"),
+            DriverError::ProtocolMismatch {
+                expected,
+                found: Some(found),
+            } => write!(f, "incompatible driver: expected {expected}, found {found}"),
+            DriverError::ProtocolMismatch {
+                expected,
+                found: None,
+            } => write!(
+                f,
+                "incompatible driver: expected {expected}, found a driver without version"
+            ),
+        }
+    }
+}
+
+impl Error for DriverError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DriverError::MalformedReply(e) | DriverError::MalformedRequest(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<DecodeError> for DriverError {
+    fn from(e: DecodeError) -> Self {
+        DriverError::MalformedReply(e)
+    }
+}
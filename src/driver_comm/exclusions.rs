@@ -0,0 +1,100 @@
+//! Processes trusted by this app, whose operations the minifilter does not record at all.
+//!
+//! Backup agents, antiviruses or compilers can fill the queue of the minifilter with harmless
+//! operations. Once excluded with [`Driver::exclude_pid`](super::Driver::exclude_pid),
+//! [`exclude_gid`](super::Driver::exclude_gid) or
+//! [`exclude_image_path`](super::Driver::exclude_image_path), their operations are dropped in the
+//! kernel. The [`ExclusionSet`] keeps track of what has been sent, to send it again when the
+//! minifilter has been restarted.
+//!
+//! The minifilter only takes exclusions from a process running as an administrator or SYSTEM,
+//! others get a [`DriverError::Send`](super::error::DriverError::Send): an excluded process is
+//! invisible to this app.
+
+use std::collections::BTreeSet;
+
+/// The exclusions, as sent to the minifilter.
+///
+/// Image paths are stored in the driver form (`\Device\HarddiskVolume3\...\app.exe`, see
+/// [`ScanScope::normalize_file`](super::scan_scope::ScanScope::normalize_file)), lowercased since
+/// the minifilter compares them case-insensitively.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExclusionSet {
+    pids: BTreeSet<u32>,
+    gids: BTreeSet<u64>,
+    images: BTreeSet<String>,
+}
+
+impl ExclusionSet {
+    pub fn new() -> ExclusionSet {
+        ExclusionSet::default()
+    }
+
+    pub fn pids(&self) -> impl Iterator<Item = u32> + '_ {
+        self.pids.iter().copied()
+    }
+
+    pub fn gids(&self) -> impl Iterator<Item = u64> + '_ {
+        self.gids.iter().copied()
+    }
+
+    /// The excluded images, in the driver form.
+    pub fn images(&self) -> impl Iterator<Item = &str> {
+        self.images.iter().map(String::as_str)
+    }
+
+    pub fn contains_pid(&self, pid: u32) -> bool {
+        self.pids.contains(&pid)
+    }
+
+    pub fn contains_gid(&self, gid: u64) -> bool {
+        self.gids.contains(&gid)
+    }
+
+    /// Whether `image`, in the driver form, is excluded.
+    pub fn contains_image(&self, image: &str) -> bool {
+        self.images.contains(&image.to_lowercase())
+    }
+
+    /// Whether the minifilter drops the operations of process `pid`, in the family `gid` and
+    /// started from `image` (driver form) if known.
+    pub fn excludes(&self, pid: u32, gid: u64, image: Option<&str>) -> bool {
+        self.contains_pid(pid)
+            || self.contains_gid(gid)
+            || image.is_some_and(|image| self.contains_image(image))
+    }
+
+    pub fn len(&self) -> usize {
+        self.pids.len() + self.gids.len() + self.images.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub(crate) fn insert_pid(&mut self, pid: u32) -> bool {
+        self.pids.insert(pid)
+    }
+
+    pub(crate) fn remove_pid(&mut self, pid: u32) -> bool {
+        self.pids.remove(&pid)
+    }
+
+    pub(crate) fn insert_gid(&mut self, gid: u64) -> bool {
+        self.gids.insert(gid)
+    }
+
+    pub(crate) fn remove_gid(&mut self, gid: u64) -> bool {
+        self.gids.remove(&gid)
+    }
+
+    /// Records `image`, already normalized. Returns false if it was already there.
+    pub(crate) fn insert_image(&mut self, image: &str) -> bool {
+        self.images.insert(image.to_lowercase())
+    }
+
+    /// Forgets `image`, already normalized. Returns false if it was not there.
+    pub(crate) fn remove_image(&mut self, image: &str) -> bool {
+        self.images.remove(&image.to_lowercase())
+    }
+}
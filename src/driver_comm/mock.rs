@@ -0,0 +1,1480 @@
+//! An in-memory minifilter, to exercise [`Driver`](super::Driver) without Windows nor FSFilter.
+//!
+//! [`MockDriver`] is a [`DriverTransport`]: events are scripted with [`MockDriver::push_event`] and
+//! returned on [`GetOps`](DriverComMessageType::GetOps) in the very same layout as the minifilter
+//! (`RWD_REPLY_IRPS` header followed by `DRIVER_MESSAGE`s, each one followed by its
+//! `UNICODE_STRING` buffer). Every [`DriverComMessage`] received is recorded.
+//!
+//! It is also a [`VerdictTransport`]: writes and file changes in its verdict directories are held
+//! as [`VerdictRequest`]s until a [`VerdictServer`](super::verdict::VerdictServer) answers them,
+//! or until [`expire_verdicts`](MockDriver::expire_verdicts) applies the fail policy.
+//!
+//! ```
+//! use minifilter_rs::driver_comm::mock::{MockDriver, MockEvent};
+//! use minifilter_rs::driver_comm::{Driver, IrpMajorOp};
+//! use minifilter_rs::shared_def::{CDriverMsgs, IOMessage};
+//!
+//! let mock = MockDriver::new();
+//! mock.push_event(MockEvent::new(1234, 7, IrpMajorOp::IrpWrite, r"C:\Users\Dev\notes.txt"));
+//!
+//! let driver = Driver::with_transport(mock.clone());
+//! let mut vecnew: Vec<u8> = Vec::with_capacity(65536);
+//! driver.get_irp(&mut vecnew).unwrap();
+//! let iomsgs: Vec<IOMessage> = CDriverMsgs::new(&vecnew)
+//!     .unwrap()
+//!     .map(|drivermsg| IOMessage::from(&drivermsg))
+//!     .collect();
+//!
+//! assert_eq!(iomsgs[0].filepathstr, r"C:\Users\Dev\notes.txt");
+//! assert_eq!(mock.received().len(), 1);
+//! ```
+
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::mem;
+use std::ptr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::SystemTime;
+
+use windows::core::HRESULT;
+use windows::Win32::Storage::FileSystem::{FILE_ID_128, FILE_ID_INFO};
+
+use crate::driver_comm::error::DriverError;
+use crate::driver_comm::exclusions::ExclusionSet;
+use crate::driver_comm::report::{
+    GidReport, PidOutcome, GID_REPORT_SIZE, MAX_GID_REPORT_PIDS, STATUS_NO_SUCH_GROUP,
+};
+use crate::driver_comm::stats::{DriverStats, DRIVER_STATS_SIZE, IRP_MAJOR_OP_COUNT};
+use crate::driver_comm::transport::DriverTransport;
+use crate::driver_comm::tuning::{FilterConfig, FILTER_CONFIG_SIZE, MAX_OPS_SAVE_LIMIT};
+use crate::driver_comm::verdict::{Verdict, VerdictRequest, VerdictTransport, MAX_VERDICT_TIMEOUT};
+use crate::driver_comm::version::DriverVersion;
+use crate::driver_comm::{
+    DriverComMessage, DriverComMessageType, IrpMajorOp, MIN_COMM_BUFFER_SIZE,
+};
+use crate::process::tree::{to_system_time, ProcessTree, GID_TREE_SIZE};
+use crate::shared_def::decoder::MAX_FILE_NAME_SIZE;
+use crate::shared_def::{
+    CDriverMsg, FileChangeInfo, ProcessEvent, ProcessEventKind, ReplyIrp, UnicodeString,
+    IRP_PROCESS_CREATE, IRP_PROCESS_EXIT,
+};
+
+/// `HRESULT_FROM_WIN32(ERROR_FILE_NOT_FOUND)`: what `FilterConnectCommunicationPort` returns
+/// while the port does not exist.
+const E_FILE_NOT_FOUND: HRESULT = HRESULT(0x8007_0002_u32 as i32);
+
+/// `HRESULT_FROM_NT(STATUS_INTERNAL_ERROR)`: what the minifilter returns on unknown messages.
+const E_INTERNAL_ERROR: HRESULT = HRESULT(0xD000_00E5_u32 as i32);
+
+/// `HRESULT_FROM_NT(STATUS_INVALID_PARAMETER)`: what the minifilter returns on a reply buffer
+/// below [`MIN_COMM_BUFFER_SIZE`], or on a tuning value out of range.
+pub(crate) const E_INVALID_PARAMETER: HRESULT = HRESULT(0xD000_000D_u32 as i32);
+
+/// A file-system event, or a process creation or exit, as it would be recorded by the minifilter.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MockEvent {
+    pub extension: [u16; 12],
+    pub file_id_vsn: u64,
+    pub file_id_id: [u8; 16],
+    pub mem_sized_used: u64,
+    pub entropy: f64,
+    pub pid: u32,
+    pub irp_op: u8,
+    pub is_entropy_calc: u8,
+    pub file_change: u8,
+    pub file_location_info: u8,
+    pub filepath: String,
+    pub gid: u64,
+    /// System time in 100ns units, set when pushed if 0
+    pub time: i64,
+    /// Set when queued, as the minifilter does
+    pub sequence: u64,
+}
+
+impl MockEvent {
+    pub fn new(pid: u32, gid: u64, irp_op: IrpMajorOp, filepath: &str) -> MockEvent {
+        MockEvent {
+            pid,
+            gid,
+            irp_op: irp_op as u8,
+            filepath: filepath.to_string(),
+            ..MockEvent::default()
+        }
+    }
+
+    /// Sets the extension, truncated to the 11 chars kept by the minifilter.
+    pub fn extension(mut self, extension: &str) -> MockEvent {
+        self.extension = [0; 12];
+        for (i, c) in extension.encode_utf16().take(11).enumerate() {
+            self.extension[i] = c;
+        }
+        self
+    }
+
+    pub fn file_id(mut self, volume_serial: u64, file_id: [u8; 16]) -> MockEvent {
+        self.file_id_vsn = volume_serial;
+        self.file_id_id = file_id;
+        self
+    }
+
+    pub fn file_change(mut self, file_change: FileChangeInfo) -> MockEvent {
+        self.file_change = file_change as u8;
+        self
+    }
+
+    /// When the minifilter caught the event.
+    pub fn time(mut self, time: SystemTime) -> MockEvent {
+        self.time = to_system_time(time);
+        self
+    }
+
+    /// Bytes read or written, with the entropy calculated by the minifilter.
+    pub fn transferred(mut self, bytes: u64, entropy: f64) -> MockEvent {
+        self.mem_sized_used = bytes;
+        self.entropy = entropy;
+        self.is_entropy_calc = 1;
+        self
+    }
+
+    /// The creation or exit of a process, queued as the minifilter does: the `FileID` holds its
+    /// time, parent pid and the number of processes still alive in its gid.
+    pub fn process(event: &ProcessEvent) -> MockEvent {
+        let mut info = [0u8; 16];
+        info[..4].copy_from_slice(&event.parent_pid.to_ne_bytes());
+        info[4..8].copy_from_slice(&event.alive_pids.to_ne_bytes());
+        MockEvent {
+            file_id_vsn: to_system_time(event.time) as u64,
+            file_id_id: info,
+            pid: event.pid,
+            irp_op: match event.kind {
+                ProcessEventKind::Created => IRP_PROCESS_CREATE,
+                ProcessEventKind::Exited => IRP_PROCESS_EXIT,
+            },
+            filepath: event.image_path.clone(),
+            gid: event.gid,
+            time: to_system_time(event.time),
+            ..MockEvent::default()
+        }
+    }
+
+    fn filepath_u16(&self) -> Vec<u16> {
+        let mut filepath: Vec<u16> = self.filepath.encode_utf16().collect();
+        filepath.truncate(MAX_FILE_NAME_SIZE / 2);
+        filepath
+    }
+
+    fn to_drivermsg(&self) -> CDriverMsg {
+        CDriverMsg {
+            extension: self.extension,
+            file_id: FILE_ID_INFO {
+                VolumeSerialNumber: self.file_id_vsn,
+                FileId: FILE_ID_128 {
+                    Identifier: self.file_id_id,
+                },
+            },
+            mem_sized_used: self.mem_sized_used,
+            entropy: self.entropy,
+            pid: self.pid,
+            irp_op: self.irp_op,
+            is_entropy_calc: self.is_entropy_calc,
+            file_change: self.file_change,
+            file_location_info: self.file_location_info,
+            filepath: UnicodeString {
+                length: 0,
+                maximum_length: 0,
+                buffer: ptr::null(),
+            },
+            gid: self.gid,
+            time: self.time,
+            sequence: self.sequence,
+            next: ptr::null(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct MockState {
+    events: VecDeque<MockEvent>,
+    received: Vec<DriverComMessage>,
+    scan_directories: Vec<String>,
+    closed: bool,
+    connections: usize,
+    refused_connections: usize,
+    version: Option<DriverVersion>,
+    gids: HashMap<u64, Vec<(u32, String)>>,
+    trees: HashMap<u64, ProcessTree>,
+    pid_statuses: HashMap<u32, HRESULT>,
+    suspended: BTreeSet<u32>,
+    killed: BTreeSet<u32>,
+    filter_config: FilterConfig,
+    exclusions: ExclusionSet,
+    image_paths: HashMap<u32, String>,
+    stats: DriverStats,
+    verdict_directories: Vec<String>,
+    /// `VERDICT_REQUEST`s waiting for a verdict by message id, with the event let through if
+    /// allowed.
+    verdict_requests: VecDeque<(u64, Vec<u8>, MockEvent)>,
+    verdicts: Vec<(VerdictRequest, Verdict)>,
+    next_message_id: u64,
+    verdict_closed: bool,
+    next_sequence: u64,
+}
+
+/// A scripted minifilter. Clones share the same state, so a test can keep one to script events
+/// and inspect the messages while a [`Driver`](super::Driver) owns another.
+#[derive(Debug, Clone)]
+pub struct MockDriver {
+    state: Arc<Mutex<MockState>>,
+}
+
+impl Default for MockDriver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockDriver {
+    pub fn new() -> MockDriver {
+        MockDriver {
+            state: Arc::new(Mutex::new(MockState {
+                events: VecDeque::new(),
+                received: Vec::new(),
+                scan_directories: Vec::new(),
+                closed: false,
+                connections: 1,
+                refused_connections: 0,
+                version: Some(DriverVersion::current()),
+                gids: HashMap::new(),
+                trees: HashMap::new(),
+                pid_statuses: HashMap::new(),
+                suspended: BTreeSet::new(),
+                killed: BTreeSet::new(),
+                filter_config: FilterConfig::default(),
+                exclusions: ExclusionSet::new(),
+                image_paths: HashMap::new(),
+                stats: DriverStats {
+                    attached_instances: 1,
+                    ..DriverStats::default()
+                },
+                verdict_directories: Vec::new(),
+                verdict_requests: VecDeque::new(),
+                verdicts: Vec::new(),
+                next_message_id: 1,
+                verdict_closed: false,
+                next_sequence: 0,
+            })),
+        }
+    }
+
+    /// Queues an event, returned by the next [`GetOps`](DriverComMessageType::GetOps). As with the
+    /// minifilter, the event is ignored if its process is excluded or while the collection is
+    /// paused, loses its entropy if it is not sampled (see [`FilterConfig`]), and is dropped if the
+    /// queue is full. It is counted in the [`DriverStats`] likewise.
+    ///
+    /// Writes and file changes whose path (in the device form) is in a verdict directory are held
+    /// until their [`Verdict`], and only queued if allowed.
+    pub fn push_event(&self, event: MockEvent) {
+        let mut state = self.state();
+        let config = state.filter_config;
+        let image = state.image_paths.get(&event.pid).map(String::as_str);
+        if state.exclusions.excludes(event.pid, event.gid, image) || config.collection_paused {
+            return;
+        }
+        let mut event = event;
+        if event.time == 0 {
+            event.time = to_system_time(SystemTime::now());
+        }
+        if !config.entropy_enabled || event.mem_sized_used < config.entropy_min_size as u64 {
+            event.entropy = 0.0;
+            event.is_entropy_calc = 0;
+        }
+        if event.is_entropy_calc != 0 {
+            state.stats.entropy_calcs += 1;
+        }
+        let irp_op = IrpMajorOp::try_from(event.irp_op).unwrap_or(IrpMajorOp::IrpNone);
+        if matches!(irp_op, IrpMajorOp::IrpWrite | IrpMajorOp::IrpSetInfo)
+            && state
+                .verdict_directories
+                .iter()
+                .any(|dir| event.filepath.starts_with(dir.as_str()))
+        {
+            let message_id = state.next_message_id;
+            let mut request = VerdictRequest {
+                message_id,
+                pid: event.pid,
+                gid: event.gid,
+                irp_op,
+                file_change: FileChangeInfo::FileChangeNotSet,
+                size: event.mem_sized_used,
+                entropy: (event.is_entropy_calc != 0).then_some(event.entropy),
+                path: event.filepath.clone(),
+                new_path: None,
+            }
+            .to_bytes();
+            // Copied as is, like the minifilter does
+            request[5] = event.file_change;
+            state.next_message_id += 1;
+            state
+                .verdict_requests
+                .push_back((message_id, request, event));
+            return;
+        }
+        Self::queue_event(&mut state, event);
+    }
+
+    /// Same as `DriverData::AddIrpMessage`.
+    fn queue_event(state: &mut MockState, mut event: MockEvent) {
+        event.sequence = state.next_sequence;
+        state.next_sequence += 1;
+        if state.events.len() >= state.filter_config.max_ops as usize {
+            state.stats.ops_dropped += 1;
+            return;
+        }
+        state.stats.ops_queued += 1;
+        if (event.irp_op as usize) < IRP_MAJOR_OP_COUNT {
+            state.stats.ops_by_irp[event.irp_op as usize] += 1;
+        }
+        state.events.push_back(event);
+    }
+
+    pub fn push_events<I: IntoIterator<Item = MockEvent>>(&self, events: I) {
+        for event in events {
+            self.push_event(event);
+        }
+    }
+
+    /// The current settings of the minifilter.
+    pub fn filter_config(&self) -> FilterConfig {
+        self.state().filter_config
+    }
+
+    /// Number of events not yet fetched.
+    pub fn pending_events(&self) -> usize {
+        self.state().events.len()
+    }
+
+    /// All messages received so far, oldest first.
+    pub fn received(&self) -> Vec<DriverComMessage> {
+        self.state().received.clone()
+    }
+
+    /// Version returned on [`GetVersion`](DriverComMessageType::GetVersion), `None` to reject it
+    /// like a minifilter built before it. Defaults to [`DriverVersion::current`].
+    pub fn set_version(&self, version: Option<DriverVersion>) {
+        self.state().version = version;
+    }
+
+    /// Declares the pids of the family `gid` with their image names, for
+    /// [`KillGid`](DriverComMessageType::KillGid), [`SuspendGid`](DriverComMessageType::SuspendGid)
+    /// and [`ResumeGid`](DriverComMessageType::ResumeGid).
+    pub fn set_gid(&self, gid: u64, pids: &[(u32, &str)]) {
+        let pids = pids
+            .iter()
+            .map(|(pid, image_name)| (*pid, image_name.to_string()))
+            .collect();
+        self.state().gids.insert(gid, pids);
+    }
+
+    /// Declares the processes of the family `tree.gid`, for
+    /// [`GetGidTree`](DriverComMessageType::GetGidTree).
+    pub fn set_gid_tree(&self, tree: ProcessTree) {
+        self.state().trees.insert(tree.gid, tree);
+    }
+
+    /// Declares the image file of `pid`, in the device form, for
+    /// [`ExcludeImage`](DriverComMessageType::ExcludeImage).
+    pub fn set_image_path(&self, pid: u32, image_path: &str) {
+        self.state().image_paths.insert(pid, image_path.to_string());
+    }
+
+    /// The exclusions held by the minifilter.
+    pub fn exclusions(&self) -> ExclusionSet {
+        self.state().exclusions.clone()
+    }
+
+    /// Status of any operation on `pid`, e.g. `STATUS_INVALID_PARAMETER` as if it had exited.
+    /// Defaults to `S_OK`.
+    pub fn set_pid_status(&self, pid: u32, status: HRESULT) {
+        self.state().pid_statuses.insert(pid, status);
+    }
+
+    /// The pids currently suspended.
+    pub fn suspended(&self) -> Vec<u32> {
+        self.state().suspended.iter().copied().collect()
+    }
+
+    /// The pids killed so far.
+    pub fn killed(&self) -> Vec<u32> {
+        self.state().killed.iter().copied().collect()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.state().closed
+    }
+
+    /// Number of times the port has been opened.
+    pub fn connections(&self) -> usize {
+        self.state().connections
+    }
+
+    /// The scan directories held by the minifilter, oldest first.
+    pub fn scan_directories(&self) -> Vec<String> {
+        self.state().scan_directories.clone()
+    }
+
+    /// The verdict directories held by the minifilter, oldest first.
+    pub fn verdict_directories(&self) -> Vec<String> {
+        self.state().verdict_directories.clone()
+    }
+
+    /// The requests waiting for a verdict, oldest first. Malformed ones are left out.
+    pub fn pending_verdicts(&self) -> Vec<VerdictRequest> {
+        self.state()
+            .verdict_requests
+            .iter()
+            .filter_map(|(message_id, request, _)| {
+                VerdictRequest::from_bytes(*message_id, request).ok()
+            })
+            .collect()
+    }
+
+    /// The verdicts given in time so far, oldest first.
+    pub fn verdicts(&self) -> Vec<(VerdictRequest, Verdict)> {
+        self.state().verdicts.clone()
+    }
+
+    /// Emulates the timeout of all the pending requests: their operations are allowed, or denied
+    /// if the [`FilterConfig`] fails closed. Returns the number of requests expired.
+    pub fn expire_verdicts(&self) -> usize {
+        let mut state = self.state();
+        let requests = mem::take(&mut state.verdict_requests);
+        let expired = requests.len();
+        if !state.filter_config.verdict_fail_closed {
+            for (_, _, event) in requests {
+                Self::queue_event(&mut state, event);
+            }
+        }
+        expired
+    }
+
+    /// The next `count` reconnections fail with [`DriverError::Connect`], as if the minifilter was
+    /// still stopped.
+    pub fn refuse_connections(&self, count: usize) {
+        self.state().refused_connections = count;
+    }
+
+    /// Emulates a restart of the minifilter: the ports are disconnected, the scan and verdict
+    /// directories, exclusions, gid trees and pending events are lost, and the [`FilterConfig`] and
+    /// [`DriverStats`] are back to their defaults. The pending verdict requests are expired first.
+    pub fn unload(&self) {
+        self.expire_verdicts();
+        let mut state = self.state();
+        state.closed = true;
+        state.verdict_closed = true;
+        state.scan_directories.clear();
+        state.verdict_directories.clear();
+        state.trees.clear();
+        state.events.clear();
+        state.next_sequence = 0;
+        state.filter_config = FilterConfig::default();
+        state.exclusions = ExclusionSet::new();
+        state.stats = DriverStats {
+            attached_instances: state.stats.attached_instances,
+            ..DriverStats::default()
+        };
+    }
+
+    /// Same as the tuning messages of `RWFNewMessage`.
+    fn tune(
+        config: &mut FilterConfig,
+        commsgtype: DriverComMessageType,
+        value: u64,
+    ) -> Result<(), DriverError> {
+        let invalid = DriverError::Send(E_INVALID_PARAMETER);
+        match commsgtype {
+            DriverComMessageType::SetEntropy => config.entropy_enabled = value != 0,
+            DriverComMessageType::SetEntropyMinSize => {
+                config.entropy_min_size = u32::try_from(value).map_err(|_| invalid)?
+            }
+            DriverComMessageType::SetMaxOps => {
+                if value == 0 || value > MAX_OPS_SAVE_LIMIT as u64 {
+                    return Err(invalid);
+                }
+                config.max_ops = value as u32;
+            }
+            DriverComMessageType::PauseCollection => config.collection_paused = true,
+            DriverComMessageType::ResumeCollection => config.collection_paused = false,
+            DriverComMessageType::SetVerdictTimeout => {
+                if value == 0 || value > MAX_VERDICT_TIMEOUT.as_millis() as u64 {
+                    return Err(invalid);
+                }
+                config.verdict_timeout_ms = value as u32;
+            }
+            DriverComMessageType::SetVerdictFailClosed => config.verdict_fail_closed = value != 0,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn state(&self) -> MutexGuard<'_, MockState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The NUL terminated path of `msg`.
+    fn path_of(msg: &DriverComMessage) -> String {
+        let end = msg
+            .path
+            .iter()
+            .position(|c| *c == 0)
+            .unwrap_or(msg.path.len());
+        String::from_utf16_lossy(&msg.path[..end])
+    }
+
+    /// Same as `RWFApplyGid`: applies the command to each pid of `gid`, only the report is capped
+    /// to [`MAX_GID_REPORT_PIDS`].
+    fn apply_gid_command(
+        state: &mut MockState,
+        gid: u64,
+        commsgtype: DriverComMessageType,
+    ) -> GidReport {
+        let Some(pids) = state.gids.get(&gid).cloned() else {
+            return GidReport {
+                gid,
+                status: STATUS_NO_SUCH_GROUP,
+                num_pids: 0,
+                pids: vec![],
+            };
+        };
+        let mut outcomes: Vec<PidOutcome> = pids
+            .iter()
+            .map(|(pid, image_name)| {
+                let status = state.pid_statuses.get(pid).copied().unwrap_or(HRESULT(0));
+                if status.0 >= 0 {
+                    match commsgtype {
+                        DriverComMessageType::KillGid => {
+                            state.suspended.remove(pid);
+                            state.killed.insert(*pid);
+                        }
+                        DriverComMessageType::SuspendGid => {
+                            state.suspended.insert(*pid);
+                        }
+                        _ => {
+                            state.suspended.remove(pid);
+                        }
+                    }
+                }
+                PidOutcome {
+                    pid: *pid,
+                    image_name: image_name.clone(),
+                    status,
+                }
+            })
+            .collect();
+        outcomes.truncate(MAX_GID_REPORT_PIDS);
+        GidReport {
+            gid,
+            status: HRESULT(0),
+            num_pids: pids.len() as u32,
+            pids: outcomes,
+        }
+    }
+
+    /// Same as `DriverData::DriverGetIrps`: writes as many events as possible after the
+    /// `RWD_REPLY_IRPS` header, with the file path right after each `DRIVER_MESSAGE`.
+    fn write_reply_irps(events: &mut VecDeque<MockEvent>, buf: &mut [u8]) -> u32 {
+        let header_size = mem::size_of::<ReplyIrp>();
+        let drivermsg_size = mem::size_of::<CDriverMsg>();
+
+        let base = buf.as_mut_ptr();
+        let mut offset = header_size;
+        let mut remaining = buf.len() - header_size;
+        let mut num_ops = 0u64;
+        let mut prev_offset: Option<usize> = None;
+
+        while let Some(event) = events.front() {
+            let filepath = event.filepath_u16();
+            let path_size = filepath.len() * 2;
+            if drivermsg_size + path_size >= remaining {
+                break;
+            }
+            let event = events.pop_front().unwrap();
+
+            let mut drivermsg = event.to_drivermsg();
+            if path_size > 0 {
+                drivermsg.filepath = UnicodeString {
+                    length: path_size as u16,
+                    maximum_length: path_size as u16,
+                    buffer: unsafe { base.add(offset + drivermsg_size) } as *const u16,
+                };
+            }
+            unsafe {
+                if let Some(prev) = prev_offset {
+                    let prev_msg = base.add(prev) as *mut CDriverMsg;
+                    let mut prev_drivermsg = ptr::read_unaligned(prev_msg);
+                    prev_drivermsg.next = base.add(offset) as *const CDriverMsg;
+                    ptr::write_unaligned(prev_msg, prev_drivermsg);
+                }
+                ptr::write_unaligned(base.add(offset) as *mut CDriverMsg, drivermsg);
+            }
+            for (i, c) in filepath.iter().enumerate() {
+                let at = offset + drivermsg_size + i * 2;
+                buf[at..at + 2].copy_from_slice(&c.to_ne_bytes());
+            }
+
+            prev_offset = Some(offset);
+            offset += drivermsg_size + path_size;
+            remaining -= drivermsg_size + path_size;
+            num_ops += 1;
+        }
+
+        let header = ReplyIrp {
+            data_size: offset as u64,
+            data: if num_ops > 0 {
+                unsafe { base.add(header_size) as *const CDriverMsg }
+            } else {
+                ptr::null()
+            },
+            num_ops,
+        };
+        unsafe {
+            ptr::write_unaligned(base as *mut ReplyIrp, header);
+        }
+        offset as u32
+    }
+}
+
+impl DriverTransport for MockDriver {
+    fn send_message(
+        &self,
+        msg: &DriverComMessage,
+        reply: Option<&mut [u8]>,
+    ) -> Result<u32, DriverError> {
+        let mut state = self.state();
+        if state.closed {
+            return Err(DriverError::Disconnected);
+        }
+        state.received.push(msg.clone());
+
+        match (num::FromPrimitive::from_u32(msg.r#type), reply) {
+            (Some(DriverComMessageType::GetOps), Some(buf)) => {
+                if buf.len() < MIN_COMM_BUFFER_SIZE {
+                    return Err(DriverError::Send(E_INVALID_PARAMETER));
+                }
+                Ok(Self::write_reply_irps(&mut state.events, buf))
+            }
+            (Some(DriverComMessageType::GetVersion), Some(buf)) if buf.len() >= 16 => {
+                let version = state.version.ok_or(DriverError::Send(E_INTERNAL_ERROR))?;
+                buf[..16].copy_from_slice(&version.to_bytes());
+                Ok(16)
+            }
+            (
+                Some(
+                    commsgtype @ (DriverComMessageType::KillGid
+                    | DriverComMessageType::SuspendGid
+                    | DriverComMessageType::ResumeGid),
+                ),
+                Some(buf),
+            ) if buf.len() >= GID_REPORT_SIZE => {
+                let report = Self::apply_gid_command(&mut state, msg.gid, commsgtype);
+                buf[..GID_REPORT_SIZE].copy_from_slice(&report.to_bytes());
+                Ok(GID_REPORT_SIZE as u32)
+            }
+            (
+                Some(
+                    commsgtype @ (DriverComMessageType::SetEntropy
+                    | DriverComMessageType::SetEntropyMinSize
+                    | DriverComMessageType::SetMaxOps
+                    | DriverComMessageType::PauseCollection
+                    | DriverComMessageType::ResumeCollection
+                    | DriverComMessageType::GetConfig
+                    | DriverComMessageType::SetVerdictTimeout
+                    | DriverComMessageType::SetVerdictFailClosed),
+                ),
+                Some(buf),
+            ) if buf.len() >= FILTER_CONFIG_SIZE => {
+                Self::tune(&mut state.filter_config, commsgtype, msg.gid)?;
+                buf[..FILTER_CONFIG_SIZE].copy_from_slice(&state.filter_config.to_bytes());
+                Ok(FILTER_CONFIG_SIZE as u32)
+            }
+            (
+                Some(
+                    commsgtype @ (DriverComMessageType::ExcludePid
+                    | DriverComMessageType::IncludePid
+                    | DriverComMessageType::ExcludeGid
+                    | DriverComMessageType::IncludeGid
+                    | DriverComMessageType::ExcludeImage
+                    | DriverComMessageType::IncludeImage),
+                ),
+                Some(buf),
+            ) if !buf.is_empty() => {
+                let exclusions = &mut state.exclusions;
+                buf[0] = match commsgtype {
+                    DriverComMessageType::ExcludePid => exclusions.insert_pid(msg.pid),
+                    DriverComMessageType::IncludePid => exclusions.remove_pid(msg.pid),
+                    DriverComMessageType::ExcludeGid => exclusions.insert_gid(msg.gid),
+                    DriverComMessageType::IncludeGid => exclusions.remove_gid(msg.gid),
+                    DriverComMessageType::ExcludeImage => {
+                        exclusions.insert_image(&Self::path_of(msg))
+                    }
+                    _ => exclusions.remove_image(&Self::path_of(msg)),
+                } as u8;
+                Ok(1)
+            }
+            (Some(DriverComMessageType::GetGidTree), Some(buf)) if buf.len() >= GID_TREE_SIZE => {
+                match state.trees.get(&msg.gid) {
+                    Some(tree) => buf[..GID_TREE_SIZE].copy_from_slice(&tree.to_bytes()),
+                    None => {
+                        buf[..GID_TREE_SIZE].fill(0);
+                        buf[..4].copy_from_slice(&STATUS_NO_SUCH_GROUP.0.to_ne_bytes());
+                    }
+                }
+                Ok(GID_TREE_SIZE as u32)
+            }
+            (Some(DriverComMessageType::GetStats), Some(buf)) if buf.len() >= DRIVER_STATS_SIZE => {
+                let stats = DriverStats {
+                    queue_depth: state.events.len() as u32,
+                    gids: state.gids.len() as u64,
+                    ..state.stats
+                };
+                buf[..DRIVER_STATS_SIZE].copy_from_slice(&stats.to_bytes());
+                Ok(DRIVER_STATS_SIZE as u32)
+            }
+            (Some(DriverComMessageType::AddScanDirectory), Some(buf)) if !buf.is_empty() => {
+                let path = Self::path_of(msg);
+                let added = !state.scan_directories.contains(&path);
+                if added {
+                    state.scan_directories.push(path);
+                }
+                buf[0] = added as u8;
+                Ok(1)
+            }
+            (Some(DriverComMessageType::RemScanDirectory), Some(buf)) if !buf.is_empty() => {
+                let path = Self::path_of(msg);
+                let len = state.scan_directories.len();
+                state.scan_directories.retain(|dir| *dir != path);
+                buf[0] = (state.scan_directories.len() != len) as u8;
+                Ok(1)
+            }
+            (Some(DriverComMessageType::AddVerdictDirectory), Some(buf)) if !buf.is_empty() => {
+                let path = Self::path_of(msg);
+                let added = !state.verdict_directories.contains(&path);
+                if added {
+                    state.verdict_directories.push(path);
+                }
+                buf[0] = added as u8;
+                Ok(1)
+            }
+            (Some(DriverComMessageType::RemVerdictDirectory), Some(buf)) if !buf.is_empty() => {
+                let path = Self::path_of(msg);
+                let len = state.verdict_directories.len();
+                state.verdict_directories.retain(|dir| *dir != path);
+                buf[0] = (state.verdict_directories.len() != len) as u8;
+                Ok(1)
+            }
+            _ => Ok(0),
+        }
+    }
+
+    fn close(&self) -> bool {
+        let mut state = self.state();
+        let was_open = !state.closed;
+        state.closed = true;
+        was_open
+    }
+
+    fn reconnect(&self) -> Result<(), DriverError> {
+        let mut state = self.state();
+        if state.refused_connections > 0 {
+            state.refused_connections -= 1;
+            state.closed = true;
+            return Err(DriverError::Connect(E_FILE_NOT_FOUND));
+        }
+        state.closed = false;
+        state.verdict_closed = false;
+        state.connections += 1;
+        Ok(())
+    }
+}
+
+/// Requests are handed oldest first. Without any left, the port is reported as disconnected, so
+/// that [`serve`](super::verdict::VerdictServer::serve) returns once they are all answered.
+impl VerdictTransport for MockDriver {
+    fn get_request(&self) -> Result<VerdictRequest, DriverError> {
+        let mut state = self.state();
+        if state.verdict_closed {
+            return Err(DriverError::Disconnected);
+        }
+        let (message_id, request, _) = state
+            .verdict_requests
+            .front()
+            .ok_or(DriverError::Disconnected)?;
+        match VerdictRequest::from_bytes(*message_id, request) {
+            Ok(request) => Ok(request),
+            Err(e) => {
+                // Answered without a verdict, the fail policy applies
+                let (_, _, event) = state.verdict_requests.pop_front().unwrap();
+                if !state.filter_config.verdict_fail_closed {
+                    Self::queue_event(&mut state, event);
+                }
+                Err(DriverError::MalformedRequest(e))
+            }
+        }
+    }
+
+    fn reply(&self, request: &VerdictRequest, verdict: Verdict) -> Result<bool, DriverError> {
+        let mut state = self.state();
+        if state.verdict_closed {
+            return Err(DriverError::Disconnected);
+        }
+        let Some(at) = state
+            .verdict_requests
+            .iter()
+            .position(|(message_id, _, _)| *message_id == request.message_id)
+        else {
+            return Ok(false);
+        };
+        let (_, _, event) = state.verdict_requests.remove(at).unwrap();
+        if verdict.allows() {
+            Self::queue_event(&mut state, event);
+        }
+        state.verdicts.push((request.clone(), verdict));
+        Ok(true)
+    }
+
+    fn close(&self) -> bool {
+        let mut state = self.state();
+        let was_open = !state.verdict_closed;
+        state.verdict_closed = true;
+        was_open
+    }
+}
+
+/// Shared by the tests scripting a [`MockDriver`].
+#[cfg(test)]
+pub(crate) mod fixtures {
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    use crate::driver_comm::mock::MockDriver;
+    use crate::driver_comm::scan_scope::ScanScope;
+    use crate::driver_comm::Driver;
+    use crate::shared_def::{CDriverMsgs, IOMessage};
+    use crate::worker::process_record_handling::Exepath;
+
+    /// `C:` is `\Device\HarddiskVolume3`.
+    pub(crate) fn scan_scope() -> ScanScope {
+        ScanScope::new()
+            .dos_devices(Box::new(HashMap::from([(
+                'C',
+                r"\Device\HarddiskVolume3".to_string(),
+            )])))
+            .build()
+    }
+
+    /// The [`IOMessage`]s of one `GetOps`.
+    pub(crate) fn fetch_iomsgs(
+        driver: &Driver<MockDriver>,
+        vecnew: &mut Vec<u8>,
+    ) -> Vec<IOMessage> {
+        let reply_irp = driver.get_irp(vecnew).unwrap().unwrap();
+        if reply_irp.num_ops == 0 {
+            return vec![];
+        }
+        CDriverMsgs::new(vecnew)
+            .unwrap()
+            .map(|drivermsg| IOMessage::from(&drivermsg))
+            .collect()
+    }
+
+    /// Every i/o comes from `C:\Users\Dev\Downloads\bad.exe`.
+    #[derive(Debug)]
+    pub(crate) struct ExepathFixed;
+
+    impl Exepath for ExepathFixed {
+        fn exepath(&self, _iomsg: &IOMessage) -> Option<PathBuf> {
+            Some(PathBuf::from(r"C:\Users\Dev\Downloads\bad.exe"))
+        }
+    }
+}
+
+#[cfg(test)]
+#[doc(hidden)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    use windows::core::HRESULT;
+
+    use crate::driver_comm::config::{DrainPolicy, DriverConfig};
+    use crate::driver_comm::error::{DriverError, MAX_PATH_LEN};
+    use crate::driver_comm::mock::fixtures::{fetch_iomsgs, scan_scope};
+    use crate::driver_comm::mock::{MockDriver, MockEvent, E_INVALID_PARAMETER};
+    use crate::driver_comm::report::{
+        GidReport, KillOutcome, PidOutcome, MAX_GID_REPORT_PIDS, STATUS_NO_SUCH_GROUP,
+    };
+    use crate::driver_comm::transport::DriverTransport;
+    use crate::driver_comm::tuning::{FilterConfig, MAX_OPS_SAVE_LIMIT};
+    use crate::driver_comm::version::DriverVersion;
+    use crate::driver_comm::{Driver, DriverComMessageType, IrpMajorOp, MIN_COMM_BUFFER_SIZE};
+    use crate::process::{ProcessRecord, ProcessState};
+    use crate::shared_def::{Event, FileChangeInfo};
+    use crate::worker::process_record_handling::{
+        try_resume, try_suspend, GidKiller, ProcessTable,
+    };
+
+    #[test]
+    fn test_get_irp_decodes_scripted_events() {
+        let mock = MockDriver::new();
+        mock.push_events([
+            MockEvent::new(10, 1, IrpMajorOp::IrpCreate, r"C:\Users\Dev\report.docx")
+                .extension("docx")
+                .file_change(FileChangeInfo::FileChangeNewFile),
+            MockEvent::new(10, 1, IrpMajorOp::IrpWrite, r"C:\Users\Dev\report.docx")
+                .extension("docx")
+                .transferred(4096, 7.9),
+            MockEvent::new(11, 1, IrpMajorOp::IrpRead, "").transferred(12, 1.5),
+        ]);
+        let driver = Driver::with_transport(mock.clone());
+        let mut vecnew: Vec<u8> = Vec::with_capacity(65536);
+
+        let iomsgs = fetch_iomsgs(&driver, &mut vecnew);
+
+        assert_eq!(iomsgs.len(), 3);
+        assert_eq!(iomsgs[0].filepathstr, r"C:\Users\Dev\report.docx");
+        assert_eq!(iomsgs[0].irp_op, IrpMajorOp::IrpCreate as u8);
+        assert_eq!(
+            iomsgs[0].file_change,
+            FileChangeInfo::FileChangeNewFile as u8
+        );
+        assert_eq!(String::from_utf16_lossy(&iomsgs[1].extension[..4]), "docx");
+        assert_eq!(iomsgs[1].mem_sized_used, 4096);
+        assert_eq!(iomsgs[1].entropy, 7.9);
+        assert_eq!(iomsgs[2].filepathstr, "");
+        assert_eq!(iomsgs[2].pid, 11);
+        assert_eq!(mock.pending_events(), 0);
+        assert!(fetch_iomsgs(&driver, &mut vecnew).is_empty());
+    }
+
+    #[test]
+    fn test_get_irp_is_capped_by_reply_buffer() {
+        let mock = MockDriver::new();
+        let filepath = format!(r"C:\{}", "a".repeat(400));
+        mock.push_events((0..100).map(|i| MockEvent::new(i, 1, IrpMajorOp::IrpWrite, &filepath)));
+        let driver = Driver::with_transport(mock.clone());
+        let mut vecnew: Vec<u8> = Vec::with_capacity(65536);
+
+        let first = fetch_iomsgs(&driver, &mut vecnew);
+        assert!(!first.is_empty() && first.len() < 100);
+        assert_eq!(mock.pending_events(), 100 - first.len());
+        assert!(first.iter().all(|iomsg| iomsg.filepathstr == filepath));
+
+        let second = fetch_iomsgs(&driver, &mut vecnew);
+        assert_eq!(second[0].pid, first.len() as u32);
+    }
+
+    #[test]
+    fn test_get_irp_uses_vec_capacity() {
+        let mock = MockDriver::new();
+        let filepath = format!(r"C:\{}", "a".repeat(515));
+        mock.push_events((0..10).map(|i| MockEvent::new(i, 1, IrpMajorOp::IrpWrite, &filepath)));
+        let config = DriverConfig::builder()
+            .buffer_size(4 * MIN_COMM_BUFFER_SIZE)
+            .build();
+        let driver = Driver::with_transport(mock.clone()).with_config(config);
+
+        let mut vecnew: Vec<u8> = Vec::with_capacity(MIN_COMM_BUFFER_SIZE);
+        assert_eq!(fetch_iomsgs(&driver, &mut vecnew).len(), 1);
+        assert_eq!(vecnew.len(), vecnew.capacity());
+
+        // Too small, grown to the buffer size of the config
+        let mut vecnew: Vec<u8> = Vec::new();
+        assert_eq!(fetch_iomsgs(&driver, &mut vecnew).len(), 4);
+        assert!(vecnew.capacity() >= 4 * MIN_COMM_BUFFER_SIZE);
+
+        assert_eq!(
+            mock.send_message(
+                &Driver::<MockDriver>::build_irp_msg(DriverComMessageType::GetOps, 1, 0, "")
+                    .unwrap(),
+                Some(&mut [0u8; MIN_COMM_BUFFER_SIZE - 1])
+            ),
+            Err(DriverError::Send(E_INVALID_PARAMETER))
+        );
+    }
+
+    #[test]
+    fn test_poll_iomsgs_drain_policy() {
+        let filepath = format!(r"C:\{}", "a".repeat(400));
+        let poll = |drain: DrainPolicy| {
+            let mock = MockDriver::new();
+            mock.push_events(
+                (0..200).map(|i| MockEvent::new(i, 1, IrpMajorOp::IrpWrite, &filepath)),
+            );
+            let driver = Driver::with_transport(mock.clone())
+                .with_config(DriverConfig::builder().drain(drain).build());
+            let mut vecnew = Vec::with_capacity(driver.config().buffer_size());
+            let mut pids = vec![];
+            let replies = driver
+                .poll_iomsgs(&mut vecnew, |iomsg| {
+                    pids.push(iomsg.unwrap().pid);
+                    true
+                })
+                .unwrap();
+            (replies, pids, mock)
+        };
+
+        let (replies, pids, mock) = poll(DrainPolicy::Once);
+        assert_eq!(replies, 1);
+        assert_eq!(mock.pending_events(), 200 - pids.len());
+
+        let (replies, pids, mock) = poll(DrainPolicy::UntilEmpty { max_replies: 2 });
+        assert_eq!(replies, 2);
+        assert_eq!(mock.pending_events(), 200 - pids.len());
+
+        let (replies, pids, mock) = poll(DrainPolicy::UntilEmpty { max_replies: 100 });
+        assert_eq!(pids, (0..200).collect::<Vec<u32>>());
+        assert_eq!(mock.pending_events(), 0);
+        // The last reply is not full, no empty GetOps is needed to know that the queue is empty
+        assert_eq!(mock.received().len(), replies);
+    }
+
+    #[test]
+    fn test_messages_are_recorded() {
+        let mock = MockDriver::new();
+        let driver = Driver::with_transport(mock.clone());
+
+        driver.driver_set_app_pid().unwrap();
+        let kill_report = driver.try_kill(42).unwrap();
+        driver.close_kernel_communication().unwrap();
+
+        let received = mock.received();
+        assert_eq!(received.len(), 2);
+        assert_eq!(received[0].r#type, DriverComMessageType::SetPid as u32);
+        assert_eq!(received[0].pid, std::process::id());
+        assert_eq!(received[1].r#type, DriverComMessageType::KillGid as u32);
+        assert_eq!(received[1].gid, 42);
+        assert_eq!(kill_report.status, STATUS_NO_SUCH_GROUP);
+        assert!(mock.is_closed());
+    }
+
+    #[test]
+    fn test_closed_driver_is_disconnected() {
+        let mock = MockDriver::new();
+        mock.push_event(MockEvent::new(10, 3, IrpMajorOp::IrpRead, r"C:\a.txt"));
+        let driver = Driver::with_transport(mock.clone());
+        let mut vecnew: Vec<u8> = Vec::with_capacity(65536);
+
+        driver.close_kernel_communication().unwrap();
+        assert_eq!(
+            driver.close_kernel_communication(),
+            Err(DriverError::Disconnected)
+        );
+        assert_eq!(
+            driver.get_irp(&mut vecnew).unwrap_err(),
+            DriverError::Disconnected
+        );
+        assert_eq!(driver.try_kill(3), Err(DriverError::Disconnected));
+        assert_eq!(mock.pending_events(), 1);
+    }
+
+    #[test]
+    fn test_scan_directories_survive_reconnect() {
+        let mock = MockDriver::new();
+        let driver = Driver::with_transport(mock.clone()).with_scan_scope(scan_scope());
+
+        assert!(driver.add_scan_directory(r"C:\Users\Dev\Shares").unwrap());
+        assert!(driver.add_scan_directory(r"C:\Data").unwrap());
+        assert!(!driver.add_scan_directory("C:/Data/").unwrap());
+        assert!(driver
+            .add_scan_directory(r"E:\Data")
+            .unwrap_err()
+            .to_string()
+            .contains(r"E:\Data"));
+        assert!(driver.remove_scan_directory(r"C:\Data").unwrap());
+        assert!(!driver.remove_scan_directory(r"C:\Data").unwrap());
+        let expected = vec![r"\Device\HarddiskVolume3\Users\Dev\Shares\".to_string()];
+        assert_eq!(driver.scan_directories(), expected);
+        assert_eq!(mock.scan_directories(), expected);
+
+        mock.unload();
+        assert_eq!(
+            driver.add_scan_directory(r"C:\Temp"),
+            Err(DriverError::Disconnected)
+        );
+        driver.reconnect().unwrap();
+        assert_eq!(mock.connections(), 2);
+        assert_eq!(mock.scan_directories(), expected);
+        assert_eq!(driver.scan_directories(), expected);
+    }
+
+    #[test]
+    fn test_runtime_tuning() {
+        let mock = MockDriver::new();
+        let driver = Driver::with_transport(mock.clone());
+        let event = || MockEvent::new(10, 1, IrpMajorOp::IrpWrite, r"C:\a.txt");
+        assert_eq!(driver.get_config(), Ok(FilterConfig::default()));
+
+        let config = driver.set_entropy_min_size(4096).unwrap();
+        assert_eq!(config.entropy_min_size, 4096);
+        mock.push_events([
+            event().transferred(100, 7.9),
+            event().transferred(8192, 7.9),
+        ]);
+        let mut vecnew = Vec::new();
+        let iomsgs = fetch_iomsgs(&driver, &mut vecnew);
+        assert_eq!(iomsgs[0].is_entropy_calc, 0);
+        assert_eq!(iomsgs[1].entropy, 7.9);
+
+        assert!(!driver.set_entropy(false).unwrap().entropy_enabled);
+        mock.push_event(event().transferred(8192, 7.9));
+        assert_eq!(fetch_iomsgs(&driver, &mut vecnew)[0].is_entropy_calc, 0);
+
+        assert_eq!(driver.set_max_ops(2).unwrap().max_ops, 2);
+        assert_eq!(
+            driver.set_max_ops(MAX_OPS_SAVE_LIMIT + 1),
+            Err(DriverError::Send(E_INVALID_PARAMETER))
+        );
+        mock.push_events([event(), event(), event()]);
+        assert_eq!(mock.pending_events(), 2);
+        fetch_iomsgs(&driver, &mut vecnew);
+
+        assert!(driver.pause_collection().unwrap().collection_paused);
+        mock.push_event(event());
+        assert_eq!(mock.pending_events(), 0);
+        assert!(!driver.resume_collection().unwrap().collection_paused);
+        mock.push_event(event());
+        assert_eq!(mock.pending_events(), 1);
+
+        // The settings are lost by a restart of the minifilter, and sent again
+        driver.pause_collection().unwrap();
+        let expected = mock.filter_config();
+        mock.unload();
+        assert_eq!(mock.filter_config(), FilterConfig::default());
+        driver.reconnect().unwrap();
+        assert_eq!(mock.filter_config(), expected);
+        assert_eq!(driver.get_config(), Ok(expected));
+        assert_eq!(
+            expected.to_string(),
+            "collection paused, entropy off (from 4096 bytes), max 2 ops, verdicts fail open after 1000 ms"
+        );
+    }
+
+    #[test]
+    fn test_exclusions_survive_reconnect() {
+        let mock = MockDriver::new();
+        let driver = Driver::with_transport(mock.clone()).with_scan_scope(scan_scope());
+        let backup = r"\Device\HarddiskVolume3\Program Files\Backup\agent.exe";
+        mock.set_image_path(30, backup);
+        let push = || {
+            mock.push_events([
+                MockEvent::new(10, 1, IrpMajorOp::IrpWrite, r"C:\a.txt"),
+                MockEvent::new(20, 2, IrpMajorOp::IrpWrite, r"C:\b.txt"),
+                MockEvent::new(21, 2, IrpMajorOp::IrpWrite, r"C:\c.txt"),
+                MockEvent::new(30, 3, IrpMajorOp::IrpWrite, r"C:\d.txt"),
+                MockEvent::new(40, 4, IrpMajorOp::IrpWrite, r"C:\e.txt"),
+            ])
+        };
+
+        assert!(driver.exclude_pid(10).unwrap());
+        assert!(!driver.exclude_pid(10).unwrap());
+        assert!(driver.exclude_gid(2).unwrap());
+        assert!(driver
+            .exclude_image_path(r"c:\program files\backup\AGENT.exe")
+            .unwrap());
+        assert!(!driver.exclude_image_path(backup).unwrap());
+        assert_eq!(
+            driver.exclude_image_path(r"C:\Program Files\Backup\"),
+            Err(DriverError::InvalidFilePath {
+                path: r"C:\Program Files\Backup\".to_string()
+            })
+        );
+        push();
+        let mut vecnew = Vec::new();
+        let pids: Vec<u32> = fetch_iomsgs(&driver, &mut vecnew)
+            .iter()
+            .map(|iomsg| iomsg.pid)
+            .collect();
+        assert_eq!(pids, vec![40]);
+
+        assert!(driver.include_gid(2).unwrap());
+        assert!(!driver.include_gid(2).unwrap());
+        let expected = driver.exclusions();
+        assert_eq!(mock.exclusions(), expected);
+        assert_eq!(expected.pids().collect::<Vec<_>>(), vec![10]);
+        assert!(expected.contains_image(backup));
+        assert_eq!(expected.len(), 2);
+
+        // The exclusions are lost by a restart of the minifilter, and sent again
+        mock.unload();
+        assert!(mock.exclusions().is_empty());
+        driver.reconnect().unwrap();
+        assert_eq!(mock.exclusions(), expected);
+        push();
+        assert_eq!(mock.pending_events(), 3);
+    }
+
+    #[test]
+    fn test_protocol_version() {
+        let mock = MockDriver::new();
+        let driver = Driver::with_transport(mock.clone());
+        assert_eq!(driver.get_version(), Ok(DriverVersion::current()));
+        assert_eq!(driver.check_version(), Ok(()));
+
+        let old = DriverVersion {
+            driver_message_size: 96,
+            ..DriverVersion::current()
+        };
+        mock.set_version(Some(old));
+        let mismatch = driver.check_version().unwrap_err();
+        assert_eq!(
+            mismatch,
+            DriverError::ProtocolMismatch {
+                expected: DriverVersion::current(),
+                found: Some(old)
+            }
+        );
+        assert!(mismatch.to_string().contains("DRIVER_MESSAGE: 96 bytes"));
+
+        // Restarted with a build older than GetVersion
+        mock.unload();
+        mock.set_version(None);
+        assert_eq!(
+            driver.reconnect(),
+            Err(DriverError::ProtocolMismatch {
+                expected: DriverVersion::current(),
+                found: None
+            })
+        );
+    }
+
+    #[test]
+    fn test_suspend_resume_gid() {
+        let mock = MockDriver::new();
+        mock.set_gid(
+            7,
+            &[(100, "bad.exe"), (101, "conhost.exe"), (102, "bad.exe")],
+        );
+        mock.set_pid_status(101, HRESULT(0xC000_000D_u32 as i32));
+        mock.push_event(MockEvent::new(100, 7, IrpMajorOp::IrpWrite, r"C:\a.txt"));
+        let driver = Driver::with_transport(mock.clone());
+        let mut vecnew: Vec<u8> = Vec::with_capacity(65536);
+        let iomsg = fetch_iomsgs(&driver, &mut vecnew).remove(0);
+        let mut precord = ProcessRecord::from(&iomsg, "bad.exe".to_string(), PathBuf::new());
+
+        let report = try_suspend(&driver, &mut precord).unwrap();
+        assert_eq!(report.num_pids, 3);
+        assert!(!report.is_complete());
+        assert_eq!(
+            report.failed().map(|o| o.pid).collect::<Vec<_>>(),
+            vec![101]
+        );
+        assert_eq!(
+            report.to_string(),
+            "gid 7: 2/3 pids, pid 101 failed (0xC000000D)"
+        );
+        assert_eq!(mock.suspended(), vec![100, 102]);
+        assert_eq!(precord.process_state, ProcessState::Suspended);
+        assert!(precord.time_suspended.is_some());
+
+        mock.set_pid_status(101, HRESULT(0));
+        let report = try_resume(&driver, &mut precord).unwrap();
+        assert!(report.is_complete());
+        assert!(mock.suspended().is_empty());
+        assert_eq!(precord.process_state, ProcessState::Running);
+        assert!(precord.time_suspended.is_none());
+
+        let report = driver.suspend_gid(8).unwrap();
+        assert_eq!(report.status, STATUS_NO_SUCH_GROUP);
+        assert!(report.pids.is_empty() && !report.is_complete());
+
+        // Only the report is capped, every pid is suspended
+        let pids: Vec<(u32, &str)> = (0..MAX_GID_REPORT_PIDS as u32 + 6)
+            .map(|i| (1000 + i, "fork.exe"))
+            .collect();
+        mock.set_gid(8, &pids);
+        let report = driver.suspend_gid(8).unwrap();
+        assert_eq!(report.num_pids as usize, pids.len());
+        assert_eq!(report.pids.len(), MAX_GID_REPORT_PIDS);
+        assert_eq!(mock.suspended().len(), pids.len());
+    }
+
+    /// The processes of a [`MockDriver`]: killed pids linger for `lingering` checks.
+    #[derive(Debug)]
+    struct MockProcesses {
+        mock: MockDriver,
+        running: HashMap<u32, String>,
+        lingering: Mutex<u32>,
+    }
+
+    impl ProcessTable for MockProcesses {
+        fn image_name(&self, pid: u32) -> Option<String> {
+            if self.mock.killed().contains(&pid) {
+                let mut lingering = self.lingering.lock().unwrap();
+                if *lingering == 0 {
+                    return None;
+                }
+                *lingering -= 1;
+            }
+            self.running.get(&pid).cloned()
+        }
+    }
+
+    #[test]
+    fn test_kill_gid() {
+        let mock = MockDriver::new();
+        mock.set_gid(9, &[(200, "bad.exe"), (201, "svc.exe"), (202, "")]);
+        // Protected, and already exited
+        mock.set_pid_status(201, HRESULT(0xC000_0022_u32 as i32));
+        mock.set_pid_status(202, HRESULT(0xC000_000B_u32 as i32));
+        mock.push_event(MockEvent::new(200, 9, IrpMajorOp::IrpWrite, r"C:\a.txt"));
+        let driver = Driver::with_transport(mock.clone());
+        let mut vecnew: Vec<u8> = Vec::with_capacity(65536);
+        let iomsg = fetch_iomsgs(&driver, &mut vecnew).remove(0);
+        let mut precord = ProcessRecord::from(&iomsg, "bad.exe".to_string(), PathBuf::new());
+        let killer = GidKiller::new()
+            .process_table(Box::new(MockProcesses {
+                mock: mock.clone(),
+                running: HashMap::from([(200, "bad.exe".into()), (201, "svc.exe".into())]),
+                lingering: Mutex::new(2),
+            }))
+            .retries(3, Duration::from_millis(1))
+            .build();
+
+        let report = killer.kill(&driver, &mut precord).unwrap();
+        let outcomes: Vec<(u32, KillOutcome)> = report
+            .pids
+            .iter()
+            .map(|pid| (pid.pid, pid.outcome))
+            .collect();
+        assert_eq!(
+            outcomes,
+            vec![
+                (200, KillOutcome::Killed),
+                (201, KillOutcome::Survived),
+                (202, KillOutcome::AlreadyGone)
+            ]
+        );
+        assert_eq!(
+            report.to_string(),
+            "gid 9: 200 (bad.exe) Killed, 201 (svc.exe) Survived, 202 () AlreadyGone"
+        );
+        assert!(!report.is_complete());
+        assert_eq!(precord.process_state, ProcessState::Running);
+        assert!(precord.time_killed.is_none());
+
+        mock.set_pid_status(201, HRESULT(0));
+        let report = killer.kill(&driver, &mut precord).unwrap();
+        assert!(report.is_complete());
+        assert_eq!(mock.killed(), vec![200, 201]);
+        assert_eq!(precord.process_state, ProcessState::Killed);
+        assert!(precord.time_killed.is_some());
+
+        // 300 is reused by another process, 301 keeps its truncated name
+        mock.set_gid(10, &[(300, "bad.exe"), (301, "a_very_long_image_name.exe")]);
+        mock.set_pid_status(301, HRESULT(0xC000_0022_u32 as i32));
+        let killer = GidKiller::new()
+            .process_table(Box::new(HashMap::from([
+                (300, "notepad.exe".to_string()),
+                (301, "a_very_long_image_name.exe".to_string()),
+            ])))
+            .retries(1, Duration::from_millis(1))
+            .build();
+        let report = killer.verify(driver.try_kill(10).unwrap());
+        let outcomes: Vec<(u32, KillOutcome)> = report
+            .pids
+            .iter()
+            .map(|pid| (pid.pid, pid.outcome))
+            .collect();
+        assert_eq!(
+            outcomes,
+            vec![(300, KillOutcome::Killed), (301, KillOutcome::Survived)]
+        );
+
+        // Truncated to the 15 chars kept by the kernel
+        let report = GidReport {
+            gid: 1,
+            status: HRESULT(0),
+            num_pids: 1,
+            pids: vec![PidOutcome {
+                pid: 1,
+                image_name: "a_very_long_image_name.exe".to_string(),
+                status: HRESULT(0),
+            }],
+        };
+        let decoded = GidReport::from_bytes(1, &report.to_bytes()).unwrap();
+        assert_eq!(decoded.pids[0].image_name, "a_very_long_ima");
+    }
+
+    #[test]
+    fn test_invalid_paths() {
+        let longest = "a".repeat(MAX_PATH_LEN);
+        let buf = Driver::<MockDriver>::string_to_commessage_buffer(&longest).unwrap();
+        assert_eq!(buf[MAX_PATH_LEN - 1], u16::from(b'a'));
+        assert_eq!(buf[MAX_PATH_LEN], 0);
+
+        assert_eq!(
+            Driver::<MockDriver>::string_to_commessage_buffer(&"a".repeat(MAX_PATH_LEN + 1)),
+            Err(DriverError::PathTooLong {
+                len: MAX_PATH_LEN + 1
+            })
+        );
+        assert_eq!(
+            Driver::<MockDriver>::string_to_commessage_buffer("C:\\Users\0Dev"),
+            Err(DriverError::PathWithNul { position: 8 })
+        );
+    }
+
+    #[test]
+    fn test_sequence_gaps_reported_as_events_lost() {
+        let mock = MockDriver::new();
+        let driver = Driver::with_transport(mock.clone());
+        let mut vecnew: Vec<u8> = Vec::with_capacity(65536);
+        let mut poll = || {
+            let mut events = Vec::new();
+            driver
+                .poll_events(&mut vecnew, |event| {
+                    events.push(event.unwrap());
+                    true
+                })
+                .unwrap();
+            events
+        };
+        let caught = std::time::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        mock.push_events([
+            MockEvent::new(10, 3, IrpMajorOp::IrpRead, r"C:\a.txt").time(caught),
+            MockEvent::new(10, 3, IrpMajorOp::IrpRead, r"C:\b.txt"),
+        ]);
+        let events = poll();
+        assert_eq!(events.len(), 2);
+        match &events[0] {
+            Event::Io(iomsg) => {
+                assert_eq!((iomsg.sequence, iomsg.time), (0, caught));
+                assert!(iomsg.received > caught);
+            }
+            event => panic!("unexpected {event:?}"),
+        }
+        assert!(matches!(&events[1], Event::Io(iomsg) if iomsg.sequence == 1));
+
+        driver.set_max_ops(1).unwrap();
+        mock.push_events([
+            MockEvent::new(10, 3, IrpMajorOp::IrpRead, r"C:\a.txt"),
+            MockEvent::new(10, 3, IrpMajorOp::IrpRead, r"C:\b.txt"),
+            MockEvent::new(10, 3, IrpMajorOp::IrpRead, r"C:\c.txt"),
+        ]);
+        assert!(matches!(&poll()[..], [Event::Io(iomsg)] if iomsg.sequence == 2));
+        mock.push_event(MockEvent::new(10, 3, IrpMajorOp::IrpRead, r"C:\d.txt"));
+        let events = poll();
+        assert!(matches!(events[0], Event::EventsLost { count: 2 }));
+        assert!(matches!(&events[1], Event::Io(iomsg) if iomsg.filepathstr == r"C:\d.txt"));
+
+        // The sequence restarts with the minifilter
+        mock.unload();
+        driver.reconnect().unwrap();
+        mock.push_event(MockEvent::new(10, 3, IrpMajorOp::IrpRead, r"C:\a.txt"));
+        assert!(matches!(&poll()[..], [Event::Io(iomsg)] if iomsg.sequence == 0));
+        // Nor does it overflow
+        assert_eq!(driver.sequence_gap(u64::MAX - 1), Some(u64::MAX - 2));
+        assert_eq!(driver.sequence_gap(u64::MAX), None);
+        assert_eq!(driver.sequence_gap(0), None);
+    }
+}
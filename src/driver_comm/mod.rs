@@ -0,0 +1,917 @@
+//! Low-level communication with the minifilter.
+
+pub mod config;
+pub mod error;
+pub mod exclusions;
+pub mod mock;
+pub mod report;
+pub mod scan_scope;
+pub mod session;
+pub mod stats;
+#[cfg(feature = "async")]
+pub mod stream;
+pub mod transport;
+pub mod tuning;
+pub mod verdict;
+pub mod version;
+
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fmt;
+use std::os::raw::*;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+use num_derive::FromPrimitive;
+use serde::{Deserialize, Serialize};
+
+use crate::driver_comm::config::{DrainPolicy, DriverConfig};
+use crate::driver_comm::error::{DriverError, MAX_PATH_LEN};
+use crate::driver_comm::exclusions::ExclusionSet;
+use crate::driver_comm::report::{GidReport, GID_REPORT_SIZE};
+use crate::driver_comm::scan_scope::ScanScope;
+use crate::driver_comm::stats::{DriverStats, DRIVER_STATS_SIZE};
+use crate::driver_comm::transport::{DriverTransport, FilterPort};
+use crate::driver_comm::tuning::{FilterConfig, FILTER_CONFIG_SIZE};
+use crate::driver_comm::version::DriverVersion;
+use crate::driver_comm::DriveType::{
+    DriveCDRom, DriveFixed, DriveNoRootDir, DriveRamDisk, DriveRemote, DriveRemovable, DriveUnknown,
+};
+use crate::driver_comm::IrpMajorOp::{
+    IrpCleanUp, IrpCreate, IrpNone, IrpRead, IrpSetInfo, IrpWrite,
+};
+use crate::process::tree::{ProcessTree, GID_TREE_SIZE};
+use crate::shared_def::decoder::{
+    DecodeError, ReplyDecoder, DRIVER_MSG_SIZE, MAX_FILE_NAME_SIZE, REPLY_HEADER_SIZE,
+};
+use crate::shared_def::{CDriverMsgs, Event, IOMessage, ReplyIrp};
+
+/// Default size of the buffer in which the minifilter writes a [`ReplyIrp`]
+/// (`MAX_COMM_BUFFER_SIZE` in `SharedDefs.h`), see [`DriverConfig`].
+pub const MAX_COMM_BUFFER_SIZE: usize = 0x10000;
+
+/// Smallest buffer accepted by the minifilter for a [`ReplyIrp`], large enough for a message with
+/// the longest file path (`MIN_COMM_BUFFER_SIZE` in `SharedDefs.h`).
+pub const MIN_COMM_BUFFER_SIZE: usize =
+    REPLY_HEADER_SIZE + DRIVER_MSG_SIZE + MAX_FILE_NAME_SIZE + 2;
+
+/// Name of the communication port opened by the minifilter (`ComPortName` in `SharedDefs.h`).
+pub const COM_PORT_NAME: &str = "\\RWFilter";
+
+pub type BufPath = [u16; 520];
+
+/// The user-mode app (this app) can send several messages types to the driver. See [`DriverComMessageType`]
+/// for details.
+/// Depending on the message type, the *pid*, *gid* and *path* fields can be optional.
+#[derive(Debug, Clone)]
+#[repr(C)]
+pub struct DriverComMessage {
+    /// The type message to send. See [`DriverComMessageType`].
+    pub r#type: u32,
+    /// The pid of the process which triggered an i/o activity;
+    pub pid: u32,
+    /// The gid is maintained by the driver. Carries the value of the tuning messages instead, e.g.
+    /// [`SetMaxOps`](DriverComMessageType::SetMaxOps).
+    pub gid: c_ulonglong,
+    pub path: BufPath,
+}
+
+/// Messages types to send directives to the minifilter, by using te [`DriverComMessage`] struct.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, FromPrimitive)]
+#[repr(C)]
+pub enum DriverComMessageType {
+    /// Add a scan directory, see [`ScanScope`]. The minifilter replies with a `BOOLEAN`.
+    AddScanDirectory,
+    /// Remove a scan directory, see [`ScanScope`]. The minifilter replies with a `BOOLEAN`.
+    RemScanDirectory,
+    /// Ask for a [`ReplyIrp`], if any available.
+    GetOps,
+    /// Set this app pid to the minifilter (related IRPs will be ignored);
+    SetPid,
+    /// Instruct the minifilter to kill all pids in the family designated by a given gid. The
+    /// minifilter replies with a [`GidReport`].
+    KillGid,
+    /// Ask for the [`DriverVersion`] of the minifilter.
+    GetVersion,
+    /// Suspend all pids in the family designated by a given gid. The minifilter replies with a
+    /// [`GidReport`].
+    SuspendGid,
+    /// Resume all pids in the family designated by a given gid. The minifilter replies with a
+    /// [`GidReport`].
+    ResumeGid,
+    /// Turn the entropy calculation on (value 1) or off (value 0). This and the following messages
+    /// are replied with the resulting [`FilterConfig`].
+    SetEntropy,
+    /// Set the size in bytes below which reads and writes are not sampled for entropy.
+    SetEntropyMinSize,
+    /// Set how many operations can wait to be fetched, up to
+    /// [`MAX_OPS_SAVE_LIMIT`](tuning::MAX_OPS_SAVE_LIMIT).
+    SetMaxOps,
+    /// Stop recording operations.
+    PauseCollection,
+    /// Record operations again.
+    ResumeCollection,
+    /// Ask for the current [`FilterConfig`].
+    GetConfig,
+    /// Stop recording the operations of a pid. This and the following messages are replied with a
+    /// `BOOLEAN`, false if there was nothing to change. See [`ExclusionSet`].
+    ExcludePid,
+    /// Record the operations of an excluded pid again.
+    IncludePid,
+    /// Stop recording the operations of the pids in the family designated by a given gid.
+    ExcludeGid,
+    /// Record the operations of an excluded gid again.
+    IncludeGid,
+    /// Stop recording the operations of the processes, running or to come, started from the image
+    /// file in *path* (device form).
+    ExcludeImage,
+    /// Record the operations of the processes of an excluded image again.
+    IncludeImage,
+    /// Ask for the [`DriverStats`] of the minifilter.
+    GetStats,
+    /// Hold the writes, renames and deletes in *path* until a
+    /// [`Verdict`](verdict::Verdict) is given on the verdict port. This and the following message
+    /// are replied with a `BOOLEAN`, false if there was nothing to change.
+    AddVerdictDirectory,
+    /// Stop holding the operations in *path*.
+    RemVerdictDirectory,
+    /// Set how many milliseconds an operation waits for its verdict, up to
+    /// [`MAX_VERDICT_TIMEOUT`](verdict::MAX_VERDICT_TIMEOUT). Replied with the resulting
+    /// [`FilterConfig`], as is the following message.
+    SetVerdictTimeout,
+    /// Deny (value 1) or allow (value 0) the operations whose verdict does not come in time.
+    SetVerdictFailClosed,
+    /// Ask for the processes of the family designated by a given gid, as a
+    /// [`ProcessTree`](crate::process::tree::ProcessTree).
+    GetGidTree,
+}
+
+/// A minifilter is identified by a port (know in advance), like a named pipe used for communication,
+/// and a handle, retrieved by [`open_kernel_driver_com`](Driver::open_kernel_driver_com).
+///
+/// The messages are carried by a [`DriverTransport`], which is the real communication port
+/// ([`FilterPort`]) unless another one is given to [`with_transport`](Self::with_transport).
+#[derive(Debug)]
+pub struct Driver<T: DriverTransport = FilterPort> {
+    transport: T,
+    config: DriverConfig,
+    scan_scope: Mutex<ScanScope>,
+    /// Settings changed by the tuning methods, to be restored on reconnection.
+    tuning: Mutex<Option<FilterConfig>>,
+    exclusions: Mutex<ExclusionSet>,
+    verdict_directories: Mutex<BTreeSet<String>>,
+    /// [`sequence`](IOMessage::sequence) expected next, unknown until the first message.
+    next_sequence: Mutex<Option<u64>>,
+}
+
+impl Driver {
+    /// Try to open a com canal with the minifilter before this app is registered. This fn can fail
+    /// is the minifilter is unreachable:
+    ///
+    /// * if it is not started (try `sc start FSFilter` first
+    /// * if a connection is already established: it can accepts only one at a time.
+    ///
+    /// In that case a [`DriverError::Connect`] is returned, with the code raised by the OS.
+    ///
+    /// The minifilter must speak the same protocol than this crate, otherwise the port is closed
+    /// and a [`DriverError::ProtocolMismatch`] is returned.
+    pub fn open_kernel_driver_com() -> Result<Driver, DriverError> {
+        Driver::open(DriverConfig::default())
+    }
+
+    /// Same as [`open_kernel_driver_com`](Self::open_kernel_driver_com), with the port and the
+    /// polling settings of `config`.
+    pub fn open(config: DriverConfig) -> Result<Driver, DriverError> {
+        let driver =
+            Driver::with_transport(FilterPort::connect(config.port_name())?).with_config(config);
+        if let Err(e) = driver.check_version() {
+            driver.transport.close();
+            return Err(e);
+        }
+        Ok(driver)
+    }
+}
+
+impl<T: DriverTransport> Driver<T> {
+    /// Uses `transport` to communicate with the minifilter, e.g. a
+    /// [`MockDriver`](crate::driver_comm::mock::MockDriver).
+    pub fn with_transport(transport: T) -> Driver<T> {
+        Driver {
+            transport,
+            config: DriverConfig::default(),
+            scan_scope: Mutex::new(ScanScope::new()),
+            tuning: Mutex::new(None),
+            exclusions: Mutex::new(ExclusionSet::new()),
+            verdict_directories: Mutex::new(BTreeSet::new()),
+            next_sequence: Mutex::new(None),
+        }
+    }
+
+    /// Replaces the default [`DriverConfig`]. Its port name is only used by
+    /// [`open`](Driver::open).
+    pub fn with_config(mut self, config: DriverConfig) -> Driver<T> {
+        self.config = config;
+        self
+    }
+
+    /// Replaces the (empty) [`ScanScope`], e.g. to resolve drives with other
+    /// [`DosDevices`](scan_scope::DosDevices).
+    pub fn with_scan_scope(mut self, scan_scope: ScanScope) -> Driver<T> {
+        self.scan_scope = Mutex::new(scan_scope);
+        self
+    }
+
+    /// The [`DriverTransport`] carrying the messages.
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    pub fn config(&self) -> &DriverConfig {
+        &self.config
+    }
+
+    /// Can be used to properly close the communication (and unregister) with the minifilter.
+    /// If this fn is not used and the program has stopped, the handle is automatically closed,
+    /// seemingly without any side-effects.
+    ///
+    /// Fails with [`DriverError::Disconnected`] if the communication was already closed.
+    pub fn close_kernel_communication(&self) -> Result<(), DriverError> {
+        if self.transport.close() {
+            Ok(())
+        } else {
+            Err(DriverError::Disconnected)
+        }
+    }
+
+    /// The user-mode running app (this one) has to register itself to the driver.
+    pub fn driver_set_app_pid(&self) -> Result<(), DriverError> {
+        let buf = Self::string_to_commessage_buffer(r"\Device\harddiskVolume")?;
+
+        let set_pid_msg = DriverComMessage {
+            r#type: DriverComMessageType::SetPid as u32,
+            pid: std::process::id(),
+            gid: 140713315094899,
+            path: buf, //wch!("\0"),
+        };
+
+        self.transport.send_message(&set_pid_msg, None).map(|_| ())
+    }
+
+    /// Ask the driver for a [ReplyIrp], if any. This is a low-level function and the returned object
+    /// uses C pointers. Managing C pointers requires a special care, because of the Rust timelines.
+    /// [ReplyIrp] is optional since the minifilter returns null if there is no new activity.
+    ///
+    /// The whole capacity of `vecnew` is given to the minifilter. If it is below
+    /// [`MIN_COMM_BUFFER_SIZE`], `vecnew` is grown to the [`buffer_size`](DriverConfig::buffer_size)
+    /// first. The header of the reply is checked, [`CDriverMsgs`] can then read the messages in
+    /// `vecnew`.
+    pub fn get_irp(&self, vecnew: &mut Vec<u8>) -> Result<Option<ReplyIrp>, DriverError> {
+        let get_irp_msg =
+            Self::build_irp_msg(DriverComMessageType::GetOps, std::process::id(), 0, "")?;
+        if vecnew.capacity() < MIN_COMM_BUFFER_SIZE {
+            vecnew.reserve_exact(self.config.buffer_size().saturating_sub(vecnew.len()));
+        }
+        vecnew.resize(vecnew.capacity().min(u32::MAX as usize), 0);
+
+        let tmp = self
+            .transport
+            .send_message(&get_irp_msg, Some(&mut vecnew[..]))?;
+
+        if tmp != 0 {
+            ReplyDecoder::new(vecnew)?;
+            let reply_irp: ReplyIrp;
+            unsafe {
+                reply_irp = std::ptr::read_unaligned(vecnew.as_ptr() as *const ReplyIrp);
+            }
+            return Ok(Some(reply_irp));
+        }
+        Ok(None)
+    }
+
+    /// Fetches the pending [`IOMessage`]s with [`get_irp`](Self::get_irp), once or until the queue
+    /// of the minifilter is empty according to the [`DrainPolicy`], and hands them to `on_iomsg`.
+    /// A reply that cannot be decoded is handed as an error, and draining stops there. So does it
+    /// when `on_iomsg` returns false.
+    ///
+    /// The [`ProcessEvent`](crate::shared_def::ProcessEvent)s and the [`Event::EventsLost`]
+    /// notifications are skipped: use [`poll_events`](Self::poll_events) to learn of the dropped
+    /// operations.
+    ///
+    /// Returns the number of replies with at least one message, 0 meaning there was no activity.
+    pub fn poll_iomsgs<F>(
+        &self,
+        vecnew: &mut Vec<u8>,
+        mut on_iomsg: F,
+    ) -> Result<usize, DriverError>
+    where
+        F: FnMut(Result<IOMessage, DriverError>) -> bool,
+    {
+        self.poll_events(vecnew, |event| match event {
+            Ok(Event::Io(iomsg)) => on_iomsg(Ok(iomsg)),
+            Ok(Event::Process(_) | Event::EventsLost { .. }) => true,
+            Err(e) => on_iomsg(Err(e)),
+        })
+    }
+
+    /// Same as [`poll_iomsgs`](Self::poll_iomsgs), with the creations and exits of the recorded
+    /// processes as well.
+    ///
+    /// The operations dropped by the minifilter are reported by an [`Event::EventsLost`] before
+    /// the next event.
+    pub fn poll_events<F>(
+        &self,
+        vecnew: &mut Vec<u8>,
+        mut on_event: F,
+    ) -> Result<usize, DriverError>
+    where
+        F: FnMut(Result<Event, DriverError>) -> bool,
+    {
+        let max_replies = match self.config.drain() {
+            DrainPolicy::Once => 1,
+            DrainPolicy::UntilEmpty { max_replies } => max_replies.max(1),
+        };
+        let mut replies = 0;
+        while replies < max_replies {
+            let reply_irp = match self.get_irp(vecnew)? {
+                Some(reply_irp) if reply_irp.num_ops > 0 => reply_irp,
+                _ => break,
+            };
+            replies += 1;
+            let drivermsgs = match CDriverMsgs::new(vecnew) {
+                Ok(drivermsgs) => drivermsgs,
+                Err(e) => {
+                    on_event(Err(e.into()));
+                    break;
+                }
+            };
+            for drivermsg in drivermsgs {
+                if let Some(count) = self.sequence_gap(drivermsg.sequence) {
+                    if !on_event(Ok(Event::EventsLost { count })) {
+                        return Ok(replies);
+                    }
+                }
+                if !on_event(Ok(Event::from(&drivermsg))) {
+                    return Ok(replies);
+                }
+            }
+            // The minifilter stops when the next message does not fit: if any would have, its
+            // queue is empty and another GetOps would come back empty.
+            if vecnew.len() - (reply_irp.data_size as usize).min(vecnew.len())
+                > DRIVER_MSG_SIZE + MAX_FILE_NAME_SIZE
+            {
+                break;
+            }
+        }
+        Ok(replies)
+    }
+
+    /// Number of operations missing before `sequence`, if any. A sequence going back means the
+    /// minifilter restarted: the count starts over, as after `u64::MAX`.
+    fn sequence_gap(&self, sequence: u64) -> Option<u64> {
+        let mut next_sequence = self.next_sequence.lock().unwrap_or_else(|e| e.into_inner());
+        let gap = next_sequence
+            .filter(|&next| sequence > next)
+            .map(|next| sequence - next);
+        *next_sequence = sequence.checked_add(1);
+        gap
+    }
+
+    /// Ask the minifilter to kill all pids related to the given *gid*. Pids are killed in driver-mode
+    /// by calls to `ZwTerminateProcess`.
+    ///
+    /// The [`GidReport`] details the outcome for each pid, as seen by the minifilter. See
+    /// [`GidKiller`](crate::worker::process_record_handling::GidKiller) to check that they are
+    /// actually gone.
+    pub fn try_kill(&self, gid: c_ulonglong) -> Result<GidReport, DriverError> {
+        self.send_gid_command(DriverComMessageType::KillGid, gid)
+    }
+
+    /// Ask the minifilter to suspend all pids related to the given *gid*, e.g. to analyse them before
+    /// deciding to [`try_kill`](Self::try_kill) or to [`resume_gid`](Self::resume_gid).
+    ///
+    /// The [`GidReport`] details the outcome for each pid. Like [`resume_gid`](Self::resume_gid),
+    /// it is denied with a [`DriverError::Send`] unless this process runs as an administrator or
+    /// SYSTEM.
+    pub fn suspend_gid(&self, gid: c_ulonglong) -> Result<GidReport, DriverError> {
+        self.send_gid_command(DriverComMessageType::SuspendGid, gid)
+    }
+
+    /// Ask the minifilter to resume all pids related to the given *gid*, previously suspended with
+    /// [`suspend_gid`](Self::suspend_gid).
+    pub fn resume_gid(&self, gid: c_ulonglong) -> Result<GidReport, DriverError> {
+        self.send_gid_command(DriverComMessageType::ResumeGid, gid)
+    }
+
+    /// Ask the minifilter for all the processes of the family designated by `gid`: their parents,
+    /// image files and creation times, including the processes which never touched a file and
+    /// the ones which have exited since.
+    ///
+    /// Returns `None` if the gid is unknown, or has ended with its last process.
+    pub fn get_gid_tree(&self, gid: c_ulonglong) -> Result<Option<ProcessTree>, DriverError> {
+        let msg = Self::build_irp_msg(
+            DriverComMessageType::GetGidTree,
+            std::process::id(),
+            gid,
+            "",
+        )?;
+        let mut res = vec![0u8; GID_TREE_SIZE];
+        let len = self.transport.send_message(&msg, Some(&mut res))? as usize;
+        ProcessTree::from_bytes(gid, &res[..len.min(GID_TREE_SIZE)])
+    }
+
+    /// Ask the minifilter to flag the files in `path` with
+    /// [`FileLocationInfo`](crate::shared_def::FileLocationInfo). `path` is converted with
+    /// [`ScanScope::normalize`] and kept, to be sent again by [`reconnect`](Self::reconnect).
+    ///
+    /// Returns false if the minifilter already had this directory.
+    pub fn add_scan_directory(&self, path: &str) -> Result<bool, DriverError> {
+        let mut scan_scope = self.scan_scope();
+        let directory = scan_scope.normalize(path)?;
+        let added = self.send_scan_directory(DriverComMessageType::AddScanDirectory, &directory)?;
+        scan_scope.insert(directory);
+        Ok(added)
+    }
+
+    /// Stop monitoring `path`, previously given to [`add_scan_directory`](Self::add_scan_directory).
+    ///
+    /// Returns false if the minifilter did not have this directory.
+    pub fn remove_scan_directory(&self, path: &str) -> Result<bool, DriverError> {
+        let mut scan_scope = self.scan_scope();
+        let directory = scan_scope.normalize(path)?;
+        let removed =
+            self.send_scan_directory(DriverComMessageType::RemScanDirectory, &directory)?;
+        scan_scope.remove(&directory);
+        Ok(removed)
+    }
+
+    /// The current scan directories, in the minifilter form (`\Device\HarddiskVolumeN\...\`).
+    pub fn scan_directories(&self) -> Vec<String> {
+        self.scan_scope().directories().map(String::from).collect()
+    }
+
+    /// Ask the minifilter to hold the writes, renames and deletes in `path` until a
+    /// [`VerdictServer`](verdict::VerdictServer) allows or denies them. `path` is converted with
+    /// [`ScanScope::normalize`] and kept, to be sent again by [`reconnect`](Self::reconnect).
+    ///
+    /// Returns false if the minifilter already had this directory.
+    pub fn add_verdict_directory(&self, path: &str) -> Result<bool, DriverError> {
+        let directory = self.scan_scope().normalize(path)?;
+        let mut verdict_directories = self.verdict_directories_guard();
+        let added =
+            self.send_scan_directory(DriverComMessageType::AddVerdictDirectory, &directory)?;
+        verdict_directories.insert(directory);
+        Ok(added)
+    }
+
+    /// Returns false if the minifilter did not have this directory.
+    pub fn remove_verdict_directory(&self, path: &str) -> Result<bool, DriverError> {
+        let directory = self.scan_scope().normalize(path)?;
+        let mut verdict_directories = self.verdict_directories_guard();
+        let removed =
+            self.send_scan_directory(DriverComMessageType::RemVerdictDirectory, &directory)?;
+        verdict_directories.remove(&directory);
+        Ok(removed)
+    }
+
+    /// The current verdict directories, in the minifilter form.
+    pub fn verdict_directories(&self) -> Vec<String> {
+        self.verdict_directories_guard().iter().cloned().collect()
+    }
+
+    /// Ask the minifilter for its protocol version and the sizes of its structures.
+    ///
+    /// A minifilter built before [`GetVersion`](DriverComMessageType::GetVersion) rejects it, this
+    /// is reported as a [`DriverError::ProtocolMismatch`] without version.
+    pub fn get_version(&self) -> Result<DriverVersion, DriverError> {
+        let msg = Self::build_irp_msg(DriverComMessageType::GetVersion, std::process::id(), 0, "")?;
+        let mismatch = || DriverError::ProtocolMismatch {
+            expected: DriverVersion::current(),
+            found: None,
+        };
+        let mut res = [0u8; 16];
+        let len = match self.transport.send_message(&msg, Some(&mut res)) {
+            Ok(len) => len as usize,
+            Err(DriverError::Send(_)) => return Err(mismatch()),
+            Err(e) => return Err(e),
+        };
+        DriverVersion::from_bytes(&res[..len.min(res.len())]).ok_or_else(mismatch)
+    }
+
+    /// Fails with [`DriverError::ProtocolMismatch`] unless the minifilter has the
+    /// [`DriverVersion::current`] one.
+    pub fn check_version(&self) -> Result<(), DriverError> {
+        let found = self.get_version()?;
+        let expected = DriverVersion::current();
+        if found != expected {
+            return Err(DriverError::ProtocolMismatch {
+                expected,
+                found: Some(found),
+            });
+        }
+        Ok(())
+    }
+
+    /// Opens the communication again (e.g. after the minifilter has been restarted) and sends it
+    /// the scan and verdict directories, the [`FilterConfig`] changes and the [`ExclusionSet`]
+    /// back. The
+    /// minifilter may have been updated meanwhile, so its version is checked first.
+    pub fn reconnect(&self) -> Result<(), DriverError> {
+        self.transport.reconnect()?;
+        *self.next_sequence.lock().unwrap_or_else(|e| e.into_inner()) = None;
+        self.check_version()?;
+        let scan_scope = self.scan_scope();
+        for directory in scan_scope.directories() {
+            self.send_scan_directory(DriverComMessageType::AddScanDirectory, directory)?;
+        }
+        drop(scan_scope);
+        for directory in self.verdict_directories_guard().iter() {
+            self.send_scan_directory(DriverComMessageType::AddVerdictDirectory, directory)?;
+        }
+        let tuning = *self.tuning.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(tuning) = tuning {
+            self.restore_tuning(tuning)?;
+        }
+        let exclusions = self.exclusions_guard();
+        for pid in exclusions.pids() {
+            self.send_exclusion(DriverComMessageType::ExcludePid, pid, 0, "")?;
+        }
+        for gid in exclusions.gids() {
+            self.send_exclusion(DriverComMessageType::ExcludeGid, 0, gid, "")?;
+        }
+        for image in exclusions.images() {
+            self.send_exclusion(DriverComMessageType::ExcludeImage, 0, 0, image)?;
+        }
+        Ok(())
+    }
+
+    /// Ask the minifilter to drop the operations of `pid`, e.g. a trusted process flooding its
+    /// queue. Pids are reused by Windows: prefer [`exclude_image_path`](Self::exclude_image_path)
+    /// for long-lived exclusions.
+    ///
+    /// Returns false if the minifilter already excluded this pid.
+    pub fn exclude_pid(&self, pid: u32) -> Result<bool, DriverError> {
+        let mut exclusions = self.exclusions_guard();
+        let excluded = self.send_exclusion(DriverComMessageType::ExcludePid, pid, 0, "")?;
+        exclusions.insert_pid(pid);
+        Ok(excluded)
+    }
+
+    /// Returns false if the minifilter did not exclude this pid.
+    pub fn include_pid(&self, pid: u32) -> Result<bool, DriverError> {
+        let mut exclusions = self.exclusions_guard();
+        let included = self.send_exclusion(DriverComMessageType::IncludePid, pid, 0, "")?;
+        exclusions.remove_pid(pid);
+        Ok(included)
+    }
+
+    /// Ask the minifilter to drop the operations of all pids related to `gid`, including the ones
+    /// started later.
+    ///
+    /// Returns false if the minifilter already excluded this gid.
+    pub fn exclude_gid(&self, gid: c_ulonglong) -> Result<bool, DriverError> {
+        let mut exclusions = self.exclusions_guard();
+        let excluded = self.send_exclusion(DriverComMessageType::ExcludeGid, 0, gid, "")?;
+        exclusions.insert_gid(gid);
+        Ok(excluded)
+    }
+
+    /// Returns false if the minifilter did not exclude this gid.
+    pub fn include_gid(&self, gid: c_ulonglong) -> Result<bool, DriverError> {
+        let mut exclusions = self.exclusions_guard();
+        let included = self.send_exclusion(DriverComMessageType::IncludeGid, 0, gid, "")?;
+        exclusions.remove_gid(gid);
+        Ok(included)
+    }
+
+    /// Ask the minifilter to drop the operations of the processes started from the image file
+    /// `path`, running or to come. `path` is converted with
+    /// [`ScanScope::normalize_file`] and compared case-insensitively.
+    ///
+    /// Returns false if the minifilter already excluded this image.
+    pub fn exclude_image_path(&self, path: &str) -> Result<bool, DriverError> {
+        let image = self.scan_scope().normalize_file(path)?;
+        let mut exclusions = self.exclusions_guard();
+        let excluded = self.send_exclusion(DriverComMessageType::ExcludeImage, 0, 0, &image)?;
+        exclusions.insert_image(&image);
+        Ok(excluded)
+    }
+
+    /// Returns false if the minifilter did not exclude this image.
+    pub fn include_image_path(&self, path: &str) -> Result<bool, DriverError> {
+        let image = self.scan_scope().normalize_file(path)?;
+        let mut exclusions = self.exclusions_guard();
+        let included = self.send_exclusion(DriverComMessageType::IncludeImage, 0, 0, &image)?;
+        exclusions.remove_image(&image);
+        Ok(included)
+    }
+
+    /// The current exclusions.
+    pub fn exclusions(&self) -> ExclusionSet {
+        self.exclusions_guard().clone()
+    }
+
+    /// Turns the entropy calculation of reads and writes on or off, e.g. to lower the overhead of
+    /// the minifilter on a busy machine.
+    pub fn set_entropy(&self, enabled: bool) -> Result<FilterConfig, DriverError> {
+        self.tune(DriverComMessageType::SetEntropy, enabled as u64)
+    }
+
+    /// Reads and writes of fewer than `min_size` bytes are no longer sampled for entropy.
+    pub fn set_entropy_min_size(&self, min_size: u32) -> Result<FilterConfig, DriverError> {
+        self.tune(DriverComMessageType::SetEntropyMinSize, min_size as u64)
+    }
+
+    /// Changes how many operations the minifilter keeps until they are fetched, the next ones
+    /// being dropped. The minifilter rejects 0 and values above
+    /// [`MAX_OPS_SAVE_LIMIT`](tuning::MAX_OPS_SAVE_LIMIT) with a [`DriverError::Send`].
+    pub fn set_max_ops(&self, max_ops: u32) -> Result<FilterConfig, DriverError> {
+        self.tune(DriverComMessageType::SetMaxOps, max_ops as u64)
+    }
+
+    /// Stops recording operations until [`resume_collection`](Self::resume_collection). Processes
+    /// are still tracked, so that gids stay valid.
+    ///
+    /// Denied with a [`DriverError::Send`] unless this process runs as an administrator or SYSTEM.
+    pub fn pause_collection(&self) -> Result<FilterConfig, DriverError> {
+        self.tune(DriverComMessageType::PauseCollection, 0)
+    }
+
+    pub fn resume_collection(&self) -> Result<FilterConfig, DriverError> {
+        self.tune(DriverComMessageType::ResumeCollection, 0)
+    }
+
+    /// Changes how long an operation in a verdict directory waits for its verdict. The minifilter
+    /// rejects 0 and durations above [`MAX_VERDICT_TIMEOUT`](verdict::MAX_VERDICT_TIMEOUT) with a
+    /// [`DriverError::Send`].
+    pub fn set_verdict_timeout(&self, timeout: Duration) -> Result<FilterConfig, DriverError> {
+        self.tune(
+            DriverComMessageType::SetVerdictTimeout,
+            timeout.as_millis().min(u32::MAX as u128) as u64,
+        )
+    }
+
+    /// Denies the operations whose verdict does not come in time, or when no
+    /// [`VerdictServer`](verdict::VerdictServer) is connected. They are allowed by default.
+    pub fn set_verdict_fail_closed(&self, fail_closed: bool) -> Result<FilterConfig, DriverError> {
+        self.tune(
+            DriverComMessageType::SetVerdictFailClosed,
+            fail_closed as u64,
+        )
+    }
+
+    /// The current settings of the minifilter.
+    pub fn get_config(&self) -> Result<FilterConfig, DriverError> {
+        self.send_filter_config(DriverComMessageType::GetConfig, 0)
+    }
+
+    /// The counters of the minifilter, e.g. to find out whether operations have been dropped
+    /// because they were not fetched fast enough.
+    pub fn stats(&self) -> Result<DriverStats, DriverError> {
+        let msg = Self::build_irp_msg(DriverComMessageType::GetStats, std::process::id(), 0, "")?;
+        let mut res = [0u8; DRIVER_STATS_SIZE];
+        let len = self.transport.send_message(&msg, Some(&mut res))? as usize;
+        DriverStats::from_bytes(&res[..len.min(DRIVER_STATS_SIZE)]).ok_or(
+            DriverError::MalformedReply(DecodeError::TruncatedHeader { buffer_len: len }),
+        )
+    }
+
+    /// Sends a tuning message, and keeps the resulting settings for [`reconnect`](Self::reconnect).
+    fn tune(
+        &self,
+        commsgtype: DriverComMessageType,
+        value: u64,
+    ) -> Result<FilterConfig, DriverError> {
+        let mut tuning = self.tuning.lock().unwrap_or_else(|e| e.into_inner());
+        let config = self.send_filter_config(commsgtype, value)?;
+        *tuning = Some(config);
+        Ok(config)
+    }
+
+    /// Sends the settings of `tuning` which differ from the defaults of a started minifilter.
+    fn restore_tuning(&self, tuning: FilterConfig) -> Result<(), DriverError> {
+        let default = FilterConfig::default();
+        if tuning.entropy_enabled != default.entropy_enabled {
+            self.send_filter_config(
+                DriverComMessageType::SetEntropy,
+                tuning.entropy_enabled as u64,
+            )?;
+        }
+        if tuning.entropy_min_size != default.entropy_min_size {
+            self.send_filter_config(
+                DriverComMessageType::SetEntropyMinSize,
+                tuning.entropy_min_size as u64,
+            )?;
+        }
+        if tuning.max_ops != default.max_ops {
+            self.send_filter_config(DriverComMessageType::SetMaxOps, tuning.max_ops as u64)?;
+        }
+        if tuning.collection_paused {
+            self.send_filter_config(DriverComMessageType::PauseCollection, 0)?;
+        }
+        if tuning.verdict_fail_closed != default.verdict_fail_closed {
+            self.send_filter_config(
+                DriverComMessageType::SetVerdictFailClosed,
+                tuning.verdict_fail_closed as u64,
+            )?;
+        }
+        if tuning.verdict_timeout_ms != default.verdict_timeout_ms {
+            self.send_filter_config(
+                DriverComMessageType::SetVerdictTimeout,
+                tuning.verdict_timeout_ms as u64,
+            )?;
+        }
+        Ok(())
+    }
+
+    fn send_filter_config(
+        &self,
+        commsgtype: DriverComMessageType,
+        value: u64,
+    ) -> Result<FilterConfig, DriverError> {
+        let msg = Self::build_irp_msg(commsgtype, std::process::id(), value, "")?;
+        let mut res = [0u8; FILTER_CONFIG_SIZE];
+        let len = self.transport.send_message(&msg, Some(&mut res))? as usize;
+        FilterConfig::from_bytes(&res[..len.min(FILTER_CONFIG_SIZE)]).ok_or(
+            DriverError::MalformedReply(DecodeError::TruncatedHeader { buffer_len: len }),
+        )
+    }
+
+    fn scan_scope(&self) -> MutexGuard<'_, ScanScope> {
+        self.scan_scope.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn verdict_directories_guard(&self) -> MutexGuard<'_, BTreeSet<String>> {
+        self.verdict_directories
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn exclusions_guard(&self) -> MutexGuard<'_, ExclusionSet> {
+        self.exclusions.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn send_gid_command(
+        &self,
+        commsgtype: DriverComMessageType,
+        gid: c_ulonglong,
+    ) -> Result<GidReport, DriverError> {
+        let msg = Self::build_irp_msg(commsgtype, std::process::id(), gid, "")?;
+        let mut res = vec![0u8; GID_REPORT_SIZE];
+        let len = self.transport.send_message(&msg, Some(&mut res))? as usize;
+        GidReport::from_bytes(gid, &res[..len.min(GID_REPORT_SIZE)])
+    }
+
+    fn send_scan_directory(
+        &self,
+        commsgtype: DriverComMessageType,
+        directory: &str,
+    ) -> Result<bool, DriverError> {
+        let msg = Self::build_irp_msg(commsgtype, std::process::id(), 0, directory)?;
+        let mut res = [0u8; 1];
+        self.transport.send_message(&msg, Some(&mut res))?;
+        Ok(res[0] != 0)
+    }
+
+    fn send_exclusion(
+        &self,
+        commsgtype: DriverComMessageType,
+        pid: u32,
+        gid: c_ulonglong,
+        image: &str,
+    ) -> Result<bool, DriverError> {
+        let msg = Self::build_irp_msg(commsgtype, pid, gid, image)?;
+        let mut res = [0u8; 1];
+        self.transport.send_message(&msg, Some(&mut res))?;
+        Ok(res[0] != 0)
+    }
+
+    /// Copies `bufstr` with its terminating NUL, as expected by the minifilter.
+    fn string_to_commessage_buffer(bufstr: &str) -> Result<BufPath, DriverError> {
+        let mut buf: BufPath = [0; 520];
+        for (i, c) in bufstr.encode_utf16().enumerate() {
+            if i >= MAX_PATH_LEN {
+                return Err(DriverError::PathTooLong {
+                    len: bufstr.encode_utf16().count(),
+                });
+            }
+            if c == 0 {
+                return Err(DriverError::PathWithNul { position: i });
+            }
+            buf[i] = c;
+        }
+        Ok(buf)
+    }
+
+    // TODO: move to ComMessage?
+    fn build_irp_msg(
+        commsgtype: DriverComMessageType,
+        pid: u32,
+        gid: u64,
+        path: &str,
+    ) -> Result<DriverComMessage, DriverError> {
+        Ok(DriverComMessage {
+            r#type: commsgtype as u32, // SetPid
+            pid,
+            gid,
+            path: Self::string_to_commessage_buffer(path)?,
+        })
+    }
+}
+
+/// See [`IOMessage`](crate::shared_def::IOMessage) struct and
+/// [this doc](https://docs.microsoft.com/en-us/windows-hardware/drivers/kernel/irp-major-function-codes).
+///
+/// Serialized with stable names (`"read"`, `"write"`...), see
+/// [`FileEvent`](crate::shared_def::file_event::FileEvent).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[repr(C)]
+pub enum IrpMajorOp {
+    /// Nothing happened
+    #[serde(rename = "none")]
+    IrpNone,
+    /// On read, any time following the successful completion of a create request.
+    #[serde(rename = "read")]
+    IrpRead,
+    /// On write, any time following the successful completion of a create request.
+    #[serde(rename = "write")]
+    IrpWrite,
+    /// Set Metadata about a file or file handle. In that case, [`FileChangeInfo`](crate::shared_def::FileChangeInfo) indicates
+    /// the nature of the modification.
+    #[serde(rename = "set_info")]
+    IrpSetInfo,
+    /// Open a handle to a file object or device object.
+    #[serde(rename = "create")]
+    IrpCreate,
+    /// File object handle has been closed
+    #[serde(rename = "cleanup")]
+    IrpCleanUp,
+}
+
+impl TryFrom<u8> for IrpMajorOp {
+    type Error = UnknownIrpOp;
+
+    /// Reads the `IRP_OP` of a `DRIVER_MESSAGE`.
+    fn try_from(b: u8) -> Result<IrpMajorOp, UnknownIrpOp> {
+        match b {
+            0 => Ok(IrpNone),
+            1 => Ok(IrpRead),
+            2 => Ok(IrpWrite),
+            3 => Ok(IrpSetInfo),
+            4 => Ok(IrpCreate),
+            5 => Ok(IrpCleanUp),
+            _ => Err(UnknownIrpOp(b)),
+        }
+    }
+}
+
+/// An `IRP_OP` unknown to this crate, sent by a newer minifilter or read from a corrupted message.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct UnknownIrpOp(pub u8);
+
+impl fmt::Display for UnknownIrpOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown irp operation {}", self.0)
+    }
+}
+
+impl Error for UnknownIrpOp {}
+
+/// See [`IOMessage`](crate::shared_def::IOMessage) struct and
+/// [this doc](https://docs.microsoft.com/en-us/windows/win32/api/fileapi/nf-fileapi-getdrivetypea).
+///
+/// Use a [`VolumeResolver`](crate::volume::VolumeResolver) to get the one of a file path.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[repr(C)]
+pub enum DriveType {
+    /// The drive type cannot be determined.
+    #[default]
+    DriveUnknown,
+    /// The root path is invalid; for example, there is no volume mounted at the specified path.
+    DriveNoRootDir,
+    /// The drive has removable media; for example, a floppy drive, thumb drive, or flash card reader.
+    DriveRemovable,
+    /// The drive has fixed media; for example, a hard disk drive or flash drive.
+    DriveFixed,
+    /// The drive is a remote (network) drive.
+    DriveRemote,
+    /// The drive is a CD-ROM drive.
+    DriveCDRom,
+    /// The drive is a RAM disk.
+    DriveRamDisk,
+}
+
+impl DriveType {
+    /// Reads the value returned by `GetDriveType`.
+    pub fn from_win32(drive_type: u32) -> DriveType {
+        match drive_type {
+            0 => DriveUnknown,
+            1 => DriveNoRootDir,
+            2 => DriveRemovable,
+            3 => DriveFixed,
+            4 => DriveRemote,
+            5 => DriveCDRom,
+            6 => DriveRamDisk,
+            _ => DriveNoRootDir,
+        }
+    }
+}
@@ -0,0 +1,208 @@
+//! Outcome of the commands applied by the minifilter to a whole gid family.
+
+use std::fmt;
+
+use windows::core::HRESULT;
+
+use crate::driver_comm::error::DriverError;
+use crate::shared_def::decoder::DecodeError;
+
+/// Max number of pids detailed in a [`GidReport`] (`MAX_GID_REPORT_PIDS` in `SharedDefs.h`), the
+/// command itself goes to every pid of the gid.
+pub const MAX_GID_REPORT_PIDS: usize = 1024;
+
+/// Size of the image file name of a [`PidOutcome`], the terminating NUL included
+/// (`MAX_IMAGE_NAME_LENGTH` in `SharedDefs.h`).
+pub const MAX_IMAGE_NAME_LENGTH: usize = 16;
+
+/// Size of a `PID_OUTCOME`.
+const PID_OUTCOME_SIZE: usize = 8 + MAX_IMAGE_NAME_LENGTH;
+
+/// Size of the reply buffer (`sizeof(GID_REPORT)`).
+pub const GID_REPORT_SIZE: usize = 8 + PID_OUTCOME_SIZE * MAX_GID_REPORT_PIDS;
+
+/// `STATUS_NO_SUCH_GROUP`: the gid is unknown to the minifilter, or has already ended.
+pub const STATUS_NO_SUCH_GROUP: HRESULT = HRESULT(0xC000_0066_u32 as i32);
+
+/// Outcome of a command on one pid (`PID_OUTCOME` in `SharedDefs.h`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PidOutcome {
+    pub pid: u32,
+    /// Image file name, truncated to 15 chars by the kernel. Empty if the pid was not found.
+    pub image_name: String,
+    /// The `NTSTATUS` of the operation, e.g. `STATUS_INVALID_CID` if the pid has exited.
+    pub status: HRESULT,
+}
+
+impl PidOutcome {
+    pub fn is_ok(&self) -> bool {
+        self.status.0 >= 0
+    }
+}
+
+/// Reply of the minifilter to [`try_kill`](super::Driver::try_kill),
+/// [`suspend_gid`](super::Driver::suspend_gid) and [`resume_gid`](super::Driver::resume_gid)
+/// (`GID_REPORT` in `SharedDefs.h`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GidReport {
+    pub gid: u64,
+    /// [`STATUS_NO_SUCH_GROUP`] if the gid is unknown, `S_OK` otherwise.
+    pub status: HRESULT,
+    /// Number of pids in the gid, may be more than `pids.len()` (see [`MAX_GID_REPORT_PIDS`]).
+    pub num_pids: u32,
+    pub pids: Vec<PidOutcome>,
+}
+
+impl GidReport {
+    /// Reads the reply of the minifilter, `buf` being cut to the length it returned.
+    pub fn from_bytes(gid: u64, buf: &[u8]) -> Result<GidReport, DriverError> {
+        let read_u32 =
+            |offset: usize| u32::from_ne_bytes(buf[offset..offset + 4].try_into().unwrap());
+        if buf.len() < 8 {
+            return Err(DriverError::MalformedReply(DecodeError::TruncatedHeader {
+                buffer_len: buf.len(),
+            }));
+        }
+        let num_pids = read_u32(4);
+        let available = ((buf.len() - 8) / PID_OUTCOME_SIZE).min(MAX_GID_REPORT_PIDS);
+        let pids = (0..(num_pids as usize).min(available))
+            .map(|i| {
+                let offset = 8 + PID_OUTCOME_SIZE * i;
+                let image_name = &buf[offset + 8..offset + PID_OUTCOME_SIZE];
+                let end = image_name.iter().position(|c| *c == 0).unwrap_or(0);
+                PidOutcome {
+                    pid: read_u32(offset),
+                    image_name: String::from_utf8_lossy(&image_name[..end]).into_owned(),
+                    status: HRESULT(read_u32(offset + 4) as i32),
+                }
+            })
+            .collect();
+        Ok(GidReport {
+            gid,
+            status: HRESULT(read_u32(0) as i32),
+            num_pids,
+            pids,
+        })
+    }
+
+    /// Writes the report as the minifilter does, in a buffer of [`GID_REPORT_SIZE`].
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = vec![0u8; GID_REPORT_SIZE];
+        buf[0..4].copy_from_slice(&self.status.0.to_ne_bytes());
+        buf[4..8].copy_from_slice(&self.num_pids.to_ne_bytes());
+        for (i, outcome) in self.pids.iter().take(MAX_GID_REPORT_PIDS).enumerate() {
+            let offset = 8 + PID_OUTCOME_SIZE * i;
+            buf[offset..offset + 4].copy_from_slice(&outcome.pid.to_ne_bytes());
+            buf[offset + 4..offset + 8].copy_from_slice(&outcome.status.0.to_ne_bytes());
+            let image_name = outcome.image_name.as_bytes();
+            let len = image_name.len().min(MAX_IMAGE_NAME_LENGTH - 1);
+            buf[offset + 8..offset + 8 + len].copy_from_slice(&image_name[..len]);
+        }
+        buf
+    }
+
+    /// The gid exists and the command succeeded on every one of its pids.
+    pub fn is_complete(&self) -> bool {
+        self.status.0 >= 0
+            && self.pids.len() == self.num_pids as usize
+            && self.pids.iter().all(PidOutcome::is_ok)
+    }
+
+    /// The pids on which the command failed.
+    pub fn failed(&self) -> impl Iterator<Item = &PidOutcome> {
+        self.pids.iter().filter(|outcome| !outcome.is_ok())
+    }
+}
+
+impl fmt::Display for GidReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.status.0 < 0 {
+            return write!(f, "gid {}: {:#010X}", self.gid, self.status.0);
+        }
+        write!(
+            f,
+            "gid {}: {}/{} pids",
+            self.gid,
+            self.pids.iter().filter(|outcome| outcome.is_ok()).count(),
+            self.num_pids
+        )?;
+        for outcome in self.failed() {
+            write!(
+                f,
+                ", pid {} failed ({:#010X})",
+                outcome.pid, outcome.status.0
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// What became of a pid after a [`KillReport`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum KillOutcome {
+    /// Terminated by the minifilter, and no longer running.
+    Killed,
+    /// The minifilter could not open it, and it is not running: it had already exited.
+    AlreadyGone,
+    /// Still running after the last check.
+    Survived,
+}
+
+/// A pid of a [`KillReport`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PidKill {
+    pub pid: u32,
+    pub image_name: String,
+    /// The `NTSTATUS` returned by the minifilter.
+    pub status: HRESULT,
+    pub outcome: KillOutcome,
+}
+
+/// A [`GidReport`] of [`try_kill`](super::Driver::try_kill), checked in user mode: a pid may
+/// survive a successful `ZwTerminateProcess` for a while, or have exited before.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KillReport {
+    pub gid: u64,
+    /// [`STATUS_NO_SUCH_GROUP`] if the gid is unknown, `S_OK` otherwise.
+    pub status: HRESULT,
+    /// Number of pids in the gid, may be more than `pids.len()` (see [`MAX_GID_REPORT_PIDS`]).
+    pub num_pids: u32,
+    pub pids: Vec<PidKill>,
+}
+
+impl KillReport {
+    /// Every pid of the gid is known to be gone.
+    pub fn is_complete(&self) -> bool {
+        self.status.0 >= 0
+            && self.pids.len() == self.num_pids as usize
+            && self.survivors().next().is_none()
+    }
+
+    pub fn survivors(&self) -> impl Iterator<Item = &PidKill> {
+        self.pids
+            .iter()
+            .filter(|pid| pid.outcome == KillOutcome::Survived)
+    }
+}
+
+impl fmt::Display for KillReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.status.0 < 0 {
+            return write!(f, "gid {}: {:#010X}", self.gid, self.status.0);
+        }
+        write!(f, "gid {}:", self.gid)?;
+        for (i, pid) in self.pids.iter().enumerate() {
+            let separator = if i == 0 { " " } else { ", " };
+            write!(
+                f,
+                "{separator}{} ({}) {:?}",
+                pid.pid, pid.image_name, pid.outcome
+            )?;
+        }
+        let missing = self.num_pids as usize - self.pids.len().min(self.num_pids as usize);
+        if missing > 0 {
+            write!(f, " and {missing} more")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,283 @@
+//! The directories monitored by the minifilter.
+//!
+//! The minifilter flags the files located in its *scan directories* with
+//! [`FileLocationInfo`](crate::shared_def::FileLocationInfo): `FileProtected`, or `FileMovedIn` /
+//! `FileMovedOut` when a rename crosses their boundary. It compares them with the device paths
+//! of the files (`\Device\HarddiskVolume3\Users\...`), so a [`ScanScope`] converts the DOS paths
+//! (`C:\Users\...`) before sending them and keeps track of what has been sent, to send it again
+//! when the minifilter has been restarted.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Debug;
+
+use crate::driver_comm::error::DriverError;
+use crate::shared_def::nt_path::{NtPath, NtRoot};
+
+/// Resolves a drive letter to the device it is mounted on.
+pub trait DosDevices: Debug + Send {
+    /// The device of `drive` (e.g. `\Device\HarddiskVolume3` for `C`), if any.
+    fn device_of(&self, drive: char) -> Option<String>;
+}
+
+/// Asks Windows, with `QueryDosDevice`.
+#[derive(Debug, Default)]
+pub struct DosDevicesLive;
+
+#[cfg(windows)]
+impl DosDevices for DosDevicesLive {
+    fn device_of(&self, drive: char) -> Option<String> {
+        use widestring::U16CString;
+        use windows::core::PCWSTR;
+        use windows::Win32::Storage::FileSystem::QueryDosDeviceW;
+
+        let name = U16CString::from_str(format!("{drive}:")).ok()?;
+        let mut buffer = [0u16; 260];
+        let len = unsafe { QueryDosDeviceW(PCWSTR(name.as_ptr()), Some(&mut buffer)) } as usize;
+        // Multi-string: the first one is the current mapping
+        let end = buffer[..len].iter().position(|c| *c == 0)?;
+        (end > 0).then(|| String::from_utf16_lossy(&buffer[..end]))
+    }
+}
+
+/// There are no drives outside of Windows: use another [`DosDevices`] there.
+#[cfg(not(windows))]
+impl DosDevices for DosDevicesLive {
+    fn device_of(&self, _drive: char) -> Option<String> {
+        None
+    }
+}
+
+/// A fixed mapping, e.g. `{'C': r"\Device\HarddiskVolume3"}`.
+impl DosDevices for HashMap<char, String> {
+    fn device_of(&self, drive: char) -> Option<String> {
+        self.get(&drive.to_ascii_uppercase()).cloned()
+    }
+}
+
+/// The set of scan directories, as sent to the minifilter.
+///
+/// Directories are stored in the driver form, with a trailing `\` since the minifilter matches
+/// them as prefixes of the file paths (`...\Dev\` does not contain `...\Dev2\a.txt`).
+#[derive(Debug)]
+pub struct ScanScope {
+    directories: BTreeSet<String>,
+    dos_devices: Box<dyn DosDevices>,
+}
+
+impl Default for ScanScope {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ScanScope {
+    pub fn new() -> ScanScope {
+        ScanScope {
+            directories: BTreeSet::new(),
+            dos_devices: Box::new(DosDevicesLive),
+        }
+    }
+
+    pub fn dos_devices(mut self, dos_devices: Box<dyn DosDevices>) -> ScanScope {
+        self.dos_devices = dos_devices;
+        self
+    }
+
+    pub fn build(self) -> ScanScope {
+        self
+    }
+
+    /// Converts `path` to the form compared by the minifilter:
+    /// - `C:\Users\Dev` and `\\?\C:\Users\Dev` become `\Device\HarddiskVolume3\Users\Dev\`,
+    /// - `\Device\...` and `\??\C:\...` paths are accepted as well,
+    /// - `/` are read as `\`, repeated separators are merged, as [`NtPath`] does.
+    ///
+    /// Relative paths, UNC paths, alternate data streams and drives without device are rejected
+    /// with [`DriverError::InvalidScanDirectory`].
+    pub fn normalize(&self, path: &str) -> Result<String, DriverError> {
+        let (mut normalized, _) =
+            self.device_path(path)
+                .ok_or_else(|| DriverError::InvalidScanDirectory {
+                    path: path.to_string(),
+                })?;
+        normalized.push('\\');
+        Ok(normalized)
+    }
+
+    /// Converts the path of a file, e.g. the image of a process, as [`normalize`](Self::normalize)
+    /// does for directories: `C:\Program Files\App\app.exe` becomes
+    /// `\Device\HarddiskVolume3\Program Files\App\app.exe`.
+    ///
+    /// The same paths are rejected, with [`DriverError::InvalidFilePath`], as well as the paths
+    /// ending with a separator and the roots of the volumes.
+    pub fn normalize_file(&self, path: &str) -> Result<String, DriverError> {
+        let invalid = || DriverError::InvalidFilePath {
+            path: path.to_string(),
+        };
+        if path.ends_with(['\\', '/']) {
+            return Err(invalid());
+        }
+        match self.device_path(path) {
+            Some((normalized, components)) if components > 0 => Ok(normalized),
+            _ => Err(invalid()),
+        }
+    }
+
+    /// The device path of `path` without trailing separator, and its number of components after
+    /// the device.
+    fn device_path(&self, path: &str) -> Option<(String, usize)> {
+        let path = NtPath::parse(path)?.to_device_path(self.dos_devices.as_ref())?;
+        let NtRoot::Device(device) = path.root() else {
+            return None;
+        };
+        if path.stream().is_some() {
+            return None;
+        }
+        let mut normalized = device.clone();
+        for component in path.components() {
+            normalized.push('\\');
+            normalized.push_str(component);
+        }
+        Some((normalized, path.components().len()))
+    }
+
+    /// Records `directory`, already normalized. Returns false if it was already there.
+    pub(crate) fn insert(&mut self, directory: String) -> bool {
+        self.directories.insert(directory)
+    }
+
+    /// Forgets `directory`, already normalized. Returns false if it was not there.
+    pub(crate) fn remove(&mut self, directory: &str) -> bool {
+        self.directories.remove(directory)
+    }
+
+    /// Whether `path`, once normalized, is one of the scan directories.
+    pub fn contains(&self, path: &str) -> bool {
+        self.normalize(path)
+            .map(|directory| self.directories.contains(&directory))
+            .unwrap_or(false)
+    }
+
+    /// The scan directories, in the driver form.
+    pub fn directories(&self) -> impl Iterator<Item = &str> {
+        self.directories.iter().map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.directories.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.directories.is_empty()
+    }
+}
+
+#[cfg(test)]
+#[doc(hidden)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::driver_comm::error::DriverError;
+    use crate::driver_comm::scan_scope::ScanScope;
+
+    fn scope() -> ScanScope {
+        ScanScope::new()
+            .dos_devices(Box::new(HashMap::from([
+                ('C', r"\Device\HarddiskVolume3".to_string()),
+                ('D', r"\Device\HarddiskVolume5\".to_string()),
+            ])))
+            .build()
+    }
+
+    #[test]
+    fn test_normalize() {
+        let scope = scope();
+        for (path, expected) in [
+            (r"C:\Users\Dev", r"\Device\HarddiskVolume3\Users\Dev\"),
+            (r"c:\Users\Dev\\", r"\Device\HarddiskVolume3\Users\Dev\"),
+            (
+                "C:/Users/Dev/Documents",
+                r"\Device\HarddiskVolume3\Users\Dev\Documents\",
+            ),
+            (r"\\?\D:\Shares", r"\Device\HarddiskVolume5\Shares\"),
+            (r"\??\D:\Shares", r"\Device\HarddiskVolume5\Shares\"),
+            (r"D:", r"\Device\HarddiskVolume5\"),
+            (
+                r"\\?\GLOBALROOT\Device\HarddiskVolume2\Data",
+                r"\Device\HarddiskVolume2\Data\",
+            ),
+            (
+                r"\Device\HarddiskVolume2\Data",
+                r"\Device\HarddiskVolume2\Data\",
+            ),
+        ] {
+            assert_eq!(scope.normalize(path).unwrap(), expected, "{path}");
+        }
+
+        for path in [
+            r"Users\Dev",
+            r"E:\Data",
+            r"C:Users",
+            r"\\server\share",
+            r"C:\Users\..\Windows",
+            r"C:\Users\Dev:stream",
+            "",
+        ] {
+            assert_eq!(
+                scope.normalize(path),
+                Err(DriverError::InvalidScanDirectory {
+                    path: path.to_string()
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn test_normalize_file() {
+        let scope = scope();
+        for (path, expected) in [
+            (
+                r"C:\Program Files\App\app.exe",
+                r"\Device\HarddiskVolume3\Program Files\App\app.exe",
+            ),
+            (
+                r"\\?\D:/Tools//build.exe",
+                r"\Device\HarddiskVolume5\Tools\build.exe",
+            ),
+            (
+                r"\Device\HarddiskVolume2\backup.exe",
+                r"\Device\HarddiskVolume2\backup.exe",
+            ),
+        ] {
+            assert_eq!(scope.normalize_file(path).unwrap(), expected, "{path}");
+        }
+
+        for path in [
+            r"C:\Program Files\App\",
+            r"C:",
+            r"D:\",
+            r"\Device\HarddiskVolume2",
+            r"app.exe",
+            r"E:\app.exe",
+        ] {
+            assert_eq!(
+                scope.normalize_file(path),
+                Err(DriverError::InvalidFilePath {
+                    path: path.to_string()
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn test_contains() {
+        let mut scope = scope();
+        assert!(scope.insert(scope.normalize(r"C:\Users\Dev").unwrap()));
+        assert!(!scope.insert(scope.normalize(r"C:\Users\Dev\").unwrap()));
+        assert!(scope.contains(r"c:/Users/Dev"));
+        assert!(!scope.contains(r"C:\Users"));
+        assert_eq!(scope.len(), 1);
+        assert!(scope.remove(r"\Device\HarddiskVolume3\Users\Dev\"));
+        assert!(scope.is_empty());
+    }
+}
@@ -0,0 +1,337 @@
+//! A supervised connection to the minifilter, which survives its restarts.
+//!
+//! When FSFilter is stopped (`sc stop FSFilter`) or unloaded, the communication port is
+//! disconnected and every message fails with [`DriverError::Disconnected`]. A [`DriverSession`]
+//! polls the minifilter in its own thread and, on disconnection, tries to reconnect with an
+//! exponential [`Backoff`]. Once reconnected, this app is registered again
+//! ([`driver_set_app_pid`](Driver::driver_set_app_pid)) and the scan directories are restored.
+//!
+//! The consumer receives the [`IOMessage`]s and [`ProcessEvent`]s along with the lifecycle of the
+//! connection, as [`SessionEvent`]s:
+//!
+//! ```no_run
+//! use minifilter_rs::driver_comm::session::{DriverSession, SessionEvent};
+//! use minifilter_rs::driver_comm::Driver;
+//!
+//! let driver = Driver::open_kernel_driver_com().unwrap();
+//! let (events, _session) = DriverSession::new(driver).spawn();
+//! for event in events {
+//!     match event {
+//!         SessionEvent::IoMessage(iomsg) => println!("{:?}", iomsg),
+//!         SessionEvent::Connected => println!("connected"),
+//!         SessionEvent::Disconnected(e) => println!("{e}, reconnecting..."),
+//!         _ => {}
+//!     }
+//! }
+//! ```
+
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::driver_comm::error::DriverError;
+use crate::driver_comm::transport::{DriverTransport, FilterPort};
+use crate::driver_comm::Driver;
+use crate::shared_def::{Event, IOMessage, ProcessEvent};
+
+/// Delays between reconnection attempts: `initial`, then doubled after each failure, up to `max`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    current: Duration,
+}
+
+impl Default for Backoff {
+    /// From 100 ms to 30 s.
+    fn default() -> Self {
+        Backoff::new(Duration::from_millis(100), Duration::from_secs(30))
+    }
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Backoff {
+        Backoff {
+            initial,
+            max: max.max(initial),
+            current: initial,
+        }
+    }
+
+    /// The delay before the next attempt.
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.current;
+        self.current = (self.current * 2).min(self.max);
+        delay
+    }
+
+    /// Back to `initial`, once connected.
+    pub fn reset(&mut self) {
+        self.current = self.initial;
+    }
+}
+
+/// What happens in a [`DriverSession`].
+#[derive(Debug, Clone)]
+pub enum SessionEvent {
+    /// This app is registered to the minifilter: first on [`spawn`](DriverSession::spawn), then
+    /// after each successful reconnection.
+    Connected,
+    /// The port has been disconnected. The session is reconnecting.
+    Disconnected(DriverError),
+    /// A reconnection attempt failed, the next one starts in `retry_in`.
+    ReconnectFailed {
+        attempt: u32,
+        error: DriverError,
+        retry_in: Duration,
+    },
+    /// An i/o activity reported by the minifilter.
+    IoMessage(IOMessage),
+    /// The creation or exit of a process recorded by the minifilter.
+    Process(ProcessEvent),
+    /// `count` operations were dropped by the minifilter, see [`Event::EventsLost`].
+    EventsLost { count: u64 },
+    /// An error which did not break the connection, e.g. a malformed reply.
+    Error(DriverError),
+}
+
+/// Polls a [`Driver`] and reconnects it when needed. See the [module](self) documentation.
+#[derive(Debug)]
+pub struct DriverSession<T: DriverTransport = FilterPort> {
+    driver: Driver<T>,
+    backoff: Backoff,
+    poll_interval: Duration,
+}
+
+impl<T: DriverTransport + Send + 'static> DriverSession<T> {
+    /// Supervises `driver`, which should be connected. Its scan directories are kept.
+    pub fn new(driver: Driver<T>) -> DriverSession<T> {
+        DriverSession {
+            driver,
+            backoff: Backoff::default(),
+            poll_interval: Duration::from_millis(10),
+        }
+    }
+
+    pub fn backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// How long to wait when the minifilter has nothing to report (10 ms by default).
+    pub fn poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Starts the session in a new thread. It runs until the [`SessionHandle`] is stopped or
+    /// dropped, or until the receiver is dropped.
+    pub fn spawn(self) -> (Receiver<SessionEvent>, SessionHandle) {
+        let (tx_events, rx_events) = channel();
+        let (tx_stop, rx_stop) = channel();
+        let thread = thread::spawn(move || self.run(&tx_events, &rx_stop));
+        (
+            rx_events,
+            SessionHandle {
+                stop: Some(tx_stop),
+                thread: Some(thread),
+            },
+        )
+    }
+
+    fn run(mut self, tx_events: &Sender<SessionEvent>, rx_stop: &Receiver<()>) {
+        let mut vecnew: Vec<u8> = Vec::with_capacity(self.driver.config().buffer_size());
+
+        let mut connected = match self.driver.driver_set_app_pid() {
+            Ok(()) => tx_events.send(SessionEvent::Connected).is_ok(),
+            Err(e) => self.reconnect(e, tx_events, rx_stop),
+        };
+
+        while connected {
+            let mut receiver_dropped = false;
+            let polled = self.driver.poll_events(&mut vecnew, |event| {
+                let event = match event {
+                    Ok(Event::Io(iomsg)) => SessionEvent::IoMessage(iomsg),
+                    Ok(Event::Process(process_event)) => SessionEvent::Process(process_event),
+                    Ok(Event::EventsLost { count }) => SessionEvent::EventsLost { count },
+                    Err(e) => SessionEvent::Error(e),
+                };
+                receiver_dropped = tx_events.send(event).is_err();
+                !receiver_dropped
+            });
+            if receiver_dropped {
+                return;
+            }
+            let wait = match polled {
+                Ok(0) => self.poll_interval,
+                Ok(_) => Duration::ZERO,
+                Err(e) if e.is_disconnected() => {
+                    connected = self.reconnect(e, tx_events, rx_stop);
+                    continue;
+                }
+                Err(e) => {
+                    if tx_events.send(SessionEvent::Error(e)).is_err() {
+                        return;
+                    }
+                    self.poll_interval
+                }
+            };
+            connected = !Self::stopped(rx_stop, wait);
+        }
+    }
+
+    /// Reconnects until it succeeds, or the session is stopped (false is then returned).
+    fn reconnect(
+        &mut self,
+        error: DriverError,
+        tx_events: &Sender<SessionEvent>,
+        rx_stop: &Receiver<()>,
+    ) -> bool {
+        if tx_events.send(SessionEvent::Disconnected(error)).is_err() {
+            return false;
+        }
+        self.backoff.reset();
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let delay = self.backoff.next_delay();
+            if Self::stopped(rx_stop, delay) {
+                return false;
+            }
+            match self
+                .driver
+                .reconnect()
+                .and_then(|_| self.driver.driver_set_app_pid())
+            {
+                Ok(()) => return tx_events.send(SessionEvent::Connected).is_ok(),
+                Err(error) => {
+                    let event = SessionEvent::ReconnectFailed {
+                        attempt,
+                        error,
+                        retry_in: self.backoff.current,
+                    };
+                    if tx_events.send(event).is_err() {
+                        return false;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Waits for `timeout`, unless the session is stopped meanwhile.
+    fn stopped(rx_stop: &Receiver<()>, timeout: Duration) -> bool {
+        if timeout.is_zero() {
+            return !matches!(rx_stop.try_recv(), Err(TryRecvError::Empty));
+        }
+        !matches!(
+            rx_stop.recv_timeout(timeout),
+            Err(RecvTimeoutError::Timeout)
+        )
+    }
+}
+
+/// Controls a running [`DriverSession`]. Dropping it stops the session too, without waiting.
+#[derive(Debug)]
+pub struct SessionHandle {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl SessionHandle {
+    /// Stops the session and waits for its thread to end.
+    pub fn stop(mut self) {
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for SessionHandle {
+    fn drop(&mut self) {
+        self.stop.take();
+    }
+}
+
+#[cfg(test)]
+#[doc(hidden)]
+mod tests {
+    use std::sync::mpsc::Receiver;
+    use std::time::Duration;
+
+    use crate::driver_comm::error::DriverError;
+    use crate::driver_comm::mock::fixtures::scan_scope;
+    use crate::driver_comm::mock::{MockDriver, MockEvent};
+    use crate::driver_comm::session::{Backoff, DriverSession, SessionEvent};
+    use crate::driver_comm::{Driver, DriverComMessageType, IrpMajorOp};
+
+    fn next(events: &Receiver<SessionEvent>) -> SessionEvent {
+        events.recv_timeout(Duration::from_secs(5)).unwrap()
+    }
+
+    #[test]
+    fn test_backoff() {
+        let mut backoff = Backoff::new(Duration::from_millis(100), Duration::from_millis(500));
+        let delays: Vec<u128> = (0..5).map(|_| backoff.next_delay().as_millis()).collect();
+        assert_eq!(delays, vec![100, 200, 400, 500, 500]);
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_session_reconnects() {
+        let mock = MockDriver::new();
+        let driver = Driver::with_transport(mock.clone()).with_scan_scope(scan_scope());
+        driver.add_scan_directory(r"C:\Users\Dev").unwrap();
+        mock.push_event(MockEvent::new(10, 1, IrpMajorOp::IrpWrite, r"C:\a.txt"));
+
+        let (events, session) = DriverSession::new(driver)
+            .backoff(Backoff::new(
+                Duration::from_millis(1),
+                Duration::from_millis(4),
+            ))
+            .poll_interval(Duration::from_millis(1))
+            .spawn();
+        assert!(matches!(next(&events), SessionEvent::Connected));
+        assert!(matches!(next(&events), SessionEvent::IoMessage(iomsg) if iomsg.pid == 10));
+
+        mock.refuse_connections(2);
+        mock.unload();
+        assert!(matches!(
+            next(&events),
+            SessionEvent::Disconnected(DriverError::Disconnected)
+        ));
+        for (expected, delay) in [(1, 2), (2, 4)] {
+            match next(&events) {
+                SessionEvent::ReconnectFailed {
+                    attempt,
+                    error: DriverError::Connect(_),
+                    retry_in,
+                } => {
+                    assert_eq!(attempt, expected);
+                    assert_eq!(retry_in, Duration::from_millis(delay));
+                }
+                event => panic!("unexpected {event:?}"),
+            }
+        }
+        assert!(matches!(next(&events), SessionEvent::Connected));
+
+        assert_eq!(mock.connections(), 2);
+        assert_eq!(
+            mock.scan_directories(),
+            vec![r"\Device\HarddiskVolume3\Users\Dev\".to_string()]
+        );
+        let set_pids = mock
+            .received()
+            .iter()
+            .filter(|msg| msg.r#type == DriverComMessageType::SetPid as u32)
+            .count();
+        assert_eq!(set_pids, 2);
+
+        mock.push_event(MockEvent::new(11, 1, IrpMajorOp::IrpRead, r"C:\b.txt"));
+        assert!(matches!(next(&events), SessionEvent::IoMessage(iomsg) if iomsg.pid == 11));
+
+        session.stop();
+        assert!(events.recv().is_err());
+    }
+}
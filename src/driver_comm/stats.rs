@@ -0,0 +1,103 @@
+//! Counters of the minifilter, to know whether the events received are all the events recorded,
+//! see [`DriverStats`].
+
+use std::fmt;
+
+use crate::driver_comm::IrpMajorOp;
+
+/// Number of [`IrpMajorOp`]s counted by the minifilter (`IRP_MAJOR_OP_COUNT` in `SharedDefs.h`).
+pub const IRP_MAJOR_OP_COUNT: usize = 6;
+
+const _: () = assert!(IrpMajorOp::IrpCleanUp as usize + 1 == IRP_MAJOR_OP_COUNT);
+
+/// Size of a `DRIVER_STATS`.
+pub const DRIVER_STATS_SIZE: usize = 40 + 8 * IRP_MAJOR_OP_COUNT;
+
+/// Reply of the minifilter to [`GetStats`](super::DriverComMessageType::GetStats): its counters
+/// since it started (`DRIVER_STATS` in `SharedDefs.h`).
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct DriverStats {
+    /// Operations added to the queue.
+    pub ops_queued: u64,
+    /// Operations discarded because the queue was full, see
+    /// [`set_max_ops`](super::Driver::set_max_ops).
+    pub ops_dropped: u64,
+    /// Operations added to the queue, indexed by [`IrpMajorOp`].
+    pub ops_by_irp: [u64; IRP_MAJOR_OP_COUNT],
+    /// Reads and writes whose entropy has been calculated.
+    pub entropy_calcs: u64,
+    /// Operations waiting to be fetched.
+    pub queue_depth: u32,
+    /// Volume instances the minifilter is attached to.
+    pub attached_instances: u32,
+    /// Gids currently tracked.
+    pub gids: u64,
+}
+
+impl DriverStats {
+    /// Reads the reply of the minifilter, `None` if it is too short.
+    pub fn from_bytes(buf: &[u8]) -> Option<DriverStats> {
+        if buf.len() < DRIVER_STATS_SIZE {
+            return None;
+        }
+        let read_u64 =
+            |offset: usize| u64::from_ne_bytes(buf[offset..offset + 8].try_into().unwrap());
+        let read_u32 =
+            |offset: usize| u32::from_ne_bytes(buf[offset..offset + 4].try_into().unwrap());
+        let irp_end = 16 + 8 * IRP_MAJOR_OP_COUNT;
+        Some(DriverStats {
+            ops_queued: read_u64(0),
+            ops_dropped: read_u64(8),
+            ops_by_irp: std::array::from_fn(|i| read_u64(16 + 8 * i)),
+            entropy_calcs: read_u64(irp_end),
+            queue_depth: read_u32(irp_end + 8),
+            attached_instances: read_u32(irp_end + 12),
+            gids: read_u64(irp_end + 16),
+        })
+    }
+
+    pub fn to_bytes(self) -> [u8; DRIVER_STATS_SIZE] {
+        let mut buf = [0u8; DRIVER_STATS_SIZE];
+        buf[0..8].copy_from_slice(&self.ops_queued.to_ne_bytes());
+        buf[8..16].copy_from_slice(&self.ops_dropped.to_ne_bytes());
+        for (i, ops) in self.ops_by_irp.iter().enumerate() {
+            buf[16 + 8 * i..24 + 8 * i].copy_from_slice(&ops.to_ne_bytes());
+        }
+        let irp_end = 16 + 8 * IRP_MAJOR_OP_COUNT;
+        buf[irp_end..irp_end + 8].copy_from_slice(&self.entropy_calcs.to_ne_bytes());
+        buf[irp_end + 8..irp_end + 12].copy_from_slice(&self.queue_depth.to_ne_bytes());
+        buf[irp_end + 12..irp_end + 16].copy_from_slice(&self.attached_instances.to_ne_bytes());
+        buf[irp_end + 16..irp_end + 24].copy_from_slice(&self.gids.to_ne_bytes());
+        buf
+    }
+
+    /// Operations of type `irp_op` added to the queue.
+    pub fn ops(&self, irp_op: IrpMajorOp) -> u64 {
+        self.ops_by_irp[irp_op as usize]
+    }
+
+    /// Operations dropped since `previous` was received. The counters restart from 0 with the
+    /// minifilter, all of its drops are new then.
+    pub fn dropped_since(&self, previous: &DriverStats) -> u64 {
+        if self.ops_dropped >= previous.ops_dropped && self.ops_queued >= previous.ops_queued {
+            self.ops_dropped - previous.ops_dropped
+        } else {
+            self.ops_dropped
+        }
+    }
+}
+
+impl fmt::Display for DriverStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ops queued, {} dropped, {} waiting, {} entropies, {} instances, {} gids",
+            self.ops_queued,
+            self.ops_dropped,
+            self.queue_depth,
+            self.entropy_calcs,
+            self.attached_instances,
+            self.gids
+        )
+    }
+}
@@ -0,0 +1,488 @@
+//! Driver events as a [`Stream`], for async consumers (`async` feature).
+//!
+//! An [`EventStream`] owns a polling thread which fetches the [`ReplyIrp`](crate::shared_def::ReplyIrp)s
+//! and queues their [`Event`]s in a bounded buffer: the
+//! [`IOMessage`](crate::shared_def::IOMessage)s, and the process creations and exits which open
+//! and close the records of a [`Worker`](crate::worker::Worker). The polling interval adapts to the
+//! activity: it is reset to its minimum as soon as the minifilter has something to say, and
+//! doubles up to its maximum while it has not.
+//!
+//! The operations dropped by the minifilter itself, reported by [`Event::EventsLost`], are also
+//! counted by [`lost`](EventStream::lost).
+//!
+//! ```no_run
+//! use std::sync::Arc;
+//! use futures::StreamExt;
+//! use minifilter_rs::driver_comm::stream::{EventStream, OverflowPolicy};
+//! use minifilter_rs::driver_comm::Driver;
+//!
+//! # futures::executor::block_on(async {
+//! let driver = Arc::new(Driver::open_kernel_driver_com().unwrap());
+//! driver.driver_set_app_pid().unwrap();
+//! let mut events = EventStream::builder(driver)
+//!     .capacity(4096)
+//!     .overflow(OverflowPolicy::DropOldest)
+//!     .build();
+//! while let Some(event) = events.next().await {
+//!     println!("{:?}", event);
+//! }
+//! # });
+//! ```
+
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use std::time::Duration;
+
+use futures::Stream;
+
+use crate::driver_comm::error::DriverError;
+use crate::driver_comm::transport::{DriverTransport, FilterPort};
+use crate::driver_comm::Driver;
+use crate::shared_def::Event;
+
+/// What to do with a new [`Event::Io`] when the buffer of an [`EventStream`] is full. The other
+/// events are always queued.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Stop polling until the consumer makes room. Meanwhile, the minifilter keeps the operations
+    /// (and drops them itself past its own limit).
+    Block,
+    /// Make room by dropping the oldest buffered message.
+    DropOldest,
+    /// Drop the new message.
+    DropNewest,
+}
+
+type Item = Result<Event, DriverError>;
+
+#[derive(Debug, Default)]
+struct Buffer {
+    items: VecDeque<Item>,
+    waker: Option<Waker>,
+    /// The polling thread has ended (the port is disconnected)
+    finished: bool,
+    /// The stream has been dropped
+    cancelled: bool,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    buffer: Mutex<Buffer>,
+    /// Signaled when the consumer takes an item or cancels
+    changed: Condvar,
+    received: AtomicU64,
+    dropped: AtomicU64,
+    lost: AtomicU64,
+}
+
+impl Shared {
+    fn buffer(&self) -> MutexGuard<'_, Buffer> {
+        self.buffer.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Configures and starts an [`EventStream`].
+#[derive(Debug)]
+pub struct EventStreamBuilder<T: DriverTransport = FilterPort> {
+    driver: Arc<Driver<T>>,
+    capacity: usize,
+    overflow: OverflowPolicy,
+    min_interval: Duration,
+    max_interval: Duration,
+}
+
+impl<T: DriverTransport + Send + Sync + 'static> EventStreamBuilder<T> {
+    /// Max number of buffered messages (1024 by default).
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    /// [`OverflowPolicy::Block`] by default.
+    pub fn overflow(mut self, overflow: OverflowPolicy) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    /// Bounds of the polling interval (1 ms to 100 ms by default).
+    pub fn poll_interval(mut self, min: Duration, max: Duration) -> Self {
+        self.min_interval = min;
+        self.max_interval = max.max(min);
+        self
+    }
+
+    /// Starts polling the driver.
+    pub fn build(self) -> EventStream {
+        let shared = Arc::new(Shared::default());
+        let poller = Poller {
+            driver: self.driver,
+            shared: shared.clone(),
+            capacity: self.capacity,
+            overflow: self.overflow,
+            min_interval: self.min_interval,
+            max_interval: self.max_interval,
+        };
+        thread::spawn(move || poller.run());
+        EventStream { shared }
+    }
+}
+
+/// The [`Event`]s fetched from a [`Driver`], as a [`Stream`].
+///
+/// Errors are yielded as they happen. The stream ends after a [`DriverError::Disconnected`].
+/// Dropping it stops the polling thread.
+#[derive(Debug)]
+pub struct EventStream {
+    shared: Arc<Shared>,
+}
+
+impl EventStream {
+    pub fn builder<T: DriverTransport + Send + Sync + 'static>(
+        driver: Arc<Driver<T>>,
+    ) -> EventStreamBuilder<T> {
+        EventStreamBuilder {
+            driver,
+            capacity: 1024,
+            overflow: OverflowPolicy::Block,
+            min_interval: Duration::from_millis(1),
+            max_interval: Duration::from_millis(100),
+        }
+    }
+
+    /// Number of i/o and process events fetched from the driver so far, dropped ones included.
+    pub fn received(&self) -> u64 {
+        self.shared.received.load(Ordering::Relaxed)
+    }
+
+    /// Number of [`Event::Io`] dropped because the buffer was full.
+    pub fn dropped(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Number of operations dropped by the minifilter, its queue being full, even if their
+    /// [`Event::EventsLost`] was dropped by the [`OverflowPolicy`]. They are counted before the
+    /// next message is buffered.
+    pub fn lost(&self) -> u64 {
+        self.shared.lost.load(Ordering::Relaxed)
+    }
+
+    /// Number of items waiting to be consumed.
+    pub fn buffered(&self) -> usize {
+        self.shared.buffer().items.len()
+    }
+}
+
+impl Stream for EventStream {
+    type Item = Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut buffer = self.shared.buffer();
+        if let Some(item) = buffer.items.pop_front() {
+            drop(buffer);
+            self.shared.changed.notify_all();
+            return Poll::Ready(Some(item));
+        }
+        if buffer.finished {
+            return Poll::Ready(None);
+        }
+        buffer.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl Drop for EventStream {
+    fn drop(&mut self) {
+        self.shared.buffer().cancelled = true;
+        self.shared.changed.notify_all();
+    }
+}
+
+struct Poller<T: DriverTransport> {
+    driver: Arc<Driver<T>>,
+    shared: Arc<Shared>,
+    capacity: usize,
+    overflow: OverflowPolicy,
+    min_interval: Duration,
+    max_interval: Duration,
+}
+
+impl<T: DriverTransport> Poller<T> {
+    fn run(self) {
+        let mut vecnew: Vec<u8> = Vec::with_capacity(self.driver.config().buffer_size());
+        let mut interval = self.min_interval;
+
+        loop {
+            let mut idle = true;
+            let mut cancelled = false;
+            let polled = self.driver.poll_events(&mut vecnew, |event| {
+                match &event {
+                    Ok(Event::Io(_) | Event::Process(_)) => {
+                        self.shared.received.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Ok(Event::EventsLost { count }) => {
+                        self.shared.lost.fetch_add(*count, Ordering::Relaxed);
+                    }
+                    Err(_) => {}
+                }
+                cancelled = !self.push(event);
+                !cancelled
+            });
+            if cancelled {
+                return;
+            }
+            match polled {
+                Ok(0) => {}
+                Ok(_) => idle = false,
+                Err(e) => {
+                    let disconnected = e.is_disconnected();
+                    if !self.push(Err(e)) || disconnected {
+                        break;
+                    }
+                }
+            }
+
+            if idle {
+                let buffer = self.shared.buffer();
+                if buffer.cancelled {
+                    return;
+                }
+                let (buffer, _) = self
+                    .shared
+                    .changed
+                    .wait_timeout_while(buffer, interval, |b| !b.cancelled)
+                    .unwrap_or_else(|e| e.into_inner());
+                if buffer.cancelled {
+                    return;
+                }
+                interval = (interval * 2).min(self.max_interval);
+            } else {
+                interval = self.min_interval;
+            }
+        }
+
+        let mut buffer = self.shared.buffer();
+        buffer.finished = true;
+        if let Some(waker) = buffer.waker.take() {
+            waker.wake();
+        }
+    }
+
+    /// Queues `item` according to the [`OverflowPolicy`] if it is an [`Event::Io`], the others are
+    /// always queued. Returns false if the stream has been dropped.
+    fn push(&self, item: Item) -> bool {
+        let mut buffer = self.shared.buffer();
+        if is_io(&item) && buffer.items.len() >= self.capacity {
+            match self.overflow {
+                OverflowPolicy::Block => {
+                    buffer = self
+                        .shared
+                        .changed
+                        .wait_while(buffer, |b| !b.cancelled && b.items.len() >= self.capacity)
+                        .unwrap_or_else(|e| e.into_inner());
+                }
+                OverflowPolicy::DropOldest => {
+                    if let Some(pos) = buffer.items.iter().position(is_io) {
+                        buffer.items.remove(pos);
+                    }
+                    self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+                OverflowPolicy::DropNewest => {
+                    self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+                    return !buffer.cancelled;
+                }
+            }
+        }
+        if buffer.cancelled {
+            return false;
+        }
+        buffer.items.push_back(item);
+        if let Some(waker) = buffer.waker.take() {
+            waker.wake();
+        }
+        true
+    }
+}
+
+fn is_io(item: &Item) -> bool {
+    matches!(item, Ok(Event::Io(_)))
+}
+
+#[cfg(test)]
+#[doc(hidden)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant, UNIX_EPOCH};
+
+    use futures::executor::block_on;
+    use futures::StreamExt;
+
+    use crate::driver_comm::error::DriverError;
+    use crate::driver_comm::mock::{MockDriver, MockEvent};
+    use crate::driver_comm::stream::{EventStream, OverflowPolicy};
+    use crate::driver_comm::{Driver, IrpMajorOp};
+    use crate::shared_def::{Event, ProcessEvent, ProcessEventKind};
+
+    fn events(pids: std::ops::Range<u32>) -> impl Iterator<Item = MockEvent> {
+        pids.map(|pid| MockEvent::new(pid, 1, IrpMajorOp::IrpWrite, r"C:\Users\Dev\a.txt"))
+    }
+
+    /// Waits until the poller has fetched every event.
+    fn wait_fetched(mock: &MockDriver, stream: &EventStream, count: u64) {
+        let start = Instant::now();
+        while mock.pending_events() > 0 || stream.received() < count {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn test_stream_yields_events_until_disconnected() {
+        let mock = MockDriver::new();
+        mock.push_events(events(0..3));
+        let mut stream = EventStream::builder(Arc::new(Driver::with_transport(mock.clone())))
+            .poll_interval(Duration::from_millis(1), Duration::from_millis(5))
+            .build();
+
+        block_on(async {
+            for pid in 0..3 {
+                assert_eq!(stream.next().await.unwrap().unwrap().pid(), Some(pid));
+            }
+            mock.push_events(events(3..4));
+            assert_eq!(stream.next().await.unwrap().unwrap().pid(), Some(3));
+
+            mock.unload();
+            assert_eq!(
+                stream.next().await.unwrap().unwrap_err(),
+                DriverError::Disconnected
+            );
+            assert!(stream.next().await.is_none());
+        });
+        assert_eq!(stream.dropped(), 0);
+    }
+
+    #[test]
+    fn test_drop_oldest() {
+        let mock = MockDriver::new();
+        mock.push_events(events(0..10));
+        let mut stream = EventStream::builder(Arc::new(Driver::with_transport(mock.clone())))
+            .capacity(4)
+            .overflow(OverflowPolicy::DropOldest)
+            .build();
+        wait_fetched(&mock, &stream, 10);
+
+        assert_eq!(stream.dropped(), 6);
+        let pids: Vec<u32> = block_on(
+            stream
+                .by_ref()
+                .take(4)
+                .map(|i| i.unwrap().pid().unwrap())
+                .collect(),
+        );
+        assert_eq!(pids, vec![6, 7, 8, 9]);
+    }
+
+    #[test]
+    fn test_drop_newest() {
+        let mock = MockDriver::new();
+        mock.push_events(events(0..10));
+        let mut stream = EventStream::builder(Arc::new(Driver::with_transport(mock.clone())))
+            .capacity(4)
+            .overflow(OverflowPolicy::DropNewest)
+            .build();
+        wait_fetched(&mock, &stream, 10);
+
+        assert_eq!(stream.dropped(), 6);
+        let pids: Vec<u32> = block_on(
+            stream
+                .by_ref()
+                .take(4)
+                .map(|i| i.unwrap().pid().unwrap())
+                .collect(),
+        );
+        assert_eq!(pids, vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_block_keeps_everything() {
+        let mock = MockDriver::new();
+        mock.push_events(events(0..10));
+        let mut stream = EventStream::builder(Arc::new(Driver::with_transport(mock.clone())))
+            .capacity(2)
+            .build();
+
+        let pids: Vec<u32> = block_on(
+            stream
+                .by_ref()
+                .take(10)
+                .map(|i| i.unwrap().pid().unwrap())
+                .collect(),
+        );
+        assert_eq!(pids, (0..10).collect::<Vec<u32>>());
+        assert_eq!(stream.dropped(), 0);
+    }
+
+    #[test]
+    fn test_ops_lost_by_the_minifilter() {
+        let mock = MockDriver::new();
+        let driver = Arc::new(Driver::with_transport(mock.clone()));
+        driver.set_max_ops(2).unwrap();
+        // 2 and 3 are dropped by the minifilter
+        mock.push_events(events(0..4));
+        let mut stream = EventStream::builder(driver).build();
+
+        let pids: Vec<u32> = block_on(
+            stream
+                .by_ref()
+                .take(2)
+                .map(|i| i.unwrap().pid().unwrap())
+                .collect(),
+        );
+        assert_eq!(pids, vec![0, 1]);
+        assert_eq!(stream.lost(), 0);
+        mock.push_events(events(4..5));
+        assert!(matches!(
+            block_on(stream.next()),
+            Some(Ok(Event::EventsLost { count: 2 }))
+        ));
+        assert_eq!(stream.lost(), 2);
+        assert_eq!(block_on(stream.next()).unwrap().unwrap().pid(), Some(4));
+        assert_eq!(stream.dropped(), 0);
+    }
+
+    #[test]
+    fn test_process_events_are_never_dropped() {
+        let mock = MockDriver::new();
+        let process = |kind, alive_pids| ProcessEvent {
+            kind,
+            pid: 100,
+            parent_pid: 4,
+            gid: 5,
+            image_path: r"\Device\HarddiskVolume3\Tools\bad.exe".to_string(),
+            time: UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+            alive_pids,
+        };
+        mock.push_events([
+            MockEvent::process(&process(ProcessEventKind::Created, 1)),
+            MockEvent::new(100, 5, IrpMajorOp::IrpWrite, r"C:\Users\Dev\a.txt"),
+            MockEvent::process(&process(ProcessEventKind::Exited, 0)),
+        ]);
+        let stream = EventStream::builder(Arc::new(Driver::with_transport(mock.clone())))
+            .capacity(1)
+            .overflow(OverflowPolicy::DropNewest)
+            .build();
+        wait_fetched(&mock, &stream, 3);
+
+        // Only the write is dropped by the full buffer
+        assert_eq!(stream.dropped(), 1);
+        let events: Vec<Event> = block_on(stream.take(2).map(Result::unwrap).collect());
+        assert!(
+            matches!(&events[0], Event::Process(e) if e == &process(ProcessEventKind::Created, 1))
+        );
+        assert!(matches!(&events[1], Event::Process(e) if e.ends_gid()));
+    }
+}
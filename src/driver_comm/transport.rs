@@ -0,0 +1,126 @@
+//! How [`DriverComMessage`] are carried to the minifilter.
+//!
+//! [`Driver`](super::Driver) never talks to the OS directly: it hands its messages to a
+//! [`DriverTransport`]. [`FilterPort`] is the real one, backed by the Filter Manager communication
+//! port, and [`MockDriver`](super::mock::MockDriver) is an in-memory one used for tests.
+
+use core::ffi::c_void;
+use std::fmt::Debug;
+use std::mem;
+use std::ptr;
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use widestring::U16CString;
+use windows::core::PCWSTR;
+use windows::Win32::Foundation::{CloseHandle, HANDLE};
+use windows::Win32::Storage::InstallableFileSystems::{
+    FilterConnectCommunicationPort, FilterSendMessage,
+};
+
+use crate::driver_comm::error::DriverError;
+use crate::driver_comm::DriverComMessage;
+
+/// A channel able to deliver a [`DriverComMessage`] to the minifilter and to bring back its reply.
+pub trait DriverTransport: Debug {
+    /// Sends `msg` to the minifilter. If `reply` is given, the minifilter can write its answer
+    /// there, the same way `FilterSendMessage` does with its output buffer.
+    ///
+    /// Returns the number of bytes written to `reply`, or [`DriverError::Disconnected`] once
+    /// closed.
+    fn send_message(
+        &self,
+        msg: &DriverComMessage,
+        reply: Option<&mut [u8]>,
+    ) -> Result<u32, DriverError>;
+
+    /// Closes the communication. Returns false if it was already closed.
+    fn close(&self) -> bool;
+
+    /// Closes the communication if needed and opens it again, e.g. after the minifilter has been
+    /// restarted.
+    fn reconnect(&self) -> Result<(), DriverError>;
+}
+
+/// The Filter Manager communication port opened with `FilterConnectCommunicationPort`.
+#[derive(Debug)]
+pub struct FilterPort {
+    port_name: String,
+    /// None once closed
+    handle: RwLock<Option<HANDLE>>,
+}
+
+impl FilterPort {
+    /// Connects to the port named `port_name` (`\RWFilter` for the FSFilter minifilter).
+    pub fn connect(port_name: &str) -> Result<FilterPort, DriverError> {
+        Ok(FilterPort {
+            port_name: port_name.to_string(),
+            handle: RwLock::new(Some(Self::open(port_name)?)),
+        })
+    }
+
+    fn open(port_name: &str) -> Result<HANDLE, DriverError> {
+        let com_port_name =
+            U16CString::from_str(port_name).map_err(|e| DriverError::PathWithNul {
+                position: e.nul_position(),
+            })?;
+        unsafe {
+            FilterConnectCommunicationPort(PCWSTR(com_port_name.as_ptr()), 0, None, 0, None)
+                .map_err(|e| DriverError::Connect(e.code()))
+        }
+    }
+
+    fn handle(&self) -> RwLockReadGuard<'_, Option<HANDLE>> {
+        self.handle.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn handle_mut(&self) -> RwLockWriteGuard<'_, Option<HANDLE>> {
+        self.handle.write().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl DriverTransport for FilterPort {
+    fn send_message(
+        &self,
+        msg: &DriverComMessage,
+        reply: Option<&mut [u8]>,
+    ) -> Result<u32, DriverError> {
+        let guard = self.handle();
+        let handle = guard.ok_or(DriverError::Disconnected)?;
+        let (reply_ptr, reply_size) = match reply {
+            Some(buf) => (Some(buf.as_mut_ptr() as *mut c_void), buf.len() as u32),
+            None => (None, 0),
+        };
+        let mut bytes_returned: u32 = 0;
+
+        unsafe {
+            FilterSendMessage(
+                handle,
+                ptr::addr_of!(*msg) as *const c_void,
+                mem::size_of::<DriverComMessage>() as u32,
+                reply_ptr,
+                reply_size,
+                ptr::addr_of_mut!(bytes_returned),
+            )
+            .map_err(|e| DriverError::from_send(e.code()))?;
+        }
+        Ok(bytes_returned)
+    }
+
+    fn close(&self) -> bool {
+        match self.handle_mut().take() {
+            Some(handle) => unsafe { CloseHandle(handle).as_bool() },
+            None => false,
+        }
+    }
+
+    fn reconnect(&self) -> Result<(), DriverError> {
+        let mut handle = self.handle_mut();
+        if let Some(old) = handle.take() {
+            unsafe {
+                CloseHandle(old);
+            }
+        }
+        *handle = Some(Self::open(&self.port_name)?);
+        Ok(())
+    }
+}
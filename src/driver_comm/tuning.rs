@@ -0,0 +1,107 @@
+//! Settings of the minifilter that can be changed while it runs, see [`FilterConfig`].
+//!
+//! They reset to their defaults when the minifilter is restarted: [`Driver`](super::Driver)
+//! sends the ones it changed again on [`reconnect`](super::Driver::reconnect).
+
+use std::fmt;
+
+use crate::driver_comm::verdict::DEFAULT_VERDICT_TIMEOUT;
+
+/// Max number of operations queued by the minifilter until they are fetched, by default
+/// (`MAX_OPS_SAVE` in `SharedDefs.h`).
+pub const MAX_OPS_SAVE: u32 = 0x1000;
+
+/// Highest value accepted by [`set_max_ops`](super::Driver::set_max_ops), to keep the queue from
+/// filling the non-paged pool (`MAX_OPS_SAVE_LIMIT` in `SharedDefs.h`).
+pub const MAX_OPS_SAVE_LIMIT: u32 = 0x10000;
+
+/// Size of a `FILTER_CONFIG`.
+pub const FILTER_CONFIG_SIZE: usize = 16;
+
+/// Reply of the minifilter to the tuning messages and to
+/// [`GetConfig`](super::DriverComMessageType::GetConfig): its settings after the change
+/// (`FILTER_CONFIG` in `SharedDefs.h`).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FilterConfig {
+    /// The entropy of reads and writes is calculated. Otherwise
+    /// [`is_entropy_calc`](crate::shared_def::IOMessage::is_entropy_calc) is always 0.
+    pub entropy_enabled: bool,
+    /// No operation is recorded, processes are still tracked.
+    pub collection_paused: bool,
+    /// Reads and writes of fewer bytes are not sampled for entropy.
+    pub entropy_min_size: u32,
+    /// Operations are dropped once that many are waiting to be fetched.
+    pub max_ops: u32,
+    /// Operations waiting for a [`Verdict`](super::verdict::Verdict) are denied if none comes,
+    /// allowed otherwise.
+    pub verdict_fail_closed: bool,
+    /// Milliseconds given to answer a [`VerdictRequest`](super::verdict::VerdictRequest).
+    pub verdict_timeout_ms: u32,
+}
+
+impl Default for FilterConfig {
+    /// The settings of a freshly started minifilter.
+    fn default() -> Self {
+        FilterConfig {
+            entropy_enabled: true,
+            collection_paused: false,
+            entropy_min_size: 0,
+            max_ops: MAX_OPS_SAVE,
+            verdict_fail_closed: false,
+            verdict_timeout_ms: DEFAULT_VERDICT_TIMEOUT.as_millis() as u32,
+        }
+    }
+}
+
+impl FilterConfig {
+    /// Reads the reply of the minifilter, `None` if it is too short.
+    pub fn from_bytes(buf: &[u8]) -> Option<FilterConfig> {
+        if buf.len() < FILTER_CONFIG_SIZE {
+            return None;
+        }
+        let read_u32 =
+            |offset: usize| u32::from_ne_bytes(buf[offset..offset + 4].try_into().unwrap());
+        Some(FilterConfig {
+            entropy_enabled: buf[0] != 0,
+            collection_paused: buf[1] != 0,
+            entropy_min_size: read_u32(4),
+            max_ops: read_u32(8),
+            verdict_fail_closed: buf[2] != 0,
+            verdict_timeout_ms: read_u32(12),
+        })
+    }
+
+    pub fn to_bytes(self) -> [u8; FILTER_CONFIG_SIZE] {
+        let mut buf = [0u8; FILTER_CONFIG_SIZE];
+        buf[0] = self.entropy_enabled as u8;
+        buf[1] = self.collection_paused as u8;
+        buf[2] = self.verdict_fail_closed as u8;
+        buf[4..8].copy_from_slice(&self.entropy_min_size.to_ne_bytes());
+        buf[8..12].copy_from_slice(&self.max_ops.to_ne_bytes());
+        buf[12..16].copy_from_slice(&self.verdict_timeout_ms.to_ne_bytes());
+        buf
+    }
+}
+
+impl fmt::Display for FilterConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "collection {}, entropy {} (from {} bytes), max {} ops, verdicts fail {} after {} ms",
+            if self.collection_paused {
+                "paused"
+            } else {
+                "running"
+            },
+            if self.entropy_enabled { "on" } else { "off" },
+            self.entropy_min_size,
+            self.max_ops,
+            if self.verdict_fail_closed {
+                "closed"
+            } else {
+                "open"
+            },
+            self.verdict_timeout_ms
+        )
+    }
+}
@@ -0,0 +1,705 @@
+//! Synchronous verdicts: the minifilter asks this app before letting an operation happen.
+//!
+//! Writes, renames and deletes in the *verdict directories* (see
+//! [`Driver::add_verdict_directory`](super::Driver::add_verdict_directory)) are held in their
+//! pre-operation callback while the minifilter sends a [`VerdictRequest`] on its verdict port
+//! (`\RWFVerdict`). A [`VerdictServer`] answers it with the [`Verdict`] of a [`VerdictHandler`].
+//!
+//! Without an answer within the timeout of the [`FilterConfig`](super::tuning::FilterConfig), or
+//! without any [`VerdictServer`] connected, the minifilter applies its fail policy: the operation
+//! is allowed (fail-open, by default) or denied (fail-closed, see
+//! [`set_verdict_fail_closed`](super::Driver::set_verdict_fail_closed)).
+//!
+//! Like the verdict port, removing a verdict directory and changing the timeout or the fail policy
+//! are left to a process running as an administrator or SYSTEM.
+//!
+//! Paging writes are not held: the writes through memory-mapped files escape the verdicts.
+
+use core::ffi::c_void;
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+use std::time::Duration;
+
+use widestring::U16CString;
+use windows::core::{HRESULT, PCWSTR};
+use windows::Win32::Foundation::{CloseHandle, HANDLE, NTSTATUS};
+use windows::Win32::Storage::InstallableFileSystems::{
+    FilterConnectCommunicationPort, FilterGetMessage, FilterReplyMessage, FILTER_MESSAGE_HEADER,
+    FILTER_REPLY_HEADER,
+};
+use windows::Win32::System::IO::CancelIoEx;
+
+use crate::driver_comm::error::DriverError;
+use crate::driver_comm::transport::DriverTransport;
+use crate::driver_comm::{Driver, IrpMajorOp};
+use crate::shared_def::decoder::{DecodeError, MAX_FILE_NAME_SIZE};
+use crate::shared_def::FileChangeInfo;
+
+/// Name of the port on which the minifilter asks for verdicts (`VerdictPortName` in
+/// `SharedDefs.h`). Only Administrators and SYSTEM can connect to it.
+pub const VERDICT_PORT_NAME: &str = "\\RWFVerdict";
+
+/// Time given to answer a [`VerdictRequest`], by default (`DEFAULT_VERDICT_TIMEOUT_MS` in
+/// `SharedDefs.h`).
+pub const DEFAULT_VERDICT_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// Longest timeout accepted by [`set_verdict_timeout`](super::Driver::set_verdict_timeout)
+/// (`MAX_VERDICT_TIMEOUT_MS` in `SharedDefs.h`): the writing thread is blocked meanwhile.
+pub const MAX_VERDICT_TIMEOUT: Duration = Duration::from_millis(60000);
+
+/// Size of a `VERDICT_REQUEST`.
+pub const VERDICT_REQUEST_SIZE: usize = 32 + 2 * MAX_FILE_NAME_SIZE;
+
+/// Size of a `FILTER_MESSAGE_HEADER`, preceding the `VERDICT_REQUEST` read from the port.
+const MESSAGE_HEADER_SIZE: usize = 16;
+
+/// `VERDICT_ALLOW` in `SharedDefs.h`.
+const VERDICT_ALLOW: u32 = 0;
+/// `VERDICT_DENY` in `SharedDefs.h`.
+const VERDICT_DENY: u32 = 1;
+
+/// `HRESULT_FROM_WIN32(ERROR_OPERATION_ABORTED)`: the port handle has been closed while waiting.
+const E_OPERATION_ABORTED: HRESULT = HRESULT(0x8007_03E3_u32 as i32);
+/// `ERROR_FLT_NO_WAITER_FOR_REPLY`: the minifilter stopped waiting, its fail policy applied.
+const E_FLT_NO_WAITER_FOR_REPLY: HRESULT = HRESULT(0x801F_0020_u32 as i32);
+
+/// The answer to a [`VerdictRequest`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    /// The operation happens, and is recorded as usual.
+    Allow,
+    /// The operation fails with `STATUS_ACCESS_DENIED`, and is not recorded.
+    Deny,
+    /// Same as [`Deny`](Self::Deny), then the family of the process is killed with
+    /// [`Driver::try_kill`].
+    DenyAndKillGid,
+}
+
+impl Verdict {
+    pub fn allows(self) -> bool {
+        self == Verdict::Allow
+    }
+
+    /// The `VERDICT` replied to the minifilter, which does not kill by itself.
+    fn to_win32(self) -> u32 {
+        match self {
+            Verdict::Allow => VERDICT_ALLOW,
+            Verdict::Deny | Verdict::DenyAndKillGid => VERDICT_DENY,
+        }
+    }
+}
+
+/// An operation waiting for a [`Verdict`] (`VERDICT_REQUEST` in `SharedDefs.h`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerdictRequest {
+    /// Identifies the request on the port, to reply to it.
+    pub message_id: u64,
+    pub pid: u32,
+    pub gid: u64,
+    /// [`IrpWrite`](IrpMajorOp::IrpWrite) or [`IrpSetInfo`](IrpMajorOp::IrpSetInfo).
+    pub irp_op: IrpMajorOp,
+    /// [`FileChangeWrite`](FileChangeInfo::FileChangeWrite), a rename or a delete.
+    pub file_change: FileChangeInfo,
+    /// Bytes to write.
+    pub size: u64,
+    /// Entropy of the bytes to write, if calculated (see
+    /// [`FilterConfig`](super::tuning::FilterConfig)).
+    pub entropy: Option<f64>,
+    /// The file, in the same form as [`IOMessage::filepathstr`](crate::shared_def::IOMessage).
+    pub path: String,
+    /// The destination of a rename.
+    pub new_path: Option<String>,
+}
+
+impl VerdictRequest {
+    /// Reads a `VERDICT_REQUEST`.
+    pub fn from_bytes(message_id: u64, buf: &[u8]) -> Result<VerdictRequest, DecodeError> {
+        if buf.len() < VERDICT_REQUEST_SIZE {
+            return Err(DecodeError::TooShort {
+                buffer_len: buf.len(),
+                min_len: VERDICT_REQUEST_SIZE,
+            });
+        }
+        let read_u64 =
+            |offset: usize| u64::from_ne_bytes(buf[offset..offset + 8].try_into().unwrap());
+        let read_path = |offset: usize| {
+            let units: Vec<u16> = buf[offset..offset + MAX_FILE_NAME_SIZE]
+                .chunks_exact(2)
+                .map(|c| u16::from_ne_bytes([c[0], c[1]]))
+                .take_while(|c| *c != 0)
+                .collect();
+            String::from_utf16_lossy(&units)
+        };
+        let new_path = read_path(32 + MAX_FILE_NAME_SIZE);
+        Ok(VerdictRequest {
+            message_id,
+            pid: u32::from_ne_bytes(buf[0..4].try_into().unwrap()),
+            irp_op: IrpMajorOp::try_from(buf[4]).map_err(|_| DecodeError::UnknownCode {
+                field: "irp_op",
+                value: buf[4],
+            })?,
+            file_change: num::FromPrimitive::from_u8(buf[5]).ok_or(DecodeError::UnknownCode {
+                field: "file_change",
+                value: buf[5],
+            })?,
+            gid: read_u64(8),
+            size: read_u64(16),
+            entropy: (buf[6] != 0).then(|| f64::from_bits(read_u64(24))),
+            path: read_path(32),
+            new_path: (!new_path.is_empty()).then_some(new_path),
+        })
+    }
+
+    /// Writes the `VERDICT_REQUEST`, paths truncated to the length kept by the minifilter.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = vec![0u8; VERDICT_REQUEST_SIZE];
+        buf[0..4].copy_from_slice(&self.pid.to_ne_bytes());
+        buf[4] = self.irp_op as u8;
+        buf[5] = self.file_change as u8;
+        buf[6] = self.entropy.is_some() as u8;
+        buf[8..16].copy_from_slice(&self.gid.to_ne_bytes());
+        buf[16..24].copy_from_slice(&self.size.to_ne_bytes());
+        buf[24..32].copy_from_slice(&self.entropy.unwrap_or_default().to_ne_bytes());
+        let mut write_path = |offset: usize, path: &str| {
+            for (i, c) in path
+                .encode_utf16()
+                .take(MAX_FILE_NAME_SIZE / 2 - 1)
+                .enumerate()
+            {
+                buf[offset + 2 * i..offset + 2 * i + 2].copy_from_slice(&c.to_ne_bytes());
+            }
+        };
+        write_path(32, &self.path);
+        write_path(
+            32 + MAX_FILE_NAME_SIZE,
+            self.new_path.as_deref().unwrap_or_default(),
+        );
+        buf
+    }
+}
+
+/// Decides whether the operations held by the minifilter can happen.
+///
+/// The writing thread waits meanwhile: the decision has to be quick, and cannot wait for
+/// operations of the same process.
+pub trait VerdictHandler {
+    fn verdict(&mut self, request: &VerdictRequest) -> Verdict;
+}
+
+impl<F: FnMut(&VerdictRequest) -> Verdict> VerdictHandler for F {
+    fn verdict(&mut self, request: &VerdictRequest) -> Verdict {
+        self(request)
+    }
+}
+
+/// A channel on which the minifilter asks for verdicts.
+pub trait VerdictTransport: Debug {
+    /// Waits for the next request. Fails with [`DriverError::Disconnected`] once closed, and with
+    /// [`DriverError::MalformedRequest`] for a request that cannot be decoded, which is answered
+    /// with the fail policy of the minifilter.
+    fn get_request(&self) -> Result<VerdictRequest, DriverError>;
+
+    /// Answers `request`. Returns false if the minifilter stopped waiting for it, its fail policy
+    /// being applied instead.
+    fn reply(&self, request: &VerdictRequest, verdict: Verdict) -> Result<bool, DriverError>;
+
+    /// Closes the communication, and ends a [`get_request`](Self::get_request) waiting in
+    /// another thread. Returns false if it was already closed.
+    fn close(&self) -> bool;
+}
+
+/// The verdict port of the minifilter, opened with `FilterConnectCommunicationPort`.
+#[derive(Debug)]
+pub struct VerdictPort {
+    /// None once closed. Read-locked while waiting for a request, so that it is not closed under
+    /// `FilterGetMessage`.
+    handle: RwLock<Option<HANDLE>>,
+    closing: AtomicBool,
+}
+
+#[repr(C)]
+struct VerdictReplyMessage {
+    header: FILTER_REPLY_HEADER,
+    verdict: u32,
+}
+
+impl VerdictPort {
+    /// Connects to the port named `port_name` ([`VERDICT_PORT_NAME`] for the FSFilter
+    /// minifilter). Fails with [`DriverError::Connect`] if another app is connected to it.
+    pub fn connect(port_name: &str) -> Result<VerdictPort, DriverError> {
+        let port_name = U16CString::from_str(port_name).map_err(|e| DriverError::PathWithNul {
+            position: e.nul_position(),
+        })?;
+        let handle = unsafe {
+            FilterConnectCommunicationPort(PCWSTR(port_name.as_ptr()), 0, None, 0, None)
+                .map_err(|e| DriverError::Connect(e.code()))?
+        };
+        Ok(VerdictPort {
+            handle: RwLock::new(Some(handle)),
+            closing: AtomicBool::new(false),
+        })
+    }
+
+    fn handle(&self) -> RwLockReadGuard<'_, Option<HANDLE>> {
+        self.handle.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Answers without a verdict: the minifilter applies its fail policy.
+    fn reply_fail_policy(handle: HANDLE, message_id: u64) {
+        let reply = FILTER_REPLY_HEADER {
+            Status: NTSTATUS(0),
+            MessageId: message_id,
+        };
+        let _ = unsafe {
+            FilterReplyMessage(
+                handle,
+                &reply,
+                std::mem::size_of::<FILTER_REPLY_HEADER>() as u32,
+            )
+        };
+    }
+}
+
+impl VerdictTransport for VerdictPort {
+    fn get_request(&self) -> Result<VerdictRequest, DriverError> {
+        let guard = self.handle();
+        if self.closing.load(Ordering::Acquire) {
+            return Err(DriverError::Disconnected);
+        }
+        let handle = guard.ok_or(DriverError::Disconnected)?;
+        // u64 for the alignment of the header and of the request
+        let mut buffer = vec![0u64; (MESSAGE_HEADER_SIZE + VERDICT_REQUEST_SIZE) / 8];
+        unsafe {
+            FilterGetMessage(
+                handle,
+                buffer.as_mut_ptr() as *mut FILTER_MESSAGE_HEADER,
+                (buffer.len() * 8) as u32,
+                None,
+            )
+        }
+        .map_err(|e| match e.code() {
+            E_OPERATION_ABORTED => DriverError::Disconnected,
+            code => DriverError::from_send(code),
+        })?;
+        let bytes =
+            unsafe { std::slice::from_raw_parts(buffer.as_ptr() as *const u8, buffer.len() * 8) };
+        let header = unsafe { *(buffer.as_ptr() as *const FILTER_MESSAGE_HEADER) };
+        VerdictRequest::from_bytes(header.MessageId, &bytes[MESSAGE_HEADER_SIZE..]).map_err(|e| {
+            Self::reply_fail_policy(handle, header.MessageId);
+            DriverError::MalformedRequest(e)
+        })
+    }
+
+    fn reply(&self, request: &VerdictRequest, verdict: Verdict) -> Result<bool, DriverError> {
+        let handle = self.handle().ok_or(DriverError::Disconnected)?;
+        let reply = VerdictReplyMessage {
+            header: FILTER_REPLY_HEADER {
+                Status: NTSTATUS(0),
+                MessageId: request.message_id,
+            },
+            verdict: verdict.to_win32(),
+        };
+        let sent = unsafe {
+            FilterReplyMessage(
+                handle,
+                &reply as *const VerdictReplyMessage as *const c_void as *const FILTER_REPLY_HEADER,
+                (std::mem::size_of::<FILTER_REPLY_HEADER>() + 4) as u32,
+            )
+        };
+        match sent {
+            Ok(()) => Ok(true),
+            Err(e) if e.code() == E_FLT_NO_WAITER_FOR_REPLY => Ok(false),
+            Err(e) => Err(DriverError::from_send(e.code())),
+        }
+    }
+
+    fn close(&self) -> bool {
+        self.closing.store(true, Ordering::Release);
+        loop {
+            // A get_request holds the read lock until its FilterGetMessage is cancelled
+            if let Ok(mut handle) = self.handle.try_write() {
+                return match handle.take() {
+                    Some(handle) => unsafe { CloseHandle(handle).as_bool() },
+                    None => false,
+                };
+            }
+            if let Ok(handle) = self.handle.try_read() {
+                if let Some(handle) = *handle {
+                    unsafe { CancelIoEx(handle, None) };
+                }
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+/// Answers the [`VerdictRequest`]s of the minifilter with a [`VerdictHandler`].
+///
+/// ```no_run
+/// use minifilter_rs::driver_comm::verdict::{Verdict, VerdictRequest, VerdictServer};
+/// use minifilter_rs::driver_comm::Driver;
+///
+/// let driver = Driver::open_kernel_driver_com().unwrap();
+/// driver.add_verdict_directory(r"C:\Users\Dev\Documents").unwrap();
+/// // Encrypted content written to the documents
+/// let mut server = VerdictServer::connect(|request: &VerdictRequest| {
+///     match request.entropy {
+///         Some(entropy) if entropy > 7.9 => Verdict::DenyAndKillGid,
+///         _ => Verdict::Allow,
+///     }
+/// })
+/// .unwrap();
+/// server.serve(&driver).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct VerdictServer<H: VerdictHandler, V: VerdictTransport = VerdictPort> {
+    transport: Arc<V>,
+    handler: H,
+    late_replies: u64,
+    malformed_requests: u64,
+    failed_kills: u64,
+}
+
+impl<H: VerdictHandler> VerdictServer<H> {
+    /// Connects to the verdict port of the minifilter, see [`VerdictPort::connect`].
+    pub fn connect(handler: H) -> Result<VerdictServer<H>, DriverError> {
+        Ok(VerdictServer::with_transport(
+            VerdictPort::connect(VERDICT_PORT_NAME)?,
+            handler,
+        ))
+    }
+}
+
+impl<H: VerdictHandler, V: VerdictTransport> VerdictServer<H, V> {
+    /// Uses `transport` to receive the requests, e.g. a
+    /// [`MockDriver`](crate::driver_comm::mock::MockDriver).
+    pub fn with_transport(transport: V, handler: H) -> VerdictServer<H, V> {
+        VerdictServer {
+            transport: Arc::new(transport),
+            handler,
+            late_replies: 0,
+            malformed_requests: 0,
+            failed_kills: 0,
+        }
+    }
+
+    /// The [`VerdictTransport`] carrying the requests.
+    pub fn transport(&self) -> &V {
+        &self.transport
+    }
+
+    pub fn handler(&self) -> &H {
+        &self.handler
+    }
+
+    /// Verdicts given after the minifilter stopped waiting, its fail policy applied instead.
+    pub fn late_replies(&self) -> u64 {
+        self.late_replies
+    }
+
+    /// Requests that could not be decoded, answered with the fail policy and skipped by
+    /// [`serve`](Self::serve).
+    pub fn malformed_requests(&self) -> u64 {
+        self.malformed_requests
+    }
+
+    /// [`Verdict::DenyAndKillGid`] whose kill could not be sent, e.g. while the command port of
+    /// the minifilter reconnects: the operation was denied, the gid may still run.
+    pub fn failed_kills(&self) -> u64 {
+        self.failed_kills
+    }
+
+    /// A handle to [`close`](VerdictCloser::close) the port from another thread, while
+    /// [`serve`](Self::serve) is running.
+    pub fn closer(&self) -> VerdictCloser<V> {
+        VerdictCloser {
+            transport: self.transport.clone(),
+        }
+    }
+
+    /// Waits for the next request and answers it. On [`Verdict::DenyAndKillGid`], the gid is
+    /// killed through `driver` once the operation has been denied, a failure is only counted in
+    /// [`failed_kills`](Self::failed_kills) so that the next requests are still answered.
+    pub fn serve_one<T: DriverTransport>(
+        &mut self,
+        driver: &Driver<T>,
+    ) -> Result<Verdict, DriverError> {
+        let request = match self.transport.get_request() {
+            Ok(request) => request,
+            Err(e @ DriverError::MalformedRequest(_)) => {
+                self.malformed_requests += 1;
+                return Err(e);
+            }
+            Err(e) => return Err(e),
+        };
+        let verdict = self.handler.verdict(&request);
+        if !self.transport.reply(&request, verdict)? {
+            self.late_replies += 1;
+        }
+        if verdict == Verdict::DenyAndKillGid && driver.try_kill(request.gid).is_err() {
+            self.failed_kills += 1;
+        }
+        Ok(verdict)
+    }
+
+    /// Answers the requests until the port is closed, by a [`closer`](Self::closer) or by the
+    /// minifilter when unloaded. Malformed requests are skipped.
+    pub fn serve<T: DriverTransport>(&mut self, driver: &Driver<T>) -> Result<(), DriverError> {
+        loop {
+            match self.serve_one(driver) {
+                Ok(_) | Err(DriverError::MalformedRequest(_)) => {}
+                Err(DriverError::Disconnected) => return Ok(()),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Closes the port: the minifilter applies its fail policy from then on.
+    pub fn close(&self) -> bool {
+        self.transport.close()
+    }
+}
+
+/// Closes the port of a [`VerdictServer`], see [`VerdictServer::closer`]:
+///
+/// ```no_run
+/// use minifilter_rs::driver_comm::verdict::{Verdict, VerdictRequest, VerdictServer};
+/// use minifilter_rs::driver_comm::Driver;
+///
+/// let driver = Driver::open_kernel_driver_com().unwrap();
+/// let mut server = VerdictServer::connect(|_: &VerdictRequest| Verdict::Allow).unwrap();
+/// let closer = server.closer();
+/// let serving = std::thread::spawn(move || server.serve(&driver));
+/// // Later on
+/// closer.close();
+/// serving.join().unwrap().unwrap();
+/// ```
+#[derive(Debug)]
+pub struct VerdictCloser<V: VerdictTransport = VerdictPort> {
+    transport: Arc<V>,
+}
+
+impl<V: VerdictTransport> Clone for VerdictCloser<V> {
+    fn clone(&self) -> Self {
+        VerdictCloser {
+            transport: self.transport.clone(),
+        }
+    }
+}
+
+impl<V: VerdictTransport> VerdictCloser<V> {
+    /// Closes the port, [`serve`](VerdictServer::serve) returns. Returns false if it was already
+    /// closed.
+    pub fn close(&self) -> bool {
+        self.transport.close()
+    }
+}
+
+#[cfg(test)]
+#[doc(hidden)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::driver_comm::error::DriverError;
+    use crate::driver_comm::mock::fixtures::scan_scope;
+    use crate::driver_comm::mock::{MockDriver, MockEvent, E_INVALID_PARAMETER};
+    use crate::driver_comm::tuning::FilterConfig;
+    use crate::driver_comm::verdict::{
+        Verdict, VerdictRequest, VerdictServer, VerdictTransport, VERDICT_REQUEST_SIZE,
+    };
+    use crate::driver_comm::{Driver, IrpMajorOp};
+    use crate::shared_def::decoder::DecodeError;
+    use crate::shared_def::FileChangeInfo;
+
+    #[test]
+    fn test_verdicts_allow_or_deny() {
+        let mock = MockDriver::new();
+        let driver = Driver::with_transport(mock.clone()).with_scan_scope(scan_scope());
+        mock.set_gid(7, &[(10, "evil.exe")]);
+        assert!(driver.add_verdict_directory(r"C:\Vault").unwrap());
+        assert!(!driver.add_verdict_directory(r"C:\Vault\").unwrap());
+
+        let vault = |name: &str| format!(r"\Device\HarddiskVolume3\Vault\{}", name);
+        mock.push_events([
+            MockEvent::new(11, 5, IrpMajorOp::IrpWrite, &vault("a.txt")).transferred(4096, 4.2),
+            MockEvent::new(10, 7, IrpMajorOp::IrpWrite, &vault("b.txt")).transferred(4096, 7.99),
+            MockEvent::new(10, 7, IrpMajorOp::IrpSetInfo, &vault("c.txt"))
+                .file_change(FileChangeInfo::FileChangeDeleteFile),
+            // Not held: reads, and writes elsewhere
+            MockEvent::new(10, 7, IrpMajorOp::IrpRead, &vault("a.txt")),
+            MockEvent::new(
+                10,
+                7,
+                IrpMajorOp::IrpWrite,
+                r"\Device\HarddiskVolume3\Temp\b.txt",
+            ),
+        ]);
+        assert_eq!(mock.pending_verdicts().len(), 3);
+        assert_eq!(mock.pending_events(), 2);
+
+        let mut server = VerdictServer::with_transport(mock.clone(), |request: &VerdictRequest| {
+            match request.entropy {
+                Some(entropy) if entropy > 7.9 => Verdict::DenyAndKillGid,
+                _ if request.file_change == FileChangeInfo::FileChangeDeleteFile => Verdict::Deny,
+                _ => Verdict::Allow,
+            }
+        });
+        server.serve(&driver).unwrap();
+        assert!(mock.pending_verdicts().is_empty());
+        assert_eq!(server.late_replies(), 0);
+        let verdicts: Vec<(String, Verdict)> = mock
+            .verdicts()
+            .into_iter()
+            .map(|(request, verdict)| (request.path, verdict))
+            .collect();
+        assert_eq!(
+            verdicts,
+            vec![
+                (vault("a.txt"), Verdict::Allow),
+                (vault("b.txt"), Verdict::DenyAndKillGid),
+                (vault("c.txt"), Verdict::Deny),
+            ]
+        );
+        assert_eq!(mock.killed(), vec![10]);
+        // Only the allowed write is recorded
+        assert_eq!(mock.pending_events(), 3);
+        assert!(server.close());
+        assert!(!server.close());
+    }
+
+    #[test]
+    fn test_verdict_server_skips_malformed_requests_until_closed() {
+        let mock = MockDriver::new();
+        let driver = Driver::with_transport(mock.clone()).with_scan_scope(scan_scope());
+        driver.add_verdict_directory(r"C:\Vault").unwrap();
+        let path = r"\Device\HarddiskVolume3\Vault\a.txt";
+        // A file change unknown to this crate
+        let mut newer = MockEvent::new(10, 7, IrpMajorOp::IrpSetInfo, path);
+        newer.file_change = 200;
+        mock.push_events([newer, MockEvent::new(10, 7, IrpMajorOp::IrpWrite, path)]);
+        assert_eq!(mock.pending_verdicts().len(), 1);
+
+        let mut server =
+            VerdictServer::with_transport(mock.clone(), |_: &VerdictRequest| Verdict::Deny);
+        assert_eq!(
+            server.serve_one(&driver),
+            Err(DriverError::MalformedRequest(DecodeError::UnknownCode {
+                field: "file_change",
+                value: 200
+            }))
+        );
+        // Fail-open
+        assert_eq!(mock.pending_events(), 1);
+        server.serve(&driver).unwrap();
+        assert_eq!(server.malformed_requests(), 1);
+        assert_eq!(mock.verdicts().len(), 1);
+
+        let mut request = mock.verdicts().remove(0).0.to_bytes();
+        request[4] = 9;
+        assert_eq!(
+            VerdictRequest::from_bytes(0, &request),
+            Err(DecodeError::UnknownCode {
+                field: "irp_op",
+                value: 9
+            })
+        );
+        assert_eq!(
+            VerdictRequest::from_bytes(0, &request[..32]),
+            Err(DecodeError::TooShort {
+                buffer_len: 32,
+                min_len: VERDICT_REQUEST_SIZE
+            })
+        );
+
+        // Closed from another thread, the pending request is left to the fail policy
+        mock.push_event(MockEvent::new(10, 7, IrpMajorOp::IrpWrite, path));
+        let closer = server.closer();
+        std::thread::spawn(move || assert!(closer.close()))
+            .join()
+            .unwrap();
+        server.serve(&driver).unwrap();
+        assert_eq!(mock.pending_verdicts().len(), 1);
+        assert!(!server.closer().close());
+    }
+
+    #[test]
+    fn test_verdict_fail_policy_survives_reconnect() {
+        let mock = MockDriver::new();
+        let driver = Driver::with_transport(mock.clone()).with_scan_scope(scan_scope());
+        driver.add_verdict_directory(r"C:\Vault").unwrap();
+        let event = || {
+            MockEvent::new(
+                10,
+                7,
+                IrpMajorOp::IrpWrite,
+                r"\Device\HarddiskVolume3\Vault\a.txt",
+            )
+        };
+
+        // Fail-open by default
+        mock.push_event(event());
+        assert_eq!(mock.expire_verdicts(), 1);
+        assert_eq!(mock.pending_events(), 1);
+
+        let config = driver.set_verdict_fail_closed(true).unwrap();
+        assert!(config.verdict_fail_closed);
+        assert_eq!(
+            driver
+                .set_verdict_timeout(Duration::from_millis(250))
+                .unwrap()
+                .verdict_timeout_ms,
+            250
+        );
+        for timeout in [Duration::ZERO, Duration::from_secs(61)] {
+            assert_eq!(
+                driver.set_verdict_timeout(timeout),
+                Err(DriverError::Send(E_INVALID_PARAMETER))
+            );
+        }
+        mock.push_event(event());
+        let request = mock.pending_verdicts().remove(0);
+        assert_eq!(mock.expire_verdicts(), 1);
+        assert_eq!(mock.pending_events(), 1);
+        // Too late
+        assert_eq!(mock.reply(&request, Verdict::Allow), Ok(false));
+
+        mock.unload();
+        driver.reconnect().unwrap();
+        let expected = vec![r"\Device\HarddiskVolume3\Vault\".to_string()];
+        assert_eq!(mock.verdict_directories(), expected);
+        assert_eq!(driver.verdict_directories(), expected);
+        assert_eq!(
+            mock.filter_config(),
+            FilterConfig {
+                verdict_timeout_ms: 250,
+                ..config
+            }
+        );
+    }
+
+    #[test]
+    fn test_verdict_server_survives_failed_kills() {
+        let mock = MockDriver::new();
+        let driver = Driver::with_transport(mock.clone()).with_scan_scope(scan_scope());
+        driver.add_verdict_directory(r"C:\Vault").unwrap();
+        mock.set_gid(7, &[(10, "evil.exe")]);
+        let path = r"\Device\HarddiskVolume3\Vault\a.txt";
+        mock.push_events([
+            MockEvent::new(10, 7, IrpMajorOp::IrpWrite, path),
+            MockEvent::new(10, 7, IrpMajorOp::IrpWrite, path),
+        ]);
+
+        // The command port is lost, the verdict port is not
+        driver.close_kernel_communication().unwrap();
+        let mut server = VerdictServer::with_transport(mock.clone(), |_: &VerdictRequest| {
+            Verdict::DenyAndKillGid
+        });
+        server.serve(&driver).unwrap();
+        assert!(mock.pending_verdicts().is_empty());
+        assert_eq!(mock.verdicts().len(), 2);
+        assert_eq!(server.failed_kills(), 2);
+        assert!(mock.killed().is_empty());
+        assert_eq!(mock.pending_events(), 0);
+    }
+}
@@ -0,0 +1,140 @@
+//! The protocol spoken with the minifilter, and its layout checks.
+//!
+//! [`DriverComMessage`], [`CDriverMsg`] and [`ReplyIrp`] mirror `COM_MESSAGE`, `DRIVER_MESSAGE`
+//! and `RWD_REPLY_IRPS` in `SharedDefs.h` by hand. Their sizes and offsets are asserted at compile
+//! time here, and compared at runtime with the ones of the minifilter
+//! ([`GetVersion`](super::DriverComMessageType::GetVersion)) when the port is opened.
+
+use std::fmt;
+use std::mem::{offset_of, size_of};
+
+use crate::driver_comm::report::GID_REPORT_SIZE;
+use crate::driver_comm::stats::DRIVER_STATS_SIZE;
+use crate::driver_comm::tuning::FILTER_CONFIG_SIZE;
+use crate::driver_comm::verdict::VERDICT_REQUEST_SIZE;
+use crate::driver_comm::{DriverComMessage, MIN_COMM_BUFFER_SIZE};
+use crate::process::tree::GID_TREE_SIZE;
+use crate::shared_def::{CDriverMsg, ReplyIrp, UnicodeString};
+
+/// Version of the protocol implemented by this crate (`PROTOCOL_VERSION` in `SharedDefs.h`).
+/// Bumped on every change of the messages or of their layout.
+pub const PROTOCOL_VERSION: u32 = 11;
+
+// COM_MESSAGE
+const _: () = assert!(size_of::<DriverComMessage>() == 1056);
+const _: () = assert!(offset_of!(DriverComMessage, pid) == 4);
+const _: () = assert!(offset_of!(DriverComMessage, gid) == 8);
+const _: () = assert!(offset_of!(DriverComMessage, path) == 16);
+
+// UNICODE_STRING
+const _: () = assert!(size_of::<UnicodeString>() == 16);
+const _: () = assert!(offset_of!(UnicodeString, buffer) == 8);
+
+// DRIVER_MESSAGE
+const _: () = assert!(size_of::<CDriverMsg>() == 120);
+const _: () = assert!(offset_of!(CDriverMsg, file_id) == 24);
+const _: () = assert!(offset_of!(CDriverMsg, mem_sized_used) == 48);
+const _: () = assert!(offset_of!(CDriverMsg, entropy) == 56);
+const _: () = assert!(offset_of!(CDriverMsg, pid) == 64);
+const _: () = assert!(offset_of!(CDriverMsg, irp_op) == 68);
+const _: () = assert!(offset_of!(CDriverMsg, is_entropy_calc) == 69);
+const _: () = assert!(offset_of!(CDriverMsg, file_change) == 70);
+const _: () = assert!(offset_of!(CDriverMsg, file_location_info) == 71);
+const _: () = assert!(offset_of!(CDriverMsg, filepath) == 72);
+const _: () = assert!(offset_of!(CDriverMsg, gid) == 88);
+const _: () = assert!(offset_of!(CDriverMsg, time) == 96);
+const _: () = assert!(offset_of!(CDriverMsg, sequence) == 104);
+const _: () = assert!(offset_of!(CDriverMsg, next) == 112);
+
+// RWD_REPLY_IRPS, MIN_COMM_BUFFER_SIZE
+const _: () = assert!(size_of::<ReplyIrp>() == 24);
+const _: () = assert!(offset_of!(ReplyIrp, data) == 8);
+const _: () = assert!(offset_of!(ReplyIrp, num_ops) == 16);
+const _: () = assert!(MIN_COMM_BUFFER_SIZE == 1186);
+
+/// Reply of the minifilter to [`GetVersion`](super::DriverComMessageType::GetVersion)
+/// (`DRIVER_VERSION` in `SharedDefs.h`).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(C)]
+pub struct DriverVersion {
+    pub protocol_version: u32,
+    /// `sizeof(COM_MESSAGE)`
+    pub com_message_size: u32,
+    /// `sizeof(DRIVER_MESSAGE)`
+    pub driver_message_size: u32,
+    /// `sizeof(RWD_REPLY_IRPS)`
+    pub reply_irps_size: u32,
+}
+
+const _: () = assert!(size_of::<DriverVersion>() == 16);
+
+// GID_REPORT
+const _: () = assert!(GID_REPORT_SIZE == 24584);
+
+// FILTER_CONFIG
+const _: () = assert!(FILTER_CONFIG_SIZE == 16);
+
+// DRIVER_STATS
+const _: () = assert!(DRIVER_STATS_SIZE == 88);
+
+// VERDICT_REQUEST
+const _: () = assert!(VERDICT_REQUEST_SIZE == 2112);
+
+// GID_TREE
+const _: () = assert!(GID_TREE_SIZE == 137224);
+
+impl DriverVersion {
+    /// The version and sizes expected by this crate.
+    pub const fn current() -> DriverVersion {
+        DriverVersion {
+            protocol_version: PROTOCOL_VERSION,
+            com_message_size: size_of::<DriverComMessage>() as u32,
+            driver_message_size: size_of::<CDriverMsg>() as u32,
+            reply_irps_size: size_of::<ReplyIrp>() as u32,
+        }
+    }
+
+    /// Reads the reply of the minifilter, `None` if it is too short.
+    pub fn from_bytes(buf: &[u8]) -> Option<DriverVersion> {
+        let field = |i: usize| -> Option<u32> {
+            Some(u32::from_ne_bytes(
+                buf.get(4 * i..4 * i + 4)?.try_into().ok()?,
+            ))
+        };
+        Some(DriverVersion {
+            protocol_version: field(0)?,
+            com_message_size: field(1)?,
+            driver_message_size: field(2)?,
+            reply_irps_size: field(3)?,
+        })
+    }
+
+    pub fn to_bytes(self) -> [u8; 16] {
+        let mut buf = [0u8; 16];
+        for (i, field) in [
+            self.protocol_version,
+            self.com_message_size,
+            self.driver_message_size,
+            self.reply_irps_size,
+        ]
+        .iter()
+        .enumerate()
+        {
+            buf[4 * i..4 * i + 4].copy_from_slice(&field.to_ne_bytes());
+        }
+        buf
+    }
+}
+
+impl fmt::Display for DriverVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "protocol v{} (COM_MESSAGE: {} bytes, DRIVER_MESSAGE: {} bytes, RWD_REPLY_IRPS: {} bytes)",
+            self.protocol_version,
+            self.com_message_size,
+            self.driver_message_size,
+            self.reply_irps_size
+        )
+    }
+}
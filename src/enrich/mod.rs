@@ -0,0 +1,359 @@
+//! Enrichment of the [`IOMessage`]s, once decoded.
+//!
+//! Decoding a reply of the minifilter only copies what it sent. Anything which has to be looked up
+//! (the size of the file, the volume it is on, the executable of its gid...) is filled afterwards
+//! by a chain of [`Enricher`]s, so that consumers only pay for the lookups they need:
+//!
+//! ```
+//! use minifilter_rs::enrich::{DriveTypeEnricher, Enrichers, FileSizeEnricher};
+//! use minifilter_rs::volume::VolumeCache;
+//!
+//! let enrichers = Enrichers::new()
+//!     .with(Box::new(FileSizeEnricher::new()))
+//!     .with(Box::new(DriveTypeEnricher::new(Box::new(VolumeCache::new(vec![])))));
+//! assert_eq!(enrichers.names(), vec!["file_size", "drive_type"]);
+//! ```
+//!
+//! Each enricher keeps its own cache, and the time spent in each one is measured in its
+//! [`EnricherStats`].
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::driver_comm::IrpMajorOp;
+use crate::shared_def::nt_path::NtPath;
+use crate::shared_def::IOMessage;
+use crate::volume::VolumeResolver;
+use crate::worker::process_record_handling::Exepath;
+
+/// Max number of entries kept in the cache of an enricher. The cache is emptied when it is full.
+pub const MAX_CACHE_ENTRIES: usize = 4096;
+
+/// Fills some fields of an [`IOMessage`].
+pub trait Enricher: Debug {
+    /// Identifies the enricher in an [`Enrichers`] chain.
+    fn name(&self) -> &'static str;
+
+    fn enrich(&mut self, iomsg: &mut IOMessage);
+}
+
+/// Time spent in an [`Enricher`].
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct EnricherStats {
+    /// Number of [`IOMessage`]s enriched.
+    pub calls: u64,
+    pub total: Duration,
+    /// The slowest call.
+    pub max: Duration,
+}
+
+impl EnricherStats {
+    pub fn mean(&self) -> Duration {
+        if self.calls == 0 {
+            return Duration::ZERO;
+        }
+        self.total / self.calls.min(u32::MAX as u64) as u32
+    }
+
+    fn record(&mut self, elapsed: Duration) {
+        self.calls += 1;
+        self.total += elapsed;
+        self.max = self.max.max(elapsed);
+    }
+}
+
+/// A chain of [`Enricher`]s, run in order on each [`IOMessage`].
+#[derive(Debug, Default)]
+pub struct Enrichers {
+    chain: Vec<(Box<dyn Enricher>, EnricherStats)>,
+}
+
+impl Enrichers {
+    /// No enrichment at all.
+    pub fn new() -> Enrichers {
+        Enrichers { chain: Vec::new() }
+    }
+
+    /// Appends `enricher` to the chain, or replaces the one with the same name.
+    pub fn with(mut self, enricher: Box<dyn Enricher>) -> Self {
+        self.replace(enricher);
+        self
+    }
+
+    pub fn build(self) -> Self {
+        self
+    }
+
+    /// Same as [`with`](Self::with), on a chain already built. The stats of a replaced enricher
+    /// are reset.
+    pub fn replace(&mut self, enricher: Box<dyn Enricher>) {
+        match self
+            .chain
+            .iter_mut()
+            .find(|(current, _)| current.name() == enricher.name())
+        {
+            Some(entry) => *entry = (enricher, EnricherStats::default()),
+            None => self.chain.push((enricher, EnricherStats::default())),
+        }
+    }
+
+    /// Removes the enricher called `name`. Returns false if there is none.
+    pub fn remove(&mut self, name: &str) -> bool {
+        let len = self.chain.len();
+        self.chain.retain(|(enricher, _)| enricher.name() != name);
+        self.chain.len() != len
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.chain
+            .iter()
+            .map(|(enricher, _)| enricher.name())
+            .collect()
+    }
+
+    pub fn enrich(&mut self, iomsg: &mut IOMessage) {
+        for (enricher, stats) in &mut self.chain {
+            let start = Instant::now();
+            enricher.enrich(iomsg);
+            stats.record(start.elapsed());
+        }
+    }
+
+    /// The time spent in each enricher, in the order of the chain.
+    pub fn stats(&self) -> Vec<(&'static str, EnricherStats)> {
+        self.chain
+            .iter()
+            .map(|(enricher, stats)| (enricher.name(), *stats))
+            .collect()
+    }
+}
+
+/// Fills [`file_size`](IOMessage::file_size) from the metadata of the file, -1 if it cannot be
+/// read. Device paths are read through `\\?\GLOBALROOT`, see [`NtPath::to_win32`].
+///
+/// Sizes are cached per path until the file is opened, written or its information set again:
+/// reads and cleanups do not ask the file system.
+#[derive(Debug, Default)]
+pub struct FileSizeEnricher {
+    sizes: HashMap<String, i64>,
+}
+
+impl FileSizeEnricher {
+    pub fn new() -> FileSizeEnricher {
+        FileSizeEnricher {
+            sizes: HashMap::new(),
+        }
+    }
+}
+
+impl Enricher for FileSizeEnricher {
+    fn name(&self) -> &'static str {
+        "file_size"
+    }
+
+    fn enrich(&mut self, iomsg: &mut IOMessage) {
+        let unchanged = matches!(
+            IrpMajorOp::try_from(iomsg.irp_op),
+            Ok(IrpMajorOp::IrpRead | IrpMajorOp::IrpCleanUp)
+        );
+        if unchanged {
+            if let Some(size) = self.sizes.get(&iomsg.filepathstr) {
+                iomsg.file_size = *size;
+                return;
+            }
+        }
+        let path = NtPath::parse(&iomsg.filepathstr)
+            .map_or_else(|| iomsg.filepathstr.clone(), |path| path.to_win32());
+        iomsg.file_size = match Path::new(&path).metadata() {
+            Ok(metadata) => metadata.len() as i64,
+            Err(_) => -1,
+        };
+        if self.sizes.len() >= MAX_CACHE_ENTRIES {
+            self.sizes.clear();
+        }
+        self.sizes
+            .insert(iomsg.filepathstr.clone(), iomsg.file_size);
+    }
+}
+
+/// Fills the [`drive_type`](crate::shared_def::RuntimeFeatures::drive_type) of the file, with a
+/// [`VolumeResolver`] (which has its own cache).
+#[derive(Debug)]
+pub struct DriveTypeEnricher {
+    volume_resolver: Box<dyn VolumeResolver>,
+}
+
+impl DriveTypeEnricher {
+    pub fn new(volume_resolver: Box<dyn VolumeResolver>) -> DriveTypeEnricher {
+        DriveTypeEnricher { volume_resolver }
+    }
+}
+
+impl Enricher for DriveTypeEnricher {
+    fn name(&self) -> &'static str {
+        "drive_type"
+    }
+
+    fn enrich(&mut self, iomsg: &mut IOMessage) {
+        iomsg.runtime_features.drive_type = self.volume_resolver.drive_type(&iomsg.filepathstr);
+    }
+}
+
+/// Fills the [`exepath`](crate::shared_def::RuntimeFeatures::exepath) of the gid with an
+/// [`Exepath`] handler, looked up once per gid.
+///
+/// The [`Worker`](crate::worker::Worker) takes it from its records instead: this one is for the
+/// consumers which do not keep any.
+#[derive(Debug)]
+pub struct ExepathEnricher {
+    exepath_handler: Box<dyn Exepath>,
+    exepaths: HashMap<u64, Option<PathBuf>>,
+}
+
+impl ExepathEnricher {
+    pub fn new(exepath_handler: Box<dyn Exepath>) -> ExepathEnricher {
+        ExepathEnricher {
+            exepath_handler,
+            exepaths: HashMap::new(),
+        }
+    }
+}
+
+impl Enricher for ExepathEnricher {
+    fn name(&self) -> &'static str {
+        "exepath"
+    }
+
+    fn enrich(&mut self, iomsg: &mut IOMessage) {
+        if !self.exepaths.contains_key(&iomsg.gid) && self.exepaths.len() >= MAX_CACHE_ENTRIES {
+            self.exepaths.clear();
+        }
+        let exepath = self
+            .exepaths
+            .entry(iomsg.gid)
+            .or_insert_with(|| self.exepath_handler.exepath(iomsg));
+        if let Some(exepath) = exepath {
+            iomsg.runtime_features.exepath = exepath.clone();
+            iomsg.runtime_features.exe_still_exists = true;
+        }
+    }
+}
+
+#[cfg(test)]
+#[doc(hidden)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+    use std::time::UNIX_EPOCH;
+
+    use crate::driver_comm::{DriveType, IrpMajorOp};
+    use crate::enrich::{
+        DriveTypeEnricher, Enricher, Enrichers, ExepathEnricher, FileSizeEnricher,
+    };
+    use crate::shared_def::{IOMessage, RuntimeFeatures};
+    use crate::volume::{VolumeCache, VolumeInfo};
+    use crate::worker::process_record_handling::Exepath;
+
+    #[derive(Debug)]
+    struct ExepathFixed;
+
+    impl Exepath for ExepathFixed {
+        fn exepath(&self, iomsg: &IOMessage) -> Option<PathBuf> {
+            (iomsg.gid != 3).then(|| PathBuf::from(r"C:\Tools\bad.exe"))
+        }
+    }
+
+    fn iomsg(gid: u64, irp_op: IrpMajorOp, filepathstr: &str) -> IOMessage {
+        IOMessage {
+            extension: [0; 12],
+            file_id_vsn: 0,
+            file_id_id: [0; 16],
+            mem_sized_used: 0,
+            entropy: 0.0,
+            pid: 10,
+            irp_op: irp_op as u8,
+            is_entropy_calc: 0,
+            file_change: 0,
+            file_location_info: 0,
+            filepathstr: filepathstr.to_string(),
+            gid,
+            runtime_features: RuntimeFeatures::new(),
+            file_size: -1,
+            time: UNIX_EPOCH,
+            sequence: 0,
+            received: UNIX_EPOCH,
+        }
+    }
+
+    #[test]
+    fn test_enrichers_chain() {
+        let path = std::env::temp_dir().join(format!("enrich-{}.txt", std::process::id()));
+        fs::write(&path, b"0123456789").unwrap();
+        let filepath = path.to_string_lossy().to_string();
+
+        let mut enrichers = Enrichers::new()
+            .with(Box::new(FileSizeEnricher::new()))
+            .with(Box::new(DriveTypeEnricher::new(Box::new(
+                VolumeCache::new(vec![VolumeInfo {
+                    device: r"\Device\HarddiskVolume3".to_string(),
+                    drive_type: DriveType::DriveFixed,
+                    serial: 0,
+                    mount_points: vec![r"C:\".to_string()],
+                }]),
+            ))))
+            .build();
+
+        let mut read = iomsg(1, IrpMajorOp::IrpRead, &filepath);
+        enrichers.enrich(&mut read);
+        assert_eq!(read.file_size, 10);
+
+        // Reads hit the cache, writes do not
+        fs::write(&path, b"01234567890123456789").unwrap();
+        let mut read = iomsg(1, IrpMajorOp::IrpRead, &filepath);
+        enrichers.enrich(&mut read);
+        assert_eq!(read.file_size, 10);
+        let mut write = iomsg(1, IrpMajorOp::IrpWrite, &filepath);
+        enrichers.enrich(&mut write);
+        assert_eq!(write.file_size, 20);
+        fs::remove_file(&path).unwrap();
+        let mut cleanup = iomsg(1, IrpMajorOp::IrpCleanUp, r"C:\Users\Dev\gone.txt");
+        enrichers.enrich(&mut cleanup);
+        assert_eq!(cleanup.file_size, -1);
+        assert_eq!(cleanup.runtime_features.drive_type, DriveType::DriveFixed);
+
+        let stats = enrichers.stats();
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0].0, "file_size");
+        assert!(stats.iter().all(|(_, stats)| stats.calls == 4));
+        assert!(stats[0].1.max <= stats[0].1.total);
+
+        // Users choose what to pay for
+        assert!(enrichers.remove("file_size"));
+        assert!(!enrichers.remove("file_size"));
+        let mut write = iomsg(1, IrpMajorOp::IrpWrite, &filepath);
+        enrichers.enrich(&mut write);
+        assert_eq!(write.file_size, -1);
+        assert_eq!(enrichers.names(), vec!["drive_type"]);
+    }
+
+    #[test]
+    fn test_exepath_looked_up_once_per_gid() {
+        let mut enricher = ExepathEnricher::new(Box::new(ExepathFixed));
+        let mut iomsgs = vec![
+            iomsg(1, IrpMajorOp::IrpWrite, r"C:\a.txt"),
+            iomsg(1, IrpMajorOp::IrpWrite, r"C:\b.txt"),
+            iomsg(3, IrpMajorOp::IrpWrite, r"C:\c.txt"),
+        ];
+        for iomsg in &mut iomsgs {
+            enricher.enrich(iomsg);
+        }
+        assert_eq!(
+            iomsgs[1].runtime_features.exepath,
+            PathBuf::from(r"C:\Tools\bad.exe")
+        );
+        assert_eq!(iomsgs[2].runtime_features.exepath, PathBuf::new());
+        assert_eq!(enricher.exepaths.len(), 2);
+    }
+}
@@ -35,6 +35,8 @@
 //!
 //! You should be able to see the driver at `"C:\Windows\System32\drivers\FsFilter.sys"`
 //!
+//! Deployment tools can do the same from Rust with [`FilterService::deploy`](service::FilterService::deploy).
+//!
 //! ### Loading/Removing Driver
 //!
 //! 1. Open Powershell or command prompt as Administrator
@@ -66,6 +68,8 @@
 //!    [SC] DeleteService SUCCESS
 //!    ```
 //!
+//! [`FilterService`](service::FilterService) also loads, unloads and uninstalls the driver.
+//!
 //! You can also run `Fltmc.exe` to see the currently loaded drivers:
 //!
 //! ```ignore
@@ -94,12 +98,14 @@
 //!
 //! Use `cargo run --bin minifilter --release` to run the application
 //!
-//! The program starts to print the `IOMessage` which is defined like:
+//! The program starts to print the `IOMessage`s as JSON Lines, one per line, or to write them to the
+//! file given as argument (e.g. `cargo run --bin minifilter --release -- events.jsonl`), rotated by
+//! size. The `output` module reads them back. `IOMessage` is defined like:
 //!
 //! ```ignore
 //! #[repr(C)]
 //! pub struct IOMessage {
-//!     pub extension: [wchar_t; 12],
+//!     pub extension: [u16; 12],
 //!     pub file_id_vsn: c_ulonglong,
 //!     pub file_id_id: [u8; 16],
 //!     pub mem_sized_used: c_ulonglong,
@@ -113,6 +119,9 @@
 //!     pub gid: c_ulonglong,
 //!     pub runtime_features: RuntimeFeatures,
 //!     pub file_size: i64,
+//!     pub time: SystemTime,
+//!     pub sequence: c_ulonglong,
+//!     pub received: SystemTime,
 //! }
 //! ```
 //!
@@ -133,9 +142,19 @@
 //!
 //! We use [channels](https://!doc.rust-lang.org/std/sync/mpsc/fn.channel.html) to process
 //! all [IRPs](https://!docs.microsoft.com/en-us/windows-hardware/drivers/ifs/irps-are-different-from-fast-i-o).
+//!
+//! These IRPs are reported once done. In the directories given to
+//! [`Driver::add_verdict_directory`](driver_comm::Driver::add_verdict_directory), writes, renames
+//! and deletes can be allowed or denied beforehand by a
+//! [`VerdictServer`](driver_comm::verdict::VerdictServer).
 
 pub mod driver_comm;
+pub mod enrich;
+pub mod output;
 pub mod process;
+pub mod service;
 pub mod shared_def;
 pub mod slc_paths;
+pub mod trace;
+pub mod volume;
 pub mod worker;
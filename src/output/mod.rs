@@ -0,0 +1,358 @@
+//! Export and import of the [`IOMessage`]s as JSON Lines, for `jq`, pandas and the like.
+//!
+//! Each line is a [`FileEvent`]: typed op names (`"op":"write"`) and ISO 8601 timestamps. A
+//! [`JsonLinesReader`] rebuilds the [`IOMessage`]s, which can be given again to a
+//! [`Worker`](crate::worker::Worker):
+//!
+//! ```no_run
+//! use std::path::Path;
+//! use minifilter_rs::output::JsonLinesReader;
+//! use minifilter_rs::worker::Worker;
+//!
+//! let mut worker = Worker::new();
+//! for iomsg in JsonLinesReader::open_rotated(Path::new("events.jsonl")).unwrap() {
+//!     worker.process_io(&mut iomsg.unwrap());
+//! }
+//! ```
+//!
+//! Long captures are split by a [`RotatingFile`].
+
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::shared_def::file_event::{FileEvent, UnknownCode};
+use crate::shared_def::IOMessage;
+
+/// A [`RotatingFile`] is rotated beyond this size, unless tuned.
+pub const DEFAULT_MAX_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Number of rotated files kept by a [`RotatingFile`], unless tuned.
+pub const DEFAULT_KEEP: usize = 9;
+
+/// Why an event could not be exported or imported.
+#[derive(Debug)]
+pub enum OutputError {
+    Io(io::Error),
+    /// The [`IOMessage`] holds a code unknown to this crate.
+    UnknownCode(UnknownCode),
+    /// The line `line` (from 1) is not a [`FileEvent`].
+    Json {
+        line: u64,
+        error: serde_json::Error,
+    },
+}
+
+impl fmt::Display for OutputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputError::Io(e) => write!(f, "output i/o error: {e}"),
+            OutputError::UnknownCode(e) => write!(f, "cannot export the event: {e}"),
+            OutputError::Json { line, error } => write!(f, "line {line}: {error}"),
+        }
+    }
+}
+
+impl Error for OutputError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            OutputError::Io(e) => Some(e),
+            OutputError::UnknownCode(e) => Some(e),
+            OutputError::Json { error, .. } => Some(error),
+        }
+    }
+}
+
+impl From<io::Error> for OutputError {
+    fn from(e: io::Error) -> Self {
+        OutputError::Io(e)
+    }
+}
+
+/// Writes one [`FileEvent`] per line.
+#[derive(Debug)]
+pub struct JsonLinesWriter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> JsonLinesWriter<W> {
+    pub fn new(out: W) -> JsonLinesWriter<W> {
+        JsonLinesWriter { out }
+    }
+
+    pub fn write_iomsg(&mut self, iomsg: &IOMessage) -> Result<(), OutputError> {
+        let event = FileEvent::try_from(iomsg).map_err(OutputError::UnknownCode)?;
+        self.write_event(&event)
+    }
+
+    /// The line is written at once, a [`RotatingFile`] never splits it.
+    pub fn write_event(&mut self, event: &FileEvent) -> Result<(), OutputError> {
+        let mut line = serde_json::to_vec(event).map_err(io::Error::from)?;
+        line.push(b'\n');
+        self.out.write_all(&line)?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), OutputError> {
+        Ok(self.out.flush()?)
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+/// A file rotated by size: `events.jsonl` is renamed to `events.jsonl.1` once it would grow beyond
+/// the [`max_size`](Self::max_size), `events.jsonl.1` to `events.jsonl.2`, and so on. The oldest
+/// files beyond the [`keep`](Self::keep) are removed.
+///
+/// Files are only rotated between two writes, give it whole lines.
+#[derive(Debug)]
+pub struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    keep: usize,
+    file: BufWriter<File>,
+    size: u64,
+}
+
+impl RotatingFile {
+    /// Appends to `path`, rotated beyond [`DEFAULT_MAX_SIZE`] with [`DEFAULT_KEEP`] files kept.
+    pub fn create(path: &Path) -> io::Result<RotatingFile> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile {
+            path: path.to_path_buf(),
+            max_size: DEFAULT_MAX_SIZE,
+            keep: DEFAULT_KEEP,
+            file: BufWriter::new(file),
+            size,
+        })
+    }
+
+    pub fn max_size(mut self, max_size: u64) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Number of rotated files kept, 0 to only keep the current one.
+    pub fn keep(mut self, keep: usize) -> Self {
+        self.keep = keep;
+        self
+    }
+
+    /// The files of `path`, from the oldest to the current one, if they exist.
+    pub fn files(path: &Path) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = (1..)
+            .map(|i| Self::rotated_path(path, i))
+            .take_while(|rotated| rotated.exists())
+            .collect();
+        files.reverse();
+        if path.exists() {
+            files.push(path.to_path_buf());
+        }
+        files
+    }
+
+    fn rotated_path(path: &Path, i: usize) -> PathBuf {
+        let mut rotated = path.as_os_str().to_owned();
+        rotated.push(format!(".{i}"));
+        PathBuf::from(rotated)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let oldest = Self::rotated_path(&self.path, self.keep + 1);
+        for i in (1..=self.keep).rev() {
+            let rotated = Self::rotated_path(&self.path, i);
+            if rotated.exists() {
+                fs::rename(&rotated, Self::rotated_path(&self.path, i + 1))?;
+            }
+        }
+        if self.keep > 0 {
+            fs::rename(&self.path, Self::rotated_path(&self.path, 1))?;
+        }
+        if oldest.exists() {
+            fs::remove_file(oldest)?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)?;
+        self.file = BufWriter::new(file);
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        self.file.write_all(buf)?;
+        self.size += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Reads the lines written by a [`JsonLinesWriter`] back to [`IOMessage`]s. Empty lines are
+/// skipped.
+#[derive(Debug)]
+pub struct JsonLinesReader<R: BufRead> {
+    input: R,
+    line: u64,
+}
+
+impl<R: BufRead> JsonLinesReader<R> {
+    pub fn new(input: R) -> JsonLinesReader<R> {
+        JsonLinesReader { input, line: 0 }
+    }
+
+    /// The next event, `None` at the end of the input.
+    pub fn next_event(&mut self) -> Option<Result<FileEvent, OutputError>> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            self.line += 1;
+            match self.input.read_line(&mut line) {
+                Ok(0) => return None,
+                Ok(_) if line.trim().is_empty() => continue,
+                Ok(_) => {
+                    return Some(
+                        serde_json::from_str(&line).map_err(|error| OutputError::Json {
+                            line: self.line,
+                            error,
+                        }),
+                    )
+                }
+                Err(e) => return Some(Err(e.into())),
+            }
+        }
+    }
+}
+
+impl JsonLinesReader<Box<dyn BufRead>> {
+    /// Reads all the [`files`](RotatingFile::files) of `path`, the oldest first. Line numbers run
+    /// across them.
+    pub fn open_rotated(path: &Path) -> io::Result<JsonLinesReader<Box<dyn BufRead>>> {
+        let mut input: Box<dyn BufRead> = Box::new(io::empty());
+        for file in RotatingFile::files(path) {
+            input = Box::new(Read::chain(input, BufReader::new(File::open(file)?)));
+        }
+        Ok(JsonLinesReader::new(input))
+    }
+}
+
+impl<R: BufRead> Iterator for JsonLinesReader<R> {
+    type Item = Result<IOMessage, OutputError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_event()
+            .map(|event| event.map(|event| event.to_iomessage()))
+    }
+}
+
+#[cfg(test)]
+#[doc(hidden)]
+mod tests {
+    use std::fs;
+    use std::io::Cursor;
+    use std::path::PathBuf;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use crate::driver_comm::{DriveType, IrpMajorOp};
+    use crate::enrich::Enrichers;
+    use crate::output::{JsonLinesReader, JsonLinesWriter, OutputError, RotatingFile};
+    use crate::shared_def::{IOMessage, RuntimeFeatures};
+    use crate::worker::Worker;
+
+    fn iomsg(sequence: u64) -> IOMessage {
+        let time = UNIX_EPOCH + Duration::from_secs(1_700_000_000 + sequence);
+        IOMessage {
+            extension: [116, 120, 116, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            file_id_vsn: 0x1234,
+            file_id_id: [sequence as u8; 16],
+            mem_sized_used: 4096,
+            entropy: 7.5,
+            pid: 1200,
+            irp_op: IrpMajorOp::IrpWrite as u8,
+            is_entropy_calc: 1,
+            file_change: 2,
+            file_location_info: 0,
+            filepathstr: format!(r"C:\Users\Dev\{sequence}.txt"),
+            gid: 7,
+            runtime_features: RuntimeFeatures {
+                exepath: PathBuf::from(r"C:\Tools\bad.exe"),
+                exe_still_exists: true,
+                drive_type: DriveType::DriveFixed,
+            },
+            file_size: 65536,
+            time,
+            sequence,
+            received: time + Duration::from_micros(250),
+        }
+    }
+
+    #[test]
+    fn test_rotated_export_read_back() {
+        let dir = std::env::temp_dir().join(format!("output-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("events.jsonl");
+
+        let file = RotatingFile::create(&path).unwrap().max_size(1000).keep(2);
+        let mut writer = JsonLinesWriter::new(file);
+        for sequence in 0..10 {
+            writer.write_iomsg(&iomsg(sequence)).unwrap();
+        }
+        writer.flush().unwrap();
+        drop(writer);
+
+        let files = RotatingFile::files(&path);
+        assert_eq!(files.len(), 3);
+        assert_eq!(files[2], path);
+        let first_line = fs::read_to_string(&files[0]).unwrap();
+        let first_line = first_line.lines().next().unwrap();
+        assert!(first_line.contains(r#""op":"write""#));
+        assert!(first_line.contains(r#""time":"2023-11-14T22:1"#));
+        let lines: usize = files
+            .iter()
+            .map(|file| fs::read_to_string(file).unwrap().lines().count())
+            .sum();
+        assert!(lines < 10);
+
+        // The oldest events are gone with the oldest file, the others are in order
+        let iomsgs: Vec<IOMessage> = JsonLinesReader::open_rotated(&path)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(iomsgs.len(), lines);
+        let first = 10 - lines as u64;
+        for (iomsg, sequence) in iomsgs.iter().zip(first..) {
+            assert_eq!(
+                serde_json::to_value(iomsg).unwrap(),
+                serde_json::to_value(self::iomsg(sequence)).unwrap()
+            );
+        }
+
+        let mut worker = Worker::new().enrichers(Enrichers::new()).build();
+        for mut iomsg in iomsgs {
+            worker.process_io(&mut iomsg);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+
+        let mut reader = JsonLinesReader::new(Cursor::new("\n{\"pid\":1}\n"));
+        assert!(matches!(
+            reader.next(),
+            Some(Err(OutputError::Json { line: 2, .. }))
+        ));
+        assert!(reader.next().is_none());
+    }
+}
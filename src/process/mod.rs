@@ -4,6 +4,7 @@
 //! Each windows process has a unique parent. However, there are notable differences with Linux:
 //! - Process creation is achieved by calling *CreateProcess*, which differs from *fork*,
 //! - A process can erase its genealogy, and event change its parent!
+//!
 //! Process Creations are monitored by the minifilter. As all processes are children of *Windows System*,
 //! identified by pid == 4, the minifilter defines subfamilies identified by a unique group id
 //! (referred to *gid* in the code).
@@ -11,23 +12,27 @@
 //! ## How is a GID state maintained over time?
 //! A [`ProcessRecord`] instance is associated to each *GID* identified by the driver.
 //! [`IOMessage`](crate::shared_def::IOMessage) fetched from the minifilter contains data that
-//! are aggregated in real time and used for predictions by the Neural Network.
+//! are aggregated in real time and used for predictions by the Neural Network. The minifilter also
+//! reports the creation and exit of the processes, as [`ProcessEvent`](crate::shared_def::ProcessEvent)s:
+//! a record is opened with its gid and closed once its last process has exited, see
+//! [`Worker::process_event`](crate::worker::Worker::process_event).
 //!
 //! ## Time is not a good metric
 //! Let's consider two scenarios about the performances of the client hardware hosting:
 //! - It is very fast: we would observe a very quick increase in activity over time, resulting in
-//! false-positive
+//!   false-positive
 //! - It is very slow: the model would have a bad recall for malware's, as they would have a very slow
-//! activity
+//!   activity
 //!
 //! Use time-independent metric which is the number of driver messages received from a driver.
 
 pub mod extensions;
+pub mod tree;
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Formatter;
 use std::ops::Mul;
-use std::os::raw::{c_ulong, c_ulonglong};
+use std::os::raw::c_ulonglong;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::mpsc;
@@ -35,11 +40,15 @@ use std::sync::mpsc::{Receiver, Sender};
 use std::time::{Duration, SystemTime};
 use std::{fmt, thread};
 
+use serde::{Deserialize, Serialize};
 use sysinfo::{Pid, ProcessExt, ProcessStatus, System, SystemExt};
 use windows::Win32::Storage::FileSystem::{FILE_ID_128, FILE_ID_INFO};
 
-use crate::driver_comm::{DriveType, DriveType::*, IrpMajorOp};
+use crate::driver_comm::error::DriverError;
+use crate::driver_comm::transport::DriverTransport;
+use crate::driver_comm::{DriveType::*, Driver, IrpMajorOp};
 use crate::process::extensions::ExtensionsCount;
+use crate::process::tree::ProcessTree;
 use crate::shared_def::{FileChangeInfo, IOMessage};
 use crate::slc_paths::clustering::clustering;
 
@@ -55,7 +64,7 @@ pub struct ProcessRecord {
     /// Group Identifier: a unique number (maintained by the minifilter) identifying this family of precesses.
     pub gid: c_ulonglong,
     /// Set of pids in this family of processes.
-    pub pids: HashSet<c_ulong>,
+    pub pids: HashSet<u32>,
     /// Count of Read operations [`IrpRead`](crate::driver_comm::IrpMajorOp::IrpRead)
     pub ops_read: u64,
     /// Count of SetInfo operations [`IrpSetInfo`](crate::driver_comm::IrpMajorOp::IrpSetInfo)
@@ -64,6 +73,10 @@ pub struct ProcessRecord {
     pub ops_written: u64,
     /// Count of Handle Creation operations [`IrpCreate`](crate::driver_comm::IrpMajorOp::IrpCreate)
     pub ops_open: u64,
+    /// Count of Handle Cleanup operations [`IrpCleanUp`](crate::driver_comm::IrpMajorOp::IrpCleanUp)
+    pub ops_cleanup: u64,
+    /// Count of operations unknown to this crate, see [`UnknownIrpOp`](crate::driver_comm::UnknownIrpOp)
+    pub ops_unknown: u64,
     /// Total of bytes read
     pub bytes_read: u64,
     /// Total bytes written
@@ -110,12 +123,20 @@ pub struct ProcessRecord {
     pub time_killed: Option<SystemTime>,
     /// Time of process suspended
     pub time_suspended: Option<SystemTime>,
+    /// Time the last process of the Gid exited, see [`Worker::process_event`](crate::worker::Worker::process_event)
+    pub time_exited: Option<SystemTime>,
     /// Number of directories (with files updated) clusters created
     pub clusters: usize,
     /// Deepest cluster size
     pub clusters_max_size: usize,
     /// Number of driver messages received for this Gid
     pub driver_msg_count: usize,
+    /// Operations dropped by the minifilter while this Gid was active, whichever their Gid. The
+    /// record may miss some of its own operations if not 0, see [`is_incomplete`](Self::is_incomplete).
+    pub ops_dropped: u64,
+    /// The whole family as last reported by the minifilter, see
+    /// [`refresh_tree`](Self::refresh_tree).
+    pub tree: Option<ProcessTree>,
 
     /// Used by [`launch_thread_clustering`](Self::launch_thread_clustering) to communicate with a thread in charge of the heavy computations (clustering).
     tx: Sender<MultiThreadClustering>,
@@ -160,6 +181,33 @@ pub struct ProcessRecord {
     pub on_removable_drive_read_count: u32,
     /// Count of Write operations ['IrpWrite'](crate::driver_comm::IrpMajorOp::IrpWrite) on a removable drive
     pub on_removable_drive_write_count: u32,
+
+    /// Duration of each closed handle session, from its opening to its cleanup, as timestamped by
+    /// the minifilter
+    pub handle_lifetimes: Vec<Duration>,
+    /// Length of each closed handle session, in number of driver messages received for this Gid
+    /// (see [Time is not a good metric](self#time-is-not-a-good-metric)).
+    pub handle_lifetimes_msgs: Vec<usize>,
+    /// File descriptors whose handle session was closed without being read or written
+    pub files_closed_without_io: HashSet<FileId>,
+    /// Number of bytes written during each closed handle session
+    pub bytes_written_per_handle: Vec<c_ulonglong>,
+    /// Handle sessions opened and not closed yet.
+    open_handles: HashMap<FileId, HandleSession>,
+}
+
+/// The handles opened by a gid on a file, from the first [`IrpCreate`](IrpMajorOp::IrpCreate)
+/// to the last [`IrpCleanUp`](IrpMajorOp::IrpCleanUp).
+#[derive(Debug)]
+struct HandleSession {
+    /// [`driver_msg_count`](ProcessRecord::driver_msg_count) at the opening.
+    opened_at: usize,
+    opened: SystemTime,
+    /// Number of handles still opened on the file.
+    handles: u32,
+    /// The file has been read or written.
+    has_io: bool,
+    bytes_written: c_ulonglong,
 }
 
 /// A tuple-struct to communicate with the thread in charge of calculating the clusters.
@@ -170,17 +218,26 @@ pub struct MultiThreadClustering {
 }
 
 impl ProcessRecord {
+    /// The record starts with `iomsg`, at its [`time`](IOMessage::time).
     pub fn from(iomsg: &IOMessage, appname: String, exepath: PathBuf) -> ProcessRecord {
+        let mut precord = ProcessRecord::new(iomsg.gid, appname, exepath);
+        precord.time_started = iomsg.time;
+        precord
+    }
+
+    pub fn new(gid: c_ulonglong, appname: String, exepath: PathBuf) -> ProcessRecord {
         let (tx, rx) = mpsc::channel::<MultiThreadClustering>();
 
         ProcessRecord {
             appname,
-            gid: iomsg.gid,
+            gid,
             pids: HashSet::new(),
             ops_read: 0,
             ops_setinfo: 0,
             ops_written: 0,
             ops_open: 0,
+            ops_cleanup: 0,
+            ops_unknown: 0,
             bytes_read: 0,
             bytes_written: 0,
             entropy_read: 0.0,
@@ -204,6 +261,8 @@ impl ProcessRecord {
             time_started: SystemTime::now(),
             time_killed: None,
             driver_msg_count: 0,
+            ops_dropped: 0,
+            tree: None,
             clusters: 0,
             clusters_max_size: 0,
             tx,
@@ -224,10 +283,16 @@ impl ProcessRecord {
             bytes_size_large: Vec::new(),
             bytes_size_huge: Vec::new(),
             time_suspended: None,
+            time_exited: None,
             on_shared_drive_read_count: 0,
             on_shared_drive_write_count: 0,
             on_removable_drive_read_count: 0,
             on_removable_drive_write_count: 0,
+            handle_lifetimes: Vec::new(),
+            handle_lifetimes_msgs: Vec::new(),
+            files_closed_without_io: HashSet::new(),
+            bytes_written_per_handle: Vec::new(),
+            open_handles: HashMap::new(),
         }
     }
 
@@ -246,7 +311,12 @@ impl ProcessRecord {
 
     fn update_clusters(&mut self) {
         if self.driver_msg_count % 100 == 0 {
-            if self.is_to_cluster() {
+            if cfg!(feature = "replay") {
+                // The clusters of a replay must not depend on the speed of a thread
+                let cs = clustering(self.dirs_with_files_updated.clone());
+                self.clusters = cs.len();
+                self.clusters_max_size = cs.iter().map(|c| c.size()).max().unwrap_or(0);
+            } else if self.is_to_cluster() {
                 self.launch_thread_clustering();
                 self.is_thread_clustering_running = true;
                 self.last_thread_clustering_time = SystemTime::now();
@@ -267,18 +337,45 @@ impl ProcessRecord {
         }
     }
 
+    /// Accounts for `dropped` operations discarded by the minifilter, reported by its
+    /// [`DriverStats`](crate::driver_comm::stats::DriverStats).
+    pub fn add_dropped_ops(&mut self, dropped: u64) {
+        self.ops_dropped += dropped;
+    }
+
+    /// The features of this record are built on incomplete data: operations were dropped by the
+    /// minifilter while it was active.
+    pub fn is_incomplete(&self) -> bool {
+        self.ops_dropped > 0
+    }
+
+    /// Asks the minifilter for the whole family of this gid, including the processes which never
+    /// performed any file I/O, and adds their pids to [`pids`](Self::pids). Returns `None`, and
+    /// keeps the last tree, if the gid has ended.
+    pub fn refresh_tree<T: DriverTransport>(
+        &mut self,
+        driver: &Driver<T>,
+    ) -> Result<Option<&ProcessTree>, DriverError> {
+        let Some(tree) = driver.get_gid_tree(self.gid)? else {
+            return Ok(None);
+        };
+        self.pids.extend(tree.nodes.iter().map(|node| node.pid));
+        Ok(Some(self.tree.insert(tree)))
+    }
+
     /// Entry point to call on new drivermsg.
     pub fn add_irp_record(&mut self, iomsg: &IOMessage) {
         self.driver_msg_count += 1;
         self.pids.insert(iomsg.pid);
         self.exe_exists = iomsg.runtime_features.exe_still_exists;
-        match IrpMajorOp::from_byte(iomsg.irp_op) {
-            IrpMajorOp::IrpNone => {}
-            IrpMajorOp::IrpRead => self.update_read(iomsg),
-            IrpMajorOp::IrpWrite => self.update_write(iomsg),
-            IrpMajorOp::IrpSetInfo => self.update_set(iomsg),
-            IrpMajorOp::IrpCreate => self.update_create(iomsg),
-            _ => {}
+        match IrpMajorOp::try_from(iomsg.irp_op) {
+            Ok(IrpMajorOp::IrpNone) => {}
+            Ok(IrpMajorOp::IrpRead) => self.update_read(iomsg),
+            Ok(IrpMajorOp::IrpWrite) => self.update_write(iomsg),
+            Ok(IrpMajorOp::IrpSetInfo) => self.update_set(iomsg),
+            Ok(IrpMajorOp::IrpCreate) => self.update_create(iomsg),
+            Ok(IrpMajorOp::IrpCleanUp) => self.update_cleanup(iomsg),
+            Err(_) => self.ops_unknown += 1,
         }
         self.update_clusters();
     }
@@ -293,9 +390,12 @@ impl ProcessRecord {
             VolumeSerialNumber: iomsg.file_id_vsn,
         }));
         self.extensions_read
-            .add_cat_extension(&*String::from_utf16_lossy(&iomsg.extension));
+            .add_cat_extension(&String::from_utf16_lossy(&iomsg.extension));
         self.entropy_read += iomsg.entropy * (iomsg.mem_sized_used as f64);
-        match DriveType::from_filepath(iomsg.filepathstr.clone()) {
+        if let Some(session) = self.open_handles.get_mut(&FileId::of(iomsg)) {
+            session.has_io = true;
+        }
+        match iomsg.runtime_features.drive_type {
             DriveRemovable => self.on_removable_drive_read_count += 1,
             DriveRemote => self.on_shared_drive_read_count += 1,
             DriveCDRom => self.on_removable_drive_read_count += 1,
@@ -325,11 +425,15 @@ impl ProcessRecord {
             self.dirs_with_files_updated.insert(dir);
         }
         self.extensions_written
-            .add_cat_extension(&*String::from_utf16_lossy(&iomsg.extension));
+            .add_cat_extension(&String::from_utf16_lossy(&iomsg.extension));
         self.entropy_written += iomsg.entropy * (iomsg.mem_sized_used as f64);
+        if let Some(session) = self.open_handles.get_mut(&FileId::of(iomsg)) {
+            session.has_io = true;
+            session.bytes_written += iomsg.mem_sized_used;
+        }
         self.sort_bytes(iomsg.mem_sized_used);
         self.sort_file_size(iomsg.file_size, &iomsg.filepathstr);
-        match DriveType::from_filepath(iomsg.filepathstr.clone()) {
+        match iomsg.runtime_features.drive_type {
             DriveRemovable => self.on_removable_drive_write_count += 1,
             DriveRemote => self.on_shared_drive_write_count += 1,
             DriveCDRom => self.on_removable_drive_write_count += 1,
@@ -364,7 +468,7 @@ impl ProcessRecord {
             }
             Some(FileChangeInfo::FileChangeExtensionChanged) => {
                 self.extensions_written
-                    .add_cat_extension(&*String::from_utf16_lossy(&iomsg.extension));
+                    .add_cat_extension(&String::from_utf16_lossy(&iomsg.extension));
 
                 self.fpaths_updated.insert(fpath);
                 if let Some(dir) = Some(
@@ -409,8 +513,19 @@ impl ProcessRecord {
 
     fn update_create(&mut self, iomsg: &IOMessage) {
         self.ops_open += 1;
+        let driver_msg_count = self.driver_msg_count;
+        self.open_handles
+            .entry(FileId::of(iomsg))
+            .or_insert(HandleSession {
+                opened_at: driver_msg_count,
+                opened: iomsg.time,
+                handles: 0,
+                has_io: false,
+                bytes_written: 0,
+            })
+            .handles += 1;
         self.extensions_written
-            .add_cat_extension(&*String::from_utf16_lossy(&iomsg.extension));
+            .add_cat_extension(&String::from_utf16_lossy(&iomsg.extension));
         let file_change_enum = num::FromPrimitive::from_u8(iomsg.file_change);
         let fpath = iomsg.filepathstr.clone();
         match file_change_enum {
@@ -478,6 +593,33 @@ impl ProcessRecord {
         }
     }
 
+    /// Closes a handle, and its session once the last handle on the file is closed. Handles opened
+    /// before the first message of this Gid are ignored.
+    fn update_cleanup(&mut self, iomsg: &IOMessage) {
+        self.ops_cleanup += 1;
+        let file_id = FileId::of(iomsg);
+        let Some(session) = self.open_handles.get_mut(&file_id) else {
+            return;
+        };
+        session.handles -= 1;
+        if session.handles > 0 {
+            return;
+        }
+        let session = self.open_handles.remove(&file_id).unwrap();
+        self.handle_lifetimes.push(
+            iomsg
+                .time
+                .duration_since(session.opened)
+                .unwrap_or_default(),
+        );
+        self.handle_lifetimes_msgs
+            .push(self.driver_msg_count - session.opened_at);
+        self.bytes_written_per_handle.push(session.bytes_written);
+        if !session.has_io {
+            self.files_closed_without_io.insert(file_id);
+        }
+    }
+
     /// Sorts the number of bytes transferred according to the defined levels:
     /// - Empty   (0 KB)
     /// - Tiny    (0 – 16 KB)
@@ -544,14 +686,10 @@ impl ProcessRecord {
     /// This function is to reduce the frequency of clustering on some applications whose clustering requires a lot of CPU.
     fn is_to_cluster(&self) -> bool {
         if !self.is_thread_clustering_running {
-            if cfg!(feature = "replay") {
-                true
-            } else {
-                let multiplicator = 100;
-                self.last_thread_clustering_time
-                    + self.last_thread_clustering_duration.mul(multiplicator)
-                    <= SystemTime::now()
-            }
+            let multiplicator = 100;
+            self.last_thread_clustering_time
+                + self.last_thread_clustering_duration.mul(multiplicator)
+                <= SystemTime::now()
         } else {
             false
         }
@@ -559,7 +697,7 @@ impl ProcessRecord {
 }
 
 /// A simple tuple-struct about Windows fileids
-#[derive(Debug, Hash, PartialEq, Eq)]
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize, Deserialize)]
 pub struct FileId {
     /// Volume identifier
     pub volume_serial: u64,
@@ -574,13 +712,23 @@ impl FileId {
             file_id: file_id_info.FileId.Identifier.to_vec(),
         }
     }
+
+    /// The file of an [`IOMessage`].
+    pub fn of(iomsg: &IOMessage) -> FileId {
+        FileId {
+            volume_serial: iomsg.file_id_vsn,
+            file_id: iomsg.file_id_id.to_vec(),
+        }
+    }
 }
 
 #[derive(Eq, PartialEq, Debug)]
 pub enum ProcessState {
     Running,
     Suspended,
-    _Killed,
+    Killed,
+    /// All the processes of the Gid exited on their own.
+    Exited,
 }
 
 impl fmt::Display for ProcessState {
@@ -588,7 +736,8 @@ impl fmt::Display for ProcessState {
         match &self {
             ProcessState::Running => write!(f, "RUNNING"),
             ProcessState::Suspended => write!(f, "SUSPENDED"),
-            ProcessState::_Killed => write!(f, "KILLED"),
+            ProcessState::Killed => write!(f, "KILLED"),
+            ProcessState::Exited => write!(f, "EXITED"),
         }
     }
 }
@@ -596,11 +745,17 @@ impl fmt::Display for ProcessState {
 #[cfg(test)]
 #[doc(hidden)]
 mod tests {
+    use crate::driver_comm::mock::fixtures::fetch_iomsgs;
+    use crate::driver_comm::mock::{MockDriver, MockEvent};
+    use crate::driver_comm::{Driver, IrpMajorOp, UnknownIrpOp};
     use crate::process::extensions::ExtensionCategory::{Docs, Exe, Others};
+    use crate::process::tree::{ProcessNode, ProcessTree};
     use crate::process::{FileId, ProcessRecord};
     use crate::shared_def::{IOMessage, RuntimeFeatures};
     use std::collections::HashSet;
     use std::os::raw::c_ulonglong;
+    use std::path::PathBuf;
+    use std::time::{Duration, UNIX_EPOCH};
 
     fn get_iomsgs() -> Vec<IOMessage> {
         Vec::from([
@@ -619,6 +774,9 @@ mod tests {
                 gid : 1883,
                 runtime_features: RuntimeFeatures::new(),
                 file_size : 10899,
+                time : UNIX_EPOCH,
+                sequence : 0,
+                received : UNIX_EPOCH,
             },
 
             IOMessage {
@@ -636,6 +794,9 @@ mod tests {
                 gid : 2008,
                 runtime_features: RuntimeFeatures::new(),
                 file_size : -1,
+                time : UNIX_EPOCH,
+                sequence : 0,
+                received : UNIX_EPOCH,
             },
 
             IOMessage {
@@ -653,6 +814,9 @@ mod tests {
                 gid : 27,
                 runtime_features: RuntimeFeatures::new(),
                 file_size : 61086,
+                time : UNIX_EPOCH,
+                sequence : 0,
+                received : UNIX_EPOCH,
             },
 
             IOMessage {
@@ -670,6 +834,9 @@ mod tests {
                 gid : 1883,
                 runtime_features: RuntimeFeatures::new(),
                 file_size : 16184,
+                time : UNIX_EPOCH,
+                sequence : 0,
+                received : UNIX_EPOCH,
             },
 
             IOMessage {
@@ -687,6 +854,9 @@ mod tests {
                 gid : 2008,
                 runtime_features: RuntimeFeatures::new(),
                 file_size : 218070,
+                time : UNIX_EPOCH,
+                sequence : 0,
+                received : UNIX_EPOCH,
             },
 
             IOMessage {
@@ -704,6 +874,9 @@ mod tests {
                 gid : 1883,
                 runtime_features: RuntimeFeatures::new(),
                 file_size : 90112,
+                time : UNIX_EPOCH,
+                sequence : 0,
+                received : UNIX_EPOCH,
             },
 
             IOMessage {
@@ -721,6 +894,9 @@ mod tests {
                 gid : 1883,
                 runtime_features: RuntimeFeatures::new(),
                 file_size : -1,
+                time : UNIX_EPOCH,
+                sequence : 0,
+                received : UNIX_EPOCH,
             },
 
             IOMessage {
@@ -738,6 +914,9 @@ mod tests {
                 gid : 1883,
                 runtime_features: RuntimeFeatures::new(),
                 file_size : -1,
+                time : UNIX_EPOCH,
+                sequence : 0,
+                received : UNIX_EPOCH,
             },
 
             IOMessage {
@@ -755,6 +934,9 @@ mod tests {
                 gid : 1883,
                 runtime_features: RuntimeFeatures::new(),
                 file_size : 4096,
+                time : UNIX_EPOCH,
+                sequence : 0,
+                received : UNIX_EPOCH,
             },
 
             IOMessage {
@@ -772,11 +954,15 @@ mod tests {
                 gid : 2008,
                 runtime_features: RuntimeFeatures::new(),
                 file_size : 218070,
+                time : UNIX_EPOCH,
+                sequence : 0,
+                received : UNIX_EPOCH,
             }
         ])
     }
 
     #[test]
+    #[cfg_attr(not(windows), ignore = "relies on Windows path semantics")]
     fn test_add_irp_record() {
         let iomsgs = get_iomsgs();
         let mut pr = ProcessRecord::from(&iomsgs[0], "".to_string(), "".parse().unwrap());
@@ -788,7 +974,9 @@ mod tests {
         assert_eq!(pr.ops_read, 2);
         assert_eq!(pr.ops_setinfo, 2);
         assert_eq!(pr.ops_written, 2);
-        assert_eq!(pr.ops_open, 4);
+        assert_eq!(pr.ops_open, 2);
+        assert_eq!(pr.ops_cleanup, 2);
+        assert_eq!(pr.ops_unknown, 0);
         assert_eq!(pr.bytes_read, 308182);
         assert_eq!(pr.bytes_written, 16210);
         assert_eq!(pr.entropy_read, 1170968.3895067428);
@@ -879,4 +1067,119 @@ mod tests {
         assert_eq!(pr.on_removable_drive_read_count, 0);
         assert_eq!(pr.on_removable_drive_write_count, 0);
     }
+
+    fn handle_op(irp_op: u8, file: u8, mem_sized_used: c_ulonglong) -> IOMessage {
+        IOMessage {
+            extension: [0; 12],
+            file_id_vsn: 1,
+            file_id_id: [file, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            mem_sized_used,
+            entropy: 0.0,
+            pid: 42,
+            irp_op,
+            is_entropy_calc: 0,
+            file_change: 0,
+            file_location_info: 0,
+            filepathstr: format!(r"C:\Users\Dev\{file}.txt"),
+            gid: 7,
+            runtime_features: RuntimeFeatures::new(),
+            file_size: 0,
+            time: UNIX_EPOCH,
+            sequence: 0,
+            received: UNIX_EPOCH,
+        }
+    }
+
+    #[test]
+    fn test_handle_sessions() {
+        assert_eq!(IrpMajorOp::try_from(5), Ok(IrpMajorOp::IrpCleanUp));
+        assert_eq!(IrpMajorOp::try_from(6), Err(UnknownIrpOp(6)));
+
+        let (create, read, write, cleanup) = (4, 1, 2, 5);
+        let mut iomsgs = [
+            // opened, rewritten and closed
+            handle_op(create, 1, 0),
+            handle_op(write, 1, 100),
+            handle_op(write, 1, 50),
+            handle_op(cleanup, 1, 0),
+            // two handles on the same file, closed without I/O
+            handle_op(create, 2, 0),
+            handle_op(create, 2, 0),
+            handle_op(cleanup, 2, 0),
+            handle_op(cleanup, 2, 0),
+            // only read
+            handle_op(create, 3, 0),
+            handle_op(read, 3, 10),
+            // opened before the first message of the gid
+            handle_op(cleanup, 4, 0),
+            handle_op(9, 1, 0),
+        ];
+        for (i, iomsg) in iomsgs.iter_mut().enumerate() {
+            iomsg.time = UNIX_EPOCH + Duration::from_secs(i as u64);
+        }
+        let mut pr = ProcessRecord::from(&iomsgs[0], "".to_string(), "".parse().unwrap());
+        for iomsg in &iomsgs {
+            pr.add_irp_record(iomsg);
+        }
+
+        assert_eq!(pr.ops_open, 4);
+        assert_eq!(pr.ops_cleanup, 4);
+        assert_eq!(pr.ops_unknown, 1);
+        assert_eq!(
+            pr.handle_lifetimes,
+            [Duration::from_secs(3), Duration::from_secs(3)].to_vec()
+        );
+        assert_eq!(pr.handle_lifetimes_msgs, [3, 3].to_vec());
+        assert_eq!(pr.bytes_written_per_handle, [150, 0].to_vec());
+        assert_eq!(
+            pr.files_closed_without_io,
+            HashSet::from([FileId::of(&iomsgs[4])])
+        );
+        assert_eq!(pr.open_handles.len(), 1);
+    }
+
+    #[test]
+    fn test_gid_tree_refreshes_record() {
+        let mock = MockDriver::new();
+        mock.push_event(MockEvent::new(100, 7, IrpMajorOp::IrpWrite, r"C:\a.txt"));
+        let driver = Driver::with_transport(mock.clone());
+        let mut vecnew: Vec<u8> = Vec::with_capacity(65536);
+        let iomsg = fetch_iomsgs(&driver, &mut vecnew).remove(0);
+        let mut precord = ProcessRecord::from(&iomsg, "bad.exe".to_string(), PathBuf::new());
+        precord.add_irp_record(&iomsg);
+
+        // Unknown gid: nothing to refresh
+        assert_eq!(precord.refresh_tree(&driver), Ok(None));
+        assert_eq!(driver.get_gid_tree(8), Ok(None));
+
+        // The minifilter counts in 100ns
+        let started = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let node = |pid: u32, parent_pid: u32, image: &str, exited: bool| ProcessNode {
+            pid,
+            parent_pid,
+            image_path: format!(r"\Device\HarddiskVolume3\Tools\{}", image),
+            created: started,
+            exited: exited.then_some(started + Duration::from_secs(1)),
+        };
+        let tree = ProcessTree {
+            gid: 7,
+            num_pids: 3,
+            nodes: vec![
+                node(100, 4, "bad.exe", false),
+                // Never touched a file
+                node(101, 100, "cmd.exe", true),
+                node(102, 101, "vssadmin.exe", false),
+            ],
+        };
+        mock.set_gid_tree(tree.clone());
+        assert_eq!(precord.refresh_tree(&driver), Ok(Some(&tree)));
+        assert_eq!(precord.pids.len(), 3);
+        assert!(precord.pids.contains(&101));
+
+        // The gid has ended: the last tree is kept
+        mock.unload();
+        driver.reconnect().unwrap();
+        assert_eq!(precord.refresh_tree(&driver), Ok(None));
+        assert_eq!(precord.tree, Some(tree));
+    }
 }
@@ -0,0 +1,307 @@
+//! The whole family of a gid as tracked by the minifilter, see [`ProcessTree`].
+//!
+//! A [`ProcessRecord`](super::ProcessRecord) only learns about the pids performing file I/O. The
+//! minifilter records every process created in a gid: [`Driver::get_gid_tree`] returns them all,
+//! with their parents, image files and creation times, the exited ones included while the gid
+//! lives.
+//!
+//! [`Driver::get_gid_tree`]: crate::driver_comm::Driver::get_gid_tree
+
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use windows::core::HRESULT;
+
+use crate::driver_comm::error::DriverError;
+use crate::driver_comm::report::STATUS_NO_SUCH_GROUP;
+use crate::shared_def::decoder::{DecodeError, MAX_FILE_NAME_SIZE};
+
+/// Max number of processes detailed in a [`ProcessTree`] (`MAX_GID_TREE_PIDS` in `SharedDefs.h`).
+/// The minifilter forgets the oldest exited processes of a gid beyond it.
+pub const MAX_GID_TREE_PIDS: usize = 128;
+
+/// Size of a `PROCESS_NODE`.
+const PROCESS_NODE_SIZE: usize = 32 + MAX_FILE_NAME_SIZE;
+
+/// Size of the reply buffer (`sizeof(GID_TREE)`).
+pub const GID_TREE_SIZE: usize = 8 + PROCESS_NODE_SIZE * MAX_GID_TREE_PIDS;
+
+/// Intervals of 100ns between 1601-01-01 (system time of the kernel) and the Unix epoch.
+const UNIX_EPOCH_SYSTEM_TIME: i64 = 116_444_736_000_000_000;
+
+/// A process of a gid (`PROCESS_NODE` in `SharedDefs.h`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessNode {
+    pub pid: u32,
+    /// Outside of the gid for its root.
+    pub parent_pid: u32,
+    /// Image file, in the device form (`\Device\HarddiskVolumeN\...`).
+    pub image_path: String,
+    pub created: SystemTime,
+    /// `None` while the process runs.
+    pub exited: Option<SystemTime>,
+}
+
+impl ProcessNode {
+    pub fn is_alive(&self) -> bool {
+        self.exited.is_none()
+    }
+
+    /// File name of [`image_path`](Self::image_path).
+    pub fn image_name(&self) -> &str {
+        self.image_path
+            .rsplit('\\')
+            .next()
+            .unwrap_or(&self.image_path)
+    }
+}
+
+/// Reply of the minifilter to [`get_gid_tree`](crate::driver_comm::Driver::get_gid_tree)
+/// (`GID_TREE` in `SharedDefs.h`): the processes of a gid, oldest first.
+///
+/// Pids are reused by Windows, an exited process and a younger one may share the same. The parent
+/// of a node is the latest process with its `parent_pid` created before it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessTree {
+    pub gid: u64,
+    /// Number of processes in the gid, may be more than `nodes.len()` (see
+    /// [`MAX_GID_TREE_PIDS`]).
+    pub num_pids: u32,
+    pub nodes: Vec<ProcessNode>,
+}
+
+impl ProcessTree {
+    /// Reads the reply of the minifilter, `buf` being cut to the length it returned. `None` if
+    /// the gid is unknown to the minifilter, or has ended.
+    pub fn from_bytes(gid: u64, buf: &[u8]) -> Result<Option<ProcessTree>, DriverError> {
+        if buf.len() < 8 {
+            return Err(DriverError::MalformedReply(DecodeError::TruncatedHeader {
+                buffer_len: buf.len(),
+            }));
+        }
+        let read_u32 =
+            |offset: usize| u32::from_ne_bytes(buf[offset..offset + 4].try_into().unwrap());
+        let read_i64 =
+            |offset: usize| i64::from_ne_bytes(buf[offset..offset + 8].try_into().unwrap());
+        if HRESULT(read_u32(0) as i32) == STATUS_NO_SUCH_GROUP {
+            return Ok(None);
+        }
+        let num_pids = read_u32(4);
+        let available = ((buf.len() - 8) / PROCESS_NODE_SIZE).min(MAX_GID_TREE_PIDS);
+        let nodes = (0..(num_pids as usize).min(available))
+            .map(|i| {
+                let offset = 8 + PROCESS_NODE_SIZE * i;
+                let image_path: Vec<u16> = buf[offset + 32..offset + PROCESS_NODE_SIZE]
+                    .chunks_exact(2)
+                    .map(|c| u16::from_ne_bytes([c[0], c[1]]))
+                    .take_while(|c| *c != 0)
+                    .collect();
+                ProcessNode {
+                    pid: read_u32(offset),
+                    parent_pid: read_u32(offset + 4),
+                    image_path: String::from_utf16_lossy(&image_path),
+                    created: from_system_time(read_i64(offset + 8)),
+                    exited: (buf[offset + 24] == 0)
+                        .then(|| from_system_time(read_i64(offset + 16))),
+                }
+            })
+            .collect();
+        Ok(Some(ProcessTree {
+            gid,
+            num_pids,
+            nodes,
+        }))
+    }
+
+    /// Writes the tree as the minifilter does, in a buffer of [`GID_TREE_SIZE`].
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = vec![0u8; GID_TREE_SIZE];
+        buf[4..8].copy_from_slice(&self.num_pids.to_ne_bytes());
+        for (i, node) in self.nodes.iter().take(MAX_GID_TREE_PIDS).enumerate() {
+            let offset = 8 + PROCESS_NODE_SIZE * i;
+            buf[offset..offset + 4].copy_from_slice(&node.pid.to_ne_bytes());
+            buf[offset + 4..offset + 8].copy_from_slice(&node.parent_pid.to_ne_bytes());
+            buf[offset + 8..offset + 16]
+                .copy_from_slice(&to_system_time(node.created).to_ne_bytes());
+            let exited = node.exited.map(to_system_time).unwrap_or_default();
+            buf[offset + 16..offset + 24].copy_from_slice(&exited.to_ne_bytes());
+            buf[offset + 24] = node.is_alive() as u8;
+            for (j, c) in node
+                .image_path
+                .encode_utf16()
+                .take(MAX_FILE_NAME_SIZE / 2 - 1)
+                .enumerate()
+            {
+                let at = offset + 32 + 2 * j;
+                buf[at..at + 2].copy_from_slice(&c.to_ne_bytes());
+            }
+        }
+        buf
+    }
+
+    /// All the processes of the gid are in [`nodes`](Self::nodes).
+    pub fn is_complete(&self) -> bool {
+        self.nodes.len() == self.num_pids as usize
+    }
+
+    /// The processes still running.
+    pub fn alive(&self) -> impl Iterator<Item = &ProcessNode> {
+        self.nodes.iter().filter(|node| node.is_alive())
+    }
+
+    /// The latest process with this pid.
+    pub fn get(&self, pid: u32) -> Option<&ProcessNode> {
+        self.nodes.iter().rev().find(|node| node.pid == pid)
+    }
+
+    /// The parent of `node`, `None` for the roots.
+    pub fn parent(&self, node: &ProcessNode) -> Option<&ProcessNode> {
+        self.parent_index(self.index_of(node)?)
+            .map(|parent| &self.nodes[parent])
+    }
+
+    /// The processes started by `node`, oldest first.
+    pub fn children<'a>(&'a self, node: &ProcessNode) -> Vec<&'a ProcessNode> {
+        let Some(index) = self.index_of(node) else {
+            return vec![];
+        };
+        (index + 1..self.nodes.len())
+            .filter(|child| self.parent_index(*child) == Some(index))
+            .map(|child| &self.nodes[child])
+            .collect()
+    }
+
+    /// The processes whose parent is not in the gid: the first one, and the orphans whose parent
+    /// has been forgotten by the minifilter.
+    pub fn roots(&self) -> Vec<&ProcessNode> {
+        (0..self.nodes.len())
+            .filter(|index| self.parent_index(*index).is_none())
+            .map(|index| &self.nodes[index])
+            .collect()
+    }
+
+    fn index_of(&self, node: &ProcessNode) -> Option<usize> {
+        self.nodes.iter().position(|n| n == node)
+    }
+
+    fn parent_index(&self, index: usize) -> Option<usize> {
+        let node = &self.nodes[index];
+        self.nodes[..index]
+            .iter()
+            .rposition(|parent| parent.pid == node.parent_pid)
+    }
+
+    fn fmt_node(&self, f: &mut fmt::Formatter<'_>, index: usize, depth: usize) -> fmt::Result {
+        let node = &self.nodes[index];
+        write!(
+            f,
+            "\n{:indent$}{} {}",
+            "",
+            node.pid,
+            node.image_path,
+            indent = 2 * (depth + 1)
+        )?;
+        if !node.is_alive() {
+            write!(f, " (exited)")?;
+        }
+        for child in index + 1..self.nodes.len() {
+            if self.parent_index(child) == Some(index) {
+                self.fmt_node(f, child, depth + 1)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// One line per process, indented under its parent.
+impl fmt::Display for ProcessTree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "gid {}: {} processes, {} alive",
+            self.gid,
+            self.num_pids,
+            self.alive().count()
+        )?;
+        for index in 0..self.nodes.len() {
+            if self.parent_index(index).is_none() {
+                self.fmt_node(f, index, 0)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A kernel system time (100ns intervals since 1601) as a [`SystemTime`], clamped to the Unix epoch.
+pub(crate) fn from_system_time(time: i64) -> SystemTime {
+    let since_epoch = time.saturating_sub(UNIX_EPOCH_SYSTEM_TIME).max(0) as u64;
+    UNIX_EPOCH + Duration::from_nanos(since_epoch.saturating_mul(100))
+}
+
+pub(crate) fn to_system_time(time: SystemTime) -> i64 {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    UNIX_EPOCH_SYSTEM_TIME + (since_epoch.as_nanos() / 100) as i64
+}
+
+#[cfg(test)]
+#[doc(hidden)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use crate::process::tree::{ProcessNode, ProcessTree, GID_TREE_SIZE};
+
+    fn node(pid: u32, parent_pid: u32, image: &str, at: u64, exited: bool) -> ProcessNode {
+        let created = UNIX_EPOCH + Duration::from_secs(1_700_000_000 + at);
+        ProcessNode {
+            pid,
+            parent_pid,
+            image_path: format!(r"\Device\HarddiskVolume3\Users\Dev\{}", image),
+            created,
+            exited: exited.then(|| created + Duration::from_millis(1500)),
+        }
+    }
+
+    #[test]
+    fn test_tree_with_reused_pid() {
+        let tree = ProcessTree {
+            gid: 7,
+            num_pids: 5,
+            nodes: vec![
+                node(100, 4, "dropper.exe", 0, false),
+                node(200, 100, "cmd.exe", 1, true),
+                node(300, 200, "vssadmin.exe", 2, true),
+                // 200 reused, by a child of the root
+                node(200, 100, "crypt.exe", 3, false),
+                node(400, 200, "notepad.exe", 4, false),
+            ],
+        };
+        let decoded = ProcessTree::from_bytes(7, &tree.to_bytes())
+            .unwrap()
+            .unwrap();
+        assert_eq!(decoded, tree);
+        assert_eq!(tree.to_bytes().len(), GID_TREE_SIZE);
+        assert!(decoded.is_complete());
+
+        let root = decoded.roots();
+        assert_eq!(root.len(), 1);
+        let children: Vec<&str> = decoded
+            .children(root[0])
+            .iter()
+            .map(|node| node.image_name())
+            .collect();
+        assert_eq!(children, vec!["cmd.exe", "crypt.exe"]);
+        let notepad = decoded.get(400).unwrap();
+        assert_eq!(decoded.parent(notepad).unwrap().image_name(), "crypt.exe");
+        assert_eq!(decoded.get(200).unwrap().image_name(), "crypt.exe");
+        assert_eq!(decoded.alive().count(), 3);
+        assert_eq!(
+            decoded.to_string(),
+            "gid 7: 5 processes, 3 alive\n  \
+             100 \\Device\\HarddiskVolume3\\Users\\Dev\\dropper.exe\n    \
+             200 \\Device\\HarddiskVolume3\\Users\\Dev\\cmd.exe (exited)\n      \
+             300 \\Device\\HarddiskVolume3\\Users\\Dev\\vssadmin.exe (exited)\n    \
+             200 \\Device\\HarddiskVolume3\\Users\\Dev\\crypt.exe\n      \
+             400 \\Device\\HarddiskVolume3\\Users\\Dev\\notepad.exe"
+        );
+    }
+}
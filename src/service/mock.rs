@@ -0,0 +1,352 @@
+//! An in-memory Service Control Manager, to exercise [`FilterService`](super::FilterService)
+//! without Windows nor Administrator rights.
+//!
+//! [`MockServiceManager`] installs the services it is asked to, moves them through the pending
+//! states for a configurable number of status queries, attaches them to the volumes given by
+//! [`set_volumes`](MockServiceManager::set_volumes) while they run, and records every call.
+//!
+//! ```
+//! use minifilter_rs::service::mock::MockServiceManager;
+//! use minifilter_rs::service::{FilterService, ServiceState};
+//!
+//! let manager = MockServiceManager::new();
+//! manager.add_service("FSFilter", ServiceState::Stopped);
+//! manager.set_volumes(&[r"\Device\HarddiskVolume3"]);
+//!
+//! let service = FilterService::with_manager(manager.clone());
+//! service.load().unwrap();
+//!
+//! assert_eq!(service.status().unwrap(), ServiceState::Running);
+//! assert_eq!(service.instances().unwrap()[0].altitude, "378781");
+//! ```
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use windows::core::HRESULT;
+
+use crate::service::{FilterInstance, ServiceError, ServiceManager, ServiceState};
+
+/// `HRESULT_FROM_WIN32(ERROR_SERVICE_ALREADY_RUNNING)`: what `FilterLoad` returns on a running
+/// minifilter.
+pub const E_SERVICE_ALREADY_RUNNING: HRESULT = HRESULT(0x8007_0420_u32 as i32);
+
+/// `HRESULT_FROM_WIN32(ERROR_SERVICE_NOT_ACTIVE)`: what `FilterUnload` returns on a stopped
+/// minifilter.
+pub const E_SERVICE_NOT_ACTIVE: HRESULT = HRESULT(0x8007_0426_u32 as i32);
+
+/// `Instance1.Name` in `FsFilter.inf`.
+const INSTANCE_NAME: &str = "FsFilter Instance";
+
+/// `Instance1.Altitude` in `FsFilter.inf`.
+const ALTITUDE: &str = "378781";
+
+/// A call made to the [`MockServiceManager`]. Status and instance queries are not recorded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServiceCall {
+    Install { name: String, inf: PathBuf },
+    Load { name: String },
+    Unload { name: String },
+    Uninstall { name: String },
+}
+
+#[derive(Debug)]
+struct MockService {
+    state: ServiceState,
+    /// Status queries left before a pending state settles.
+    pending_polls: usize,
+}
+
+#[derive(Debug, Default)]
+struct MockServiceState {
+    services: HashMap<String, MockService>,
+    calls: Vec<ServiceCall>,
+    pending_polls: usize,
+    volumes: Vec<String>,
+    failure: Option<HRESULT>,
+}
+
+/// A scripted Service Control Manager. Clones share the same state, so a test can keep one to
+/// inspect the calls while a [`FilterService`](super::FilterService) owns another.
+#[derive(Debug, Clone, Default)]
+pub struct MockServiceManager {
+    state: Arc<Mutex<MockServiceState>>,
+}
+
+impl MockServiceManager {
+    pub fn new() -> MockServiceManager {
+        MockServiceManager::default()
+    }
+
+    /// Declares the service `name` as already installed, in `state`.
+    pub fn add_service(&self, name: &str, state: ServiceState) {
+        self.state().services.insert(
+            name.to_string(),
+            MockService {
+                state,
+                pending_polls: 0,
+            },
+        );
+    }
+
+    /// Number of status queries answering `START_PENDING` after a load, or `STOP_PENDING` after an
+    /// unload. Defaults to 0.
+    pub fn set_pending_polls(&self, polls: usize) {
+        self.state().pending_polls = polls;
+    }
+
+    /// The volumes a running minifilter is attached to, in the device form.
+    pub fn set_volumes(&self, volumes: &[&str]) {
+        self.state().volumes = volumes.iter().map(|v| v.to_string()).collect();
+    }
+
+    /// The next call fails with [`ServiceError::Os`], e.g. with `E_ACCESSDENIED` as if not run as
+    /// Administrator.
+    pub fn fail_next(&self, code: HRESULT) {
+        self.state().failure = Some(code);
+    }
+
+    /// All calls made so far, oldest first.
+    pub fn calls(&self) -> Vec<ServiceCall> {
+        self.state().calls.clone()
+    }
+
+    fn state(&self) -> MutexGuard<'_, MockServiceState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Records `call`, and fails it if asked to.
+    fn call(&self, call: ServiceCall) -> Result<MutexGuard<'_, MockServiceState>, ServiceError> {
+        let mut state = self.state();
+        state.calls.push(call);
+        match state.failure.take() {
+            Some(code) => Err(ServiceError::Os(code)),
+            None => Ok(state),
+        }
+    }
+
+    /// Moves `name` to `to`, through `pending` for the configured number of polls.
+    fn transition(
+        state: &mut MockServiceState,
+        name: &str,
+        from: ServiceState,
+        pending: ServiceState,
+        to: ServiceState,
+        error: HRESULT,
+    ) -> Result<(), ServiceError> {
+        let polls = state.pending_polls;
+        let service = state
+            .services
+            .get_mut(name)
+            .ok_or(ServiceError::NotInstalled)?;
+        if service.state != from {
+            return Err(ServiceError::Os(error));
+        }
+        service.pending_polls = polls;
+        service.state = if polls == 0 { to } else { pending };
+        Ok(())
+    }
+}
+
+impl ServiceManager for MockServiceManager {
+    fn install(&self, name: &str, inf: &Path) -> Result<(), ServiceError> {
+        let mut state = self.call(ServiceCall::Install {
+            name: name.to_string(),
+            inf: inf.to_path_buf(),
+        })?;
+        state
+            .services
+            .entry(name.to_string())
+            .or_insert(MockService {
+                state: ServiceState::Stopped,
+                pending_polls: 0,
+            });
+        Ok(())
+    }
+
+    fn load(&self, name: &str) -> Result<(), ServiceError> {
+        let mut state = self.call(ServiceCall::Load {
+            name: name.to_string(),
+        })?;
+        Self::transition(
+            &mut state,
+            name,
+            ServiceState::Stopped,
+            ServiceState::StartPending,
+            ServiceState::Running,
+            E_SERVICE_ALREADY_RUNNING,
+        )
+    }
+
+    fn unload(&self, name: &str) -> Result<(), ServiceError> {
+        let mut state = self.call(ServiceCall::Unload {
+            name: name.to_string(),
+        })?;
+        Self::transition(
+            &mut state,
+            name,
+            ServiceState::Running,
+            ServiceState::StopPending,
+            ServiceState::Stopped,
+            E_SERVICE_NOT_ACTIVE,
+        )
+    }
+
+    fn status(&self, name: &str) -> Result<ServiceState, ServiceError> {
+        let mut state = self.state();
+        let Some(service) = state.services.get_mut(name) else {
+            return Ok(ServiceState::NotInstalled);
+        };
+        if service.pending_polls > 0 {
+            service.pending_polls -= 1;
+        } else if service.state == ServiceState::StartPending {
+            service.state = ServiceState::Running;
+        } else if service.state == ServiceState::StopPending {
+            service.state = ServiceState::Stopped;
+        }
+        Ok(service.state)
+    }
+
+    fn instances(&self, name: &str) -> Result<Vec<FilterInstance>, ServiceError> {
+        let state = self.state();
+        match state.services.get(name) {
+            Some(service) if service.state == ServiceState::Running => Ok(state
+                .volumes
+                .iter()
+                .map(|volume| FilterInstance {
+                    name: INSTANCE_NAME.to_string(),
+                    volume: volume.clone(),
+                    altitude: ALTITUDE.to_string(),
+                })
+                .collect()),
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    fn uninstall(&self, name: &str) -> Result<(), ServiceError> {
+        let mut state = self.call(ServiceCall::Uninstall {
+            name: name.to_string(),
+        })?;
+        match state.services.get(name).map(|service| service.state) {
+            None => Err(ServiceError::NotInstalled),
+            Some(ServiceState::Stopped) => {
+                state.services.remove(name);
+                Ok(())
+            }
+            Some(_) => Err(ServiceError::Os(E_SERVICE_ALREADY_RUNNING)),
+        }
+    }
+}
+
+#[cfg(test)]
+#[doc(hidden)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+
+    use windows::core::HRESULT;
+
+    use crate::service::mock::{MockServiceManager, ServiceCall};
+    use crate::service::{FilterService, ServiceError, ServiceState, SERVICE_NAME};
+
+    const E_ACCESSDENIED: HRESULT = HRESULT(0x8007_0005_u32 as i32);
+
+    fn inf() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join(r"minifilter/FsFilter/FsFilter.inf")
+    }
+
+    fn service(manager: &MockServiceManager) -> FilterService<MockServiceManager> {
+        FilterService::with_manager(manager.clone()).poll_interval(Duration::ZERO)
+    }
+
+    #[test]
+    fn test_deploy_installs_loads_and_lists_instances() {
+        let manager = MockServiceManager::new();
+        manager.set_pending_polls(3);
+        manager.set_volumes(&[r"\Device\HarddiskVolume3", r"\Device\HarddiskVolume5"]);
+        let service = service(&manager);
+
+        assert!(service.instances().unwrap().is_empty());
+        service.deploy(&inf()).unwrap();
+        assert_eq!(service.status().unwrap(), ServiceState::Running);
+        let instances = service.instances().unwrap();
+        assert_eq!(instances.len(), 2);
+        assert_eq!(instances[1].volume, r"\Device\HarddiskVolume5");
+        assert!(instances.iter().all(|i| i.altitude == "378781"));
+
+        // Already there: nothing to do
+        service.deploy(&inf()).unwrap();
+        let name = SERVICE_NAME.to_string();
+        assert_eq!(
+            manager.calls(),
+            vec![
+                ServiceCall::Install {
+                    name: name.clone(),
+                    inf: inf()
+                },
+                ServiceCall::Load { name },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_uninstall_unloads_first() {
+        let manager = MockServiceManager::new();
+        manager.add_service("RWatch", ServiceState::Running);
+        manager.set_pending_polls(2);
+        let service = service(&manager).service_name("RWatch");
+
+        service.uninstall().unwrap();
+        assert_eq!(service.status().unwrap(), ServiceState::NotInstalled);
+        assert!(matches!(
+            manager.calls().as_slice(),
+            [ServiceCall::Unload { .. }, ServiceCall::Uninstall { .. }]
+        ));
+        // Already gone
+        service.uninstall().unwrap();
+        assert_eq!(service.unload(), Err(ServiceError::NotInstalled));
+        assert_eq!(manager.calls().len(), 2);
+    }
+
+    #[test]
+    fn test_errors() {
+        let manager = MockServiceManager::new();
+        let service = service(&manager).timeout(Duration::ZERO);
+
+        let missing = inf().with_file_name("Missing.inf");
+        assert_eq!(
+            service.install(&missing),
+            Err(ServiceError::InfNotFound { path: missing })
+        );
+        assert_eq!(service.load(), Err(ServiceError::NotInstalled));
+
+        manager.fail_next(E_ACCESSDENIED);
+        assert_eq!(
+            service.deploy(&inf()),
+            Err(ServiceError::Os(E_ACCESSDENIED))
+        );
+        assert_eq!(service.status().unwrap(), ServiceState::NotInstalled);
+
+        // Stuck in START_PENDING
+        manager.set_pending_polls(100);
+        assert_eq!(
+            service.deploy(&inf()),
+            Err(ServiceError::Timeout {
+                expected: ServiceState::Running,
+                found: ServiceState::StartPending
+            })
+        );
+        // Still starting: not loaded again
+        manager.set_pending_polls(0);
+        service.timeout(Duration::from_secs(1)).load().unwrap();
+        assert_eq!(
+            manager
+                .calls()
+                .iter()
+                .filter(|call| matches!(call, ServiceCall::Load { .. }))
+                .count(),
+            1
+        );
+    }
+}
@@ -0,0 +1,498 @@
+//! Installation and lifecycle of the minifilter, without `RUNDLL32`, `sc` nor `fltmc`.
+//!
+//! [`FilterService`] installs FSFilter from its INF, loads and unloads it, and waits for the
+//! Service Control Manager to report the expected state. It never talks to the OS directly: its
+//! calls go through a [`ServiceManager`], which is the real one ([`ServiceManagerLive`]) unless
+//! another one is given to [`with_manager`](FilterService::with_manager), e.g. a
+//! [`MockServiceManager`](mock::MockServiceManager) to test the deployment logic anywhere.
+
+pub mod mock;
+
+use std::error::Error;
+use std::fmt;
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use windows::core::HRESULT;
+
+/// Name of the service of the minifilter (`ServiceName` in `FsFilter.inf`).
+pub const SERVICE_NAME: &str = "FSFilter";
+
+/// State of the service, as reported by the Service Control Manager.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ServiceState {
+    /// No such service, or it has been deleted.
+    NotInstalled,
+    Stopped,
+    StartPending,
+    StopPending,
+    Running,
+    ContinuePending,
+    PausePending,
+    Paused,
+    /// A state unknown to this crate.
+    Unknown(u32),
+}
+
+impl ServiceState {
+    /// Converts the `dwCurrentState` of a `SERVICE_STATUS`.
+    pub fn from_win32(state: u32) -> ServiceState {
+        match state {
+            1 => ServiceState::Stopped,
+            2 => ServiceState::StartPending,
+            3 => ServiceState::StopPending,
+            4 => ServiceState::Running,
+            5 => ServiceState::ContinuePending,
+            6 => ServiceState::PausePending,
+            7 => ServiceState::Paused,
+            _ => ServiceState::Unknown(state),
+        }
+    }
+}
+
+impl fmt::Display for ServiceState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServiceState::NotInstalled => write!(f, "NOT_INSTALLED"),
+            ServiceState::Stopped => write!(f, "STOPPED"),
+            ServiceState::StartPending => write!(f, "START_PENDING"),
+            ServiceState::StopPending => write!(f, "STOP_PENDING"),
+            ServiceState::Running => write!(f, "RUNNING"),
+            ServiceState::ContinuePending => write!(f, "CONTINUE_PENDING"),
+            ServiceState::PausePending => write!(f, "PAUSE_PENDING"),
+            ServiceState::Paused => write!(f, "PAUSED"),
+            ServiceState::Unknown(state) => write!(f, "UNKNOWN ({state})"),
+        }
+    }
+}
+
+/// A volume the minifilter is attached to, as listed by `fltmc instances`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilterInstance {
+    /// e.g. `FsFilter Instance`
+    pub name: String,
+    /// e.g. `\Device\HarddiskVolume3`
+    pub volume: String,
+    /// e.g. `378781` (`Instance1.Altitude` in `FsFilter.inf`)
+    pub altitude: String,
+}
+
+/// Error returned by [`FilterService`] and the [`ServiceManager`]s.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServiceError {
+    /// The INF file does not exist.
+    InfNotFound { path: PathBuf },
+    /// The service is not installed, or the INF did not install it.
+    NotInstalled,
+    /// The service did not reach `expected` in time, it was still `found`.
+    Timeout {
+        expected: ServiceState,
+        found: ServiceState,
+    },
+    /// A call to Windows failed, e.g. with `E_ACCESSDENIED` when not run as Administrator.
+    Os(HRESULT),
+    /// There is no Service Control Manager outside of Windows: use another [`ServiceManager`].
+    Unsupported,
+}
+
+impl fmt::Display for ServiceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServiceError::InfNotFound { path } => {
+                write!(f, "INF file {} not found", path.display())
+            }
+            ServiceError::NotInstalled => write!(f, "the minifilter service is not installed"),
+            ServiceError::Timeout { expected, found } => write!(
+                f,
+                "the minifilter service is {found}, it did not become {expected} in time"
+            ),
+            ServiceError::Os(code) => {
+                write!(f, "cannot manage the minifilter service: {:#010X}", code.0)
+            }
+            ServiceError::Unsupported => {
+                write!(f, "filter services can only be managed on Windows")
+            }
+        }
+    }
+}
+
+impl Error for ServiceError {}
+
+/// The operations of the Service Control Manager and of the Filter Manager on a minifilter.
+pub trait ServiceManager: Debug + Send {
+    /// Runs the `DefaultInstall` section of `inf`, which registers the service `name`.
+    fn install(&self, name: &str, inf: &Path) -> Result<(), ServiceError>;
+
+    /// Starts the minifilter, like `fltmc load`.
+    fn load(&self, name: &str) -> Result<(), ServiceError>;
+
+    /// Stops the minifilter, like `fltmc unload`.
+    fn unload(&self, name: &str) -> Result<(), ServiceError>;
+
+    /// [`ServiceState::NotInstalled`] if there is no such service.
+    fn status(&self, name: &str) -> Result<ServiceState, ServiceError>;
+
+    /// The volumes the minifilter is attached to, none if it is not loaded.
+    fn instances(&self, name: &str) -> Result<Vec<FilterInstance>, ServiceError>;
+
+    /// Deletes the service, like `sc delete`. The minifilter has to be unloaded first.
+    fn uninstall(&self, name: &str) -> Result<(), ServiceError>;
+}
+
+/// Asks Windows: `SetupAPI` for the INF, `FltLib` for loading and listing the instances, and the
+/// Service Control Manager for the rest. Needs Administrator rights.
+#[derive(Debug, Default)]
+pub struct ServiceManagerLive;
+
+#[cfg(windows)]
+mod live {
+    use core::ffi::c_void;
+    use std::path::Path;
+
+    use windows::core::{HRESULT, PCWSTR};
+    use windows::Win32::Devices::DeviceAndDriverInstallation::InstallHinfSectionW;
+    use windows::Win32::Foundation::{HANDLE, HINSTANCE, HWND};
+    use windows::Win32::Security::SC_HANDLE;
+    use windows::Win32::Storage::FileSystem::DELETE;
+    use windows::Win32::Storage::InstallableFileSystems::{
+        FilterInstanceFindClose, FilterInstanceFindFirst, FilterInstanceFindHandle,
+        FilterInstanceFindNext, FilterLoad, FilterUnload, InstanceFullInformation,
+        INSTANCE_FULL_INFORMATION,
+    };
+    use windows::Win32::System::Services::{
+        CloseServiceHandle, DeleteService, OpenSCManagerW, OpenServiceW, QueryServiceStatus,
+        SC_MANAGER_CONNECT, SERVICE_QUERY_STATUS, SERVICE_STATUS,
+    };
+
+    use super::{FilterInstance, ServiceError, ServiceManager, ServiceManagerLive, ServiceState};
+
+    /// `HRESULT_FROM_WIN32(ERROR_NO_MORE_ITEMS)`: the last instance has been listed.
+    const E_NO_MORE_ITEMS: HRESULT = HRESULT(0x8007_0103_u32 as i32);
+    /// `HRESULT_FROM_WIN32(ERROR_SERVICE_DOES_NOT_EXIST)`
+    const E_SERVICE_DOES_NOT_EXIST: HRESULT = HRESULT(0x8007_0424_u32 as i32);
+    /// `ERROR_FLT_FILTER_NOT_FOUND`: the minifilter is not loaded.
+    const E_FLT_FILTER_NOT_FOUND: HRESULT = HRESULT(0x801F_0013_u32 as i32);
+
+    /// Null terminated UTF-16.
+    fn wide(s: &str) -> Vec<u16> {
+        s.encode_utf16().chain(Some(0)).collect()
+    }
+
+    fn os(e: windows::core::Error) -> ServiceError {
+        ServiceError::Os(e.code())
+    }
+
+    /// Runs `f` on the service `name`, opened with `access`.
+    fn with_service<R>(
+        name: &str,
+        access: u32,
+        f: impl FnOnce(SC_HANDLE) -> Result<R, ServiceError>,
+    ) -> Result<R, ServiceError> {
+        let scm = unsafe { OpenSCManagerW(PCWSTR::null(), PCWSTR::null(), SC_MANAGER_CONNECT) }
+            .map_err(os)?;
+        let result = match unsafe { OpenServiceW(scm, PCWSTR(wide(name).as_ptr()), access) } {
+            Ok(service) => {
+                let result = f(service);
+                unsafe { CloseServiceHandle(service) };
+                result
+            }
+            Err(e) if e.code() == E_SERVICE_DOES_NOT_EXIST => Err(ServiceError::NotInstalled),
+            Err(e) => Err(os(e)),
+        };
+        unsafe { CloseServiceHandle(scm) };
+        result
+    }
+
+    /// Reads an `INSTANCE_FULL_INFORMATION` and the strings following it.
+    fn read_instance(buf: &[u8]) -> FilterInstance {
+        let info = unsafe { *(buf.as_ptr() as *const INSTANCE_FULL_INFORMATION) };
+        let string = |offset: u16, len: u16| {
+            let bytes = buf
+                .get(offset as usize..offset as usize + len as usize)
+                .unwrap_or_default();
+            let units: Vec<u16> = bytes
+                .chunks_exact(2)
+                .map(|c| u16::from_ne_bytes([c[0], c[1]]))
+                .collect();
+            String::from_utf16_lossy(&units)
+        };
+        FilterInstance {
+            name: string(info.InstanceNameBufferOffset, info.InstanceNameLength),
+            volume: string(info.VolumeNameBufferOffset, info.VolumeNameLength),
+            altitude: string(info.AltitudeBufferOffset, info.AltitudeLength),
+        }
+    }
+
+    impl ServiceManager for ServiceManagerLive {
+        fn install(&self, _name: &str, inf: &Path) -> Result<(), ServiceError> {
+            // 132: the INF path is absolute (128), reboot only if needed (4)
+            let command = wide(&format!("DefaultInstall 132 {}", inf.display()));
+            unsafe { InstallHinfSectionW(HWND(0), HINSTANCE(0), PCWSTR(command.as_ptr()), 0) };
+            Ok(())
+        }
+
+        fn load(&self, name: &str) -> Result<(), ServiceError> {
+            unsafe { FilterLoad(PCWSTR(wide(name).as_ptr())) }.map_err(os)
+        }
+
+        fn unload(&self, name: &str) -> Result<(), ServiceError> {
+            unsafe { FilterUnload(PCWSTR(wide(name).as_ptr())) }.map_err(os)
+        }
+
+        fn status(&self, name: &str) -> Result<ServiceState, ServiceError> {
+            let status = with_service(name, SERVICE_QUERY_STATUS, |service| {
+                let mut status = SERVICE_STATUS::default();
+                if unsafe { QueryServiceStatus(service, &mut status) }.as_bool() {
+                    Ok(ServiceState::from_win32(status.dwCurrentState.0))
+                } else {
+                    Err(os(windows::core::Error::from_win32()))
+                }
+            });
+            match status {
+                Err(ServiceError::NotInstalled) => Ok(ServiceState::NotInstalled),
+                status => status,
+            }
+        }
+
+        fn instances(&self, name: &str) -> Result<Vec<FilterInstance>, ServiceError> {
+            let name = wide(name);
+            // u64 for the alignment of INSTANCE_FULL_INFORMATION
+            let mut buffer = vec![0u64; 512];
+            let size = (buffer.len() * 8) as u32;
+            let mut returned = 0u32;
+            let mut find = FilterInstanceFindHandle::default();
+            let mut result = unsafe {
+                FilterInstanceFindFirst(
+                    PCWSTR(name.as_ptr()),
+                    InstanceFullInformation,
+                    buffer.as_mut_ptr() as *mut c_void,
+                    size,
+                    &mut returned,
+                    &mut find,
+                )
+            };
+            let mut instances = Vec::new();
+            let outcome = loop {
+                match result {
+                    Ok(()) => {
+                        let bytes = unsafe {
+                            std::slice::from_raw_parts(buffer.as_ptr() as *const u8, size as usize)
+                        };
+                        instances.push(read_instance(&bytes[..returned as usize]));
+                    }
+                    Err(e) if e.code() == E_NO_MORE_ITEMS || e.code() == E_FLT_FILTER_NOT_FOUND => {
+                        break Ok(())
+                    }
+                    Err(e) => break Err(os(e)),
+                }
+                result = unsafe {
+                    FilterInstanceFindNext(
+                        HANDLE(find.0),
+                        InstanceFullInformation,
+                        buffer.as_mut_ptr() as *mut c_void,
+                        size,
+                        &mut returned,
+                    )
+                };
+            };
+            if !find.is_invalid() {
+                unsafe { FilterInstanceFindClose(HANDLE(find.0)) }.ok();
+            }
+            outcome.map(|()| instances)
+        }
+
+        fn uninstall(&self, name: &str) -> Result<(), ServiceError> {
+            with_service(name, DELETE.0, |service| {
+                if unsafe { DeleteService(service) }.as_bool() {
+                    Ok(())
+                } else {
+                    Err(os(windows::core::Error::from_win32()))
+                }
+            })
+        }
+    }
+}
+
+/// There is no Service Control Manager outside of Windows: use another [`ServiceManager`] there.
+#[cfg(not(windows))]
+impl ServiceManager for ServiceManagerLive {
+    fn install(&self, _name: &str, _inf: &Path) -> Result<(), ServiceError> {
+        Err(ServiceError::Unsupported)
+    }
+
+    fn load(&self, _name: &str) -> Result<(), ServiceError> {
+        Err(ServiceError::Unsupported)
+    }
+
+    fn unload(&self, _name: &str) -> Result<(), ServiceError> {
+        Err(ServiceError::Unsupported)
+    }
+
+    fn status(&self, _name: &str) -> Result<ServiceState, ServiceError> {
+        Err(ServiceError::Unsupported)
+    }
+
+    fn instances(&self, _name: &str) -> Result<Vec<FilterInstance>, ServiceError> {
+        Err(ServiceError::Unsupported)
+    }
+
+    fn uninstall(&self, _name: &str) -> Result<(), ServiceError> {
+        Err(ServiceError::Unsupported)
+    }
+}
+
+/// The minifilter service, named [`SERVICE_NAME`] unless changed with
+/// [`service_name`](Self::service_name).
+///
+/// Each call waits until the Service Control Manager reports the resulting state, polling it every
+/// [`poll_interval`](Self::poll_interval) for up to [`timeout`](Self::timeout).
+#[derive(Debug)]
+pub struct FilterService<M: ServiceManager = ServiceManagerLive> {
+    name: String,
+    manager: M,
+    poll_interval: Duration,
+    timeout: Duration,
+}
+
+impl FilterService {
+    pub fn new() -> FilterService {
+        Self::with_manager(ServiceManagerLive)
+    }
+}
+
+impl Default for FilterService {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<M: ServiceManager> FilterService<M> {
+    /// Uses `manager` to manage the service, e.g. a
+    /// [`MockServiceManager`](mock::MockServiceManager).
+    pub fn with_manager(manager: M) -> FilterService<M> {
+        FilterService {
+            name: SERVICE_NAME.to_string(),
+            manager,
+            poll_interval: Duration::from_millis(100),
+            timeout: Duration::from_secs(30),
+        }
+    }
+
+    pub fn service_name(mut self, name: &str) -> FilterService<M> {
+        self.name = name.to_string();
+        self
+    }
+
+    pub fn poll_interval(mut self, poll_interval: Duration) -> FilterService<M> {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> FilterService<M> {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The [`ServiceManager`] doing the calls.
+    pub fn manager(&self) -> &M {
+        &self.manager
+    }
+
+    pub fn status(&self) -> Result<ServiceState, ServiceError> {
+        self.manager.status(&self.name)
+    }
+
+    pub fn is_installed(&self) -> Result<bool, ServiceError> {
+        Ok(self.status()? != ServiceState::NotInstalled)
+    }
+
+    /// The volumes the minifilter is attached to, with their altitude.
+    pub fn instances(&self) -> Result<Vec<FilterInstance>, ServiceError> {
+        self.manager.instances(&self.name)
+    }
+
+    /// Installs the minifilter from `inf` (e.g. `minifilter\x64\Debug\FsFilter.inf`), like
+    /// `RUNDLL32.EXE SETUPAPI.DLL,InstallHinfSection DefaultInstall 132 <inf>`.
+    ///
+    /// `SetupAPI` reports no error: fails with [`ServiceError::NotInstalled`] if the service does
+    /// not exist afterwards.
+    pub fn install(&self, inf: &Path) -> Result<(), ServiceError> {
+        if !inf.is_file() {
+            return Err(ServiceError::InfNotFound {
+                path: inf.to_path_buf(),
+            });
+        }
+        self.manager.install(&self.name, inf)?;
+        if !self.is_installed()? {
+            return Err(ServiceError::NotInstalled);
+        }
+        Ok(())
+    }
+
+    /// Starts the minifilter, if not already running.
+    pub fn load(&self) -> Result<(), ServiceError> {
+        match self.status()? {
+            ServiceState::NotInstalled => return Err(ServiceError::NotInstalled),
+            ServiceState::Running => return Ok(()),
+            ServiceState::StartPending => {}
+            _ => self.manager.load(&self.name)?,
+        }
+        self.wait_for(ServiceState::Running)
+    }
+
+    /// Stops the minifilter, if running. Its communication port is closed: a connected
+    /// [`Driver`](crate::driver_comm::Driver) gets [`Disconnected`](crate::driver_comm::error::DriverError::Disconnected).
+    pub fn unload(&self) -> Result<(), ServiceError> {
+        match self.status()? {
+            ServiceState::NotInstalled => return Err(ServiceError::NotInstalled),
+            ServiceState::Stopped => return Ok(()),
+            ServiceState::StopPending => {}
+            _ => self.manager.unload(&self.name)?,
+        }
+        self.wait_for(ServiceState::Stopped)
+    }
+
+    /// Unloads then loads the minifilter, e.g. after its `.sys` has been updated.
+    pub fn restart(&self) -> Result<(), ServiceError> {
+        self.unload()?;
+        self.load()
+    }
+
+    /// Installs the minifilter from `inf` if needed, and loads it.
+    pub fn deploy(&self, inf: &Path) -> Result<(), ServiceError> {
+        if !self.is_installed()? {
+            self.install(inf)?;
+        }
+        self.load()
+    }
+
+    /// Unloads the minifilter and deletes its service. Does nothing if it is not installed.
+    pub fn uninstall(&self) -> Result<(), ServiceError> {
+        if !self.is_installed()? {
+            return Ok(());
+        }
+        self.unload()?;
+        self.manager.uninstall(&self.name)?;
+        self.wait_for(ServiceState::NotInstalled)
+    }
+
+    fn wait_for(&self, expected: ServiceState) -> Result<(), ServiceError> {
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            let found = self.status()?;
+            if found == expected {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                return Err(ServiceError::Timeout { expected, found });
+            }
+            thread::sleep(self.poll_interval);
+        }
+    }
+}
@@ -0,0 +1,518 @@
+//! Safe reading of the reply buffer filled by the minifilter on
+//! [`GetOps`](crate::driver_comm::DriverComMessageType::GetOps).
+//!
+//! The buffer starts with a [`ReplyIrp`] header followed by packed [`CDriverMsg`], each one directly
+//! followed by its file path. The `data`, `next` and `filepath.buffer` pointers written by the
+//! minifilter are never dereferenced: they are turned into offsets relative to the base of the
+//! buffer (`data` points right after the header) and checked against its bounds before anything
+//! is read.
+
+use std::error::Error;
+use std::fmt;
+use std::mem::{offset_of, size_of};
+
+use windows::Win32::Storage::FileSystem::FILE_ID_INFO;
+
+use crate::shared_def::{CDriverMsg, DriverMsg, ReplyIrp, UnicodeString};
+
+/// Size of the [`ReplyIrp`] header (`RWD_REPLY_IRPS`).
+pub const REPLY_HEADER_SIZE: usize = size_of::<ReplyIrp>();
+/// Size of a [`CDriverMsg`] (`DRIVER_MESSAGE`), without its file path.
+pub const DRIVER_MSG_SIZE: usize = size_of::<CDriverMsg>();
+/// Max size in bytes of a file path copied by the minifilter (`MAX_FILE_NAME_SIZE`). Longer paths
+/// keep their full `Length` but are truncated in the buffer.
+pub const MAX_FILE_NAME_SIZE: usize = 520 * 2;
+
+/// Why a buffer written by the minifilter could not be decoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The buffer cannot even hold the [`ReplyIrp`] header.
+    TruncatedHeader { buffer_len: usize },
+    /// `data_size` is smaller than the header or larger than the buffer.
+    DataSizeOutOfBounds { data_size: u64, buffer_len: usize },
+    /// More operations announced than `data_size` can hold.
+    TooManyOps { num_ops: u64, max_ops: u64 },
+    /// Operations are announced but `data` is null.
+    NullData { num_ops: u64 },
+    /// The message `index` does not fit in `data_size`, or overlaps the previous one.
+    MessageOutOfBounds { index: u64, offset: i128 },
+    /// The message `index` or its file path is not aligned on a UTF-16 code unit.
+    Misaligned { index: u64, offset: i128 },
+    /// The file path of the message `index` has an odd byte length.
+    OddPathLength { index: u64, length: u16 },
+    /// The file path of the message `index` does not fit in `data_size`.
+    PathOutOfBounds {
+        index: u64,
+        offset: i128,
+        length: usize,
+    },
+    /// The chain of messages ends before `num_ops`.
+    MissingOps { num_ops: u64, found: u64 },
+    /// The buffer is shorter than the `min_len` bytes of the structure it should hold.
+    TooShort { buffer_len: usize, min_len: usize },
+    /// `field` holds a code unknown to this crate, sent by a newer minifilter or corrupted.
+    UnknownCode { field: &'static str, value: u8 },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::TruncatedHeader { buffer_len } => write!(
+                f,
+                "reply of {buffer_len} bytes is smaller than its {REPLY_HEADER_SIZE} bytes header"
+            ),
+            DecodeError::DataSizeOutOfBounds {
+                data_size,
+                buffer_len,
+            } => write!(
+                f,
+                "data size {data_size} is out of the {buffer_len} bytes reply buffer"
+            ),
+            DecodeError::TooManyOps { num_ops, max_ops } => write!(
+                f,
+                "{num_ops} operations announced, at most {max_ops} fit in the reply"
+            ),
+            DecodeError::NullData { num_ops } => {
+                write!(f, "{num_ops} operations announced without data")
+            }
+            DecodeError::MessageOutOfBounds { index, offset } => {
+                write!(f, "message {index} at offset {offset} is out of bounds")
+            }
+            DecodeError::Misaligned { index, offset } => {
+                write!(f, "message {index}: offset {offset} is misaligned")
+            }
+            DecodeError::OddPathLength { index, length } => {
+                write!(f, "message {index}: odd file path length {length}")
+            }
+            DecodeError::PathOutOfBounds {
+                index,
+                offset,
+                length,
+            } => write!(
+                f,
+                "message {index}: file path of {length} bytes at offset {offset} is out of bounds"
+            ),
+            DecodeError::MissingOps { num_ops, found } => write!(
+                f,
+                "{num_ops} operations announced, the chain ends after {found}"
+            ),
+            DecodeError::TooShort {
+                buffer_len,
+                min_len,
+            } => write!(f, "buffer of {buffer_len} bytes is shorter than {min_len}"),
+            DecodeError::UnknownCode { field, value } => write!(f, "unknown {field} {value}"),
+        }
+    }
+}
+
+impl Error for DecodeError {}
+
+/// Iterates over the [`DriverMsg`] of a reply buffer, checking each one before reading it.
+///
+/// Decoding stops at the first error, which is returned as the last item.
+#[derive(Debug)]
+pub struct ReplyDecoder<'a> {
+    /// The reply, cut at `data_size`.
+    data: &'a [u8],
+    /// Address of the reply buffer when the minifilter wrote it.
+    base: u64,
+    num_ops: u64,
+    index: u64,
+    /// Offset of the next message to read, as written by the minifilter (not checked yet).
+    next: Option<i128>,
+    /// Offset from which the next message may start.
+    min_offset: usize,
+    failed: bool,
+}
+
+impl<'a> ReplyDecoder<'a> {
+    /// Checks the [`ReplyIrp`] header of `reply`.
+    pub fn new(reply: &'a [u8]) -> Result<ReplyDecoder<'a>, DecodeError> {
+        let header = Self::header(reply)?;
+        let buffer_len = reply.len();
+
+        if header.data_size < REPLY_HEADER_SIZE as u64 || header.data_size > buffer_len as u64 {
+            return Err(DecodeError::DataSizeOutOfBounds {
+                data_size: header.data_size,
+                buffer_len,
+            });
+        }
+        let data_size = header.data_size as usize;
+        let max_ops = ((data_size - REPLY_HEADER_SIZE) / DRIVER_MSG_SIZE) as u64;
+        if header.num_ops > max_ops {
+            return Err(DecodeError::TooManyOps {
+                num_ops: header.num_ops,
+                max_ops,
+            });
+        }
+
+        let data = header.data as u64;
+        if header.num_ops > 0 && data == 0 {
+            return Err(DecodeError::NullData {
+                num_ops: header.num_ops,
+            });
+        }
+
+        Ok(ReplyDecoder {
+            data: &reply[..data_size],
+            base: data.wrapping_sub(REPLY_HEADER_SIZE as u64),
+            num_ops: header.num_ops,
+            index: 0,
+            next: (header.num_ops > 0).then_some(REPLY_HEADER_SIZE as i128),
+            min_offset: REPLY_HEADER_SIZE,
+            failed: false,
+        })
+    }
+
+    /// Number of messages announced by the header.
+    pub fn num_ops(&self) -> u64 {
+        self.num_ops
+    }
+
+    fn header(reply: &[u8]) -> Result<ReplyIrp, DecodeError> {
+        if reply.len() < REPLY_HEADER_SIZE {
+            return Err(DecodeError::TruncatedHeader {
+                buffer_len: reply.len(),
+            });
+        }
+        Ok(ReplyIrp {
+            data_size: read_u64(reply, offset_of!(ReplyIrp, data_size)),
+            data: read_u64(reply, offset_of!(ReplyIrp, data)) as usize as *const CDriverMsg,
+            num_ops: read_u64(reply, offset_of!(ReplyIrp, num_ops)),
+        })
+    }
+
+    /// Offset in the reply of a pointer written by the minifilter. Can be negative or beyond the
+    /// reply if the pointer is corrupted.
+    fn offset_of_ptr(&self, ptr: u64) -> i128 {
+        ptr as i128 - self.base as i128
+    }
+
+    /// Checks that `len` bytes at `offset` are in `min..data_size`, returning the offset as usize.
+    fn in_bounds(&self, offset: i128, min: usize, len: usize) -> Option<usize> {
+        let offset = usize::try_from(offset).ok()?;
+        (offset >= min && offset.checked_add(len)? <= self.data.len()).then_some(offset)
+    }
+
+    /// Checks that a whole message fits at `offset`, after the previous one.
+    fn check_msg(&self, offset: i128) -> Result<usize, DecodeError> {
+        let index = self.index;
+        let start = self
+            .in_bounds(offset, self.min_offset, DRIVER_MSG_SIZE)
+            .ok_or(DecodeError::MessageOutOfBounds { index, offset })?;
+        if start % 2 != 0 {
+            return Err(DecodeError::Misaligned { index, offset });
+        }
+        Ok(start)
+    }
+
+    fn decode_at(&mut self, offset: usize) -> Result<DriverMsg, DecodeError> {
+        let index = self.index;
+        let msg = &self.data[offset..offset + DRIVER_MSG_SIZE];
+        let filepath_offset = offset_of!(CDriverMsg, filepath);
+        let length = read_u16(msg, filepath_offset + offset_of!(UnicodeString, length));
+        let buffer = read_u64(msg, filepath_offset + offset_of!(UnicodeString, buffer));
+        let next = read_u64(msg, offset_of!(CDriverMsg, next));
+
+        if length % 2 != 0 {
+            return Err(DecodeError::OddPathLength { index, length });
+        }
+        let path_len = (length as usize).min(MAX_FILE_NAME_SIZE);
+        let mut end = offset + DRIVER_MSG_SIZE;
+        let mut filepath = Vec::with_capacity(path_len / 2);
+        if path_len > 0 && buffer != 0 {
+            let path_offset = self.offset_of_ptr(buffer);
+            let path_start =
+                self.in_bounds(path_offset, end, path_len)
+                    .ok_or(DecodeError::PathOutOfBounds {
+                        index,
+                        offset: path_offset,
+                        length: path_len,
+                    })?;
+            if path_start % 2 != 0 {
+                return Err(DecodeError::Misaligned {
+                    index,
+                    offset: path_offset,
+                });
+            }
+            filepath.extend(
+                self.data[path_start..path_start + path_len]
+                    .chunks_exact(2)
+                    .map(|c| u16::from_ne_bytes([c[0], c[1]])),
+            );
+            end = path_start + path_len;
+        }
+
+        self.min_offset = end;
+        self.next = (next != 0).then(|| self.offset_of_ptr(next));
+
+        let file_id_offset = offset_of!(CDriverMsg, file_id);
+        let mut extension = [0u16; 12];
+        for (i, c) in extension.iter_mut().enumerate() {
+            *c = read_u16(msg, offset_of!(CDriverMsg, extension) + 2 * i);
+        }
+        let mut file_id_id = [0u8; 16];
+        let id_offset = file_id_offset + offset_of!(FILE_ID_INFO, FileId);
+        file_id_id.copy_from_slice(&msg[id_offset..id_offset + 16]);
+
+        Ok(DriverMsg {
+            extension,
+            file_id_vsn: read_u64(
+                msg,
+                file_id_offset + offset_of!(FILE_ID_INFO, VolumeSerialNumber),
+            ),
+            file_id_id,
+            mem_sized_used: read_u64(msg, offset_of!(CDriverMsg, mem_sized_used)),
+            entropy: f64::from_bits(read_u64(msg, offset_of!(CDriverMsg, entropy))),
+            pid: read_u32(msg, offset_of!(CDriverMsg, pid)),
+            irp_op: msg[offset_of!(CDriverMsg, irp_op)],
+            is_entropy_calc: msg[offset_of!(CDriverMsg, is_entropy_calc)],
+            file_change: msg[offset_of!(CDriverMsg, file_change)],
+            file_location_info: msg[offset_of!(CDriverMsg, file_location_info)],
+            filepath,
+            gid: read_u64(msg, offset_of!(CDriverMsg, gid)),
+            time: read_u64(msg, offset_of!(CDriverMsg, time)) as i64,
+            sequence: read_u64(msg, offset_of!(CDriverMsg, sequence)),
+        })
+    }
+}
+
+impl Iterator for ReplyDecoder<'_> {
+    type Item = Result<DriverMsg, DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed || self.index == self.num_ops {
+            return None;
+        }
+        let res = match self.next {
+            None => Err(DecodeError::MissingOps {
+                num_ops: self.num_ops,
+                found: self.index,
+            }),
+            Some(offset) => self.check_msg(offset).and_then(|o| self.decode_at(o)),
+        };
+        match res {
+            Ok(_) => self.index += 1,
+            Err(_) => self.failed = true,
+        }
+        Some(res)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        if self.failed {
+            (0, Some(0))
+        } else {
+            (0, Some((self.num_ops - self.index) as usize))
+        }
+    }
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_ne_bytes([buf[offset], buf[offset + 1]])
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&buf[offset..offset + 4]);
+    u32::from_ne_bytes(bytes)
+}
+
+fn read_u64(buf: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&buf[offset..offset + 8]);
+    u64::from_ne_bytes(bytes)
+}
+
+#[cfg(test)]
+#[doc(hidden)]
+mod tests {
+    use std::mem::offset_of;
+
+    use crate::driver_comm::mock::{MockDriver, MockEvent};
+    use crate::driver_comm::{Driver, IrpMajorOp, MAX_COMM_BUFFER_SIZE};
+    use crate::shared_def::decoder::{
+        DecodeError, ReplyDecoder, DRIVER_MSG_SIZE, REPLY_HEADER_SIZE,
+    };
+    use crate::shared_def::{CDriverMsg, DriverMsg, ReplyIrp, UnicodeString};
+
+    const PATHS: [&str; 3] = [
+        r"C:\Users\Dev\a.txt",
+        r"C:\Users\Dev\Documents\report.docx",
+        r"C:\tmp",
+    ];
+
+    /// A reply with the three [`PATHS`], as written by the minifilter.
+    fn reply() -> Vec<u8> {
+        let mock = MockDriver::new();
+        mock.push_events(
+            PATHS
+                .iter()
+                .enumerate()
+                .map(|(i, p)| MockEvent::new(i as u32, 3, IrpMajorOp::IrpWrite, p)),
+        );
+        let mut vecnew = Vec::with_capacity(MAX_COMM_BUFFER_SIZE);
+        Driver::with_transport(mock).get_irp(&mut vecnew).unwrap();
+        vecnew
+    }
+
+    fn decode(reply: &[u8]) -> Result<Vec<DriverMsg>, DecodeError> {
+        ReplyDecoder::new(reply)?.collect()
+    }
+
+    fn set_u64(reply: &mut [u8], offset: usize, value: u64) {
+        reply[offset..offset + 8].copy_from_slice(&value.to_ne_bytes());
+    }
+
+    fn get_u64(reply: &[u8], offset: usize) -> u64 {
+        u64::from_ne_bytes(reply[offset..offset + 8].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_decode_reply() {
+        let reply = reply();
+        let drivermsgs = decode(&reply).unwrap();
+        assert_eq!(drivermsgs.len(), 3);
+        for (i, drivermsg) in drivermsgs.iter().enumerate() {
+            assert_eq!(drivermsg.pid, i as u32);
+            assert_eq!(drivermsg.gid, 3);
+            assert_eq!(drivermsg.filepath_string(), PATHS[i]);
+        }
+
+        // Pointers are relative to the reply, which can be moved
+        let moved = reply[..MAX_COMM_BUFFER_SIZE / 2].to_vec();
+        assert_eq!(decode(&moved).unwrap(), drivermsgs);
+    }
+
+    #[test]
+    fn test_decode_empty_reply() {
+        let mock = MockDriver::new();
+        let mut vecnew = vec![];
+        Driver::with_transport(mock).get_irp(&mut vecnew).unwrap();
+        assert_eq!(decode(&vecnew).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_decode_bad_header() {
+        let reply = reply();
+        assert_eq!(
+            decode(&reply[..REPLY_HEADER_SIZE - 1]),
+            Err(DecodeError::TruncatedHeader {
+                buffer_len: REPLY_HEADER_SIZE - 1
+            })
+        );
+
+        let data_size = get_u64(&reply, offset_of!(ReplyIrp, data_size));
+        assert_eq!(
+            decode(&reply[..data_size as usize - 1]),
+            Err(DecodeError::DataSizeOutOfBounds {
+                data_size,
+                buffer_len: data_size as usize - 1
+            })
+        );
+
+        let mut corrupted = reply.clone();
+        set_u64(&mut corrupted, offset_of!(ReplyIrp, num_ops), 1 << 40);
+        assert!(matches!(
+            decode(&corrupted),
+            Err(DecodeError::TooManyOps { .. })
+        ));
+
+        let mut corrupted = reply;
+        set_u64(&mut corrupted, offset_of!(ReplyIrp, data), 0);
+        assert_eq!(
+            decode(&corrupted),
+            Err(DecodeError::NullData { num_ops: 3 })
+        );
+    }
+
+    #[test]
+    fn test_decode_bad_messages() {
+        let reply = reply();
+        let first = REPLY_HEADER_SIZE;
+        let second = first + DRIVER_MSG_SIZE + PATHS[0].len() * 2;
+        let path_offset = offset_of!(CDriverMsg, filepath);
+        let next_offset = offset_of!(CDriverMsg, next);
+
+        // next points back to the first message
+        let mut corrupted = reply.clone();
+        let data = get_u64(&reply, offset_of!(ReplyIrp, data));
+        set_u64(&mut corrupted, first + next_offset, data);
+        let mut decoder = ReplyDecoder::new(&corrupted).unwrap();
+        assert!(decoder.next().unwrap().is_ok());
+        assert_eq!(
+            decoder.next(),
+            Some(Err(DecodeError::MessageOutOfBounds {
+                index: 1,
+                offset: first as i128
+            }))
+        );
+        assert_eq!(decoder.next(), None);
+
+        // next is odd
+        let mut corrupted = reply.clone();
+        let next = get_u64(&reply, first + next_offset);
+        set_u64(&mut corrupted, first + next_offset, next + 1);
+        assert_eq!(
+            decode(&corrupted),
+            Err(DecodeError::Misaligned {
+                index: 1,
+                offset: second as i128 + 1
+            })
+        );
+
+        // the chain ends after the second message
+        let mut corrupted = reply.clone();
+        set_u64(&mut corrupted, second + next_offset, 0);
+        assert_eq!(
+            decode(&corrupted),
+            Err(DecodeError::MissingOps {
+                num_ops: 3,
+                found: 2
+            })
+        );
+
+        // odd length
+        let mut corrupted = reply.clone();
+        corrupted[first + path_offset + offset_of!(UnicodeString, length)] += 1;
+        assert!(matches!(
+            decode(&corrupted),
+            Err(DecodeError::OddPathLength { index: 0, .. })
+        ));
+
+        // path buffer beyond data_size
+        let mut corrupted = reply;
+        let buffer = first + path_offset + offset_of!(UnicodeString, buffer);
+        let ptr = get_u64(&corrupted, buffer);
+        set_u64(&mut corrupted, buffer, ptr + MAX_COMM_BUFFER_SIZE as u64);
+        assert!(matches!(
+            decode(&corrupted),
+            Err(DecodeError::PathOutOfBounds { index: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn test_decode_corrupted_replies() {
+        let reply = reply();
+        let data_size = get_u64(&reply, offset_of!(ReplyIrp, data_size)) as usize;
+        let mut seed: u64 = 0x2545F4914F6CDD1D;
+        let mut rand = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+
+        for _ in 0..2000 {
+            let mut corrupted = reply.clone();
+            for _ in 0..(rand() % 8 + 1) {
+                let at = rand() as usize % data_size;
+                corrupted[at] = rand() as u8;
+            }
+            let len = (rand() as usize % (data_size + 16)).min(corrupted.len());
+            // Must never panic nor read out of the buffer
+            if let Ok(drivermsgs) = decode(&corrupted[..len]) {
+                assert!(drivermsgs.len() <= 3);
+            }
+        }
+    }
+}
@@ -0,0 +1,407 @@
+//! A typed, serializable view of an [`IOMessage`].
+//!
+//! [`IOMessage`] mirrors the C layout of the minifilter's `DRIVER_MESSAGE`: its codes are raw
+//! bytes and its extension a NUL-padded UTF-16 buffer, and both change with the driver. A
+//! [`FileEvent`] carries the same data decoded once, with stable serde names, so that events
+//! written to disk or sent over the network can still be read after the driver layout changes.
+//!
+//! ```
+//! use minifilter_rs::driver_comm::IrpMajorOp;
+//! use minifilter_rs::shared_def::file_event::FileEvent;
+//!
+//! let event: FileEvent = serde_json::from_str(
+//!     r#"{"version":1,"pid":1200,"gid":7,"op":"write","file_change":"write",
+//!         "file_location":"not_protected","path":"C:\\Users\\Dev\\notes.txt",
+//!         "extension":"txt","file_id":{"volume_serial":1,"file_id":[]},"bytes":4096}"#,
+//! )
+//! .unwrap();
+//! assert_eq!(event.op, IrpMajorOp::IrpWrite);
+//! assert_eq!(event.entropy, None);
+//! ```
+
+use std::error::Error;
+use std::fmt;
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::driver_comm::IrpMajorOp;
+use crate::process::FileId;
+use crate::shared_def::{unix_epoch, FileChangeInfo, FileLocationInfo, IOMessage, RuntimeFeatures};
+
+/// Version of the [`FileEvent`] format, bumped on changes that older readers cannot ignore.
+pub const FILE_EVENT_VERSION: u32 = 1;
+
+/// Number of UTF-16 units of [`IOMessage::extension`] available to the extension, the last one
+/// being kept for the terminating NUL.
+const EXTENSION_LEN: usize = 11;
+
+/// A file operation reported by the minifilter, see [`IOMessage`].
+///
+/// Fields added after version 1 are optional so that older events still deserialize.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileEvent {
+    /// See [`FILE_EVENT_VERSION`].
+    #[serde(default = "default_version")]
+    pub version: u32,
+    /// Pid responsible for this io activity
+    pub pid: u32,
+    /// Group Identifier (maintained by the minifilter) of the operation
+    pub gid: u64,
+    /// The IRP caught by the minifilter
+    pub op: IrpMajorOp,
+    /// The nature of the modification
+    pub file_change: FileChangeInfo,
+    /// Is the file in a monitored directory?
+    pub file_location: FileLocationInfo,
+    /// File path on the disk
+    pub path: String,
+    /// The file extension, without the dot
+    pub extension: String,
+    /// Volume serial number and file id on the volume
+    pub file_id: FileId,
+    /// Number of bytes transferred
+    pub bytes: u64,
+    /// Entropy of the data written or read, if the driver calculated it
+    #[serde(default)]
+    pub entropy: Option<f64>,
+    /// Size of the file, if known
+    #[serde(default)]
+    pub file_size: Option<u64>,
+    /// See [`RuntimeFeatures`]
+    #[serde(default)]
+    pub runtime_features: RuntimeFeatures,
+    /// When the minifilter caught the operation, in ISO 8601 (`2023-11-14T22:13:20.000000000Z`)
+    #[serde(default = "unix_epoch", with = "iso8601")]
+    pub time: SystemTime,
+    /// See [`IOMessage::sequence`]
+    #[serde(default)]
+    pub sequence: u64,
+    /// When this app received the operation, in ISO 8601
+    #[serde(default = "unix_epoch", with = "iso8601")]
+    pub received: SystemTime,
+}
+
+fn default_version() -> u32 {
+    FILE_EVENT_VERSION
+}
+
+impl TryFrom<&IOMessage> for FileEvent {
+    type Error = UnknownCode;
+
+    fn try_from(iomsg: &IOMessage) -> Result<FileEvent, UnknownCode> {
+        let op = IrpMajorOp::try_from(iomsg.irp_op).map_err(|e| UnknownCode {
+            field: "irp_op",
+            value: e.0,
+        })?;
+        let file_change = num::FromPrimitive::from_u8(iomsg.file_change).ok_or(UnknownCode {
+            field: "file_change",
+            value: iomsg.file_change,
+        })?;
+        let file_location =
+            num::FromPrimitive::from_u8(iomsg.file_location_info).ok_or(UnknownCode {
+                field: "file_location_info",
+                value: iomsg.file_location_info,
+            })?;
+        let end = iomsg
+            .extension
+            .iter()
+            .position(|&c| c == 0)
+            .unwrap_or(iomsg.extension.len());
+        Ok(FileEvent {
+            version: FILE_EVENT_VERSION,
+            pid: iomsg.pid,
+            gid: iomsg.gid,
+            op,
+            file_change,
+            file_location,
+            path: iomsg.filepathstr.clone(),
+            extension: String::from_utf16_lossy(&iomsg.extension[..end]),
+            file_id: FileId::of(iomsg),
+            bytes: iomsg.mem_sized_used,
+            entropy: (iomsg.is_entropy_calc == 1).then_some(iomsg.entropy),
+            file_size: u64::try_from(iomsg.file_size).ok(),
+            runtime_features: iomsg.runtime_features.clone(),
+            time: iomsg.time,
+            sequence: iomsg.sequence,
+            received: iomsg.received,
+        })
+    }
+}
+
+impl FileEvent {
+    /// Back to the layout consumed by the [`Worker`](crate::worker::Worker). Extensions longer
+    /// than the driver buffer are truncated, as the driver does.
+    pub fn to_iomessage(&self) -> IOMessage {
+        let mut extension = [0u16; 12];
+        for (dst, src) in extension
+            .iter_mut()
+            .zip(self.extension.encode_utf16().take(EXTENSION_LEN))
+        {
+            *dst = src;
+        }
+        let mut file_id_id = [0u8; 16];
+        for (dst, src) in file_id_id.iter_mut().zip(&self.file_id.file_id) {
+            *dst = *src;
+        }
+        IOMessage {
+            extension,
+            file_id_vsn: self.file_id.volume_serial,
+            file_id_id,
+            mem_sized_used: self.bytes,
+            entropy: self.entropy.unwrap_or(0.0),
+            pid: self.pid,
+            irp_op: self.op as u8,
+            is_entropy_calc: u8::from(self.entropy.is_some()),
+            file_change: self.file_change as u8,
+            file_location_info: self.file_location as u8,
+            filepathstr: self.path.clone(),
+            gid: self.gid,
+            runtime_features: self.runtime_features.clone(),
+            file_size: self.file_size.map_or(-1, |size| size as i64),
+            time: self.time,
+            sequence: self.sequence,
+            received: self.received,
+        }
+    }
+}
+
+/// A code of an [`IOMessage`] unknown to this crate, sent by a newer minifilter or read from a
+/// corrupted message.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct UnknownCode {
+    /// The [`IOMessage`] field holding the code
+    pub field: &'static str,
+    pub value: u8,
+}
+
+impl fmt::Display for UnknownCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown {} {}", self.field, self.value)
+    }
+}
+
+impl Error for UnknownCode {}
+
+/// UTC timestamps with nanoseconds, e.g. `2023-11-14T22:13:20.000000000Z`. Times before the Unix
+/// epoch are written as the epoch.
+pub mod iso8601 {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn format(time: SystemTime) -> String {
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let secs = since_epoch.as_secs();
+        let (year, month, day) = civil_from_days((secs / 86400) as i64);
+        let secs_of_day = secs % 86400;
+        format!(
+            "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:09}Z",
+            secs_of_day / 3600,
+            secs_of_day / 60 % 60,
+            secs_of_day % 60,
+            since_epoch.subsec_nanos()
+        )
+    }
+
+    /// Reads `YYYY-MM-DDTHH:MM:SS[.fraction]Z`, as written by [`format()`].
+    pub fn parse(s: &str) -> Option<SystemTime> {
+        let s = s.strip_suffix('Z')?;
+        let (date, time) = s.split_once('T')?;
+        let mut date = date.splitn(3, '-').map(|n| n.parse::<i64>().ok());
+        let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+        let (time, fraction) = time.split_once('.').unwrap_or((time, ""));
+        let mut time = time.splitn(3, ':').map(|n| n.parse::<u64>().ok());
+        let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+        if !(1..=12).contains(&month)
+            || !(1..=31).contains(&day)
+            || hour > 23
+            || minute > 59
+            || second > 60
+            || fraction.len() > 9
+            || !fraction.bytes().all(|b| b.is_ascii_digit())
+        {
+            return None;
+        }
+        let nanos = format!("{fraction:0<9}").parse::<u32>().ok()?;
+        let days = u64::try_from(days_from_civil(year, month, day)?).ok()?;
+        let secs = days
+            .checked_mul(86400)?
+            .checked_add(hour * 3600 + minute * 60 + second)?;
+        UNIX_EPOCH.checked_add(Duration::new(secs, nanos))
+    }
+
+    pub fn serialize<S: Serializer>(time: &SystemTime, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format(*time))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<SystemTime, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        parse(&s).ok_or_else(|| D::Error::custom(format!("invalid ISO 8601 time {s:?}")))
+    }
+
+    /// Days since the Unix epoch to a proleptic Gregorian date, see
+    /// <http://howardhinnant.github.io/date_algorithms.html>.
+    fn civil_from_days(days: i64) -> (i64, i64, i64) {
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z - era * 146097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + (month <= 2) as i64;
+        (year, month, day)
+    }
+
+    /// `None` if the days overflow.
+    fn days_from_civil(year: i64, month: i64, day: i64) -> Option<i64> {
+        let year = if month <= 2 {
+            year.checked_sub(1)?
+        } else {
+            year
+        };
+        let era = year.div_euclid(400);
+        let yoe = year - era * 400;
+        let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        era.checked_mul(146097)?.checked_add(doe - 719468)
+    }
+}
+
+#[cfg(test)]
+#[doc(hidden)]
+mod tests {
+    use std::path::PathBuf;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::*;
+    use crate::driver_comm::DriveType;
+
+    fn iomsg() -> IOMessage {
+        let mut extension = [0u16; 12];
+        for (dst, src) in extension.iter_mut().zip("docx".encode_utf16()) {
+            *dst = src;
+        }
+        IOMessage {
+            extension,
+            file_id_vsn: 0x1234,
+            file_id_id: [7; 16],
+            mem_sized_used: 4096,
+            entropy: 7.5,
+            pid: 1200,
+            irp_op: IrpMajorOp::IrpWrite as u8,
+            is_entropy_calc: 1,
+            file_change: FileChangeInfo::FileChangeWrite as u8,
+            file_location_info: FileLocationInfo::FileProtected as u8,
+            filepathstr: r"C:\Users\Dev\report.docx".to_string(),
+            gid: 7,
+            runtime_features: RuntimeFeatures {
+                exepath: PathBuf::from(r"C:\Tools\app.exe"),
+                exe_still_exists: true,
+                drive_type: DriveType::DriveFixed,
+            },
+            file_size: 65536,
+            time: UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+            sequence: 41,
+            received: UNIX_EPOCH + Duration::from_millis(1_700_000_000_002),
+        }
+    }
+
+    #[test]
+    fn test_iomessage_round_trip() {
+        let iomsg = iomsg();
+        let event = FileEvent::try_from(&iomsg).unwrap();
+        assert_eq!(event.extension, "docx");
+        assert_eq!(event.entropy, Some(7.5));
+        assert_eq!(event.file_size, Some(65536));
+
+        let back = event.to_iomessage();
+        assert_eq!(back.extension, iomsg.extension);
+        assert_eq!(back.file_id_vsn, iomsg.file_id_vsn);
+        assert_eq!(back.file_id_id, iomsg.file_id_id);
+        assert_eq!(back.mem_sized_used, iomsg.mem_sized_used);
+        assert_eq!(back.entropy, iomsg.entropy);
+        assert_eq!(back.is_entropy_calc, iomsg.is_entropy_calc);
+        assert_eq!(back.irp_op, iomsg.irp_op);
+        assert_eq!(back.file_change, iomsg.file_change);
+        assert_eq!(back.file_location_info, iomsg.file_location_info);
+        assert_eq!(back.filepathstr, iomsg.filepathstr);
+        assert_eq!(back.gid, iomsg.gid);
+        assert_eq!(back.file_size, iomsg.file_size);
+        assert_eq!(back.time, iomsg.time);
+        assert_eq!(back.sequence, iomsg.sequence);
+        assert_eq!(back.received, iomsg.received);
+        assert_eq!(
+            back.runtime_features.exepath,
+            iomsg.runtime_features.exepath
+        );
+        assert_eq!(
+            back.runtime_features.drive_type,
+            iomsg.runtime_features.drive_type
+        );
+
+        let json = serde_json::to_string(&event).unwrap();
+        assert!(json.contains(r#""op":"write""#));
+        assert!(json.contains(r#""file_location":"protected""#));
+        assert_eq!(serde_json::from_str::<FileEvent>(&json).unwrap(), event);
+
+        let mut unknown = iomsg;
+        unknown.file_change = 42;
+        assert_eq!(
+            FileEvent::try_from(&unknown).unwrap_err(),
+            UnknownCode {
+                field: "file_change",
+                value: 42
+            }
+        );
+    }
+
+    #[test]
+    fn test_version_1_event_deserializes() {
+        let event: FileEvent = serde_json::from_str(
+            r#"{"pid":1200,"gid":7,"op":"cleanup","file_change":"delete_new_file",
+                "file_location":"moved_out","path":"C:\\tmp\\x.tmp","extension":"tmp",
+                "file_id":{"volume_serial":1,"file_id":[1,2]},"bytes":0,"unknown":true}"#,
+        )
+        .unwrap();
+        assert_eq!(event.version, FILE_EVENT_VERSION);
+        assert_eq!(event.op, IrpMajorOp::IrpCleanUp);
+        assert_eq!(event.file_change, FileChangeInfo::FileChangeDeleteNewFile);
+        assert_eq!(event.file_location, FileLocationInfo::FileMovedOut);
+        assert_eq!(event.entropy, None);
+        assert_eq!(event.file_size, None);
+        assert_eq!(event.time, UNIX_EPOCH);
+
+        let time = UNIX_EPOCH + Duration::new(1_709_208_000, 5_000);
+        assert_eq!(iso8601::format(time), "2024-02-29T12:00:00.000005000Z");
+        assert_eq!(iso8601::parse("2024-02-29T12:00:00.000005Z"), Some(time));
+        assert_eq!(iso8601::parse("1970-01-01T00:00:00Z"), Some(UNIX_EPOCH));
+        assert_eq!(iso8601::parse("2024-13-01T00:00:00Z"), None);
+
+        let iomsg = event.to_iomessage();
+        assert_eq!(iomsg.is_entropy_calc, 0);
+        assert_eq!(iomsg.file_size, -1);
+        assert_eq!(&iomsg.file_id_id[..3], &[1, 2, 0]);
+    }
+
+    #[test]
+    fn test_iso8601_out_of_range() {
+        for time in [
+            "9999999999999-01-01T00:00:00Z",
+            "584554051223-01-01T00:00:00Z",
+            "9223372036854775807-01-01T00:00:00Z",
+            "9223372036854775807-03-01T23:59:59.999999999Z",
+            "1969-12-31T23:59:59Z",
+        ] {
+            assert_eq!(iso8601::parse(time), None, "{time}");
+        }
+        let line = r#"{"pid":1,"gid":1,"op":"read","file_change":"not_set",
+            "file_location":"not_protected","path":"a","extension":"",
+            "file_id":{"volume_serial":1,"file_id":[]},"bytes":0,
+            "time":"584554051223-01-01T00:00:00Z"}"#;
+        assert!(serde_json::from_str::<FileEvent>(line).is_err());
+    }
+}
@@ -0,0 +1,399 @@
+//! Contains all definitions shared between this user-mode app and the minifilter in order to
+//! communicate properly. Those are C-representation of structures sent or received from the minifilter.
+
+pub mod decoder;
+pub mod file_event;
+pub mod nt_path;
+
+use std::os::raw::{c_uchar, c_ulonglong, c_ushort};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use num_derive::FromPrimitive;
+use serde::{Deserialize, Serialize};
+use windows::Win32::Storage::FileSystem::FILE_ID_INFO;
+
+use crate::driver_comm::DriveType;
+use crate::process::tree::from_system_time;
+use crate::shared_def::decoder::{DecodeError, ReplyDecoder};
+
+/// `IRP_OP` of a process creation, reported as a [`ProcessEvent`] rather than an [`IOMessage`].
+pub const IRP_PROCESS_CREATE: c_uchar = 6;
+/// `IRP_OP` of a process exit, reported as a [`ProcessEvent`] rather than an [`IOMessage`].
+pub const IRP_PROCESS_EXIT: c_uchar = 7;
+
+/// See [`IOMessage`] struct. Used with [`IrpSetInfo`](crate::driver_comm::IrpMajorOp::IrpSetInfo)
+#[derive(Debug, Copy, Clone, PartialEq, Eq, FromPrimitive, Serialize, Deserialize)]
+#[repr(C)]
+pub enum FileChangeInfo {
+    #[serde(rename = "not_set")]
+    FileChangeNotSet,
+    #[serde(rename = "open_directory")]
+    FileOpenDirectory,
+    #[serde(rename = "write")]
+    FileChangeWrite,
+    #[serde(rename = "new_file")]
+    FileChangeNewFile,
+    #[serde(rename = "rename")]
+    FileChangeRenameFile,
+    #[serde(rename = "extension_changed")]
+    FileChangeExtensionChanged,
+    #[serde(rename = "delete")]
+    FileChangeDeleteFile,
+    /// Temp file: created and deleted on close
+    #[serde(rename = "delete_new_file")]
+    FileChangeDeleteNewFile,
+    #[serde(rename = "overwrite")]
+    FileChangeOverwriteFile,
+}
+
+/// See [`IOMessage`] struct.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, FromPrimitive, Serialize, Deserialize)]
+#[repr(C)]
+pub enum FileLocationInfo {
+    #[serde(rename = "not_protected")]
+    FileNotProtected,
+    #[serde(rename = "protected")]
+    FileProtected,
+    #[serde(rename = "moved_in")]
+    FileMovedIn,
+    #[serde(rename = "moved_out")]
+    FileMovedOut,
+}
+
+/// Low-level C-like object to communicate with the minifilter.
+/// The minifilter yields ReplyIrp objects (retrieved by [`get_irp`](crate::driver_comm::Driver::get_irp) to
+/// manage the fixed size of the *data buffer.
+/// In other words, a ReplyIrp is a collection of [`CDriverMsg`] with a capped size.
+///
+/// This is the header of the reply buffer. Its pointers refer to the buffer as the minifilter
+/// wrote it: read the messages with a [`ReplyDecoder`] rather than dereferencing them.
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+pub struct ReplyIrp {
+    /// The size od the collection.
+    pub data_size: c_ulonglong,
+    /// The C pointer to the buffer containing the [`CDriverMsg`] events.
+    pub data: *const CDriverMsg,
+    /// The number of different operations in this collection.
+    pub num_ops: u64,
+}
+
+/// This class is the straight Rust translation of the Win32 API
+/// [`UNICODE_STRING`](https://docs.microsoft.com/en-us/windows/win32/api/ntdef/ns-ntdef-_unicode_string),
+/// returned by the driver.
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+pub struct UnicodeString {
+    pub length: c_ushort,
+    pub maximum_length: c_ushort,
+    pub buffer: *const u16,
+}
+
+/// Represents a driver message.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[repr(C)]
+pub struct IOMessage {
+    /// The file extension
+    pub extension: [u16; 12],
+    /// Hard Disk Volume Serial Number where the file is saved (from [`FILE_ID_INFO`])
+    pub file_id_vsn: c_ulonglong,
+    /// File ID on the disk ([`FILE_ID_INFO`])
+    pub file_id_id: [u8; 16],
+    /// Number of bytes transferred (`IO_STATUS_BLOCK.Information`)
+    pub mem_sized_used: c_ulonglong,
+    /// (Optional) File Entropy calculated by the driver
+    pub entropy: f64,
+    /// Pid responsible for this io activity
+    pub pid: u32,
+    /// Windows IRP Type caught by the minifilter:
+    /// - NONE (0)
+    /// - READ (1)
+    /// - WRITE (2)
+    /// - SETINFO (3)
+    /// - CREATE (4)
+    /// - CLEANUP (5)
+    pub irp_op: c_uchar,
+    /// Is the entropy calculated?
+    pub is_entropy_calc: u8,
+    /// Type of i/o operation:
+    /// - FILE_CHANGE_NOT_SET (0)
+    /// - FILE_OPEN_DIRECTORY (1)
+    /// - FILE_CHANGE_WRITE (2)
+    /// - FILE_CHANGE_NEW_FILE (3)
+    /// - FILE_CHANGE_RENAME_FILE (4)
+    /// - FILE_CHANGE_EXTENSION_CHANGED (5)
+    /// - FILE_CHANGE_DELETE_FILE (6)
+    /// - FILE_CHANGE_DELETE_NEW_FILE (7)
+    /// - FILE_CHANGE_OVERWRITE_FILE (8)
+    pub file_change: c_uchar,
+    /// The driver has the ability to monitor specific directories only (feature currently not used):
+    /// - FILE_NOT_PROTECTED (0): Monitored dirs do not contained this file
+    /// - FILE_PROTECTED (1)
+    /// - FILE_MOVED_IN (2)
+    /// - FILE_MOVED_OUT (3)
+    pub file_location_info: c_uchar,
+    /// File path on the disk
+    pub filepathstr: String,
+    /// Group Identifier (maintained by the minifilter) of the operation
+    pub gid: c_ulonglong,
+    /// see class [`RuntimeFeatures`]
+    pub runtime_features: RuntimeFeatures,
+    /// Size of the file, -1 if the file path is not found. Filled by a
+    /// [`FileSizeEnricher`](crate::enrich::FileSizeEnricher), -1 until then.
+    pub file_size: i64,
+    /// When the minifilter caught the operation
+    #[serde(default = "unix_epoch")]
+    pub time: SystemTime,
+    /// Numbers the operations queued or dropped by the minifilter since it started: a gap means
+    /// operations were lost, see [`Event::EventsLost`]
+    #[serde(default)]
+    pub sequence: c_ulonglong,
+    /// When this app received the operation
+    #[serde(default = "unix_epoch")]
+    pub received: SystemTime,
+}
+
+/// Time of the [`IOMessage`]s serialized before it was reported.
+pub(crate) fn unix_epoch() -> SystemTime {
+    UNIX_EPOCH
+}
+
+impl IOMessage {
+    /// Only copies what the minifilter sent: see [`enrich`](crate::enrich) for the rest.
+    pub fn from(drivermsg: &DriverMsg) -> IOMessage {
+        IOMessage {
+            extension: drivermsg.extension,
+            file_id_vsn: drivermsg.file_id_vsn,
+            file_id_id: drivermsg.file_id_id,
+            mem_sized_used: drivermsg.mem_sized_used,
+            entropy: drivermsg.entropy,
+            pid: drivermsg.pid,
+            irp_op: drivermsg.irp_op,
+            is_entropy_calc: drivermsg.is_entropy_calc,
+            file_change: drivermsg.file_change,
+            file_location_info: drivermsg.file_location_info,
+            filepathstr: drivermsg.filepath_string(),
+            gid: drivermsg.gid,
+            runtime_features: RuntimeFeatures::new(),
+            file_size: -1,
+            time: from_system_time(drivermsg.time),
+            sequence: drivermsg.sequence,
+            received: SystemTime::now(),
+        }
+    }
+}
+
+/// See [`ProcessEvent`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ProcessEventKind {
+    Created,
+    Exited,
+}
+
+/// The creation or exit of a process recorded in a gid by the minifilter.
+///
+/// It comes along with the [`IOMessage`]s, in a `DRIVER_MESSAGE` whose `FileID` holds a
+/// `PROCESS_MESSAGE_INFO`, and in order: the i/o of a process is reported between its creation
+/// and its exit.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProcessEvent {
+    pub kind: ProcessEventKind,
+    pub pid: u32,
+    pub parent_pid: u32,
+    /// Group Identifier (maintained by the minifilter) of the process
+    pub gid: c_ulonglong,
+    /// Image file, in the device form (`\Device\HarddiskVolumeN\...`)
+    pub image_path: String,
+    /// When the process was created, or exited
+    pub time: SystemTime,
+    /// Processes of the gid still alive after this event, 0 once the last one has exited.
+    pub alive_pids: u32,
+}
+
+impl ProcessEvent {
+    /// `None` if `drivermsg` is a file operation.
+    pub fn from(drivermsg: &DriverMsg) -> Option<ProcessEvent> {
+        let kind = match drivermsg.irp_op {
+            IRP_PROCESS_CREATE => ProcessEventKind::Created,
+            IRP_PROCESS_EXIT => ProcessEventKind::Exited,
+            _ => return None,
+        };
+        let info = &drivermsg.file_id_id;
+        Some(ProcessEvent {
+            kind,
+            pid: drivermsg.pid,
+            parent_pid: u32::from_ne_bytes([info[0], info[1], info[2], info[3]]),
+            gid: drivermsg.gid,
+            image_path: drivermsg.filepath_string(),
+            time: from_system_time(drivermsg.file_id_vsn as i64),
+            alive_pids: u32::from_ne_bytes([info[4], info[5], info[6], info[7]]),
+        })
+    }
+
+    /// The last process of the gid has exited: the minifilter forgets the gid.
+    pub fn ends_gid(&self) -> bool {
+        self.kind == ProcessEventKind::Exited && self.alive_pids == 0
+    }
+}
+
+/// What the minifilter reports on [`GetOps`](crate::driver_comm::DriverComMessageType::GetOps):
+/// i/o activities and the processes they come from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Event {
+    Io(IOMessage),
+    Process(ProcessEvent),
+    /// `count` operations were dropped by the minifilter before the next event, its queue being
+    /// full. Detected from the gaps of [`IOMessage::sequence`].
+    EventsLost {
+        count: u64,
+    },
+}
+
+impl Event {
+    pub fn from(drivermsg: &DriverMsg) -> Event {
+        match ProcessEvent::from(drivermsg) {
+            Some(process_event) => Event::Process(process_event),
+            None => Event::Io(IOMessage::from(drivermsg)),
+        }
+    }
+
+    pub fn pid(&self) -> Option<u32> {
+        match self {
+            Event::Io(iomsg) => Some(iomsg.pid),
+            Event::Process(process_event) => Some(process_event.pid),
+            Event::EventsLost { .. } => None,
+        }
+    }
+
+    pub fn gid(&self) -> Option<c_ulonglong> {
+        match self {
+            Event::Io(iomsg) => Some(iomsg.gid),
+            Event::Process(process_event) => Some(process_event.gid),
+            Event::EventsLost { .. } => None,
+        }
+    }
+}
+
+/// Stores runtime features that come from our application (and not the minifilter).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[repr(C)]
+pub struct RuntimeFeatures {
+    /// The path of the gid root process
+    pub exepath: PathBuf,
+    ///  Did the root exe file still existed (at the moment of this specific *DriverMessage* operation)?
+    pub exe_still_exists: bool,
+    /// The type of the drive the file is on, see [`VolumeResolver`](crate::volume::VolumeResolver)
+    #[serde(default)]
+    pub drive_type: DriveType,
+}
+
+impl RuntimeFeatures {
+    pub fn new() -> RuntimeFeatures {
+        RuntimeFeatures {
+            exepath: PathBuf::new(),
+            exe_still_exists: true,
+            drive_type: DriveType::DriveUnknown,
+        }
+    }
+}
+
+impl Default for RuntimeFeatures {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The C object returned by the minifilter, available through [`ReplyIrp`].
+/// It is low level and use C pointers logic which is not always compatible with RUST (in particular
+/// the lifetime of `*next`). That's why we convert it asap to a plain Rust [`IOMessage`] object.
+///
+/// `next` is null `(0x0)` when there is no [`IOMessage`] remaining.
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+pub struct CDriverMsg {
+    pub extension: [u16; 12],
+    pub file_id: FILE_ID_INFO,
+    pub mem_sized_used: c_ulonglong,
+    pub entropy: f64,
+    pub pid: u32,
+    pub irp_op: c_uchar,
+    pub is_entropy_calc: u8,
+    pub file_change: c_uchar,
+    pub file_location_info: c_uchar,
+    pub filepath: UnicodeString,
+    pub gid: c_ulonglong,
+    /// `KeQuerySystemTimePrecise` when the operation was caught
+    pub time: i64,
+    /// See [`IOMessage::sequence`]
+    pub sequence: c_ulonglong,
+    /// null (0x0) when there is no [`IOMessage`] remaining
+    pub next: *const CDriverMsg,
+}
+
+/// A [`CDriverMsg`] copied out of the reply buffer by a [`ReplyDecoder`]: the file path is owned
+/// and there are no pointers left.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DriverMsg {
+    pub extension: [u16; 12],
+    pub file_id_vsn: c_ulonglong,
+    pub file_id_id: [u8; 16],
+    pub mem_sized_used: c_ulonglong,
+    pub entropy: f64,
+    pub pid: u32,
+    pub irp_op: c_uchar,
+    pub is_entropy_calc: u8,
+    pub file_change: c_uchar,
+    pub file_location_info: c_uchar,
+    /// The `UNICODE_STRING` buffer, without terminating NUL.
+    pub filepath: Vec<u16>,
+    pub gid: c_ulonglong,
+    pub time: i64,
+    pub sequence: c_ulonglong,
+}
+
+impl DriverMsg {
+    /// The file path, up to the first NUL if any.
+    pub fn filepath_string(&self) -> String {
+        let end = self
+            .filepath
+            .iter()
+            .position(|c| *c == 0)
+            .unwrap_or(self.filepath.len());
+        String::from_utf16_lossy(&self.filepath[..end])
+    }
+}
+
+/// To iterate easily over a collection of [`IOMessage`] received from the minifilter, before they are
+/// converted to [`IOMessage`].
+///
+/// The whole reply is decoded by [`new`](Self::new): a malformed buffer yields no message at all.
+#[repr(C)]
+pub struct CDriverMsgs {
+    drivermsgs: Vec<DriverMsg>,
+    index: usize,
+}
+
+impl CDriverMsgs {
+    /// Decodes `reply`, the buffer filled by [`get_irp`](crate::driver_comm::Driver::get_irp).
+    pub fn new(reply: &[u8]) -> Result<CDriverMsgs, DecodeError> {
+        Ok(CDriverMsgs {
+            drivermsgs: ReplyDecoder::new(reply)?.collect::<Result<_, _>>()?,
+            index: 0,
+        })
+    }
+}
+
+impl Iterator for CDriverMsgs {
+    type Item = DriverMsg;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.index == self.drivermsgs.len() {
+            None
+        } else {
+            let res = self.drivermsgs[self.index].clone();
+            self.index += 1;
+            Some(res)
+        }
+    }
+}
@@ -0,0 +1,464 @@
+//! The paths reported by the minifilter, in whatever form it got them.
+//!
+//! File paths come in the device form (`\Device\HarddiskVolume3\Users\...`), and images of
+//! processes or user input may be `\??\C:\...`, `\\?\C:\...`, `C:\...` or network paths
+//! (`\\server\share\...`, `\Device\Mup\server\share\...`). A [`NtPath`] parses all of them, and
+//! converts them to DOS paths (`C:\Users\...`) with a [`DeviceLetters`] mapping.
+//!
+//! ```
+//! use std::collections::HashMap;
+//! use minifilter_rs::shared_def::nt_path::NtPath;
+//!
+//! let letters = HashMap::from([(r"\Device\HarddiskVolume3".to_string(), 'C')]);
+//! let path = NtPath::parse(r"\Device\HarddiskVolume3\Users\Dev\notes.txt:secret:$DATA").unwrap();
+//! assert_eq!(path.extension(), Some("txt"));
+//! assert_eq!(path.stream(), Some("secret"));
+//! assert_eq!(path.to_dos(&letters).unwrap(), r"C:\Users\Dev\notes.txt:secret");
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Debug;
+
+use crate::driver_comm::scan_scope::{DosDevices, DosDevicesLive};
+
+/// Resolves a device to the drive letter it is mounted on.
+pub trait DeviceLetters: Debug + Send {
+    /// The drive letter of `device` (e.g. `C` for `\Device\HarddiskVolume3`), if any.
+    fn letter_of(&self, device: &str) -> Option<char>;
+}
+
+/// A fixed mapping, e.g. `{r"\Device\HarddiskVolume3": 'C'}`. Devices are compared
+/// case-insensitively.
+impl DeviceLetters for HashMap<String, char> {
+    fn letter_of(&self, device: &str) -> Option<char> {
+        self.iter()
+            .find(|(known, _)| known.eq_ignore_ascii_case(device))
+            .map(|(_, letter)| letter.to_ascii_uppercase())
+    }
+}
+
+/// The drives of a [`DosDevices`], listed once: a device mounted on several letters gets the first
+/// one.
+#[derive(Debug, Default)]
+pub struct DeviceLetterCache {
+    letters: HashMap<String, char>,
+}
+
+impl DeviceLetterCache {
+    /// The drives of this machine, with `QueryDosDevice`.
+    pub fn live() -> DeviceLetterCache {
+        DeviceLetterCache::new(&DosDevicesLive)
+    }
+
+    pub fn new(dos_devices: &dyn DosDevices) -> DeviceLetterCache {
+        let mut letters = HashMap::new();
+        for letter in 'A'..='Z' {
+            if let Some(device) = dos_devices.device_of(letter) {
+                letters.entry(device.to_ascii_lowercase()).or_insert(letter);
+            }
+        }
+        DeviceLetterCache { letters }
+    }
+}
+
+impl DeviceLetters for DeviceLetterCache {
+    fn letter_of(&self, device: &str) -> Option<char> {
+        self.letters.get(&device.to_ascii_lowercase()).copied()
+    }
+}
+
+/// What a [`NtPath`] is relative to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NtRoot {
+    /// `C:\`, also from `\??\C:\` and `\\?\C:\`. The letter is uppercase.
+    Drive(char),
+    /// `\Device\HarddiskVolume3`, also from `\\?\GLOBALROOT\Device\HarddiskVolume3`.
+    Device(String),
+    /// `\\server\share`, also from `\??\UNC\server\share`, `\\?\UNC\server\share` and
+    /// `\Device\Mup\server\share`.
+    Unc { server: String, share: String },
+}
+
+/// An absolute path, split into its root, its components and the alternate data stream of its
+/// last component, if any.
+///
+/// `/` are read as `\`, repeated separators are merged and `.` components are dropped. Relative
+/// paths, `..` components and unknown `\\?\` namespaces (e.g. `\\?\Volume{...}`) are rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NtPath {
+    root: NtRoot,
+    components: Vec<String>,
+    stream: Option<String>,
+}
+
+impl NtPath {
+    pub fn parse(path: &str) -> Option<NtPath> {
+        let path = path.replace('/', "\\");
+        let (root, parts) = parse_root(&path)?;
+
+        let mut components = Vec::new();
+        for component in parts.into_iter().filter(|c| *c != ".") {
+            if component == ".." {
+                return None;
+            }
+            components.push(component.to_string());
+        }
+
+        // file.txt:stream or file.txt:stream:$DATA
+        let mut stream = None;
+        if let Some(last) = components.last_mut() {
+            if let Some((name, rest)) = last.split_once(':') {
+                let name_len = name.len();
+                let stream_name = rest.split(':').next().unwrap_or_default();
+                stream = (!stream_name.is_empty()).then(|| stream_name.to_string());
+                last.truncate(name_len);
+                if last.is_empty() {
+                    return None;
+                }
+            }
+        }
+
+        Some(NtPath {
+            root,
+            components,
+            stream,
+        })
+    }
+
+    pub fn root(&self) -> &NtRoot {
+        &self.root
+    }
+
+    /// The components after the root, without the stream.
+    pub fn components(&self) -> &[String] {
+        &self.components
+    }
+
+    /// The alternate data stream, without its `:$DATA` type.
+    pub fn stream(&self) -> Option<&str> {
+        self.stream.as_deref()
+    }
+
+    pub fn file_name(&self) -> Option<&str> {
+        self.components.last().map(String::as_str)
+    }
+
+    /// The extension of the [`file_name`](Self::file_name), after its last `.`. Dot files
+    /// (`.gitignore`) and names ending with a `.` have none.
+    pub fn extension(&self) -> Option<&str> {
+        let name = self.file_name()?;
+        match name.rsplit_once('.') {
+            Some((stem, extension)) if !stem.is_empty() && !extension.is_empty() => Some(extension),
+            _ => None,
+        }
+    }
+
+    pub fn is_unc(&self) -> bool {
+        matches!(self.root, NtRoot::Unc { .. })
+    }
+
+    /// Whether this path is `other` or inside it, ignoring the case. Both must have the same
+    /// root: compare their [`to_dos`](Self::to_dos) forms otherwise.
+    pub fn starts_with(&self, other: &NtPath) -> bool {
+        let same_root = match (&self.root, &other.root) {
+            (NtRoot::Drive(a), NtRoot::Drive(b)) => a == b,
+            (NtRoot::Device(a), NtRoot::Device(b)) => a.eq_ignore_ascii_case(b),
+            (
+                NtRoot::Unc { server, share },
+                NtRoot::Unc {
+                    server: other_server,
+                    share: other_share,
+                },
+            ) => {
+                server.eq_ignore_ascii_case(other_server) && share.eq_ignore_ascii_case(other_share)
+            }
+            _ => false,
+        };
+        same_root
+            && self.components.len() >= other.components.len()
+            && self
+                .components
+                .iter()
+                .zip(&other.components)
+                .all(|(a, b)| a.eq_ignore_ascii_case(b))
+    }
+
+    /// The same path on a drive root, `None` if its device has no letter. UNC paths are kept
+    /// as `\\server\share\...`.
+    pub fn to_dos_path(&self, letters: &dyn DeviceLetters) -> Option<NtPath> {
+        let root = match &self.root {
+            NtRoot::Device(device) => NtRoot::Drive(letters.letter_of(device)?),
+            root => root.clone(),
+        };
+        Some(NtPath {
+            root,
+            components: self.components.clone(),
+            stream: self.stream.clone(),
+        })
+    }
+
+    /// The same path on the device of its drive, the inverse of [`to_dos_path`](Self::to_dos_path):
+    /// `None` if the drive has no device. UNC paths are kept as `\\server\share\...`.
+    pub fn to_device_path(&self, dos_devices: &dyn DosDevices) -> Option<NtPath> {
+        let root = match &self.root {
+            NtRoot::Drive(letter) => {
+                let device = dos_devices.device_of(*letter)?;
+                NtRoot::Device(device.trim_end_matches('\\').to_string())
+            }
+            root => root.clone(),
+        };
+        Some(NtPath {
+            root,
+            components: self.components.clone(),
+            stream: self.stream.clone(),
+        })
+    }
+
+    /// `C:\Users\Dev\notes.txt` or `\\server\share\notes.txt`, see
+    /// [`to_dos_path`](Self::to_dos_path).
+    pub fn to_dos(&self, letters: &dyn DeviceLetters) -> Option<String> {
+        self.to_dos_path(letters).map(|path| path.to_string())
+    }
+
+    /// A path Win32 functions such as [`std::fs::metadata`] accept without any mapping:
+    /// `\Device\...` paths become `\\?\GLOBALROOT\Device\...`.
+    pub fn to_win32(&self) -> String {
+        match self.root {
+            NtRoot::Device(_) => format!(r"\\?\GLOBALROOT{self}"),
+            _ => self.to_string(),
+        }
+    }
+}
+
+/// The canonical form of each root: `C:\...`, `\Device\...` or `\\server\share\...`, then
+/// `:stream` if any.
+impl fmt::Display for NtPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.root {
+            NtRoot::Drive(letter) => write!(f, "{letter}:")?,
+            NtRoot::Device(device) => write!(f, "{device}")?,
+            NtRoot::Unc { server, share } => write!(f, r"\\{server}\{share}")?,
+        }
+        if self.components.is_empty() {
+            return write!(f, "\\");
+        }
+        for component in &self.components {
+            write!(f, "\\{component}")?;
+        }
+        if let Some(stream) = &self.stream {
+            write!(f, ":{stream}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The root of `path` and the components which follow it.
+fn parse_root(path: &str) -> Option<(NtRoot, Vec<&str>)> {
+    let namespaced = strip_prefix(path, r"\\?\")
+        .or_else(|| strip_prefix(path, r"\??\"))
+        .or_else(|| strip_prefix(path, r"\\.\"));
+    if let Some(rest) = namespaced {
+        if let Some(letter) = drive(rest) {
+            return Some((NtRoot::Drive(letter), parts(&rest[2..])));
+        }
+        if let Some(share) = strip_prefix(rest, r"UNC\") {
+            return unc(parts(share));
+        }
+        return strip_prefix(rest, "GLOBALROOT")
+            .filter(|device| strip_prefix(device, r"\Device\").is_some())
+            .and_then(parse_root);
+    }
+
+    if let Some(share) = strip_prefix(path, r"\Device\Mup\") {
+        return unc(parts(share));
+    }
+    if strip_prefix(path, r"\Device\").is_some() {
+        let mut parts = parts(path);
+        if parts.len() < 2 {
+            return None;
+        }
+        let device = format!(r"\{}\{}", parts[0], parts[1]);
+        return Some((NtRoot::Device(device), parts.split_off(2)));
+    }
+    if let Some(share) = strip_prefix(path, r"\\") {
+        return unc(parts(share));
+    }
+    let letter = drive(path)?;
+    Some((NtRoot::Drive(letter), parts(&path[2..])))
+}
+
+/// `path` without `prefix`, ignoring the case.
+fn strip_prefix<'a>(path: &'a str, prefix: &str) -> Option<&'a str> {
+    path.get(..prefix.len())
+        .filter(|start| start.eq_ignore_ascii_case(prefix))
+        .map(|_| &path[prefix.len()..])
+}
+
+/// The uppercase letter of `C:` or `C:\...`.
+fn drive(path: &str) -> Option<char> {
+    let mut chars = path.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some(letter), Some(':'), None | Some('\\')) if letter.is_ascii_alphabetic() => {
+            Some(letter.to_ascii_uppercase())
+        }
+        _ => None,
+    }
+}
+
+fn parts(path: &str) -> Vec<&str> {
+    path.split('\\').filter(|c| !c.is_empty()).collect()
+}
+
+/// `server`, `share`, then the components of the path.
+fn unc(mut parts: Vec<&str>) -> Option<(NtRoot, Vec<&str>)> {
+    if parts.len() < 2 {
+        return None;
+    }
+    let root = NtRoot::Unc {
+        server: parts[0].to_string(),
+        share: parts[1].to_string(),
+    };
+    Some((root, parts.split_off(2)))
+}
+
+#[cfg(test)]
+#[doc(hidden)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::shared_def::nt_path::{DeviceLetterCache, DeviceLetters, NtPath, NtRoot};
+
+    fn letters() -> HashMap<String, char> {
+        HashMap::from([
+            (r"\Device\HarddiskVolume3".to_string(), 'C'),
+            (r"\Device\HarddiskVolume5".to_string(), 'e'),
+        ])
+    }
+
+    #[test]
+    fn test_parse_forms() {
+        let cases = [
+            (r"C:\Users\Dev\a.txt", r"C:\Users\Dev\a.txt"),
+            (r"c:/Users//Dev/./a.txt", r"C:\Users\Dev\a.txt"),
+            (r"\??\C:\Users\Dev\a.txt", r"C:\Users\Dev\a.txt"),
+            (r"\\?\c:\Users\Dev\a.txt", r"C:\Users\Dev\a.txt"),
+            (
+                r"\Device\HarddiskVolume3\Users\a.txt",
+                r"\Device\HarddiskVolume3\Users\a.txt",
+            ),
+            (
+                r"\\?\GLOBALROOT\Device\HarddiskVolume3\a.txt",
+                r"\Device\HarddiskVolume3\a.txt",
+            ),
+            (r"\\server\share\dir\a.txt", r"\\server\share\dir\a.txt"),
+            (
+                r"\\?\UNC\server\share\dir\a.txt",
+                r"\\server\share\dir\a.txt",
+            ),
+            (r"\??\UNC\server\share\a.txt", r"\\server\share\a.txt"),
+            (
+                r"\Device\Mup\server\share\dir\a.txt",
+                r"\\server\share\dir\a.txt",
+            ),
+            (r"C:\", r"C:\"),
+            (r"\Device\HarddiskVolume3", r"\Device\HarddiskVolume3\"),
+        ];
+        for (path, expected) in cases {
+            let parsed = NtPath::parse(path).unwrap_or_else(|| panic!("{path} not parsed"));
+            assert_eq!(parsed.to_string(), expected, "{path}");
+        }
+
+        for path in [
+            r"Users\Dev\a.txt",
+            r"C:\Users\..\Windows",
+            r"\\?\Volume{1b3b1146-4076-11e1-84aa-806e6f6e6963}\a.txt",
+            r"\\server",
+            r"\Device\Mup\server",
+            r"\Device\",
+            "",
+        ] {
+            assert_eq!(NtPath::parse(path), None, "{path}");
+        }
+    }
+
+    #[test]
+    fn test_streams_and_extensions() {
+        let path =
+            NtPath::parse(r"\Device\HarddiskVolume3\Users\notes.tar.gz:Zone.Identifier:$DATA")
+                .unwrap();
+        assert_eq!(path.file_name(), Some("notes.tar.gz"));
+        assert_eq!(path.extension(), Some("gz"));
+        assert_eq!(path.stream(), Some("Zone.Identifier"));
+        assert_eq!(path.components(), &["Users", "notes.tar.gz"]);
+
+        // The unnamed stream is the file itself
+        let path = NtPath::parse(r"C:\a.txt::$DATA").unwrap();
+        assert_eq!(path.stream(), None);
+        assert_eq!(path.to_string(), r"C:\a.txt");
+
+        for (name, extension) in [
+            (r"C:\.gitignore", None),
+            (r"C:\README", None),
+            (r"C:\file.", None),
+            (r"C:\archive.7z", Some("7z")),
+        ] {
+            assert_eq!(
+                NtPath::parse(name).unwrap().extension(),
+                extension,
+                "{name}"
+            );
+        }
+        assert_eq!(NtPath::parse(r"C:\").unwrap().file_name(), None);
+        assert_eq!(NtPath::parse(r"C:\:stream"), None);
+    }
+
+    #[test]
+    fn test_to_dos() {
+        let letters = letters();
+        let dos = |path: &str| NtPath::parse(path).unwrap().to_dos(&letters);
+        assert_eq!(
+            dos(r"\Device\HarddiskVolume3\Users\Dev\a.txt").as_deref(),
+            Some(r"C:\Users\Dev\a.txt")
+        );
+        assert_eq!(
+            dos(r"\device\harddiskvolume5\a.txt:s").as_deref(),
+            Some(r"E:\a.txt:s")
+        );
+        assert_eq!(dos(r"\Device\HarddiskVolume9\a.txt"), None);
+        assert_eq!(
+            dos(r"\Device\Mup\server\share\a.txt").as_deref(),
+            Some(r"\\server\share\a.txt")
+        );
+        assert_eq!(dos(r"\??\D:\a.txt").as_deref(), Some(r"D:\a.txt"));
+
+        let path = NtPath::parse(r"\Device\HarddiskVolume3\Windows\System32\cmd.exe").unwrap();
+        let system32 = NtPath::parse(r"C:\windows\system32").unwrap();
+        assert!(!path.starts_with(&system32));
+        assert!(path.to_dos_path(&letters).unwrap().starts_with(&system32));
+        assert!(!NtPath::parse(r"C:\Windows\System32x\a.exe")
+            .unwrap()
+            .starts_with(&system32));
+        assert_eq!(
+            path.to_win32(),
+            r"\\?\GLOBALROOT\Device\HarddiskVolume3\Windows\System32\cmd.exe"
+        );
+        assert_eq!(
+            path.root(),
+            &NtRoot::Device(r"\Device\HarddiskVolume3".to_string())
+        );
+    }
+
+    #[test]
+    fn test_device_letter_cache() {
+        let dos_devices = HashMap::from([
+            ('C', r"\Device\HarddiskVolume3".to_string()),
+            ('D', r"\Device\CdRom0".to_string()),
+            ('Z', r"\Device\HarddiskVolume3".to_string()),
+        ]);
+        let cache = DeviceLetterCache::new(&dos_devices);
+        assert_eq!(cache.letter_of(r"\Device\HarddiskVolume3"), Some('C'));
+        assert_eq!(cache.letter_of(r"\DEVICE\CDROM0"), Some('D'));
+        assert_eq!(cache.letter_of(r"\Device\HarddiskVolume4"), None);
+    }
+}
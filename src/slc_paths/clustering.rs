@@ -200,21 +200,19 @@ pub fn distance(x: &Path, y: &Path) -> f32 {
         path2 = x.parent();
     }
 
-    while path1 != None {
-        if path2.is_none() {
-            path1 = path1.unwrap().parent();
-            dist += 1.0;
-        } else if path1.unwrap().as_os_str() == path2.unwrap().as_os_str() {
-            return dist;
-        } else {
-            path1 = path1.unwrap().parent();
-            dist += 1.0;
+    while let Some(p1) = path1 {
+        match path2 {
+            Some(p2) if p1.as_os_str() == p2.as_os_str() => return dist,
+            _ => {
+                path1 = p1.parent();
+                dist += 1.0;
+            }
         }
     }
 
     // path1 & path2 are on different root disk
-    while path2 != None {
-        path2 = path2.unwrap().parent();
+    while let Some(p2) = path2 {
+        path2 = p2.parent();
         dist += 1.0;
     }
     dist * dist
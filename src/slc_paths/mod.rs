@@ -69,21 +69,22 @@ pub mod clustering;
 #[doc(hidden)]
 mod tests {
     // use crate::clustering::clustering;
-    use crate::clustering::clustering_from_file;
+    use crate::slc_paths::clustering::clustering_from_file;
     // use std::time::Instant;
 
     #[test]
     fn test_tor_file() {
-        let nom_fichier = r".\src\testdata\tor.txt";
+        let nom_fichier = "src/slc_paths/testdata/tor.txt";
         let clusters = clustering_from_file(nom_fichier);
         assert!(!clusters.is_empty());
         assert_eq!(clusters.iter().map(|c| c.size()).sum::<usize>(), 17);
     }
 
     #[test]
+    #[cfg_attr(not(windows), ignore = "relies on Windows path semantics")]
     fn test_eclipse_file() {
         // let start = Instant::now();
-        let nom_fichier = r".\src\testdata\eclipse.txt";
+        let nom_fichier = "src/slc_paths/testdata/eclipse.txt";
         let clusters = clustering_from_file(nom_fichier);
         assert!(clusters.iter().any(|c| c.root() == r"C:\Users\lesco"));
         assert_eq!(clusters.len(), 4);
@@ -0,0 +1,531 @@
+//! Recording of the minifilter activity, to analyse it again later on any OS.
+//!
+//! A [`TraceWriter`] records the [`IOMessage`]s and [`ProcessEvent`]s given to a
+//! [`Worker`](crate::worker::Worker), and the executables looked up for their gids through a
+//! [`RecordingExepath`]. A [`TraceReader`] reads them back in the same order, and with the
+//! `replay` feature [replays](TraceReader::replay) them to a worker: a detonation recorded once in
+//! a lab gives the same records every time it is replayed.
+//!
+//! ## Format
+//! All integers are little-endian, `varint` being unsigned LEB128.
+//! - A header of [`HEADER_SIZE`] bytes: [`TRACE_MAGIC`], the [`TRACE_VERSION`] (`u16`), 2
+//!   reserved bytes, the [`PROTOCOL_VERSION`](crate::driver_comm::version::PROTOCOL_VERSION) of
+//!   the recording app (`u32`), and its start time (`u64`, nanoseconds since the Unix epoch).
+//! - Chunks of records, each one preceded by its number of records and its length in bytes (`u32`
+//!   both). A record starts with its kind ([`TraceRecord`]).
+//! - The chunk index: the offset (`u64`), length and number of records (`u32` both) of each chunk.
+//! - A trailer of [`TRAILER_SIZE`] bytes: the offset of the index (`u64`), the number of chunks
+//!   (`u32`) and [`INDEX_MAGIC`].
+//!
+//! A trace whose recording was interrupted has no index: it is read up to its last complete
+//! chunk.
+
+mod reader;
+mod writer;
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub use reader::{TraceHeader, TraceReader, TraceRecords};
+pub use writer::{RecordingExepath, TraceWriter, DEFAULT_CHUNK_RECORDS};
+
+use crate::driver_comm::DriveType;
+use crate::shared_def::{IOMessage, ProcessEvent, ProcessEventKind, RuntimeFeatures};
+
+/// First bytes of a trace file.
+pub const TRACE_MAGIC: [u8; 8] = *b"MFTRACE\0";
+
+/// Version of the trace format, bumped on every change of the records or of their layout.
+pub const TRACE_VERSION: u16 = 1;
+
+/// Last bytes of a trace file, once its index is written.
+pub const INDEX_MAGIC: [u8; 4] = *b"MFTI";
+
+pub const HEADER_SIZE: usize = 24;
+
+pub const TRAILER_SIZE: usize = 16;
+
+/// Size of an entry of the chunk index.
+const INDEX_ENTRY_SIZE: usize = 16;
+
+/// Size of the header of a chunk.
+const CHUNK_HEADER_SIZE: usize = 8;
+
+const RECORD_IO: u8 = 0;
+const RECORD_EXEPATH: u8 = 1;
+const RECORD_PROCESS: u8 = 2;
+
+/// What a trace is made of, in the order it was recorded.
+#[derive(Debug, Clone)]
+pub enum TraceRecord {
+    /// An [`IOMessage`] as processed by the worker, enrichments included.
+    Io(IOMessage),
+    /// The result of an [`Exepath`](crate::worker::process_record_handling::Exepath) lookup, made
+    /// before the [`IOMessage`] of `pid` that needed it.
+    Exepath {
+        pid: u32,
+        gid: u64,
+        exepath: Option<PathBuf>,
+    },
+    Process(ProcessEvent),
+}
+
+/// A chunk of records, see [`TraceReader::chunks`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ChunkInfo {
+    /// Offset of the chunk header in the file
+    pub offset: u64,
+    /// Length of the records, the chunk header excluded
+    pub len: u32,
+    pub records: u32,
+}
+
+/// Why a trace could not be written or read.
+#[derive(Debug)]
+pub enum TraceError {
+    Io(io::Error),
+    /// The file does not start with [`TRACE_MAGIC`].
+    NotATrace,
+    /// The trace was written by a newer version of this crate.
+    UnsupportedVersion(u16),
+    /// A record or the index cannot be decoded.
+    Corrupted {
+        offset: u64,
+        reason: &'static str,
+    },
+}
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TraceError::Io(e) => write!(f, "trace i/o error: {e}"),
+            TraceError::NotATrace => write!(f, "not a trace file"),
+            TraceError::UnsupportedVersion(version) => write!(
+                f,
+                "trace version {version} is not supported (up to {TRACE_VERSION})"
+            ),
+            TraceError::Corrupted { offset, reason } => {
+                write!(f, "trace corrupted at offset {offset}: {reason}")
+            }
+        }
+    }
+}
+
+impl Error for TraceError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TraceError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for TraceError {
+    fn from(e: io::Error) -> Self {
+        TraceError::Io(e)
+    }
+}
+
+impl TraceRecord {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            TraceRecord::Io(iomsg) => {
+                buf.push(RECORD_IO);
+                encode_iomsg(iomsg, buf);
+            }
+            TraceRecord::Exepath { pid, gid, exepath } => {
+                buf.push(RECORD_EXEPATH);
+                put_varint(buf, *pid as u64);
+                put_varint(buf, *gid);
+                match exepath {
+                    Some(exepath) => {
+                        buf.push(1);
+                        put_str(buf, &exepath.to_string_lossy());
+                    }
+                    None => buf.push(0),
+                }
+            }
+            TraceRecord::Process(event) => {
+                buf.push(RECORD_PROCESS);
+                buf.push(match event.kind {
+                    ProcessEventKind::Created => 0,
+                    ProcessEventKind::Exited => 1,
+                });
+                put_varint(buf, event.pid as u64);
+                put_varint(buf, event.parent_pid as u64);
+                put_varint(buf, event.gid);
+                put_str(buf, &event.image_path);
+                put_time(buf, event.time);
+                put_varint(buf, event.alive_pids as u64);
+            }
+        }
+    }
+
+    fn decode(cursor: &mut Cursor) -> Result<TraceRecord, TraceError> {
+        match cursor.u8()? {
+            RECORD_IO => Ok(TraceRecord::Io(decode_iomsg(cursor)?)),
+            RECORD_EXEPATH => Ok(TraceRecord::Exepath {
+                pid: cursor.varint_u32()?,
+                gid: cursor.varint()?,
+                exepath: match cursor.u8()? {
+                    0 => None,
+                    _ => Some(PathBuf::from(cursor.string()?)),
+                },
+            }),
+            RECORD_PROCESS => Ok(TraceRecord::Process(ProcessEvent {
+                kind: match cursor.u8()? {
+                    0 => ProcessEventKind::Created,
+                    _ => ProcessEventKind::Exited,
+                },
+                pid: cursor.varint_u32()?,
+                parent_pid: cursor.varint_u32()?,
+                gid: cursor.varint()?,
+                image_path: cursor.string()?,
+                time: cursor.time()?,
+                alive_pids: cursor.varint_u32()?,
+            })),
+            _ => Err(cursor.corrupted("unknown record kind")),
+        }
+    }
+}
+
+/// The extension is kept up to its NUL, and the entropy only if set: it is 0 otherwise.
+fn encode_iomsg(iomsg: &IOMessage, buf: &mut Vec<u8>) {
+    let extension_len = iomsg
+        .extension
+        .iter()
+        .position(|&c| c == 0)
+        .unwrap_or(iomsg.extension.len());
+    buf.push(extension_len as u8);
+    for c in &iomsg.extension[..extension_len] {
+        buf.extend_from_slice(&c.to_le_bytes());
+    }
+    buf.extend_from_slice(&iomsg.file_id_vsn.to_le_bytes());
+    buf.extend_from_slice(&iomsg.file_id_id);
+    put_varint(buf, iomsg.mem_sized_used);
+    if iomsg.entropy.to_bits() == 0 {
+        buf.push(0);
+    } else {
+        buf.push(1);
+        buf.extend_from_slice(&iomsg.entropy.to_le_bytes());
+    }
+    put_varint(buf, iomsg.pid as u64);
+    buf.extend_from_slice(&[
+        iomsg.irp_op,
+        iomsg.is_entropy_calc,
+        iomsg.file_change,
+        iomsg.file_location_info,
+    ]);
+    put_str(buf, &iomsg.filepathstr);
+    put_varint(buf, iomsg.gid);
+    put_str(buf, &iomsg.runtime_features.exepath.to_string_lossy());
+    buf.push(iomsg.runtime_features.exe_still_exists as u8);
+    buf.push(iomsg.runtime_features.drive_type as u8);
+    put_varint(buf, zigzag(iomsg.file_size));
+    put_time(buf, iomsg.time);
+    put_varint(buf, iomsg.sequence);
+    put_time(buf, iomsg.received);
+}
+
+fn decode_iomsg(cursor: &mut Cursor) -> Result<IOMessage, TraceError> {
+    let mut extension = [0u16; 12];
+    let extension_len = cursor.u8()? as usize;
+    if extension_len > extension.len() {
+        return Err(cursor.corrupted("extension too long"));
+    }
+    for c in &mut extension[..extension_len] {
+        *c = u16::from_le_bytes(cursor.array()?);
+    }
+    let file_id_vsn = u64::from_le_bytes(cursor.array()?);
+    let file_id_id = cursor.array()?;
+    let mem_sized_used = cursor.varint()?;
+    let entropy = match cursor.u8()? {
+        0 => 0.0,
+        _ => f64::from_le_bytes(cursor.array()?),
+    };
+    let pid = cursor.varint_u32()?;
+    let [irp_op, is_entropy_calc, file_change, file_location_info] = cursor.array()?;
+    Ok(IOMessage {
+        extension,
+        file_id_vsn,
+        file_id_id,
+        mem_sized_used,
+        entropy,
+        pid,
+        irp_op,
+        is_entropy_calc,
+        file_change,
+        file_location_info,
+        filepathstr: cursor.string()?,
+        gid: cursor.varint()?,
+        runtime_features: RuntimeFeatures {
+            exepath: PathBuf::from(cursor.string()?),
+            exe_still_exists: cursor.u8()? != 0,
+            drive_type: DriveType::from_win32(cursor.u8()? as u32),
+        },
+        file_size: unzigzag(cursor.varint()?),
+        time: cursor.time()?,
+        sequence: cursor.varint()?,
+        received: cursor.time()?,
+    })
+}
+
+fn put_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn put_str(buf: &mut Vec<u8>, s: &str) {
+    put_varint(buf, s.len() as u64);
+    buf.extend_from_slice(s.as_bytes());
+}
+
+fn put_time(buf: &mut Vec<u8>, time: SystemTime) {
+    put_varint(buf, time_to_nanos(time));
+}
+
+/// Times before the Unix epoch are recorded as the epoch.
+fn time_to_nanos(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn unzigzag(value: u64) -> i64 {
+    (value >> 1) as i64 ^ -((value & 1) as i64)
+}
+
+/// Reads the records of a chunk, `offset` being the position of `data` in the file.
+struct Cursor<'a> {
+    data: &'a [u8],
+    pos: usize,
+    offset: u64,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(data: &'a [u8], offset: u64) -> Cursor<'a> {
+        Cursor {
+            data,
+            pos: 0,
+            offset,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos == self.data.len()
+    }
+
+    fn corrupted(&self, reason: &'static str) -> TraceError {
+        TraceError::Corrupted {
+            offset: self.offset + self.pos as u64,
+            reason,
+        }
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], TraceError> {
+        if self.data.len() - self.pos < len {
+            return Err(self.corrupted("record truncated"));
+        }
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], TraceError> {
+        let mut array = [0u8; N];
+        array.copy_from_slice(self.bytes(N)?);
+        Ok(array)
+    }
+
+    fn u8(&mut self) -> Result<u8, TraceError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn varint(&mut self) -> Result<u64, TraceError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.u8()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(self.corrupted("varint too long"))
+    }
+
+    fn varint_u32(&mut self) -> Result<u32, TraceError> {
+        let value = self.varint()?;
+        u32::try_from(value).map_err(|_| self.corrupted("value out of range"))
+    }
+
+    fn string(&mut self) -> Result<String, TraceError> {
+        let len = self.varint()?;
+        let len = usize::try_from(len).map_err(|_| self.corrupted("string too long"))?;
+        let bytes = self.bytes(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| self.corrupted("string is not utf-8"))
+    }
+
+    fn time(&mut self) -> Result<SystemTime, TraceError> {
+        Ok(UNIX_EPOCH + Duration::from_nanos(self.varint()?))
+    }
+}
+
+#[cfg(test)]
+#[doc(hidden)]
+mod tests {
+    use std::io::Cursor;
+    use std::path::PathBuf;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use crate::driver_comm::{DriveType, IrpMajorOp};
+    use crate::shared_def::{IOMessage, ProcessEvent, ProcessEventKind, RuntimeFeatures};
+    use crate::trace::{TraceError, TraceReader, TraceRecord, TraceWriter, HEADER_SIZE};
+
+    fn iomsg(sequence: u64, filepathstr: &str) -> IOMessage {
+        let time = UNIX_EPOCH + Duration::from_nanos(1_700_000_000_123_456_789 + sequence);
+        IOMessage {
+            extension: [116, 120, 116, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            file_id_vsn: 0x1234,
+            file_id_id: [sequence as u8; 16],
+            mem_sized_used: 4096,
+            entropy: if sequence % 2 == 0 { 7.25 } else { 0.0 },
+            pid: 1200,
+            irp_op: IrpMajorOp::IrpWrite as u8,
+            is_entropy_calc: (sequence % 2 == 0) as u8,
+            file_change: 2,
+            file_location_info: 0,
+            filepathstr: filepathstr.to_string(),
+            gid: 7,
+            runtime_features: RuntimeFeatures {
+                exepath: PathBuf::from(r"\Device\HarddiskVolume3\Tools\bad.exe"),
+                exe_still_exists: true,
+                drive_type: DriveType::DriveRemote,
+            },
+            file_size: if sequence == 0 { -1 } else { 65536 },
+            time,
+            sequence,
+            received: time + Duration::from_micros(300),
+        }
+    }
+
+    fn trace(chunk_records: u32, iomsgs: usize) -> Vec<u8> {
+        let mut writer = TraceWriter::new(Vec::new()).unwrap();
+        writer = writer.chunk_records(chunk_records);
+        writer
+            .record_process(&ProcessEvent {
+                kind: ProcessEventKind::Created,
+                pid: 1200,
+                parent_pid: 4,
+                gid: 7,
+                image_path: r"\Device\HarddiskVolume3\Tools\bad.exe".to_string(),
+                time: UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+                alive_pids: 1,
+            })
+            .unwrap();
+        writer.record_exepath(1200, 7, None);
+        for i in 0..iomsgs {
+            writer
+                .record_iomsg(&iomsg(i as u64, &format!(r"C:\Users\Dev\{i}.txt")))
+                .unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn test_trace_round_trip() {
+        let mut reader = TraceReader::new(Cursor::new(trace(4, 10))).unwrap();
+        assert_eq!(reader.header().version, 1);
+        assert_eq!(reader.chunks().len(), 3);
+        assert_eq!(reader.chunks().iter().map(|c| c.records).sum::<u32>(), 12);
+
+        let records: Vec<TraceRecord> = reader.records().map(Result::unwrap).collect();
+        assert_eq!(records.len(), 12);
+        assert!(
+            matches!(&records[0], TraceRecord::Process(event) if event.pid == 1200 && event.alive_pids == 1)
+        );
+        assert!(matches!(
+            &records[1],
+            TraceRecord::Exepath {
+                pid: 1200,
+                gid: 7,
+                exepath: None
+            }
+        ));
+        for (i, record) in records[2..].iter().enumerate() {
+            let TraceRecord::Io(read) = record else {
+                panic!("unexpected {record:?}");
+            };
+            let written = iomsg(i as u64, &format!(r"C:\Users\Dev\{i}.txt"));
+            assert_eq!(
+                serde_json::to_value(read).unwrap(),
+                serde_json::to_value(&written).unwrap()
+            );
+        }
+
+        // Random access through the index
+        let chunk = reader.read_chunk(2).unwrap();
+        assert_eq!(chunk.len(), 4);
+        assert!(matches!(&chunk[0], TraceRecord::Io(iomsg) if iomsg.sequence == 6));
+    }
+
+    #[test]
+    fn test_interrupted_trace_read_up_to_last_chunk() {
+        let full = trace(4, 10);
+        let index_offset = full.len() - 16 - 3 * 16;
+        // The last chunk is incomplete, and there is no index
+        let mut interrupted = full[..index_offset - 5].to_vec();
+        let mut reader = TraceReader::new(Cursor::new(interrupted.clone())).unwrap();
+        assert_eq!(reader.chunks().len(), 2);
+        assert_eq!(reader.records().count(), 8);
+
+        interrupted[0] = b'X';
+        assert!(matches!(
+            TraceReader::new(Cursor::new(interrupted)),
+            Err(TraceError::NotATrace)
+        ));
+        let mut newer = full[..HEADER_SIZE].to_vec();
+        newer[8] = 2;
+        assert!(matches!(
+            TraceReader::new(Cursor::new(newer)),
+            Err(TraceError::UnsupportedVersion(2))
+        ));
+
+        // A corrupted count is an error, not a huge allocation
+        let mut corrupted = full[..HEADER_SIZE].to_vec();
+        corrupted.extend_from_slice(&u32::MAX.to_le_bytes());
+        corrupted.extend_from_slice(&0u32.to_le_bytes());
+        let mut reader = TraceReader::new(Cursor::new(corrupted)).unwrap();
+        assert_eq!(reader.chunks().len(), 1);
+        assert!(matches!(
+            reader.records().next(),
+            Some(Err(TraceError::Corrupted { offset: 24, .. }))
+        ));
+    }
+
+    #[cfg(feature = "replay")]
+    #[test]
+    fn test_replay_feeds_worker() {
+        use crate::worker::Worker;
+
+        let mut reader = TraceReader::new(Cursor::new(trace(4, 10))).unwrap();
+        let worker = reader.replay(Worker::new()).unwrap();
+        let precord = worker.precord(7).unwrap();
+        assert_eq!(precord.appname, "bad.exe");
+        assert_eq!(precord.ops_written, 10);
+        assert_eq!(precord.bytes_written, 40960);
+        assert_eq!(
+            precord.time_started,
+            UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+        );
+    }
+}
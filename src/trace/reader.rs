@@ -0,0 +1,270 @@
+use std::io::{Read, Seek, SeekFrom};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::trace::{
+    ChunkInfo, Cursor, TraceError, TraceRecord, CHUNK_HEADER_SIZE, HEADER_SIZE, INDEX_ENTRY_SIZE,
+    INDEX_MAGIC, TRACE_MAGIC, TRACE_VERSION, TRAILER_SIZE,
+};
+
+/// The header of a trace.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TraceHeader {
+    /// [`TRACE_VERSION`] of the writer
+    pub version: u16,
+    /// [`PROTOCOL_VERSION`](crate::driver_comm::version::PROTOCOL_VERSION) of the recording app
+    pub protocol_version: u32,
+    /// When the recording started
+    pub started: SystemTime,
+}
+
+/// Reads a trace written by a [`TraceWriter`](super::TraceWriter), see the [module](super)
+/// documentation.
+#[derive(Debug)]
+pub struct TraceReader<R: Read + Seek> {
+    input: R,
+    header: TraceHeader,
+    chunks: Vec<ChunkInfo>,
+}
+
+impl<R: Read + Seek> TraceReader<R> {
+    /// Reads the header and the chunk index, or finds the chunks if the trace has no index.
+    pub fn new(mut input: R) -> Result<TraceReader<R>, TraceError> {
+        let mut header = [0u8; HEADER_SIZE];
+        input.seek(SeekFrom::Start(0))?;
+        input
+            .read_exact(&mut header)
+            .map_err(|_| TraceError::NotATrace)?;
+        if header[..8] != TRACE_MAGIC {
+            return Err(TraceError::NotATrace);
+        }
+        let version = u16::from_le_bytes([header[8], header[9]]);
+        if version > TRACE_VERSION {
+            return Err(TraceError::UnsupportedVersion(version));
+        }
+        let header = TraceHeader {
+            version,
+            protocol_version: u32::from_le_bytes(header[12..16].try_into().unwrap()),
+            started: UNIX_EPOCH
+                + Duration::from_nanos(u64::from_le_bytes(header[16..24].try_into().unwrap())),
+        };
+        let chunks = match Self::read_index(&mut input)? {
+            Some(chunks) => chunks,
+            None => Self::scan_chunks(&mut input)?,
+        };
+        Ok(TraceReader {
+            input,
+            header,
+            chunks,
+        })
+    }
+
+    pub fn header(&self) -> &TraceHeader {
+        &self.header
+    }
+
+    pub fn chunks(&self) -> &[ChunkInfo] {
+        &self.chunks
+    }
+
+    /// The records of the chunk `index`.
+    pub fn read_chunk(&mut self, index: usize) -> Result<Vec<TraceRecord>, TraceError> {
+        let chunk = self.chunks[index];
+        // Each record takes at least one byte, a larger count is not to be allocated
+        if chunk.records > chunk.len {
+            return Err(TraceError::Corrupted {
+                offset: chunk.offset,
+                reason: "more records than bytes in the chunk",
+            });
+        }
+        let mut data = vec![0u8; chunk.len as usize];
+        let data_offset = chunk.offset + CHUNK_HEADER_SIZE as u64;
+        self.input.seek(SeekFrom::Start(data_offset))?;
+        self.input.read_exact(&mut data)?;
+        let mut cursor = Cursor::new(&data, data_offset);
+        let mut records = Vec::with_capacity(chunk.records as usize);
+        for _ in 0..chunk.records {
+            records.push(TraceRecord::decode(&mut cursor)?);
+        }
+        if !cursor.is_empty() {
+            return Err(cursor.corrupted("chunk longer than its records"));
+        }
+        Ok(records)
+    }
+
+    /// All the records, in the order they were recorded.
+    pub fn records(&mut self) -> TraceRecords<'_, R> {
+        TraceRecords {
+            reader: self,
+            chunk: 0,
+            records: Vec::new().into_iter(),
+        }
+    }
+
+    /// Processes the records with `worker`, as it was when they were recorded: the exepath
+    /// lookups give the recorded results, and the [`IOMessage`](crate::shared_def::IOMessage)s
+    /// are not enriched again. The worker is returned to look at its records.
+    #[cfg(feature = "replay")]
+    pub fn replay(
+        &mut self,
+        worker: crate::worker::Worker,
+    ) -> Result<crate::worker::Worker, TraceError> {
+        use crate::enrich::Enrichers;
+
+        let exepaths = replay::RecordedExepath::default();
+        let mut worker = worker
+            .exepath_handler(Box::new(exepaths.clone()))
+            .enrichers(Enrichers::new())
+            .build();
+        for record in self.records() {
+            match record? {
+                TraceRecord::Io(mut iomsg) => worker.process_io(&mut iomsg),
+                TraceRecord::Exepath { pid, gid, exepath } => exepaths.insert(pid, gid, exepath),
+                TraceRecord::Process(event) => {
+                    worker.process_event(&event);
+                }
+            }
+        }
+        Ok(worker)
+    }
+
+    /// `None` if the trace does not end with an index.
+    fn read_index(input: &mut R) -> Result<Option<Vec<ChunkInfo>>, TraceError> {
+        let len = input.seek(SeekFrom::End(0))?;
+        if len < (HEADER_SIZE + TRAILER_SIZE) as u64 {
+            return Ok(None);
+        }
+        let mut trailer = [0u8; TRAILER_SIZE];
+        input.seek(SeekFrom::End(-(TRAILER_SIZE as i64)))?;
+        input.read_exact(&mut trailer)?;
+        if trailer[12..] != INDEX_MAGIC {
+            return Ok(None);
+        }
+        let index_offset = u64::from_le_bytes(trailer[..8].try_into().unwrap());
+        let num_chunks = u32::from_le_bytes(trailer[8..12].try_into().unwrap()) as u64;
+        let corrupted = TraceError::Corrupted {
+            offset: len - TRAILER_SIZE as u64,
+            reason: "index out of the trace",
+        };
+        let index_end = index_offset.checked_add(num_chunks * INDEX_ENTRY_SIZE as u64);
+        if index_offset < HEADER_SIZE as u64 || index_end != Some(len - TRAILER_SIZE as u64) {
+            return Err(corrupted);
+        }
+        let mut index = vec![0u8; num_chunks as usize * INDEX_ENTRY_SIZE];
+        input.seek(SeekFrom::Start(index_offset))?;
+        input.read_exact(&mut index)?;
+        let chunks: Vec<ChunkInfo> = index
+            .chunks_exact(INDEX_ENTRY_SIZE)
+            .map(|entry| ChunkInfo {
+                offset: u64::from_le_bytes(entry[..8].try_into().unwrap()),
+                len: u32::from_le_bytes(entry[8..12].try_into().unwrap()),
+                records: u32::from_le_bytes(entry[12..16].try_into().unwrap()),
+            })
+            .collect();
+        if chunks.iter().any(|c| {
+            c.offset
+                .checked_add(CHUNK_HEADER_SIZE as u64 + c.len as u64)
+                .filter(|end| *end <= index_offset)
+                .is_none()
+        }) {
+            return Err(corrupted);
+        }
+        Ok(Some(chunks))
+    }
+
+    /// Follows the chunk headers from the trace header, up to the last complete chunk.
+    fn scan_chunks(input: &mut R) -> Result<Vec<ChunkInfo>, TraceError> {
+        let len = input.seek(SeekFrom::End(0))?;
+        let mut chunks = Vec::new();
+        let mut offset = HEADER_SIZE as u64;
+        while offset + CHUNK_HEADER_SIZE as u64 <= len {
+            let mut chunk_header = [0u8; CHUNK_HEADER_SIZE];
+            input.seek(SeekFrom::Start(offset))?;
+            input.read_exact(&mut chunk_header)?;
+            let chunk = ChunkInfo {
+                offset,
+                records: u32::from_le_bytes(chunk_header[..4].try_into().unwrap()),
+                len: u32::from_le_bytes(chunk_header[4..].try_into().unwrap()),
+            };
+            let end = offset + CHUNK_HEADER_SIZE as u64 + chunk.len as u64;
+            if end > len {
+                break;
+            }
+            chunks.push(chunk);
+            offset = end;
+        }
+        Ok(chunks)
+    }
+}
+
+/// Iterator over the records of a trace, see [`TraceReader::records`]. Stops after the first
+/// error.
+#[derive(Debug)]
+pub struct TraceRecords<'a, R: Read + Seek> {
+    reader: &'a mut TraceReader<R>,
+    chunk: usize,
+    records: std::vec::IntoIter<TraceRecord>,
+}
+
+impl<R: Read + Seek> Iterator for TraceRecords<'_, R> {
+    type Item = Result<TraceRecord, TraceError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(record) = self.records.next() {
+                return Some(Ok(record));
+            }
+            if self.chunk == self.reader.chunks.len() {
+                return None;
+            }
+            match self.reader.read_chunk(self.chunk) {
+                Ok(records) => {
+                    self.chunk += 1;
+                    self.records = records.into_iter();
+                }
+                Err(e) => {
+                    self.chunk = self.reader.chunks.len();
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "replay")]
+mod replay {
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
+
+    use crate::shared_def::IOMessage;
+    use crate::worker::process_record_handling::Exepath;
+
+    /// Lookups by pid and gid.
+    type Lookups = HashMap<(u32, u64), Option<PathBuf>>;
+
+    /// Gives the exepaths recorded in the trace, the last one of each pid and gid.
+    #[derive(Debug, Clone, Default)]
+    pub(super) struct RecordedExepath {
+        exepaths: Arc<Mutex<Lookups>>,
+    }
+
+    impl RecordedExepath {
+        pub(super) fn insert(&self, pid: u32, gid: u64, exepath: Option<PathBuf>) {
+            self.exepaths
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .insert((pid, gid), exepath);
+        }
+    }
+
+    impl Exepath for RecordedExepath {
+        fn exepath(&self, iomsg: &IOMessage) -> Option<PathBuf> {
+            self.exepaths
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .get(&(iomsg.pid, iomsg.gid))
+                .cloned()
+                .flatten()
+        }
+    }
+}
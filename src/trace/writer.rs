@@ -0,0 +1,184 @@
+use std::fmt;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use crate::driver_comm::version::PROTOCOL_VERSION;
+use crate::shared_def::{IOMessage, ProcessEvent};
+use crate::trace::{
+    time_to_nanos, ChunkInfo, TraceError, TraceRecord, CHUNK_HEADER_SIZE, HEADER_SIZE, INDEX_MAGIC,
+    TRACE_MAGIC, TRACE_VERSION,
+};
+use crate::worker::process_record_handling::Exepath;
+
+/// Records are written by chunks of this many records, unless tuned.
+pub const DEFAULT_CHUNK_RECORDS: u32 = 4096;
+
+/// Writes a trace, see the [module](super) documentation.
+///
+/// Records are buffered until their chunk is full: call [`finish`](Self::finish) to write the
+/// last one and the index.
+pub struct TraceWriter<W: Write> {
+    out: W,
+    /// Bytes written to `out`
+    written: u64,
+    chunk: Vec<u8>,
+    chunk_records: u32,
+    max_chunk_records: u32,
+    index: Vec<ChunkInfo>,
+}
+
+impl<W: Write> fmt::Debug for TraceWriter<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TraceWriter")
+            .field("written", &self.written)
+            .field("chunk_records", &self.chunk_records)
+            .field("chunks", &self.index.len())
+            .finish()
+    }
+}
+
+impl<W: Write> TraceWriter<W> {
+    /// Writes the header to `out`.
+    pub fn new(mut out: W) -> Result<TraceWriter<W>, TraceError> {
+        let mut header = Vec::with_capacity(HEADER_SIZE);
+        header.extend_from_slice(&TRACE_MAGIC);
+        header.extend_from_slice(&TRACE_VERSION.to_le_bytes());
+        header.extend_from_slice(&[0, 0]);
+        header.extend_from_slice(&PROTOCOL_VERSION.to_le_bytes());
+        header.extend_from_slice(&time_to_nanos(SystemTime::now()).to_le_bytes());
+        out.write_all(&header)?;
+        Ok(TraceWriter {
+            out,
+            written: HEADER_SIZE as u64,
+            chunk: Vec::new(),
+            chunk_records: 0,
+            max_chunk_records: DEFAULT_CHUNK_RECORDS,
+            index: Vec::new(),
+        })
+    }
+
+    /// Number of records per chunk, [`DEFAULT_CHUNK_RECORDS`] by default. A recording interrupted
+    /// before [`finish`](Self::finish) loses its last chunk.
+    pub fn chunk_records(mut self, records: u32) -> Self {
+        self.max_chunk_records = records.max(1);
+        self
+    }
+
+    /// Records `iomsg` as processed by the worker, i.e. after
+    /// [`process_io`](crate::worker::Worker::process_io).
+    pub fn record_iomsg(&mut self, iomsg: &IOMessage) -> Result<(), TraceError> {
+        self.push(TraceRecord::Io(iomsg.clone()));
+        if self.chunk_records >= self.max_chunk_records {
+            self.flush_chunk()?;
+        }
+        Ok(())
+    }
+
+    pub fn record_process(&mut self, event: &ProcessEvent) -> Result<(), TraceError> {
+        self.push(TraceRecord::Process(event.clone()));
+        if self.chunk_records >= self.max_chunk_records {
+            self.flush_chunk()?;
+        }
+        Ok(())
+    }
+
+    /// Records an exepath lookup, see [`RecordingExepath`]. Only full chunks are written, this
+    /// cannot fail.
+    pub fn record_exepath(&mut self, pid: u32, gid: u64, exepath: Option<PathBuf>) {
+        self.push(TraceRecord::Exepath { pid, gid, exepath });
+    }
+
+    /// Writes the last chunk and the index, and returns the output.
+    pub fn finish(mut self) -> Result<W, TraceError> {
+        self.flush_chunk()?;
+        let index_offset = self.written;
+        let mut index = Vec::new();
+        for chunk in &self.index {
+            index.extend_from_slice(&chunk.offset.to_le_bytes());
+            index.extend_from_slice(&chunk.len.to_le_bytes());
+            index.extend_from_slice(&chunk.records.to_le_bytes());
+        }
+        index.extend_from_slice(&index_offset.to_le_bytes());
+        index.extend_from_slice(&(self.index.len() as u32).to_le_bytes());
+        index.extend_from_slice(&INDEX_MAGIC);
+        self.out.write_all(&index)?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    fn push(&mut self, record: TraceRecord) {
+        record.encode(&mut self.chunk);
+        self.chunk_records += 1;
+    }
+
+    fn flush_chunk(&mut self) -> Result<(), TraceError> {
+        if self.chunk_records == 0 {
+            return Ok(());
+        }
+        let chunk = ChunkInfo {
+            offset: self.written,
+            len: self.chunk.len() as u32,
+            records: self.chunk_records,
+        };
+        self.out.write_all(&chunk.records.to_le_bytes())?;
+        self.out.write_all(&chunk.len.to_le_bytes())?;
+        self.out.write_all(&self.chunk)?;
+        self.out.flush()?;
+        self.written += (CHUNK_HEADER_SIZE + self.chunk.len()) as u64;
+        self.index.push(chunk);
+        self.chunk.clear();
+        self.chunk_records = 0;
+        Ok(())
+    }
+}
+
+/// An [`Exepath`] recording the lookups of another one to a [`TraceWriter`] shared with the app:
+///
+/// ```no_run
+/// use std::fs::File;
+/// use std::io::BufWriter;
+/// use std::sync::{Arc, Mutex};
+/// use minifilter_rs::trace::{RecordingExepath, TraceWriter};
+/// use minifilter_rs::worker::process_record_handling::ExepathLive;
+/// use minifilter_rs::worker::Worker;
+///
+/// let trace = TraceWriter::new(BufWriter::new(File::create("detonation.trace").unwrap())).unwrap();
+/// let trace = Arc::new(Mutex::new(trace));
+/// let mut worker = Worker::new()
+///     .exepath_handler(Box::new(RecordingExepath::new(Box::new(ExepathLive), trace.clone())))
+///     .build();
+/// // For each iomsg received:
+/// // worker.process_io(&mut iomsg);
+/// // trace.lock().unwrap().record_iomsg(&iomsg).unwrap();
+/// ```
+pub struct RecordingExepath<W: Write> {
+    exepath: Box<dyn Exepath>,
+    trace: Arc<Mutex<TraceWriter<W>>>,
+}
+
+impl<W: Write> RecordingExepath<W> {
+    pub fn new(exepath: Box<dyn Exepath>, trace: Arc<Mutex<TraceWriter<W>>>) -> Self {
+        RecordingExepath { exepath, trace }
+    }
+}
+
+impl<W: Write> fmt::Debug for RecordingExepath<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RecordingExepath")
+            .field("exepath", &self.exepath)
+            .finish()
+    }
+}
+
+impl<W: Write> Exepath for RecordingExepath<W> {
+    fn exepath(&self, iomsg: &IOMessage) -> Option<PathBuf> {
+        let exepath = self.exepath.exepath(iomsg);
+        self.trace
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .record_exepath(iomsg.pid, iomsg.gid, exepath.clone());
+        exepath
+    }
+}
@@ -0,0 +1,409 @@
+//! Classification of the file paths by the volume they are on.
+//!
+//! Every read and write of a [`ProcessRecord`](crate::process::ProcessRecord) is counted by
+//! [`DriveType`], e.g. to spot a process encrypting a network share. Asking Windows for each event
+//! is too slow, so a [`VolumeCache`] lists the volumes once, with their mount points
+//! (`C:\`, `D:\Mounted\Usb\`) and devices (`\Device\HarddiskVolume3`), and only lists them again
+//! when a volume arrives or is removed.
+//!
+//! The volumes are listed by a [`VolumeSource`]: [`VolumeSourceLive`] on Windows, or a fixed
+//! `Vec<VolumeInfo>` to classify recorded events elsewhere.
+
+use std::fmt::Debug;
+use std::time::{Duration, Instant};
+
+use crate::driver_comm::DriveType;
+
+/// A volume, or a network drive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VolumeInfo {
+    /// The device, e.g. `\Device\HarddiskVolume3`. Empty if unknown.
+    pub device: String,
+    pub drive_type: DriveType,
+    /// Serial number of the volume, as in the [`FileId`](crate::process::FileId)s. 0 if unknown.
+    pub serial: u32,
+    /// Where the volume is mounted, with a trailing `\`: `C:\`, `D:\Mounted\Usb\`...
+    pub mount_points: Vec<String>,
+}
+
+/// Lists the volumes.
+pub trait VolumeSource: Debug + Send {
+    /// All the volumes currently mounted.
+    fn volumes(&self) -> Vec<VolumeInfo>;
+
+    /// Changes whenever a volume arrives or is removed. Cheap enough to be called often.
+    fn generation(&self) -> u64;
+}
+
+/// A fixed list, e.g. the volumes of the machine where events have been recorded.
+impl VolumeSource for Vec<VolumeInfo> {
+    fn volumes(&self) -> Vec<VolumeInfo> {
+        self.clone()
+    }
+
+    fn generation(&self) -> u64 {
+        0
+    }
+}
+
+/// Asks Windows. The generation hashes the volume names (`\\?\Volume{GUID}\`) with their mount
+/// points, and the drive letters of `GetLogicalDrives` for the network drives: a volume mounted
+/// on a folder, or another volume taking a letter, changes it.
+#[derive(Debug, Default)]
+pub struct VolumeSourceLive;
+
+/// Null terminated UTF-16.
+#[cfg(windows)]
+fn wide(s: &str) -> Vec<u16> {
+    s.encode_utf16().chain(Some(0)).collect()
+}
+
+/// The strings of a double null terminated UTF-16 buffer.
+#[cfg(windows)]
+fn multi_string(buf: &[u16]) -> Vec<String> {
+    buf.split(|c| *c == 0)
+        .take_while(|s| !s.is_empty())
+        .map(String::from_utf16_lossy)
+        .collect()
+}
+
+/// The volume names, `\\?\Volume{GUID}\`, with their mount points.
+#[cfg(windows)]
+fn volume_paths() -> Vec<(String, Vec<String>)> {
+    use windows::core::PCWSTR;
+    use windows::Win32::Storage::FileSystem::{
+        FindFirstVolumeW, FindNextVolumeW, FindVolumeClose, GetVolumePathNamesForVolumeNameW,
+    };
+
+    let mut volumes = Vec::new();
+    let mut name = [0u16; 260];
+    if let Ok(handle) = unsafe { FindFirstVolumeW(&mut name) } {
+        loop {
+            let end = name.iter().position(|c| *c == 0).unwrap_or(name.len());
+            let volume_name = String::from_utf16_lossy(&name[..end]);
+            let mut paths = [0u16; 1024];
+            let mut len = 0u32;
+            unsafe {
+                GetVolumePathNamesForVolumeNameW(
+                    PCWSTR(wide(&volume_name).as_ptr()),
+                    Some(&mut paths),
+                    &mut len,
+                );
+            }
+            volumes.push((volume_name, multi_string(&paths)));
+            if !unsafe { FindNextVolumeW(handle, &mut name) }.as_bool() {
+                break;
+            }
+        }
+        unsafe {
+            FindVolumeClose(handle);
+        }
+    }
+    volumes
+}
+
+#[cfg(windows)]
+impl VolumeSource for VolumeSourceLive {
+    fn volumes(&self) -> Vec<VolumeInfo> {
+        use windows::core::PCWSTR;
+        use windows::Win32::Storage::FileSystem::{
+            GetDriveTypeW, GetLogicalDrives, GetVolumeInformationW, QueryDosDeviceW,
+        };
+
+        fn dos_device(name: &str) -> String {
+            let mut buf = [0u16; 512];
+            let len = unsafe { QueryDosDeviceW(PCWSTR(wide(name).as_ptr()), Some(&mut buf)) };
+            multi_string(&buf[..len as usize])
+                .into_iter()
+                .next()
+                .unwrap_or_default()
+        }
+        fn volume_info(root: &str, device: String, mount_points: Vec<String>) -> VolumeInfo {
+            let root = wide(root);
+            let mut serial = 0u32;
+            unsafe {
+                GetVolumeInformationW(
+                    PCWSTR(root.as_ptr()),
+                    None,
+                    Some(&mut serial),
+                    None,
+                    None,
+                    None,
+                );
+            }
+            VolumeInfo {
+                device,
+                drive_type: DriveType::from_win32(unsafe { GetDriveTypeW(PCWSTR(root.as_ptr())) }),
+                serial,
+                mount_points,
+            }
+        }
+
+        let mut volumes = Vec::new();
+        for (volume_name, mount_points) in volume_paths() {
+            let device = dos_device(
+                volume_name
+                    .trim_start_matches(r"\\?\")
+                    .trim_end_matches('\\'),
+            );
+            volumes.push(volume_info(&volume_name, device, mount_points));
+        }
+
+        // Network drives are not volumes
+        let drives = unsafe { GetLogicalDrives() };
+        for (i, letter) in ('A'..='Z').enumerate() {
+            let root = format!(r"{letter}:\");
+            if drives & (1 << i) != 0 && !volumes.iter().any(|v| v.mount_points.contains(&root)) {
+                let device = dos_device(&format!("{letter}:"));
+                volumes.push(volume_info(&root, device, vec![root.clone()]));
+            }
+        }
+        volumes
+    }
+
+    fn generation(&self) -> u64 {
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::{Hash, Hasher};
+        use windows::Win32::Storage::FileSystem::GetLogicalDrives;
+
+        let mut hasher = DefaultHasher::new();
+        volume_paths().hash(&mut hasher);
+        unsafe { GetLogicalDrives() }.hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+/// There are no volumes to list outside of Windows: use another [`VolumeSource`] there.
+#[cfg(not(windows))]
+impl VolumeSource for VolumeSourceLive {
+    fn volumes(&self) -> Vec<VolumeInfo> {
+        Vec::new()
+    }
+
+    fn generation(&self) -> u64 {
+        0
+    }
+}
+
+/// Finds the volume of a file path.
+pub trait VolumeResolver: Debug + Send {
+    /// The volume `path` is on, if known. `path` is a DOS path (`C:\...`), a device path
+    /// (`\Device\HarddiskVolume3\...`) or a `\\?\` path.
+    fn volume_of(&mut self, path: &str) -> Option<&VolumeInfo>;
+
+    /// The [`DriveType`] of `path`. UNC paths (`\\server\share\...`) are
+    /// [`DriveRemote`](DriveType::DriveRemote), unknown volumes
+    /// [`DriveNoRootDir`](DriveType::DriveNoRootDir).
+    fn drive_type(&mut self, path: &str) -> DriveType {
+        if is_unc(path) {
+            return DriveType::DriveRemote;
+        }
+        self.volume_of(path)
+            .map_or(DriveType::DriveNoRootDir, |volume| volume.drive_type)
+    }
+}
+
+/// Whether `path` is on a network share, without any volume: `\\server\share\...`,
+/// `\\?\UNC\server\share\...` or `\Device\Mup\server\share\...`.
+pub fn is_unc(path: &str) -> bool {
+    let starts_with = |prefix: &str| {
+        path.get(..prefix.len())
+            .is_some_and(|start| start.eq_ignore_ascii_case(prefix))
+    };
+    if starts_with(r"\\?\") || starts_with(r"\\.\") {
+        return starts_with(r"\\?\UNC\");
+    }
+    starts_with(r"\\") || starts_with(r"\Device\Mup\")
+}
+
+/// A [`VolumeResolver`] caching the volumes of a [`VolumeSource`]. They are listed again when its
+/// [`generation`](VolumeSource::generation) changes, checked at most once per `check_interval`.
+#[derive(Debug)]
+pub struct VolumeCache<S: VolumeSource = VolumeSourceLive> {
+    source: S,
+    volumes: Vec<VolumeInfo>,
+    generation: u64,
+    check_interval: Duration,
+    last_check: Instant,
+}
+
+impl VolumeCache {
+    /// The volumes of this machine, checked every second.
+    pub fn live() -> VolumeCache {
+        VolumeCache::new(VolumeSourceLive)
+    }
+}
+
+impl<S: VolumeSource> VolumeCache<S> {
+    pub fn new(source: S) -> VolumeCache<S> {
+        let mut cache = VolumeCache {
+            source,
+            volumes: Vec::new(),
+            generation: 0,
+            check_interval: Duration::from_secs(1),
+            last_check: Instant::now(),
+        };
+        cache.refresh();
+        cache
+    }
+
+    pub fn check_interval(mut self, check_interval: Duration) -> Self {
+        self.check_interval = check_interval;
+        self
+    }
+
+    pub fn build(self) -> Self {
+        self
+    }
+
+    /// Lists the volumes again.
+    pub fn refresh(&mut self) {
+        self.generation = self.source.generation();
+        self.volumes = self.source.volumes();
+        self.last_check = Instant::now();
+    }
+
+    pub fn volumes(&self) -> &[VolumeInfo] {
+        &self.volumes
+    }
+
+    fn refresh_if_changed(&mut self) {
+        if self.last_check.elapsed() < self.check_interval {
+            return;
+        }
+        self.last_check = Instant::now();
+        if self.source.generation() != self.generation {
+            self.refresh();
+        }
+    }
+}
+
+impl<S: VolumeSource> VolumeResolver for VolumeCache<S> {
+    fn volume_of(&mut self, path: &str) -> Option<&VolumeInfo> {
+        self.refresh_if_changed();
+        let path = path.strip_prefix(r"\\?\").unwrap_or(path);
+
+        // The longest root wins: D:\Mounted\Usb\ is on another volume than D:\
+        let mut best: Option<(usize, &VolumeInfo)> = None;
+        for volume in &self.volumes {
+            let device = (!volume.device.is_empty()).then_some(volume.device.as_str());
+            for root in volume.mount_points.iter().map(String::as_str).chain(device) {
+                let root = root.trim_end_matches('\\');
+                let longer = best.filter(|(len, _)| *len >= root.len()).is_none();
+                if is_under(path, root) && longer {
+                    best = Some((root.len(), volume));
+                }
+            }
+        }
+        best.map(|(_, volume)| volume)
+    }
+}
+
+/// `path` is `root` or one of its descendants, ignoring the case.
+fn is_under(path: &str, root: &str) -> bool {
+    path.get(..root.len())
+        .is_some_and(|start| start.eq_ignore_ascii_case(root))
+        && matches!(path.as_bytes().get(root.len()), None | Some(b'\\'))
+}
+
+#[cfg(test)]
+#[doc(hidden)]
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use crate::driver_comm::DriveType::*;
+    use crate::volume::{VolumeCache, VolumeInfo, VolumeResolver, VolumeSource};
+
+    fn volume(
+        device: &str,
+        drive_type: crate::driver_comm::DriveType,
+        mount_points: &[&str],
+    ) -> VolumeInfo {
+        VolumeInfo {
+            device: device.to_string(),
+            drive_type,
+            serial: 0,
+            mount_points: mount_points.iter().map(|m| m.to_string()).collect(),
+        }
+    }
+
+    fn volumes() -> Vec<VolumeInfo> {
+        vec![
+            volume(r"\Device\HarddiskVolume3", DriveFixed, &[r"C:\"]),
+            volume(
+                r"\Device\HarddiskVolume5",
+                DriveRemovable,
+                &[r"E:\", r"C:\Mnt\Usb\"],
+            ),
+            volume("", DriveRemote, &[r"Z:\"]),
+        ]
+    }
+
+    #[test]
+    fn test_drive_type() {
+        let mut cache = VolumeCache::new(volumes());
+        for (path, expected) in [
+            (r"C:\Users\Dev\a.txt", DriveFixed),
+            (r"c:\users", DriveFixed),
+            (r"C:\Mnt\Usb\a.txt", DriveRemovable),
+            (r"C:\Mnt\Usb2\a.txt", DriveFixed),
+            (r"\\?\E:\a.txt", DriveRemovable),
+            (r"\Device\HarddiskVolume5\a.txt", DriveRemovable),
+            (r"\Device\HarddiskVolume50\a.txt", DriveNoRootDir),
+            (r"Z:\Shared\a.txt", DriveRemote),
+            (r"\\server\share\a.txt", DriveRemote),
+            (r"\\?\UNC\server\share\a.txt", DriveRemote),
+            (r"\Device\Mup\server\share\a.txt", DriveRemote),
+            (r"Y:\a.txt", DriveNoRootDir),
+            ("a.txt", DriveNoRootDir),
+            ("", DriveNoRootDir),
+        ] {
+            assert_eq!(cache.drive_type(path), expected, "{path}");
+        }
+    }
+
+    /// A source whose volumes can be changed.
+    #[derive(Debug, Clone, Default)]
+    struct Volumes {
+        volumes: Arc<Mutex<Vec<VolumeInfo>>>,
+        generation: Arc<AtomicU64>,
+        listed: Arc<AtomicU64>,
+    }
+
+    impl VolumeSource for Volumes {
+        fn volumes(&self) -> Vec<VolumeInfo> {
+            self.listed.fetch_add(1, Ordering::Relaxed);
+            self.volumes.lock().unwrap().clone()
+        }
+
+        fn generation(&self) -> u64 {
+            self.generation.load(Ordering::Relaxed)
+        }
+    }
+
+    #[test]
+    fn test_refresh_on_arrival() {
+        let source = Volumes::default();
+        *source.volumes.lock().unwrap() = volumes();
+        let mut cache = VolumeCache::new(source.clone())
+            .check_interval(Duration::ZERO)
+            .build();
+
+        for _ in 0..10 {
+            assert_eq!(cache.drive_type(r"C:\a.txt"), DriveFixed);
+        }
+        assert_eq!(cache.drive_type(r"F:\a.txt"), DriveNoRootDir);
+        assert_eq!(source.listed.load(Ordering::Relaxed), 1);
+
+        source.volumes.lock().unwrap().push(volume(
+            r"\Device\HarddiskVolume9",
+            DriveCDRom,
+            &[r"F:\"],
+        ));
+        source.generation.store(1, Ordering::Relaxed);
+        assert_eq!(cache.drive_type(r"F:\a.txt"), DriveCDRom);
+        assert_eq!(source.listed.load(Ordering::Relaxed), 2);
+    }
+}
@@ -1,16 +1,27 @@
 pub mod process_record_handling;
 pub mod process_records;
 
-use crate::process::ProcessRecord;
-use crate::shared_def::IOMessage;
+use crate::driver_comm::stats::DriverStats;
+use crate::enrich::{DriveTypeEnricher, EnricherStats, Enrichers, FileSizeEnricher};
+use crate::process::{ProcessRecord, ProcessState};
+use crate::shared_def::nt_path::{DeviceLetterCache, DeviceLetters, NtPath};
+use crate::shared_def::{IOMessage, ProcessEvent, ProcessEventKind};
+use crate::volume::{VolumeCache, VolumeResolver};
 use crate::worker::process_record_handling::{Exepath, ExepathLive};
 use crate::worker::process_records::ProcessRecords;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug)]
 pub struct Worker {
     process_records: ProcessRecords,
     exepath_handler: Box<dyn Exepath>,
+    /// To recognize the executables in System32 from their device paths.
+    device_letters: Box<dyn DeviceLetters>,
+    system32: NtPath,
+    /// Run on each [`IOMessage`] before it is recorded.
+    enrichers: Enrichers,
+    /// The last [`DriverStats`] given to [`process_stats`](Self::process_stats).
+    last_stats: Option<DriverStats>,
 }
 
 impl Default for Worker {
@@ -23,7 +34,16 @@ impl Worker {
     pub fn new() -> Worker {
         Worker {
             process_records: ProcessRecords::new(),
-            exepath_handler: Box::new(ExepathLive::default()),
+            exepath_handler: Box::new(ExepathLive),
+            device_letters: Box::new(DeviceLetterCache::live()),
+            system32: NtPath::parse(r"C:\Windows\System32").expect("a drive path"),
+            enrichers: Enrichers::new()
+                .with(Box::new(FileSizeEnricher::new()))
+                .with(Box::new(DriveTypeEnricher::new(Box::new(
+                    VolumeCache::live(),
+                ))))
+                .build(),
+            last_stats: None,
         }
     }
 
@@ -32,11 +52,31 @@ impl Worker {
         self
     }
 
+    pub fn device_letters(mut self, device_letters: Box<dyn DeviceLetters>) -> Worker {
+        self.device_letters = device_letters;
+        self
+    }
+
+    /// Replaces the [`DriveTypeEnricher`] of the [`enrichers`](Self::enrichers).
+    pub fn volume_resolver(mut self, volume_resolver: Box<dyn VolumeResolver>) -> Worker {
+        self.enrichers
+            .replace(Box::new(DriveTypeEnricher::new(volume_resolver)));
+        self
+    }
+
+    /// The enrichments of the [`IOMessage`]s, [`FileSizeEnricher`] and [`DriveTypeEnricher`] by
+    /// default. The features of the records built on missing ones are left empty.
+    pub fn enrichers(mut self, enrichers: Enrichers) -> Worker {
+        self.enrichers = enrichers;
+        self
+    }
+
     pub fn build(self) -> Worker {
         self
     }
 
     pub fn process_io(&mut self, iomsg: &mut IOMessage) {
+        self.enrichers.enrich(iomsg);
         self.register_precord(iomsg);
         if let Some(precord) = self.process_records.get_precord_mut_by_gid(iomsg.gid) {
             precord.add_irp_record(iomsg);
@@ -45,31 +85,325 @@ impl Worker {
         }
     }
 
-    fn register_precord(&mut self, iomsg: &mut IOMessage) {
-        // dbg!(&iomsg);
-        match self.process_records.get_precord_by_gid(iomsg.gid) {
-            None => {
-                if let Some(exepath) = &self.exepath_handler.exepath(iomsg) {
+    /// Opens the record of a gid on the creation of its first process, before any i/o, and closes it
+    /// when its last process exits: the record is then [`Exited`](ProcessState::Exited), unless it
+    /// was killed, and is removed from this worker and returned.
+    pub fn process_event(&mut self, event: &ProcessEvent) -> Option<ProcessRecord> {
+        match event.kind {
+            ProcessEventKind::Created => {
+                if self.process_records.get_precord_by_gid(event.gid).is_none() {
+                    let exepath = PathBuf::from(&event.image_path);
+                    if self.is_system_exe(&exepath) {
+                        return None;
+                    }
                     let appname = self
-                        .appname_from_exepath(exepath)
+                        .appname_from_exepath(&exepath)
                         .unwrap_or_else(|| String::from("DEFAULT"));
-                    if !exepath
-                        .parent()
-                        .unwrap_or_else(|| Path::new("/"))
-                        .starts_with(r"C:\Windows\System32")
-                    {
-                        let precord = ProcessRecord::from(iomsg, appname, exepath.clone());
-                        self.process_records.insert_precord(iomsg.gid, precord);
-                    }
+                    let mut precord = ProcessRecord::new(event.gid, appname, exepath);
+                    precord.time_started = event.time;
+                    self.process_records.insert_precord(event.gid, precord);
+                }
+                if let Some(precord) = self.process_records.get_precord_mut_by_gid(event.gid) {
+                    precord.pids.insert(event.pid);
+                }
+                None
+            }
+            ProcessEventKind::Exited if event.ends_gid() => {
+                let mut precord = self.process_records.remove_precord(event.gid)?;
+                if precord.process_state != ProcessState::Killed {
+                    precord.process_state = ProcessState::Exited;
+                }
+                precord.time_exited = Some(event.time);
+                Some(precord)
+            }
+            ProcessEventKind::Exited => None,
+        }
+    }
+
+    /// To be called with the [`DriverStats`] fetched from time to time: the operations dropped since
+    /// the previous ones are added to the records of the running gids, which are then
+    /// [incomplete](ProcessRecord::is_incomplete). The first ones are only the baseline, the drops
+    /// before them cannot be told apart from those before the records.
+    pub fn process_stats(&mut self, stats: &DriverStats) {
+        let Some(last_stats) = self.last_stats.replace(*stats) else {
+            return;
+        };
+        let dropped = stats.dropped_since(&last_stats);
+        if dropped == 0 {
+            return;
+        }
+        for precord in self.process_records.process_records.values_mut() {
+            if precord.process_state != ProcessState::Killed {
+                precord.add_dropped_ops(dropped);
+            }
+        }
+    }
+
+    /// The time spent in each enricher.
+    pub fn enricher_stats(&self) -> Vec<(&'static str, EnricherStats)> {
+        self.enrichers.stats()
+    }
+
+    /// The record of `gid`, if any.
+    pub fn precord(&self, gid: u64) -> Option<&ProcessRecord> {
+        self.process_records.get_precord_by_gid(gid)
+    }
+
+    fn register_precord(&mut self, iomsg: &mut IOMessage) {
+        // dbg!(&iomsg);
+        if self.process_records.get_precord_by_gid(iomsg.gid).is_none() {
+            if let Some(exepath) = &self.exepath_handler.exepath(iomsg) {
+                let appname = self
+                    .appname_from_exepath(exepath)
+                    .unwrap_or_else(|| String::from("DEFAULT"));
+                if !self.is_system_exe(exepath) {
+                    let precord = ProcessRecord::from(iomsg, appname, exepath.clone());
+                    self.process_records.insert_precord(iomsg.gid, precord);
                 }
             }
-            Some(_) => {}
         }
     }
 
+    /// Whether `exepath`, in any form the minifilter or Windows reports it, is in System32.
+    fn is_system_exe(&self, exepath: &Path) -> bool {
+        NtPath::parse(&exepath.to_string_lossy())
+            .and_then(|exepath| exepath.to_dos_path(self.device_letters.as_ref()))
+            .is_some_and(|exepath| exepath.starts_with(&self.system32))
+    }
+
     fn appname_from_exepath(&self, exepath: &Path) -> Option<String> {
-        exepath
-            .file_name()
-            .map(|filename| filename.to_string_lossy().to_string())
+        match NtPath::parse(&exepath.to_string_lossy()) {
+            Some(exepath) => exepath.file_name().map(str::to_string),
+            None => exepath
+                .file_name()
+                .map(|filename| filename.to_string_lossy().to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+#[doc(hidden)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use crate::driver_comm::mock::fixtures::{fetch_iomsgs, ExepathFixed};
+    use crate::driver_comm::mock::{MockDriver, MockEvent};
+    use crate::driver_comm::stats::DriverStats;
+    use crate::driver_comm::{DriveType, Driver, IrpMajorOp};
+    use crate::process::ProcessState;
+    use crate::shared_def::{Event, ProcessEvent, ProcessEventKind};
+    use crate::volume::{VolumeCache, VolumeInfo};
+    use crate::worker::Worker;
+
+    #[test]
+    fn test_drops_before_the_first_stats_are_not_attributed() {
+        let mock = MockDriver::new();
+        mock.push_event(MockEvent::new(10, 3, IrpMajorOp::IrpWrite, r"C:\a.txt"));
+        let driver = Driver::with_transport(mock);
+        let mut worker = Worker::new()
+            .exepath_handler(Box::new(ExepathFixed))
+            .volume_resolver(Box::new(VolumeCache::new(vec![])))
+            .build();
+        let mut vecnew = Vec::new();
+        for mut iomsg in fetch_iomsgs(&driver, &mut vecnew) {
+            worker.process_io(&mut iomsg);
+        }
+
+        // Dropped since the minifilter started, before this worker
+        worker.process_stats(&DriverStats {
+            ops_queued: 100,
+            ops_dropped: 40,
+            ..DriverStats::default()
+        });
+        assert!(!worker.precord(3).unwrap().is_incomplete());
+
+        worker.process_stats(&DriverStats {
+            ops_queued: 120,
+            ops_dropped: 45,
+            ..DriverStats::default()
+        });
+        assert_eq!(worker.precord(3).unwrap().ops_dropped, 5);
+    }
+
+    #[test]
+    fn test_events_reach_worker() {
+        let mock = MockDriver::new();
+        mock.push_event(
+            MockEvent::new(10, 3, IrpMajorOp::IrpWrite, r"C:\Users\Dev\report.docx")
+                .extension("docx")
+                .transferred(4096, 7.9),
+        );
+        let driver = Driver::with_transport(mock);
+        let mut worker = Worker::new()
+            .exepath_handler(Box::new(ExepathFixed))
+            .volume_resolver(Box::new(VolumeCache::new(vec![VolumeInfo {
+                device: r"\Device\HarddiskVolume3".to_string(),
+                drive_type: DriveType::DriveRemovable,
+                serial: 0,
+                mount_points: vec![r"C:\".to_string()],
+            }])))
+            .build();
+        let mut vecnew: Vec<u8> = Vec::with_capacity(65536);
+
+        for mut iomsg in fetch_iomsgs(&driver, &mut vecnew) {
+            worker.process_io(&mut iomsg);
+            assert_eq!(
+                iomsg.runtime_features.exepath,
+                PathBuf::from(r"C:\Users\Dev\Downloads\bad.exe")
+            );
+            assert_eq!(iomsg.runtime_features.drive_type, DriveType::DriveRemovable);
+        }
+    }
+
+    #[test]
+    fn test_dropped_ops_make_records_incomplete() {
+        let mock = MockDriver::new();
+        let driver = Driver::with_transport(mock.clone());
+        let mut worker = Worker::new()
+            .exepath_handler(Box::new(ExepathFixed))
+            .volume_resolver(Box::new(VolumeCache::new(vec![])))
+            .build();
+        let mut vecnew = Vec::new();
+        mock.set_gid(3, &[(10, "bad.exe")]);
+        mock.push_events([
+            MockEvent::new(10, 3, IrpMajorOp::IrpCreate, r"C:\a.txt"),
+            MockEvent::new(10, 3, IrpMajorOp::IrpWrite, r"C:\a.txt").transferred(4096, 7.9),
+        ]);
+        for mut iomsg in fetch_iomsgs(&driver, &mut vecnew) {
+            worker.process_io(&mut iomsg);
+        }
+        let stats = driver.stats().unwrap();
+        assert_eq!(stats.ops_queued, 2);
+        assert_eq!(stats.ops(IrpMajorOp::IrpCreate), 1);
+        assert_eq!(stats.ops(IrpMajorOp::IrpWrite), 1);
+        assert_eq!(stats.entropy_calcs, 1);
+        assert_eq!(stats.gids, 1);
+        worker.process_stats(&stats);
+        assert!(!worker.precord(3).unwrap().is_incomplete());
+
+        driver.set_max_ops(1).unwrap();
+        mock.push_events([
+            MockEvent::new(10, 3, IrpMajorOp::IrpRead, r"C:\a.txt"),
+            MockEvent::new(10, 3, IrpMajorOp::IrpRead, r"C:\b.txt"),
+            MockEvent::new(10, 3, IrpMajorOp::IrpRead, r"C:\c.txt"),
+        ]);
+        let stats = driver.stats().unwrap();
+        assert_eq!((stats.ops_dropped, stats.queue_depth), (2, 1));
+        assert_eq!(
+            stats.to_string(),
+            "3 ops queued, 2 dropped, 1 waiting, 1 entropies, 1 instances, 1 gids"
+        );
+        worker.process_stats(&stats);
+        worker.process_stats(&stats);
+        assert_eq!(worker.precord(3).unwrap().ops_dropped, 2);
+
+        // The counters restart with the minifilter
+        mock.unload();
+        driver.reconnect().unwrap();
+        mock.push_events([
+            MockEvent::new(10, 3, IrpMajorOp::IrpRead, r"C:\a.txt"),
+            MockEvent::new(10, 3, IrpMajorOp::IrpRead, r"C:\b.txt"),
+        ]);
+        worker.process_stats(&driver.stats().unwrap());
+        assert_eq!(worker.precord(3).unwrap().ops_dropped, 3);
+    }
+
+    #[test]
+    fn test_process_events_open_and_close_records() {
+        let mock = MockDriver::new();
+        let driver = Driver::with_transport(mock.clone());
+        let mut worker = Worker::new()
+            .exepath_handler(Box::new(ExepathFixed))
+            .volume_resolver(Box::new(VolumeCache::new(vec![])))
+            .device_letters(Box::new(HashMap::from([(
+                r"\Device\HarddiskVolume3".to_string(),
+                'C',
+            )])))
+            .build();
+        let started = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let process = |kind, pid, parent_pid, alive_pids| ProcessEvent {
+            kind,
+            pid,
+            parent_pid,
+            gid: 5,
+            image_path: r"\Device\HarddiskVolume3\Tools\bad.exe".to_string(),
+            time: started + Duration::from_secs(pid as u64 - 100),
+            alive_pids,
+        };
+        mock.push_events([
+            MockEvent::process(&process(ProcessEventKind::Created, 100, 4, 1)),
+            MockEvent::process(&process(ProcessEventKind::Created, 101, 100, 2)),
+            MockEvent::new(101, 5, IrpMajorOp::IrpWrite, r"C:\a.txt"),
+            MockEvent::process(&process(ProcessEventKind::Exited, 100, 4, 1)),
+            MockEvent::process(&process(ProcessEventKind::Exited, 101, 100, 0)),
+        ]);
+        assert_eq!(driver.stats().unwrap().ops(IrpMajorOp::IrpWrite), 1);
+
+        let mut vecnew: Vec<u8> = Vec::with_capacity(65536);
+        let mut events = Vec::new();
+        driver
+            .poll_events(&mut vecnew, |event| {
+                events.push(event.unwrap());
+                true
+            })
+            .unwrap();
+        assert_eq!(events.len(), 5);
+        assert!(
+            matches!(&events[0], Event::Process(e) if e == &process(ProcessEventKind::Created, 100, 4, 1))
+        );
+
+        let mut closed = Vec::new();
+        for event in events {
+            match event {
+                Event::Io(mut iomsg) => {
+                    // Opened on the creation of the gid, not by its first i/o
+                    let precord = worker.precord(5).unwrap();
+                    assert_eq!(
+                        precord.exepath,
+                        PathBuf::from(r"\Device\HarddiskVolume3\Tools\bad.exe")
+                    );
+                    assert_eq!(precord.time_started, started);
+                    worker.process_io(&mut iomsg);
+                }
+                Event::Process(process_event) => {
+                    closed.extend(worker.process_event(&process_event))
+                }
+                Event::EventsLost { count } => panic!("{count} events lost"),
+            }
+        }
+        assert!(worker.precord(5).is_none());
+        assert_eq!(closed.len(), 1);
+        let precord = &closed[0];
+        assert_eq!(precord.process_state, ProcessState::Exited);
+        assert_eq!(precord.time_exited, Some(started + Duration::from_secs(1)));
+        assert_eq!(precord.pids.len(), 2);
+        assert_eq!(precord.ops_written, 1);
+        assert_eq!(precord.appname, "bad.exe");
+
+        // System32 is recognized in the device form
+        let svchost = ProcessEvent {
+            gid: 6,
+            image_path: r"\Device\HarddiskVolume3\Windows\System32\svchost.exe".to_string(),
+            ..process(ProcessEventKind::Created, 200, 4, 1)
+        };
+        assert!(worker.process_event(&svchost).is_none());
+        assert!(worker.precord(6).is_none());
+
+        // Not handed as i/o
+        mock.push_events([
+            MockEvent::process(&process(ProcessEventKind::Created, 102, 4, 1)),
+            MockEvent::new(102, 5, IrpMajorOp::IrpRead, r"C:\a.txt"),
+        ]);
+        let mut iomsgs = Vec::new();
+        driver
+            .poll_iomsgs(&mut vecnew, |iomsg| {
+                iomsgs.push(iomsg.unwrap());
+                true
+            })
+            .unwrap();
+        assert_eq!(iomsgs.len(), 1);
+        assert_eq!(iomsgs[0].pid, 102);
     }
 }
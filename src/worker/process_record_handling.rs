@@ -1,11 +1,25 @@
+use std::collections::HashMap;
 use std::fmt::Debug;
 use std::path::PathBuf;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, SystemTime};
+#[cfg(windows)]
 use windows::Win32::Foundation::{CloseHandle, GetLastError};
-// use windows::Win32::System::Diagnostics::Debug::DebugActiveProcess;
+#[cfg(windows)]
 use windows::Win32::System::ProcessStatus::K32GetProcessImageFileNameA;
+#[cfg(windows)]
 use windows::Win32::System::Threading::{OpenProcess, PROCESS_QUERY_INFORMATION, PROCESS_VM_READ};
 
-// use crate::process::{ProcessRecord, ProcessState};
+use sysinfo::{Pid, PidExt, ProcessExt, ProcessRefreshKind, System, SystemExt};
+
+use crate::driver_comm::error::DriverError;
+use crate::driver_comm::report::{
+    GidReport, KillOutcome, KillReport, PidKill, MAX_IMAGE_NAME_LENGTH,
+};
+use crate::driver_comm::transport::DriverTransport;
+use crate::driver_comm::Driver;
+use crate::process::{ProcessRecord, ProcessState};
 use crate::shared_def::IOMessage;
 
 pub trait Exepath: Debug {
@@ -15,15 +29,15 @@ pub trait Exepath: Debug {
 #[derive(Default, Debug)]
 pub struct ExepathLive;
 
+#[cfg(windows)]
 impl Exepath for ExepathLive {
     fn exepath(&self, iomsg: &IOMessage) -> Option<PathBuf> {
-        let pid = iomsg.pid as u32;
+        let pid = iomsg.pid;
         unsafe {
             let r_handle = OpenProcess(PROCESS_QUERY_INFORMATION | PROCESS_VM_READ, false, pid);
             if let Ok(handle) = r_handle {
                 if !(handle.is_invalid() || handle.0 == 0) {
-                    let mut buffer: Vec<u8> = Vec::new();
-                    buffer.resize(1024, 0);
+                    let mut buffer: Vec<u8> = vec![0; 1024];
                     let res = K32GetProcessImageFileNameA(handle, buffer.as_mut_slice());
 
                     CloseHandle(handle);
@@ -43,13 +57,189 @@ impl Exepath for ExepathLive {
     }
 }
 
-/*
-fn try_suspend(proc: &mut ProcessRecord) {
-    proc.process_state = ProcessState::Suspended;
-    for pid in &proc.pids {
-        unsafe {
-            DebugActiveProcess(*pid as u32);
+/// Processes cannot be looked up outside of Windows: use another [`Exepath`] there.
+#[cfg(not(windows))]
+impl Exepath for ExepathLive {
+    fn exepath(&self, _iomsg: &IOMessage) -> Option<PathBuf> {
+        None
+    }
+}
+
+/// Looks up the running processes.
+pub trait ProcessTable: Debug + Send {
+    /// Takes a new snapshot of the processes, before looking up a batch of pids.
+    fn refresh(&self) {}
+
+    /// The image name of `pid`, `None` if no such process is running.
+    fn image_name(&self, pid: u32) -> Option<String>;
+}
+
+/// Asks the OS, with [`sysinfo`].
+#[derive(Default, Debug)]
+pub struct ProcessTableLive {
+    system: Mutex<System>,
+}
+
+impl ProcessTableLive {
+    fn system(&self) -> std::sync::MutexGuard<'_, System> {
+        self.system.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl ProcessTable for ProcessTableLive {
+    fn refresh(&self) {
+        self.system()
+            .refresh_processes_specifics(ProcessRefreshKind::new());
+    }
+
+    fn image_name(&self, pid: u32) -> Option<String> {
+        self.system()
+            .process(Pid::from_u32(pid))
+            .map(|process| process.name().to_string())
+    }
+}
+
+/// A fixed table, e.g. `{1234: "bad.exe"}`.
+impl ProcessTable for HashMap<u32, String> {
+    fn image_name(&self, pid: u32) -> Option<String> {
+        self.get(&pid).cloned()
+    }
+}
+
+/// Kills families of processes through the minifilter, then checks with a [`ProcessTable`] that
+/// they are gone.
+#[derive(Debug)]
+pub struct GidKiller {
+    processes: Box<dyn ProcessTable>,
+    retries: u32,
+    retry_delay: Duration,
+}
+
+impl Default for GidKiller {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GidKiller {
+    /// Checks with the [`ProcessTableLive`], 5 times every 100 ms at most.
+    pub fn new() -> GidKiller {
+        GidKiller {
+            processes: Box::new(ProcessTableLive::default()),
+            retries: 5,
+            retry_delay: Duration::from_millis(100),
         }
     }
+
+    pub fn process_table(mut self, processes: Box<dyn ProcessTable>) -> GidKiller {
+        self.processes = processes;
+        self
+    }
+
+    /// After the first check, checks the survivors again `retries` times every `retry_delay`.
+    pub fn retries(mut self, retries: u32, retry_delay: Duration) -> GidKiller {
+        self.retries = retries;
+        self.retry_delay = retry_delay;
+        self
+    }
+
+    pub fn build(self) -> GidKiller {
+        self
+    }
+
+    /// Kills the family of `proc`. The record is [`Killed`](ProcessState::Killed) once all its
+    /// pids are gone.
+    pub fn kill<T: DriverTransport>(
+        &self,
+        driver: &Driver<T>,
+        proc: &mut ProcessRecord,
+    ) -> Result<KillReport, DriverError> {
+        let report = self.verify(driver.try_kill(proc.gid)?);
+        if report.is_complete() {
+            proc.process_state = ProcessState::Killed;
+            proc.time_killed = Some(SystemTime::now());
+        }
+        Ok(report)
+    }
+
+    /// Checks which pids of a [`try_kill`](Driver::try_kill) report are still running. A pid
+    /// running another image than the one reported has been reused: the killed process is gone.
+    pub fn verify(&self, report: GidReport) -> KillReport {
+        let mut pids: Vec<PidKill> = report
+            .pids
+            .into_iter()
+            .map(|outcome| PidKill {
+                pid: outcome.pid,
+                image_name: outcome.image_name,
+                status: outcome.status,
+                outcome: KillOutcome::Survived,
+            })
+            .collect();
+
+        for attempt in 0..=self.retries {
+            if attempt > 0 {
+                thread::sleep(self.retry_delay);
+            }
+            self.processes.refresh();
+            let mut survivors = 0;
+            for pid in pids
+                .iter_mut()
+                .filter(|pid| pid.outcome == KillOutcome::Survived)
+            {
+                match self.processes.image_name(pid.pid) {
+                    Some(image_name) if pid.image_name.is_empty() => {
+                        pid.image_name = image_name;
+                        survivors += 1;
+                    }
+                    Some(image_name) if same_image(&pid.image_name, &image_name) => survivors += 1,
+                    _ if pid.status.0 >= 0 => pid.outcome = KillOutcome::Killed,
+                    _ => pid.outcome = KillOutcome::AlreadyGone,
+                }
+            }
+            if survivors == 0 {
+                break;
+            }
+        }
+
+        KillReport {
+            gid: report.gid,
+            status: report.status,
+            num_pids: report.num_pids,
+            pids,
+        }
+    }
+}
+
+/// Whether `image_name`, in full, is the one `reported` by the minifilter: the kernel keeps only
+/// the first 15 characters.
+fn same_image(reported: &str, image_name: &str) -> bool {
+    let truncated: String = image_name.chars().take(MAX_IMAGE_NAME_LENGTH - 1).collect();
+    reported.eq_ignore_ascii_case(&truncated) || reported.eq_ignore_ascii_case(image_name)
+}
+
+/// Freezes the whole family of `proc` through the minifilter. The record is
+/// [`Suspended`](ProcessState::Suspended) as soon as one of its pids is.
+pub fn try_suspend<T: DriverTransport>(
+    driver: &Driver<T>,
+    proc: &mut ProcessRecord,
+) -> Result<GidReport, DriverError> {
+    let report = driver.suspend_gid(proc.gid)?;
+    if report.pids.iter().any(|outcome| outcome.is_ok()) {
+        proc.process_state = ProcessState::Suspended;
+        proc.time_suspended = Some(SystemTime::now());
+    }
+    Ok(report)
+}
+
+/// Thaws the family of `proc`, previously frozen by [`try_suspend`].
+pub fn try_resume<T: DriverTransport>(
+    driver: &Driver<T>,
+    proc: &mut ProcessRecord,
+) -> Result<GidReport, DriverError> {
+    let report = driver.resume_gid(proc.gid)?;
+    if report.is_complete() {
+        proc.process_state = ProcessState::Running;
+        proc.time_suspended = None;
+    }
+    Ok(report)
 }
-*/
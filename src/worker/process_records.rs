@@ -32,4 +32,8 @@ impl ProcessRecords {
     pub fn insert_precord(&mut self, gid: c_ulonglong, precord: ProcessRecord) {
         self.process_records.insert(gid, precord);
     }
+
+    pub fn remove_precord(&mut self, gid: c_ulonglong) -> Option<ProcessRecord> {
+        self.process_records.remove(&gid)
+    }
 }